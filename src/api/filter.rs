@@ -1,7 +1,7 @@
 //! Data structures to build criteria objects for the shopware API
 
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug, Serialize)]
 pub struct Criteria {
@@ -17,6 +17,9 @@ pub struct Criteria {
     pub associations: BTreeMap<String, Criteria>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub fields: Vec<String>,
+    /// fields of the response per entity (API alias), all other fields are left out
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub includes: BTreeMap<String, BTreeSet<String>>,
 }
 
 fn skip_page_serialize(page: &u64) -> bool {
@@ -32,6 +35,7 @@ impl Default for Criteria {
             filter: vec![],
             associations: BTreeMap::new(),
             fields: vec![],
+            includes: BTreeMap::new(),
         }
     }
 }
@@ -119,6 +123,38 @@ pub enum CriteriaFilter {
     },
 }
 
+impl CriteriaFilter {
+    /// Whether the filter (or one of its nested queries) filters by the given field
+    pub fn uses_field(&self, field: &str) -> bool {
+        match self {
+            Self::Equals { field: f, .. }
+            | Self::EqualsAny { field: f, .. }
+            | Self::Contains { field: f, .. }
+            | Self::Range { field: f, .. }
+            | Self::Prefix { field: f, .. }
+            | Self::Suffix { field: f, .. } => f == field,
+            Self::Not { queries, .. } | Self::Multi { queries, .. } => {
+                queries.iter().any(|query| query.uses_field(field))
+            }
+        }
+    }
+
+    /// Fields of the `equals` / `equalsAny` filters (including the nested queries)
+    pub fn get_equals_fields(&self) -> Vec<&str> {
+        match self {
+            Self::Equals { field, .. } | Self::EqualsAny { field, .. } => vec![field.as_str()],
+            Self::Contains { .. }
+            | Self::Range { .. }
+            | Self::Prefix { .. }
+            | Self::Suffix { .. } => vec![],
+            Self::Not { queries, .. } | Self::Multi { queries, .. } => queries
+                .iter()
+                .flat_map(CriteriaFilter::get_equals_fields)
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum LogicOperator {
@@ -143,11 +179,6 @@ pub struct RangeParameters {
     lt: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct EmptyObject {
-    // no fields
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -231,6 +262,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn filter_uses_field() {
+        let filter = CriteriaFilter::Multi {
+            operator: LogicOperator::Or,
+            queries: vec![
+                CriteriaFilter::Equals {
+                    field: "versionId".to_string(),
+                    value: json!("0fa91ce3e96a4bc2be4bd9ce752c3425"),
+                },
+                CriteriaFilter::Prefix {
+                    field: "orderNumber".to_string(),
+                    value: json!("1000"),
+                },
+            ],
+        };
+
+        assert!(filter.uses_field("versionId"));
+        assert!(filter.uses_field("orderNumber"));
+        assert!(!filter.uses_field("id"));
+        assert_eq!(filter.get_equals_fields(), vec!["versionId"]);
+    }
+
     #[test]
     fn criteria_serialize_filter() {
         let mut criteria = Criteria {
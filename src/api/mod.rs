@@ -3,50 +3,145 @@
 pub mod filter;
 
 use crate::api::filter::{Criteria, CriteriaFilter};
-use crate::config_file::Credentials;
+use crate::config_file::{Credentials, IsoCodeOptions, UnknownIsoCode};
+use anyhow::Context;
 use reqwest::blocking::{Client, RequestBuilder, Response};
 use reqwest::header::{HeaderMap, HeaderValue};
 use reqwest::{header, Method, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::Debug;
+use std::io::{BufReader, Read};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use thiserror::Error;
 
+/// Client of the Admin API of a shop
 #[derive(Debug, Clone)]
-pub struct SwClient {
+pub struct SwHttpClient {
     client: Client,
     credentials: Arc<Credentials>,
     access_token: Arc<Mutex<String>>,
+    /// Language of the read / written translatable fields (`sw-language-id` header),
+    /// the default language of the shop if not set
+    language_id: Option<String>,
+    /// Maximum size of a list response in bytes
+    max_response_size: Option<u64>,
+    /// only reading requests are sent
+    read_only: bool,
 }
 
-impl SwClient {
-    pub fn new(credentials: Credentials) -> anyhow::Result<Self> {
-        let mut default_headers = HeaderMap::default();
-        // This header is needed, otherwise the response would be "application/vnd.api+json" (by default)
-        // and that doesn't have the association data as part of the entity object
-        default_headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
-        let client = Client::builder()
+/// Configuration of the underlying HTTP client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwClientOptions {
+    /// Timeout for establishing the connection
+    pub connect_timeout: Duration,
+    /// Timeout for the whole request (until the response body is read),
+    /// `None` disables it for long-running requests
+    pub timeout: Option<Duration>,
+    /// Maximum idle connections kept in the pool per host, `0` opens a new connection per request
+    pub pool_max_idle_per_host: usize,
+    /// Interval of TCP keep-alive probes on open connections, `None` disables them
+    pub tcp_keepalive: Option<Duration>,
+    /// Use HTTP/2 without negotiation (the shop needs to support it)
+    pub http2_prior_knowledge: bool,
+    /// Maximum size of a list response in bytes, `None` disables the limit
+    pub max_response_size: Option<u64>,
+    /// Reject all requests which could write, only reading and searching is allowed
+    pub read_only: bool,
+    /// `User-Agent` header of every request, `sw-sync-cli/<version>` if not set
+    pub user_agent: Option<String>,
+    /// `X-Correlation-Id` header of every request, to find the requests of a run in the logs of the shop
+    pub correlation_id: Option<String>,
+}
+
+impl Default for SwClientOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Some(Duration::from_secs(15)),
             // workaround for long-running requests,
             // see https://github.com/hyperium/hyper/issues/2312#issuecomment-1411360500
-            .pool_max_idle_per_host(0)
-            .timeout(Duration::from_secs(15))
-            .default_headers(default_headers)
-            .build()?;
-        let credentials = Arc::new(credentials);
-        let auth_response = Self::authenticate(&client, credentials.as_ref())?;
+            pool_max_idle_per_host: 0,
+            tcp_keepalive: None,
+            http2_prior_knowledge: false,
+            max_response_size: None,
+            read_only: false,
+            user_agent: None,
+            correlation_id: None,
+        }
+    }
+}
 
-        println!("Shopware API client created and authenticated");
-        Ok(Self {
-            client,
-            credentials,
-            access_token: Arc::new(Mutex::new(auth_response.access_token)),
-        })
+/// Default `User-Agent` of the requests, with the name of the used profile (if any)
+pub fn get_user_agent(profile: Option<&str>) -> String {
+    let user_agent = format!("sw-sync-cli/{}", env!("CARGO_PKG_VERSION"));
+    match profile {
+        Some(profile) => format!("{user_agent} (profile {profile})"),
+        None => user_agent,
     }
+}
+
+/// Requests of the sync pipeline to a shop. [`SwHttpClient`] sends them to the Admin API,
+/// other implementations (like the fake shop of the `testing` module) allow running imports
+/// and exports of profiles without a live shop
+pub trait SwClient: Debug + Send + Sync {
+    fn base_url(&self) -> &str;
+
+    /// Client sharing the connections and the authentication, which requests everything in the given language
+    fn with_language(&self, language_id: String) -> Arc<dyn SwClient>;
+
+    /// Privileges (like `product:read`) of all ACL roles of the authenticated integration,
+    /// `None` for admin integrations, which have every privilege.
+    /// Reading them requires the privilege `integration:read` itself
+    fn get_integration_privileges(&self) -> Result<Option<BTreeSet<String>>, SwApiError>;
+
+    /// Write or delete the entities of the payload in a single operation
+    fn sync(
+        &self,
+        entity: &str,
+        action: SyncAction,
+        payload: &[Entity],
+    ) -> Result<SyncResult, SwApiError>;
+
+    /// Send the payload to the sync endpoint without persisting it, to get the errors of the server.
+    /// Returns the errors of the payload, with the pointers of the `write_data` operation.
+    fn validate_sync(
+        &self,
+        entity: &str,
+        action: SyncAction,
+        payload: &[Entity],
+    ) -> Result<Vec<SwError>, SwApiError>;
+
+    fn entity_schema(&self) -> Result<Entity, SwApiError>;
+
+    /// OpenAPI schema of the Admin API, which has the constraints of the fields (like `pattern`),
+    /// unlike the entity schema
+    fn openapi_schema(&self) -> Result<serde_json::Value, SwApiError>;
+
+    /// Content of a generated document (e.g. the PDF of an invoice)
+    fn download_document(
+        &self,
+        document_id: &str,
+        deep_link_code: &str,
+    ) -> Result<Vec<u8>, SwApiError>;
 
-    pub fn get_languages(&self) -> Result<IsoLanguageList, SwApiError> {
+    fn get_total(&self, entity: &str, filter: &[CriteriaFilter]) -> Result<u64, SwApiError>;
+
+    fn list(&self, entity: &str, criteria: &Criteria)
+        -> Result<SwListResponse<Entity>, SwApiError>;
+
+    fn index(&self, skip: Vec<String>) -> Result<(), SwApiError>;
+
+    /// Trigger the indexing of the given products only (asynchronously in the message queue)
+    fn index_products(&self, ids: &[&str]) -> Result<(), SwApiError>;
+
+    /// Recalculate the prices and taxes of an order
+    fn recalculate_order(&self, order_id: &str) -> Result<(), SwApiError>;
+
+    fn get_languages(&self) -> Result<IsoLanguageList, SwApiError> {
         let mut page = 1;
         let mut language_list: HashMap<String, String> = HashMap::new();
 
@@ -62,8 +157,9 @@ impl SwClient {
 
             criteria.add_association("locale");
 
-            let list: SwListResponse<Language> = self.list("language", &criteria)?;
+            let list = self.list("language", &criteria)?;
             for item in list.data {
+                let item: Language = serde_json::from_value(serde_json::Value::Object(item))?;
                 language_list.insert(item.locale.code, item.id);
             }
 
@@ -72,10 +168,11 @@ impl SwClient {
 
         Ok(IsoLanguageList {
             data: language_list,
+            iso_codes: IsoCodeOptions::default(),
         })
     }
 
-    pub fn get_currencies(&self) -> Result<CurrencyList, SwApiError> {
+    fn get_currencies(&self) -> Result<CurrencyList, SwApiError> {
         let mut page = 1;
         let mut currency_list: HashMap<String, String> = HashMap::new();
 
@@ -89,8 +186,9 @@ impl SwClient {
                 ..Default::default()
             };
 
-            let list: SwListResponse<Currency> = self.list("currency", &criteria)?;
+            let list = self.list("currency", &criteria)?;
             for item in list.data {
+                let item: Currency = serde_json::from_value(serde_json::Value::Object(item))?;
                 currency_list.insert(item.iso_code, item.id);
             }
 
@@ -99,119 +197,88 @@ impl SwClient {
 
         Ok(CurrencyList {
             data: currency_list,
+            iso_codes: IsoCodeOptions::default(),
         })
     }
 
-    pub fn sync<S: Into<String>, T: Serialize + Debug>(
-        &self,
-        entity: S,
-        action: SyncAction,
-        payload: &[T],
-    ) -> Result<(), SwApiError> {
-        let entity: String = entity.into();
-        let body = SyncBody {
-            write_data: SyncOperation {
-                entity: entity.clone(),
-                action,
-                payload,
-            },
-        };
+    /// Ids of the sales channels by their name (in the default language)
+    fn get_sales_channels(&self) -> Result<HashMap<String, String>, SwApiError> {
+        let mut page = 1;
+        let mut sales_channels: HashMap<String, String> = HashMap::new();
 
-        println!(
-            "sync {:?} '{}' with payload size {}",
-            action,
-            &entity,
-            payload.len()
-        );
+        let total = self.get_total("sales_channel", &[])?;
+        let mut received = 0;
 
-        let request_builder = self
-            .client
-            .request(
-                Method::POST,
-                format!("{}/api/_action/sync", self.credentials.base_url),
-            )
-            .header("single-operation", "1")
-            .header("indexing-behavior", "disable-indexing")
-            .header("sw-skip-trigger-flow", "1")
-            .json(&body);
+        while received < total {
+            let criteria = Criteria {
+                page,
+                limit: Some(Criteria::MAX_LIMIT),
+                fields: vec!["id".to_string(), "name".to_string()],
+                ..Default::default()
+            };
 
-        let response = self.handle_authenticated_request(request_builder)?;
+            let list = self.list("sales_channel", &criteria)?;
+            if list.data.is_empty() {
+                break;
+            }
+            received += list.data.len() as u64;
+            for item in list.data {
+                let item: SalesChannel = serde_json::from_value(serde_json::Value::Object(item))?;
+                if let Some(name) = item.name {
+                    sales_channels.insert(name, item.id);
+                }
+            }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body: SwErrorBody = Self::deserialize(response)?;
-            return Err(SwApiError::Server(status, body));
+            page += 1;
         }
 
-        Ok(())
+        Ok(sales_channels)
     }
+}
 
-    pub fn entity_schema(&self) -> Result<Entity, SwApiError> {
-        let request_builder = self.client.request(
-            Method::GET,
-            format!("{}/api/_info/entity-schema.json", self.credentials.base_url),
+impl SwHttpClient {
+    pub fn new(credentials: Credentials, options: SwClientOptions) -> anyhow::Result<Self> {
+        let mut default_headers = HeaderMap::default();
+        // This header is needed, otherwise the response would be "application/vnd.api+json" (by default)
+        // and that doesn't have the association data as part of the entity object
+        default_headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        let user_agent = options.user_agent.unwrap_or_else(|| get_user_agent(None));
+        default_headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_str(&user_agent).context("invalid user agent")?,
         );
-
-        let response = self.handle_authenticated_request(request_builder)?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body: SwErrorBody = Self::deserialize(response)?;
-            return Err(SwApiError::Server(status, body));
+        if let Some(correlation_id) = &options.correlation_id {
+            default_headers.insert(
+                "x-correlation-id",
+                HeaderValue::from_str(correlation_id).context("invalid correlation id")?,
+            );
         }
-
-        let value = Self::deserialize(response)?;
-        Ok(value)
-    }
-
-    pub fn get_total(&self, entity: &str, filter: &[CriteriaFilter]) -> Result<u64, SwApiError> {
-        // entity needs to be provided as kebab-case instead of snake_case
-        let entity = entity.replace('_', "-");
-        let body = json!({
-            "limit": 1,
-            "filter": filter,
-            "aggregations": [
-                {
-                  "name": "count",
-                  "type": "count",
-                  "field": "id"
-                }
-            ]
-        });
-
-        let request_builder = self
-            .client
-            .request(
-                Method::POST,
-                format!("{}/api/search/{}", self.credentials.base_url, entity),
-            )
-            .json(&body);
-
-        let response = self.handle_authenticated_request(request_builder)?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body: SwErrorBody = Self::deserialize(response)?;
-            return Err(SwApiError::Server(status, body));
+        let mut client_builder = Client::builder()
+            .pool_max_idle_per_host(options.pool_max_idle_per_host)
+            .tcp_keepalive(options.tcp_keepalive)
+            .connect_timeout(options.connect_timeout)
+            .timeout(options.timeout)
+            .default_headers(default_headers);
+        if options.http2_prior_knowledge {
+            client_builder = client_builder.http2_prior_knowledge();
         }
+        let client = client_builder.build()?;
+        let credentials = Arc::new(credentials);
+        let auth_response = Self::authenticate(&client, credentials.as_ref())?;
 
-        let value: serde_json::Value = Self::deserialize(response)?;
-
-        let count = value
-            .pointer("/aggregations/count/count")
-            .expect("failed to get /aggregations/count/count from response");
-        let count = count
-            .as_u64()
-            .expect("count aggregation value is not a unsigned integer");
-
-        Ok(count)
+        println!("Shopware API client created and authenticated");
+        Ok(Self {
+            client,
+            credentials,
+            access_token: Arc::new(Mutex::new(auth_response.access_token)),
+            language_id: None,
+            max_response_size: options.max_response_size,
+            read_only: options.read_only,
+        })
     }
 
-    pub fn list<T>(
-        &self,
-        entity: &str,
-        criteria: &Criteria,
-    ) -> Result<SwListResponse<T>, SwApiError>
+    /// Page of the search, deserialized into the given type
+    fn search<T>(&self, entity: &str, criteria: &Criteria) -> Result<SwListResponse<T>, SwApiError>
     where
         T: for<'a> Deserialize<'a> + Debug + Send + 'static,
     {
@@ -243,11 +310,27 @@ impl SwClient {
             return Err(SwApiError::Server(status, body));
         }
 
-        let value: SwListResponse<T> = Self::deserialize(response)?;
+        let value: SwListResponse<T> =
+            Self::deserialize_with_limit(response, self.max_response_size)?;
 
         Ok(value)
     }
 
+    fn send_sync<B: Serialize>(&self, body: &B) -> Result<Response, SwApiError> {
+        let request_builder = self
+            .client
+            .request(
+                Method::POST,
+                format!("{}/api/_action/sync", self.credentials.base_url),
+            )
+            .header("single-operation", "1")
+            .header("indexing-behavior", "disable-indexing")
+            .header("sw-skip-trigger-flow", "1")
+            .json(body);
+
+        self.handle_authenticated_request(request_builder)
+    }
+
     fn authenticate(
         client: &Client,
         credentials: &Credentials,
@@ -275,14 +358,15 @@ impl SwClient {
         Ok(res)
     }
 
-    pub fn index(&self, skip: Vec<String>) -> Result<(), SwApiError> {
-        let request_builder = self
-            .client
-            .request(
-                Method::POST,
-                format!("{}/api/_action/index", self.credentials.base_url),
-            )
-            .json(&IndexBody { skip });
+    /// Send a POST request without body to `/api/_action/{action}`, optionally in the given version
+    fn post_action(&self, action: &str, version_id: Option<&str>) -> Result<Response, SwApiError> {
+        let mut request_builder = self.client.request(
+            Method::POST,
+            format!("{}/api/_action/{action}", self.credentials.base_url),
+        );
+        if let Some(version_id) = version_id {
+            request_builder = request_builder.header("sw-version-id", version_id);
+        }
 
         let response = self.handle_authenticated_request(request_builder)?;
 
@@ -292,34 +376,42 @@ impl SwClient {
             return Err(SwApiError::Server(status, body));
         }
 
-        Ok(())
+        Ok(response)
     }
 
     fn deserialize<T>(response: Response) -> Result<T, SwApiError>
     where
         T: for<'a> Deserialize<'a> + Debug + Send + 'static,
     {
-        let bytes = response.bytes()?;
-
-        // expensive for large json objects
-        let result = match serde_json::from_slice(&bytes) {
-            Ok(t) => Ok(t),
-            Err(_e) => {
-                // try to parse any json
-                match serde_json::from_slice::<serde_json::Value>(&bytes) {
-                    Ok(json_value) => Err(SwApiError::DeserializeIntoSchema(
-                        std::any::type_name::<T>().to_string(),
-                        serde_json::to_string_pretty(&json_value)
-                            .expect("json pretty printing shouldn't fail"),
-                    )),
-                    Err(_e) => Err(SwApiError::DeserializeIntoSchema(
-                        std::any::type_name::<T>().to_string(),
-                        String::from_utf8_lossy(&bytes).into_owned(),
-                    )),
-                }
+        Self::deserialize_with_limit(response, None)
+    }
+
+    /// Parse the JSON while the body is streamed, so big responses aren't buffered in memory first.
+    /// Responses bigger than `max_size` (in bytes) fail as soon as the limit is exceeded
+    fn deserialize_with_limit<T>(response: Response, max_size: Option<u64>) -> Result<T, SwApiError>
+    where
+        T: for<'a> Deserialize<'a> + Debug + Send + 'static,
+    {
+        let url = response.url().path().to_string();
+        let too_large =
+            |max_size: u64| SwApiError::ResponseTooLarge(url.clone(), max_size / 1024 / 1024);
+        if let (Some(max_size), Some(length)) = (max_size, response.content_length()) {
+            if length > max_size {
+                return Err(too_large(max_size));
             }
-        };
-        result
+        }
+
+        let mut body = BodyReader::new(response, max_size);
+        let result = serde_json::from_reader(BufReader::new(&mut body));
+        match (result, max_size) {
+            (Ok(t), _) => Ok(t),
+            (Err(_), Some(max_size)) if body.exceeded() => Err(too_large(max_size)),
+            // only the start of the body is kept for the error, which is enough to spot the problem
+            (Err(e), _) => Err(SwApiError::DeserializeIntoSchema(
+                std::any::type_name::<T>().to_string(),
+                format!("{e}\n{}", body.start_lossy()),
+            )),
+        }
     }
 
     fn handle_authenticated_request(
@@ -330,16 +422,28 @@ impl SwClient {
         const MAX_RETRIES: u8 = 1;
         let binding = request_builder.try_clone().unwrap().build().unwrap();
         let path = binding.url().path();
+        if self.read_only && !is_read_request(binding.method(), path) {
+            return Err(SwApiError::ReadOnly(format!("{} {path}", binding.method())));
+        }
 
         loop {
             let access_token = self.access_token.lock().unwrap().clone();
-            let request = request_builder
+            let mut request = request_builder
                 .try_clone()
                 .unwrap()
                 .bearer_auth(&access_token);
+            if let Some(language_id) = &self.language_id {
+                request = request.header("sw-language-id", language_id);
+            }
 
             let start_time = Instant::now();
-            let response = request.send()?;
+            let response = request.send().map_err(|e| {
+                if e.is_timeout() {
+                    SwApiError::Timeout(path.to_string())
+                } else {
+                    SwApiError::Request(e)
+                }
+            })?;
 
             if response.status() == StatusCode::UNAUTHORIZED && try_count < MAX_RETRIES {
                 // lock the access token
@@ -370,11 +474,335 @@ impl SwClient {
         }
     }
 }
+
+impl SwClient for SwHttpClient {
+    fn base_url(&self) -> &str {
+        &self.credentials.base_url
+    }
+
+    fn with_language(&self, language_id: String) -> Arc<dyn SwClient> {
+        Arc::new(Self {
+            language_id: Some(language_id),
+            ..self.clone()
+        })
+    }
+
+    fn get_integration_privileges(&self) -> Result<Option<BTreeSet<String>>, SwApiError> {
+        let mut criteria = Criteria {
+            limit: Some(1),
+            filter: vec![CriteriaFilter::Equals {
+                field: "accessKey".to_string(),
+                value: json!(self.credentials.access_key_id),
+            }],
+            ..Default::default()
+        };
+        criteria.add_association("aclRoles");
+
+        let list: SwListResponse<Entity> = self.list("integration", &criteria)?;
+        let Some(integration) = list.data.first() else {
+            return Ok(Some(BTreeSet::new()));
+        };
+        if integration
+            .get("admin")
+            .and_then(serde_json::Value::as_bool)
+            == Some(true)
+        {
+            return Ok(None);
+        }
+
+        let privileges = integration
+            .get("aclRoles")
+            .and_then(|roles| roles.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|role| role.get("privileges").and_then(|p| p.as_array()))
+            .flatten()
+            .filter_map(|privilege| privilege.as_str())
+            .map(str::to_owned)
+            .collect();
+
+        Ok(Some(privileges))
+    }
+
+    fn sync(
+        &self,
+        entity: &str,
+        action: SyncAction,
+        payload: &[Entity],
+    ) -> Result<SyncResult, SwApiError> {
+        let entity = entity.to_string();
+        let body = SyncBody {
+            write_data: SyncOperation {
+                entity: entity.clone(),
+                action,
+                payload,
+            },
+        };
+
+        println!(
+            "sync {:?} '{}' with payload size {}",
+            action,
+            &entity,
+            payload.len()
+        );
+
+        let response = self.send_sync(&body)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let mut body: SwErrorBody = Self::deserialize(response)?;
+            body.add_row_digests(payload);
+            return Err(SwApiError::Server(status, body));
+        }
+
+        // the data was written at this point, so a response in an unexpected format isn't an error
+        let body: serde_json::Value = Self::deserialize(response).unwrap_or_default();
+        Ok(SyncResult::from_body(&body, &entity))
+    }
+
+    /// All operations of a sync request are written in a single transaction, so an additional
+    /// operation which always fails (a tag with an invalid id) rejects the whole request.
+    /// The server only reports errors of the payloads themselves (types, required fields, lengths),
+    /// because it stops before writing to the database, so e.g. foreign keys aren't checked.
+    fn validate_sync(
+        &self,
+        entity: &str,
+        action: SyncAction,
+        payload: &[Entity],
+    ) -> Result<Vec<SwError>, SwApiError> {
+        let entity = entity.to_string();
+        let rollback_payload = [json!({ "id": "sw-sync-cli-rollback" })];
+        let body = ValidateSyncBody {
+            write_data: SyncOperation {
+                entity: entity.clone(),
+                action,
+                payload,
+            },
+            rollback: SyncOperation {
+                entity: "tag".to_string(),
+                action: SyncAction::Upsert,
+                payload: &rollback_payload,
+            },
+        };
+
+        println!(
+            "validate {:?} '{}' with payload size {}",
+            action,
+            &entity,
+            payload.len()
+        );
+
+        let response = self.send_sync(&body)?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Err(SwApiError::RollbackFailed);
+        }
+
+        let body: SwErrorBody = Self::deserialize(response)?;
+        let is_rollback_error = |error: &SwError| match error {
+            SwError::WriteError { source, .. } => source.pointer.starts_with("/rollback/"),
+            SwError::GenericError { .. } => false,
+        };
+        if !body.errors.iter().any(is_rollback_error) {
+            // the request failed for another reason than the payload
+            let mut body = body;
+            body.add_row_digests(payload);
+            return Err(SwApiError::Server(status, body));
+        }
+
+        Ok(body
+            .errors
+            .into_iter()
+            .filter(|error| !is_rollback_error(error))
+            .collect())
+    }
+
+    fn entity_schema(&self) -> Result<Entity, SwApiError> {
+        let request_builder = self.client.request(
+            Method::GET,
+            format!("{}/api/_info/entity-schema.json", self.credentials.base_url),
+        );
+
+        let response = self.handle_authenticated_request(request_builder)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body: SwErrorBody = Self::deserialize(response)?;
+            return Err(SwApiError::Server(status, body));
+        }
+
+        let value = Self::deserialize(response)?;
+        Ok(value)
+    }
+
+    fn openapi_schema(&self) -> Result<serde_json::Value, SwApiError> {
+        let request_builder = self.client.request(
+            Method::GET,
+            format!("{}/api/_info/openapi3.json", self.credentials.base_url),
+        );
+
+        let response = self.handle_authenticated_request(request_builder)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body: SwErrorBody = Self::deserialize(response)?;
+            return Err(SwApiError::Server(status, body));
+        }
+
+        let value = Self::deserialize(response)?;
+        Ok(value)
+    }
+
+    fn download_document(
+        &self,
+        document_id: &str,
+        deep_link_code: &str,
+    ) -> Result<Vec<u8>, SwApiError> {
+        let request_builder = self.client.request(
+            Method::GET,
+            format!(
+                "{}/api/_action/document/{document_id}/{deep_link_code}",
+                self.credentials.base_url
+            ),
+        );
+
+        let response = self.handle_authenticated_request(request_builder)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body: SwErrorBody = Self::deserialize(response)?;
+            return Err(SwApiError::Server(status, body));
+        }
+
+        Ok(response.bytes()?.to_vec())
+    }
+
+    fn get_total(&self, entity: &str, filter: &[CriteriaFilter]) -> Result<u64, SwApiError> {
+        // entity needs to be provided as kebab-case instead of snake_case
+        let entity = entity.replace('_', "-");
+        let body = json!({
+            "limit": 1,
+            "filter": filter,
+            "aggregations": [
+                {
+                  "name": "count",
+                  "type": "count",
+                  "field": "id"
+                }
+            ]
+        });
+
+        let request_builder = self
+            .client
+            .request(
+                Method::POST,
+                format!("{}/api/search/{}", self.credentials.base_url, entity),
+            )
+            .json(&body);
+
+        let response = self.handle_authenticated_request(request_builder)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body: SwErrorBody = Self::deserialize(response)?;
+            return Err(SwApiError::Server(status, body));
+        }
+
+        let value: serde_json::Value = Self::deserialize(response)?;
+
+        let count = value
+            .pointer("/aggregations/count/count")
+            .expect("failed to get /aggregations/count/count from response");
+        let count = count
+            .as_u64()
+            .expect("count aggregation value is not a unsigned integer");
+
+        Ok(count)
+    }
+
+    fn list(
+        &self,
+        entity: &str,
+        criteria: &Criteria,
+    ) -> Result<SwListResponse<Entity>, SwApiError> {
+        self.search(entity, criteria)
+    }
+
+    fn index(&self, skip: Vec<String>) -> Result<(), SwApiError> {
+        let request_builder = self
+            .client
+            .request(
+                Method::POST,
+                format!("{}/api/_action/index", self.credentials.base_url),
+            )
+            .json(&IndexBody { skip });
+
+        let response = self.handle_authenticated_request(request_builder)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body: SwErrorBody = Self::deserialize(response)?;
+            return Err(SwApiError::Server(status, body));
+        }
+
+        Ok(())
+    }
+
+    fn index_products(&self, ids: &[&str]) -> Result<(), SwApiError> {
+        let request_builder = self
+            .client
+            .request(
+                Method::POST,
+                format!("{}/api/_action/index-products", self.credentials.base_url),
+            )
+            .json(&IndexProductsBody { ids });
+
+        let response = self.handle_authenticated_request(request_builder)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body: SwErrorBody = Self::deserialize(response)?;
+            return Err(SwApiError::Server(status, body));
+        }
+
+        Ok(())
+    }
+
+    /// Orders can't be recalculated in the live version,
+    /// so a new version of the order is recalculated and merged back into the live version
+    fn recalculate_order(&self, order_id: &str) -> Result<(), SwApiError> {
+        let response = self.post_action(&format!("version/order/{order_id}"), None)?;
+        let version: VersionResponse = Self::deserialize(response)?;
+
+        self.post_action(
+            &format!("order/{order_id}/recalculate"),
+            Some(&version.version_id),
+        )?;
+        self.post_action(
+            &format!("version/merge/order/{}", version.version_id),
+            Some(&version.version_id),
+        )?;
+
+        Ok(())
+    }
+}
 #[derive(Debug, Serialize)]
 struct IndexBody {
     skip: Vec<String>,
 }
 
+#[derive(Debug, Serialize)]
+struct IndexProductsBody<'a> {
+    ids: &'a [&'a str],
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionResponse {
+    version_id: String,
+}
+
 #[derive(Debug, Serialize)]
 struct AuthBody {
     grant_type: String,
@@ -382,11 +810,63 @@ struct AuthBody {
     client_secret: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct AuthResponse {
-    // token_type: String,
-    // expires_in: u32,
-    access_token: String,
+#[derive(Debug, Deserialize)]
+struct AuthResponse {
+    // token_type: String,
+    // expires_in: u32,
+    access_token: String,
+}
+
+/// Amount of bytes of a response body, which are kept for error messages
+const BODY_START_SIZE: usize = 64 * 1024;
+
+/// Reads the response body, counting the bytes to enforce the size limit
+/// and keeping the start of the body for error messages
+struct BodyReader<R> {
+    inner: R,
+    max_size: Option<u64>,
+    size: u64,
+    start: Vec<u8>,
+}
+
+impl<R: Read> BodyReader<R> {
+    fn new(inner: R, max_size: Option<u64>) -> Self {
+        Self {
+            inner,
+            max_size,
+            size: 0,
+            start: Vec::new(),
+        }
+    }
+
+    fn exceeded(&self) -> bool {
+        self.max_size.is_some_and(|max_size| self.size > max_size)
+    }
+
+    fn start_lossy(&self) -> String {
+        let start = String::from_utf8_lossy(&self.start);
+        if self.size > self.start.len() as u64 {
+            format!("{start}... (truncated, {} bytes in total)", self.size)
+        } else {
+            start.into_owned()
+        }
+    }
+}
+
+impl<R: Read> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.size = self.size.saturating_add(read as u64);
+        if self.start.len() < BODY_START_SIZE {
+            let keep = read.min(BODY_START_SIZE - self.start.len());
+            self.start.extend_from_slice(&buf[..keep]);
+        }
+        if self.exceeded() {
+            return Err(std::io::Error::other("response exceeds the size limit"));
+        }
+
+        Ok(read)
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -394,6 +874,12 @@ struct SyncBody<'a, T> {
     write_data: SyncOperation<'a, T>,
 }
 
+#[derive(Debug, Serialize)]
+struct ValidateSyncBody<'a, T> {
+    write_data: SyncOperation<'a, T>,
+    rollback: SyncOperation<'a, serde_json::Value>,
+}
+
 #[derive(Debug, Serialize)]
 struct SyncOperation<'a, T> {
     entity: String,
@@ -401,7 +887,34 @@ struct SyncOperation<'a, T> {
     payload: &'a [T],
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+/// Ids of the entities of a sync request (not including the written associations),
+/// they are objects with the primary keys for mapping entities
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncResult {
+    pub written: Vec<serde_json::Value>,
+    pub deleted: Vec<serde_json::Value>,
+}
+
+impl SyncResult {
+    /// Read the ids of the entity from a response body like
+    /// `{"data": {"product": [...]}, "deleted": {"product": [...]}}`
+    fn from_body(body: &serde_json::Value, entity: &str) -> Self {
+        let ids = |key: &str| {
+            body.get(key)
+                .and_then(|entities| entities.get(entity))
+                .and_then(serde_json::Value::as_array)
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        Self {
+            written: ids("data"),
+            deleted: ids("deleted"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum SyncAction {
     Upsert,
@@ -410,16 +923,33 @@ pub enum SyncAction {
 
 #[derive(Debug, Error)]
 pub enum SwApiError {
-    #[error("The server returned an {0} error response:\n{1:#?}")]
+    #[error("The server returned an {0} error response:\n{1}")]
     Server(StatusCode, SwErrorBody),
     #[error("Request error: {0}")]
     Request(#[from] reqwest::Error),
+    #[error("Request to {0} timed out; you might want to increase the timeout (--timeout)")]
+    Timeout(String),
+    #[error("The server didn't reject the validated payload, it might have been written")]
+    RollbackFailed,
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("The response of {0} is bigger than {1} MB; export fewer fields / associations or increase --max-response-size")]
+    ResponseTooLarge(String, u64),
     #[error("failed to deserialize json into schema of type {0}, got:\n{1}")]
     DeserializeIntoSchema(String, String),
     #[error("Failed to authenticate, got {0} with body:\n{1}")]
     AuthFailed(StatusCode, String),
+    #[error("Refused the request {0}, only reading requests are allowed in read-only mode (--read-only)")]
+    ReadOnly(String),
+}
+
+/// Requests which can't write: `GET` requests and searches
+fn is_read_request(method: &Method, path: &str) -> bool {
+    match *method {
+        Method::GET => true,
+        Method::POST => path.contains("/api/search/") || path.contains("/api/search-ids/"),
+        _ => false,
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -428,6 +958,25 @@ pub struct SwErrorBody {
 }
 
 impl SwErrorBody {
+    /// Add the digests of the entries of the sync payload, which the write errors point to
+    fn add_row_digests<T: Serialize>(&mut self, payload: &[T]) {
+        for error in &mut self.errors {
+            let SwError::WriteError { source, .. } = error else {
+                continue;
+            };
+            let Ok((entry, _)) = parse_error_pointer(&source.pointer) else {
+                continue;
+            };
+            let Some(row) = payload.get(entry) else {
+                continue;
+            };
+            if let Ok(row) = serde_json::to_vec(row) {
+                let digest = format!("{:x}", Sha256::digest(row));
+                source.row_digest = Some(digest[..12].to_string());
+            }
+        }
+    }
+
     pub fn check_for_error_code(&self, error_code: &str) -> bool {
         self.errors.iter().any(|error| match error {
             SwError::GenericError { code, .. } if code.eq(error_code) => true,
@@ -437,6 +986,33 @@ impl SwErrorBody {
     }
 }
 
+impl std::fmt::Display for SwErrorBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for error in &self.errors {
+            match error {
+                SwError::WriteError { code, source, .. } => {
+                    write!(f, "- {} [{code}]: {}", source.pointer, error.get_message())?;
+                    if let Some(value) = error.get_value() {
+                        write!(f, ", got {value}")?;
+                    }
+                    match &source.row_digest {
+                        Some(digest) => writeln!(f, " (row digest {digest})")?,
+                        None => writeln!(f)?,
+                    }
+                }
+                SwError::GenericError { code, .. } => {
+                    writeln!(f, "- [{code}]: {}", error.get_message())?;
+                }
+            }
+            if let Some((identifier, explanation)) = get_error_explanation(error.get_code()) {
+                writeln!(f, "  {identifier}: {explanation}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum SwError {
@@ -445,22 +1021,201 @@ pub enum SwError {
         detail: String,
         source: SwErrorSource,
         template: String,
+        /// contains the `parameters` of the violated constraint, like `{{ value }}` or `{{ limit }}`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<serde_json::Value>,
     },
     GenericError {
         code: String,
         detail: Option<String>,
         status: String,
         title: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<serde_json::Value>,
     },
 }
 
 impl SwError {
     pub const ERROR_CODE_DEADLOCK: &'static str = "1213";
+
+    /// Codes of the Symfony constraints, which are violated by write errors
+    const ERROR_CODE_NOT_BLANK: &'static str = "c1051bb4-d103-4f74-8988-acbcafc7fdc3";
+    const ERROR_CODE_NOT_NULL: &'static str = "ad32d13f-c3d4-423b-909a-857b961eb720";
+    const ERROR_CODE_TOO_LONG: &'static str = "d94b19cc-114f-4f44-9cc4-4138e80a87b9";
+    const ERROR_CODE_TOO_SHORT: &'static str = "9ff3fdc4-b214-49db-8718-39c315e33d45";
+    const ERROR_CODE_INVALID_TYPE: &'static str = "ba785a8c-82cb-4283-967c-3cf342181b40";
+
+    /// Human readable message of the error: the violated constraint of well-known codes,
+    /// missing foreign keys and duplicate keys, otherwise the detail of the server
+    pub fn get_message(&self) -> String {
+        match self {
+            SwError::WriteError { code, detail, .. } => {
+                let parameter = |name| self.get_parameter(name).unwrap_or_default();
+                match code.as_str() {
+                    Self::ERROR_CODE_NOT_BLANK => "value is required, but empty".to_string(),
+                    Self::ERROR_CODE_NOT_NULL => "value must not be null".to_string(),
+                    Self::ERROR_CODE_TOO_LONG => format!(
+                        "value is longer than the maximum of {} characters",
+                        parameter("{{ limit }}")
+                    ),
+                    Self::ERROR_CODE_TOO_SHORT => format!(
+                        "value is shorter than the minimum of {} characters",
+                        parameter("{{ limit }}")
+                    ),
+                    Self::ERROR_CODE_INVALID_TYPE => {
+                        format!("value should be of type {}", parameter("{{ type }}"))
+                    }
+                    _ => detail.clone(),
+                }
+            }
+            SwError::GenericError { detail, title, .. } => {
+                let detail = detail.as_deref().unwrap_or(title);
+                get_integrity_message(detail).unwrap_or_else(|| detail.to_string())
+            }
+        }
+    }
+
+    pub fn get_code(&self) -> &str {
+        match self {
+            SwError::WriteError { code, .. } | SwError::GenericError { code, .. } => code,
+        }
+    }
+
+    /// Offending value of a write error (as formatted by the server, e.g. `"abc"` or `null`)
+    pub fn get_value(&self) -> Option<String> {
+        self.get_parameter("{{ value }}")
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<String> {
+        let meta = match self {
+            SwError::WriteError { meta, .. } | SwError::GenericError { meta, .. } => meta.as_ref(),
+        };
+
+        match meta?.get("parameters")?.get(name)? {
+            serde_json::Value::String(value) => Some(value.clone()),
+            value => Some(value.to_string()),
+        }
+    }
+}
+
+/// Split an error pointer like `/write_data/3/name` into the entry of the chunk and the pointer inside it
+pub fn parse_error_pointer(pointer: &str) -> anyhow::Result<(usize, &str)> {
+    let rest = pointer
+        .strip_prefix("/write_data/")
+        .with_context(|| format!("unexpected error pointer '{pointer}'"))?;
+    let (entry, remaining) = match rest.find('/') {
+        Some(position) => rest.split_at(position),
+        None => (rest, ""),
+    };
+    let entry = entry
+        .parse()
+        .with_context(|| format!("error pointer '{pointer}' should contain the entry"))?;
+
+    Ok((entry, remaining))
+}
+
+/// Identifier of the violated constraint and a short explanation of common error codes,
+/// so failures are understandable without knowing the internals of Shopware
+const ERROR_EXPLANATIONS: [(&str, &str, &str); 11] = [
+    (
+        SwError::ERROR_CODE_NOT_BLANK,
+        "NotBlank",
+        "the field is required, fill the column or set a default in the mapping",
+    ),
+    (
+        SwError::ERROR_CODE_NOT_NULL,
+        "NotNull",
+        "the field is required and can't be cleared",
+    ),
+    (
+        SwError::ERROR_CODE_TOO_LONG,
+        "Length",
+        "shorten the value or truncate it with max_length in the mapping",
+    ),
+    (
+        SwError::ERROR_CODE_TOO_SHORT,
+        "Length",
+        "the value needs more characters",
+    ),
+    (
+        SwError::ERROR_CODE_INVALID_TYPE,
+        "Type",
+        "the value has the wrong type, check the column_type of the mapping",
+    ),
+    (
+        "CONTENT__DUPLICATE_PRODUCT_NUMBER",
+        "DuplicateProductNumber",
+        "productNumber must be unique, another product already uses it",
+    ),
+    (
+        "FRAMEWORK__INVALID_UUID",
+        "InvalidUuid",
+        "ids need to be 32 hexadecimal characters (a uuid without dashes)",
+    ),
+    (
+        "FRAMEWORK__WRITE_CONSTRAINT_VIOLATION",
+        "WriteConstraintViolation",
+        "a field violates a constraint of the entity definition",
+    ),
+    (
+        "FRAMEWORK__DELETE_RESTRICTED",
+        "DeleteRestricted",
+        "the entity is still referenced by other entities, delete or reassign them first",
+    ),
+    (
+        "FRAMEWORK__MISSING_PRIVILEGE_ERROR",
+        "MissingPrivilege",
+        "the integration is missing a privilege, grant it to its role in the administration",
+    ),
+    (
+        "FRAMEWORK__UNMAPPED_FIELD",
+        "UnmappedField",
+        "the field doesn't exist in the entity definition, check the entity_path of the mapping",
+    ),
+];
+
+fn get_error_explanation(code: &str) -> Option<(&'static str, &'static str)> {
+    ERROR_EXPLANATIONS
+        .iter()
+        .find(|(error_code, _, _)| *error_code == code)
+        .map(|(_, identifier, explanation)| (*identifier, *explanation))
+}
+
+/// Message of the integrity constraint violations of the database (like
+/// `1452 Cannot add or update a child row: a foreign key constraint fails (... FOREIGN KEY (`tax_id`) REFERENCES `tax` (`id`) ...)`
+/// or `1062 Duplicate entry 'SW10000' for key 'product.uniq.product.product_number__version_id'`)
+fn get_integrity_message(detail: &str) -> Option<String> {
+    let between = |text: &'_ str, start: &str, end: &str| -> Option<String> {
+        let (_, rest) = text.split_once(start)?;
+        let (value, _) = rest.split_once(end)?;
+        Some(value.to_string())
+    };
+
+    if detail.contains("foreign key constraint fails") {
+        let column = between(detail, "FOREIGN KEY (`", "`)")?;
+        let table = between(detail, "REFERENCES `", "`")?;
+        return Some(format!(
+            "the referenced {table} doesn't exist (foreign key {column}), create it first or check the id"
+        ));
+    }
+
+    if detail.contains("Duplicate entry") {
+        let value = between(detail, "Duplicate entry '", "' for key")?;
+        let key = between(detail, "for key '", "'")?;
+        return Some(format!(
+            "the value '{value}' already exists for the unique key '{key}'"
+        ));
+    }
+
+    None
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SwErrorSource {
     pub pointer: String,
+    /// digest of the payload entry the pointer points to, to identify the row in the output
+    #[serde(default, skip_serializing)]
+    pub row_digest: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -468,6 +1223,12 @@ pub struct SwListResponse<T> {
     pub data: Vec<T>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SalesChannel {
+    pub id: String,
+    pub name: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Currency {
     pub id: String,
@@ -475,19 +1236,73 @@ pub struct Currency {
     pub iso_code: String,
 }
 
+/// Id of the system default language, see `Defaults::LANGUAGE_SYSTEM` of Shopware
+const LANGUAGE_SYSTEM_ID: &str = "2fbb5fe2e29a4d70aa5854ce7ce3e20b";
+/// Id of the default currency, see `Defaults::CURRENCY` of Shopware
+const DEFAULT_CURRENCY_ID: &str = "b7d2554b0ce847cd82f3ac9bd1c0dfca";
+/// `currency` of a mapping, which refers to the default currency instead of an iso code
+pub const DEFAULT_CURRENCY: &str = "default";
+
 #[derive(Debug, Clone, Default)]
 pub struct CurrencyList {
     pub data: HashMap<String, String>,
+    pub iso_codes: IsoCodeOptions,
 }
 
 impl CurrencyList {
-    pub fn get_currency_id_by_iso_code(&self, iso_code: &str) -> String {
-        match self.data.get(iso_code) {
-            Some(id) => id.to_string(),
-            None => {
-                println!("Currency with iso code '{}' not found", iso_code);
-                "".to_string()
-            }
+    pub fn get_currency_id_by_iso_code(&self, iso_code: &str) -> anyhow::Result<String> {
+        get_id_by_iso_code(
+            &self.data,
+            &self.iso_codes,
+            iso_code,
+            "currency",
+            DEFAULT_CURRENCY_ID,
+        )
+    }
+
+    /// Id of the `currency` of a mapping, an iso code or `default` for the default currency of the shop
+    pub fn get_mapping_currency_id(&self, currency: &str) -> anyhow::Result<String> {
+        if currency == DEFAULT_CURRENCY {
+            return Ok(DEFAULT_CURRENCY_ID.to_string());
+        }
+
+        let id = self.get_currency_id_by_iso_code(currency)?;
+        if id.is_empty() {
+            anyhow::bail!("currency '{currency}' of the mapping not found in the shop");
+        }
+
+        Ok(id)
+    }
+}
+
+/// Id of the iso code (or its replacement in the `mapping`) with the `unknown` strategy of the options
+fn get_id_by_iso_code(
+    data: &HashMap<String, String>,
+    options: &IsoCodeOptions,
+    iso_code: &str,
+    kind: &str,
+    default_id: &str,
+) -> anyhow::Result<String> {
+    let mapped = options
+        .mapping
+        .get(iso_code)
+        .map_or(iso_code, String::as_str);
+    if let Some(id) = data.get(mapped) {
+        return Ok(id.clone());
+    }
+
+    match options.unknown {
+        UnknownIsoCode::Error => {
+            let mut available: Vec<&String> = data.keys().collect();
+            available.sort_unstable();
+            anyhow::bail!(
+                "{kind} with iso code '{mapped}' not found, available: {available:?} (map it with iso_codes.mapping in the profile)"
+            )
+        }
+        UnknownIsoCode::Default => Ok(default_id.to_string()),
+        UnknownIsoCode::Empty => {
+            println!("{kind} with iso code '{mapped}' not found");
+            Ok(String::new())
         }
     }
 }
@@ -506,17 +1321,18 @@ pub struct Locale {
 #[derive(Debug, Clone, Default)]
 pub struct IsoLanguageList {
     pub data: HashMap<String, String>,
+    pub iso_codes: IsoCodeOptions,
 }
 
 impl IsoLanguageList {
-    pub fn get_language_id_by_iso_code(&self, iso_code: &str) -> String {
-        match self.data.get(iso_code) {
-            Some(id) => id.to_string(),
-            None => {
-                println!("Language with iso code '{}' not found", iso_code);
-                "".to_string()
-            }
-        }
+    pub fn get_language_id_by_iso_code(&self, iso_code: &str) -> anyhow::Result<String> {
+        get_id_by_iso_code(
+            &self.data,
+            &self.iso_codes,
+            iso_code,
+            "language",
+            LANGUAGE_SYSTEM_ID,
+        )
     }
 }
 
@@ -541,19 +1357,41 @@ mod tests {
             "a13966f91ef24dcabccf1668e3618955".to_string(),
         );
 
-        let locale_list = IsoLanguageList {
+        let mut locale_list = IsoLanguageList {
             data: language_list_inner,
+            iso_codes: IsoCodeOptions::default(),
         };
 
         assert_eq!(
-            locale_list.get_language_id_by_iso_code("de-DE"),
+            locale_list.get_language_id_by_iso_code("de-DE").unwrap(),
             "cf8eb267dd2a4c54be07bf4b50d65ab5"
         );
         assert_eq!(
-            locale_list.get_language_id_by_iso_code("en-GB"),
+            locale_list.get_language_id_by_iso_code("en-GB").unwrap(),
             "a13966f91ef24dcabccf1668e3618955"
         );
-        assert_eq!(locale_list.get_language_id_by_iso_code("en-US"), "");
+        assert_eq!(
+            locale_list.get_language_id_by_iso_code("en-US").unwrap(),
+            ""
+        );
+
+        locale_list.iso_codes.unknown = UnknownIsoCode::Error;
+        assert!(locale_list
+            .get_language_id_by_iso_code("en-US")
+            .is_err_and(|e| e.to_string().contains("'en-US' not found")));
+
+        locale_list.iso_codes = IsoCodeOptions {
+            mapping: [("de-AT".to_string(), "de-DE".to_string())].into(),
+            unknown: UnknownIsoCode::Default,
+        };
+        assert_eq!(
+            locale_list.get_language_id_by_iso_code("de-AT").unwrap(),
+            "cf8eb267dd2a4c54be07bf4b50d65ab5"
+        );
+        assert_eq!(
+            locale_list.get_language_id_by_iso_code("en-US").unwrap(),
+            LANGUAGE_SYSTEM_ID
+        );
     }
 
     #[test]
@@ -568,19 +1406,184 @@ mod tests {
             "cae49554610b4df2be0fbd61be51f66d".to_string(),
         );
 
-        let currency_list = CurrencyList {
+        let mut currency_list = CurrencyList {
             data: currency_list_inner,
+            iso_codes: IsoCodeOptions::default(),
         };
 
         assert_eq!(
-            currency_list.get_currency_id_by_iso_code("EUR"),
+            currency_list.get_currency_id_by_iso_code("EUR").unwrap(),
             "a55d590baf2c432999f650f421f25eb6"
         );
         assert_eq!(
-            currency_list.get_currency_id_by_iso_code("USD"),
+            currency_list.get_currency_id_by_iso_code("USD").unwrap(),
             "cae49554610b4df2be0fbd61be51f66d"
         );
-        assert_eq!(currency_list.get_currency_id_by_iso_code("GBP"), "");
+        assert_eq!(
+            currency_list.get_currency_id_by_iso_code("GBP").unwrap(),
+            ""
+        );
+        assert!(currency_list.get_mapping_currency_id("GBP").is_err());
+
+        currency_list.iso_codes.unknown = UnknownIsoCode::Error;
+        assert!(currency_list.get_currency_id_by_iso_code("GBP").is_err());
+
+        currency_list.iso_codes.unknown = UnknownIsoCode::Default;
+        assert_eq!(
+            currency_list.get_currency_id_by_iso_code("GBP").unwrap(),
+            DEFAULT_CURRENCY_ID
+        );
+    }
+
+    #[test]
+    fn body_reader_limits_the_size() {
+        let body = br#"{"data": [1, 2, 3]}"#;
+
+        let mut reader = BodyReader::new(&body[..], Some(100));
+        let value: serde_json::Value =
+            serde_json::from_reader(BufReader::new(&mut reader)).unwrap();
+        assert_eq!(value["data"][2], 3);
+        assert!(!reader.exceeded());
+        assert_eq!(reader.start_lossy(), r#"{"data": [1, 2, 3]}"#);
+
+        let mut reader = BodyReader::new(&body[..], Some(10));
+        let result: Result<serde_json::Value, _> =
+            serde_json::from_reader(BufReader::new(&mut reader));
+        assert!(result.is_err_and(|e| e.is_io()));
+        assert!(reader.exceeded());
+    }
+
+    #[test]
+    fn error_messages() {
+        let body: SwErrorBody = serde_json::from_value(serde_json::json!({
+            "errors": [
+                {
+                    "code": "c1051bb4-d103-4f74-8988-acbcafc7fdc3",
+                    "status": "400",
+                    "detail": "This value should not be blank.",
+                    "template": "This value should not be blank.",
+                    "meta": { "parameters": { "{{ value }}": "\"\"" } },
+                    "source": { "pointer": "/write_data/0/name" }
+                },
+                {
+                    "code": "d94b19cc-114f-4f44-9cc4-4138e80a87b9",
+                    "status": "400",
+                    "detail": "This value is too long. It should have 64 characters or less.",
+                    "template": "This value is too long. It should have {{ limit }} characters or less.",
+                    "meta": { "parameters": { "{{ value }}": "\"SW-abc\"", "{{ limit }}": 64 } },
+                    "source": { "pointer": "/write_data/1/productNumber" }
+                },
+                {
+                    "code": "1452",
+                    "status": "500",
+                    "title": "Internal Server Error",
+                    "detail": "SQLSTATE[23000]: Integrity constraint violation: 1452 Cannot add or update a child row: a foreign key constraint fails (`shopware`.`product`, CONSTRAINT `fk.product.tax_id` FOREIGN KEY (`tax_id`) REFERENCES `tax` (`id`) ON DELETE RESTRICT ON UPDATE CASCADE)"
+                },
+                {
+                    "code": "1062",
+                    "status": "500",
+                    "title": "Internal Server Error",
+                    "detail": "SQLSTATE[23000]: Integrity constraint violation: 1062 Duplicate entry 'SW10000' for key 'product.uniq.product.product_number__version_id'"
+                },
+                {
+                    "code": "FRAMEWORK__WRITE_CONSTRAINT",
+                    "status": "400",
+                    "detail": "Something else.",
+                    "template": "Something else.",
+                    "source": { "pointer": "/write_data/2/stock" }
+                }
+            ]
+        }))
+        .unwrap();
+
+        let messages: Vec<_> = body.errors.iter().map(SwError::get_message).collect();
+        assert_eq!(
+            messages,
+            [
+                "value is required, but empty",
+                "value is longer than the maximum of 64 characters",
+                "the referenced tax doesn't exist (foreign key tax_id), create it first or check the id",
+                "the value 'SW10000' already exists for the unique key 'product.uniq.product.product_number__version_id'",
+                "Something else.",
+            ]
+        );
+        assert_eq!(body.errors[1].get_value(), Some("\"SW-abc\"".to_string()));
+        assert_eq!(body.errors[4].get_value(), None);
+        assert_eq!(
+            body.to_string().lines().next(),
+            Some(
+                r#"- /write_data/0/name [c1051bb4-d103-4f74-8988-acbcafc7fdc3]: value is required, but empty, got """#
+            )
+        );
+    }
+
+    #[test]
+    fn read_requests() {
+        assert!(is_read_request(
+            &Method::GET,
+            "/api/_info/entity-schema.json"
+        ));
+        assert!(is_read_request(&Method::POST, "/api/search/product"));
+        assert!(is_read_request(
+            &Method::POST,
+            "/shop/api/search-ids/product"
+        ));
+        assert!(!is_read_request(&Method::POST, "/api/_action/sync"));
+        assert!(!is_read_request(&Method::POST, "/api/_action/index"));
+        assert!(!is_read_request(&Method::DELETE, "/api/product/abc"));
+    }
+
+    #[test]
+    fn error_explanations() {
+        let mut body: SwErrorBody = serde_json::from_value(serde_json::json!({
+            "errors": [
+                {
+                    "code": "CONTENT__DUPLICATE_PRODUCT_NUMBER",
+                    "status": "400",
+                    "detail": "Product with number \"SW-1\" already exists.",
+                    "template": "Product with number \"{{ number }}\" already exists.",
+                    "source": { "pointer": "/write_data/1/productNumber" }
+                },
+                {
+                    "code": "UNKNOWN",
+                    "status": "500",
+                    "title": "Internal Server Error",
+                    "detail": "Something else."
+                }
+            ]
+        }))
+        .unwrap();
+        let payload = [
+            json!({ "productNumber": "SW-0" }),
+            json!({ "productNumber": "SW-1" }),
+        ];
+        body.add_row_digests(&payload);
+
+        let digest = format!(
+            "{:x}",
+            Sha256::digest(serde_json::to_vec(&payload[1]).unwrap())
+        );
+        assert_eq!(
+            body.to_string(),
+            format!(
+                "- /write_data/1/productNumber [CONTENT__DUPLICATE_PRODUCT_NUMBER]: Product with number \"SW-1\" already exists. (row digest {})\n  DuplicateProductNumber: productNumber must be unique, another product already uses it\n- [UNKNOWN]: Something else.\n",
+                &digest[..12]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_error_pointers() {
+        assert_eq!(
+            parse_error_pointer("/write_data/3/name").unwrap(),
+            (3, "/name")
+        );
+        assert_eq!(
+            parse_error_pointer("/write_data/0/translations/de-DE/name").unwrap(),
+            (0, "/translations/de-DE/name")
+        );
+        assert_eq!(parse_error_pointer("/write_data/12").unwrap(), (12, ""));
+        assert!(parse_error_pointer("/rollback/0/name").is_err());
     }
 
     #[test]
@@ -614,13 +1617,13 @@ mod tests {
             .create();
 
         // client new also authenticates
-        let client = SwClient::new(credentials).unwrap();
+        let client = SwHttpClient::new(credentials, SwClientOptions::default()).unwrap();
         mock.assert();
 
         assert_eq!(client.access_token.lock().unwrap().as_str(), "access_token");
     }
 
-    fn create_shopware_mock_server() -> (ServerGuard, SwClient) {
+    fn create_shopware_mock_server() -> (ServerGuard, SwHttpClient) {
         let mut server = mockito::Server::new();
 
         let base_url = server.url();
@@ -649,11 +1652,76 @@ mod tests {
             )
             .create();
 
-        let client = SwClient::new(credentials).unwrap();
+        let client = SwHttpClient::new(credentials, SwClientOptions::default()).unwrap();
         mock.assert();
         (server, client)
     }
 
+    #[test]
+    fn identifying_headers() {
+        let mut server = mockito::Server::new();
+        let credentials = Credentials {
+            base_url: server.url(),
+            access_key_id: "access_key_id".to_string(),
+            access_key_secret: "access_key_secret".to_string(),
+        };
+
+        let mock = server
+            .mock("POST", "/api/oauth/token")
+            .match_header(
+                "user-agent",
+                format!(
+                    "sw-sync-cli/{} (profile my_profile)",
+                    env!("CARGO_PKG_VERSION")
+                )
+                .as_str(),
+            )
+            .match_header("x-correlation-id", "run-1")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"access_token": "access_token"}"#)
+            .create();
+
+        let options = SwClientOptions {
+            user_agent: Some(get_user_agent(Some("my_profile"))),
+            correlation_id: Some("run-1".to_string()),
+            ..Default::default()
+        };
+        SwHttpClient::new(credentials.clone(), options).unwrap();
+        mock.assert();
+
+        let options = SwClientOptions {
+            user_agent: Some("line\nbreak".to_string()),
+            ..Default::default()
+        };
+        assert!(SwHttpClient::new(credentials, options).is_err());
+    }
+
+    #[test]
+    fn test_sw_client_recalculate_order() {
+        let (mut server, client) = create_shopware_mock_server();
+
+        let version_mock = server
+            .mock("POST", "/api/_action/version/order/order-id")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"versionId": "version-id", "versionName": null}"#)
+            .create();
+        let recalculate_mock = server
+            .mock("POST", "/api/_action/order/order-id/recalculate")
+            .match_header("sw-version-id", "version-id")
+            .with_status(204)
+            .create();
+        let merge_mock = server
+            .mock("POST", "/api/_action/version/merge/order/version-id")
+            .match_header("sw-version-id", "version-id")
+            .with_status(204)
+            .create();
+
+        client.recalculate_order("order-id").unwrap();
+        version_mock.assert();
+        recalculate_mock.assert();
+        merge_mock.assert();
+    }
+
     #[test]
     fn test_sw_client_entity_schema() {
         let (mut server, client) = create_shopware_mock_server();
@@ -697,4 +1765,163 @@ mod tests {
 
         assert_eq!(total, 42);
     }
+
+    #[test]
+    fn test_sw_client_sync_result() {
+        let (mut server, client) = create_shopware_mock_server();
+
+        let mock = server
+            .mock("POST", "/api/_action/sync")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                "data": {
+                    "product": ["a", "b"],
+                    "product_translation": [{"productId": "a", "languageId": "c"}]
+                },
+                "deleted": {},
+                "notFound": []
+            }"#,
+            )
+            .create();
+
+        let payload = ["a", "b"].map(|id| Entity::from_iter([("id".to_string(), json!(id))]));
+        let result = client
+            .sync("product", SyncAction::Upsert, &payload)
+            .unwrap();
+        mock.assert();
+
+        assert_eq!(
+            result,
+            SyncResult {
+                written: vec![json!("a"), json!("b")],
+                deleted: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_sw_client_validate_sync_with_partial_failures() {
+        let (mut server, client) = create_shopware_mock_server();
+        let write_error = |pointer: &str| {
+            json!({
+                "code": "c1051bb4-d103-4f74-8988-acbcafc7fdc3",
+                "status": "400",
+                "detail": "This value should not be blank.",
+                "template": "This value should not be blank.",
+                "source": { "pointer": pointer },
+            })
+        };
+
+        // the rollback operation is sent after the payload, so its error can't hide the ones of the payload
+        let mock = server
+            .mock("POST", "/api/_action/sync")
+            .match_body(mockito::Matcher::Regex(
+                r#"^\{"write_data":.*,"rollback":\{"entity":"tag""#.to_string(),
+            ))
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "errors": [
+                        write_error("/write_data/2/name"),
+                        write_error("/rollback/0/id"),
+                        write_error("/write_data/0/stock"),
+                    ]
+                })
+                .to_string(),
+            )
+            .create();
+
+        let payload = ["a", "b", "c"].map(|id| Entity::from_iter([("id".to_string(), json!(id))]));
+        let errors = client
+            .validate_sync("product", SyncAction::Upsert, &payload)
+            .unwrap();
+        mock.assert();
+        let pointers: Vec<&str> = errors
+            .iter()
+            .map(|error| match error {
+                SwError::WriteError { source, .. } => source.pointer.as_str(),
+                SwError::GenericError { .. } => "",
+            })
+            .collect();
+        assert_eq!(pointers, vec!["/write_data/2/name", "/write_data/0/stock"]);
+
+        // without the error of the rollback, the request failed for another reason
+        let mock = server
+            .mock("POST", "/api/_action/sync")
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "errors": [write_error("/write_data/1/name")] }).to_string())
+            .create();
+        assert!(matches!(
+            client.validate_sync("product", SyncAction::Upsert, &payload),
+            Err(SwApiError::Server(..))
+        ));
+        mock.assert();
+
+        // a successful request might have written the payload
+        let mock = server
+            .mock("POST", "/api/_action/sync")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"data": {}}"#)
+            .create();
+        assert!(matches!(
+            client.validate_sync("product", SyncAction::Upsert, &payload),
+            Err(SwApiError::RollbackFailed)
+        ));
+        mock.assert();
+    }
+
+    #[test]
+    fn test_sw_client_index_products() {
+        let (mut server, client) = create_shopware_mock_server();
+
+        let mock = server
+            .mock("POST", "/api/_action/index-products")
+            .match_body(mockito::Matcher::Json(json!({ "ids": ["a", "b"] })))
+            .with_status(204)
+            .create();
+
+        client.index_products(&["a", "b"]).unwrap();
+        mock.assert();
+    }
+
+    #[test]
+    fn test_sw_client_integration_privileges() {
+        let (mut server, client) = create_shopware_mock_server();
+
+        let mock = server
+            .mock("POST", "/api/search/integration")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "filter": [{ "type": "equals", "field": "accessKey", "value": "access_key_id" }]
+            })))
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                "total": 1,
+                "data": [{
+                    "id": "a",
+                    "admin": false,
+                    "aclRoles": [
+                        { "privileges": ["product:read", "product:update"] },
+                        { "privileges": ["product:read", "tax:read"] }
+                    ]
+                }]
+            }"#,
+            )
+            .create();
+
+        let privileges = client.get_integration_privileges().unwrap();
+        mock.assert();
+
+        assert_eq!(
+            privileges,
+            Some(BTreeSet::from([
+                "product:read".to_string(),
+                "product:update".to_string(),
+                "tax:read".to_string(),
+            ]))
+        );
+    }
 }
@@ -2,16 +2,36 @@
 //!
 //! Makes heavy use of <https://docs.rs/clap/latest/clap/>
 
-use clap::{Parser, Subcommand};
+use crate::config_file::{BackoffStrategy, EntityPathMapping};
+use chrono::{NaiveTime, Timelike};
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
 use std::num::NonZeroU8;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::string::ToString;
+use std::time::Duration;
 
 #[derive(Debug, PartialEq, Eq, Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Reject every request to the shop which could write (anything but reading and searching),
+    /// e.g. for exploratory runs against production shops
+    #[arg(long, global = true, env = "SW_SYNC_READ_ONLY", value_parser = clap::builder::FalseyValueParser::new())]
+    pub read_only: bool,
+
+    /// User-Agent of the requests to the shop,
+    /// defaults to `sw-sync-cli/<version>` (with the name of the profile for sync and preview)
+    #[arg(long, global = true, env = "SW_SYNC_USER_AGENT")]
+    pub user_agent: Option<String>,
+
+    /// Id sent with every request of this run (`X-Correlation-Id` header), to find the requests of a run
+    /// in the logs of the shop. Defaults to a random id per run, which is printed at the start
+    #[arg(long, global = true, env = "SW_SYNC_CORRELATION_ID")]
+    pub correlation_id: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Subcommand)]
@@ -41,64 +61,617 @@ pub enum Commands {
     /// Authenticate with a given shopware shop via integration admin API.
     /// Credentials are stored in .credentials.toml in the current working directory.
     Auth {
-        /// base URL of the shop
-        #[arg(short, long)]
-        domain: String,
+        /// Store the credentials as a named environment in .credentials.<env>.toml instead,
+        /// e.g. for `schema-diff`
+        #[arg(long)]
+        env: Option<String>,
+
+        /// base URL of the shop, defaults to the `shopUrl` of an app confirmation
+        #[arg(short, long, required_unless_present = "app_confirmation")]
+        domain: Option<String>,
 
         /// integration access key id
-        #[arg(short, long)]
-        id: String,
+        #[arg(
+            short,
+            long,
+            required_unless_present = "app_confirmation",
+            conflicts_with = "app_confirmation"
+        )]
+        id: Option<String>,
 
         /// integration access key secret
-        #[arg(short, long)]
-        secret: String,
-    },
+        #[arg(
+            short,
+            long,
+            required_unless_present = "app_confirmation",
+            conflicts_with = "app_confirmation"
+        )]
+        secret: Option<String>,
 
-    /// Import data into shopware or export data to a file
-    Sync {
-        /// Mode (import or export)
-        #[arg(value_enum, short, long)]
-        mode: SyncMode,
+        /// Use the integration of an installed app instead of a manual integration:
+        /// path of the confirmation payload (JSON with `apiKey`, `secretKey` and `shopUrl`),
+        /// which the shop sends to the backend of the app on its registration
+        #[arg(long)]
+        app_confirmation: Option<PathBuf>,
+    },
 
-        /// Path to profile.yaml
+    /// Write a sample file with the header line of a profile and example values,
+    /// e.g. as the specification of the import files for suppliers. No connection to the shop is needed
+    Sample {
+        /// Path to profile.yaml, or the name of a profile in ./profiles or of a default profile (like `default_product`)
         #[arg(short, long)]
         profile: PathBuf,
 
-        /// Path to data file
+        /// Additional mapping like `file_column=entity_path[:type]`, see `sync --map`
+        #[arg(long = "map", value_name = "MAPPING")]
+        map: Vec<EntityPathMapping>,
+
+        /// Path of the written sample file
         #[arg(short, long)]
         file: PathBuf,
 
-        /// Maximum amount of entities, can be used for debugging and is optional
+        /// Format of the sample file
+        #[arg(value_enum, long, default_value = "csv")]
+        format: FileFormat,
+
+        /// Amount of example rows
+        #[arg(short, long, default_value = "3")]
+        rows: usize,
+    },
+
+    /// Check a profile against the shop before it's used: the paths of the mappings, the lookups,
+    /// the referenced languages / currencies and the privileges of the integration.
+    /// Fails if any check failed, so it can be used as a gate in pipelines
+    Check {
+        /// Path to profile.yaml, or the name of a profile in ./profiles or of a default profile (like `default_product`)
+        #[arg(short, long)]
+        profile: PathBuf,
+
+        /// Additional mapping like `file_column=entity_path[:type]`, see `sync --map`
+        #[arg(long = "map", value_name = "MAPPING")]
+        map: Vec<EntityPathMapping>,
+
+        /// Mode the profile will be used with, which decides the needed privileges
+        #[arg(value_enum, short, long, default_value = "import")]
+        mode: SyncMode,
+
+        /// Sample file, the values of its lookup columns are resolved in the shop
         #[arg(short, long)]
-        limit: Option<u64>,
-
-        /// Disable triggering the indexer after sync ended successfully
-        #[arg(value_enum, short, long, default_value = "false")]
-        disable_index: bool,
-
-        // Verbose output, used for debugging
-        // #[arg(short, long, action = ArgAction::SetTrue)]
-        // verbose: bool,
-        /// How many requests can be "in-flight" at the same time
-        #[arg(short, long, default_value = in_flight_limit_default_as_string())]
-        in_flight_limit: usize,
-
-        /// Maximum number of tries a request is executed on a recoverable failure (1..=255)
-        #[arg(short, long, default_value = "10")]
-        try_count: NonZeroU8,
+        file: Option<PathBuf>,
+
+        /// Format of the sample file
+        #[arg(value_enum, long, default_value = "csv")]
+        format: FileFormat,
+
+        /// Amount of rows of the sample file, which are checked
+        #[arg(short, long, default_value = "100")]
+        rows: usize,
+    },
+
+    /// Compare the entity schemas of two shops and list the entities and fields,
+    /// which only exist in one of them, e.g. before transferring data from one shop to another
+    SchemaDiff {
+        /// Environment name (authenticated with `auth --env`) or path of the credentials file of the source shop
+        #[arg(long)]
+        source: String,
+
+        /// Environment name (authenticated with `auth --env`) or path of the credentials file of the target shop
+        #[arg(long)]
+        target: String,
     },
+
+    /// Compare the entities of two shops, e.g. to confirm their parity after a staging refresh:
+    /// their count and the values of the mapped fields of a profile, matched by a key field
+    Compare {
+        /// Entity to compare, e.g. "product"
+        #[arg(long)]
+        entity: String,
+
+        /// Environment name (authenticated with `auth --env`) or path of the credentials file of the source shop
+        #[arg(long)]
+        source: String,
+
+        /// Environment name (authenticated with `auth --env`) or path of the credentials file of the target shop
+        #[arg(long)]
+        target: String,
+
+        /// Profile with the compared mappings (and the filter), the default profile of the entity (like `default_product`) if not set
+        #[arg(short, long)]
+        profile: Option<PathBuf>,
+
+        /// Entity path which identifies the same entity in both shops, e.g. "productNumber" if the ids differ
+        #[arg(long, default_value = "id")]
+        key: String,
+
+        /// File column or entity path of a mapping, which isn't compared (e.g. ids which differ between the shops)
+        #[arg(long)]
+        ignore: Vec<String>,
+    },
+
+    /// Delete media which isn't used by any entity anymore, e.g. after products were removed.
+    /// Media only referenced in JSON fields (like CMS slot configs or custom fields) isn't detected as used
+    CleanupMedia {
+        /// Only delete the unused media of the media folder with this name, e.g. "Product Media"
+        #[arg(long)]
+        folder: Option<String>,
+
+        /// Delete the unused media, without it they are only counted
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Import data into shopware or export data to a file
+    Sync(Box<SyncArgs>),
+
+    /// Print the import payloads of the first rows of a file (after the mappings, scripts and transformers),
+    /// without sending them to shopware
+    Preview(PreviewArgs),
+}
+
+impl Commands {
+    /// Whether the command sends requests to a shop, only these have a correlation id
+    pub fn sends_requests(&self) -> bool {
+        match self {
+            Commands::CopyProfiles { .. } | Commands::Sample { .. } => false,
+            Commands::Index { .. }
+            | Commands::Auth { .. }
+            | Commands::Check { .. }
+            | Commands::SchemaDiff { .. }
+            | Commands::Compare { .. }
+            | Commands::CleanupMedia { .. }
+            | Commands::Sync(_)
+            | Commands::Preview(_) => true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Args)]
+pub struct SyncArgs {
+    /// Mode (import or export)
+    #[arg(value_enum, short, long)]
+    pub mode: SyncMode,
+
+    /// Path to profile.yaml, or the name of a profile in ./profiles or of a default profile (like `default_product`)
+    #[arg(short, long)]
+    pub profile: PathBuf,
+
+    /// Additional mapping like `file_column=entity_path[:type]` (type is string, number or boolean),
+    /// which overrides the mapping of the same column in the profile. Can be used multiple times
+    #[arg(long = "map", value_name = "MAPPING")]
+    pub map: Vec<EntityPathMapping>,
+
+    /// Path to data file, imports can also be read from stdin (`-`) or an `http(s)://` URL
+    #[arg(short, long)]
+    pub file: PathBuf,
+
+    /// Sync the payloads of a retry file (like `data.csv.retry.json`) again instead of the rows of the file,
+    /// it's written on import for rows which failed after all tries because of temporary errors
+    #[arg(long)]
+    pub retry_file: Option<PathBuf>,
+
+    /// Stop the import cleanly after this duration (like `55m`, `2h` or `1h30m`), before the next chunks are started.
+    /// The position is written to a checkpoint next to the file and the command exits with code 75
+    #[arg(long, value_parser = parse_duration)]
+    pub max_duration: Option<Duration>,
+
+    /// Continue an import, which was stopped after `--max-duration`, at the row of its checkpoint
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Only submit the sync chunks of the import within this daily window of the local time (like `22:00-06:00`),
+    /// outside of it the import pauses until the window opens again
+    #[arg(long)]
+    pub active_hours: Option<ActiveHours>,
+
+    /// Skip the sync chunks, which a previous run of the same file already synced completely (same content),
+    /// their content hashes are kept in the checkpoint next to the file
+    #[arg(long)]
+    pub skip_synced_chunks: bool,
+
+    /// Wait at most this duration (like `10m`) for another import of the same entity into the shop to finish,
+    /// by default the import fails right away if one is running
+    #[arg(long, value_parser = parse_duration, conflicts_with = "no_lock")]
+    pub lock_wait: Option<Duration>,
+
+    /// Don't lock the entity of the shop during the import, which allows concurrent imports of it
+    #[arg(long)]
+    pub no_lock: bool,
+
+    /// Format of the data file, fixed-width is only supported for export
+    #[arg(value_enum, long, default_value = "csv")]
+    pub format: FileFormat,
+
+    /// Maximum amount of entities on export, or of the first data rows of the file on import
+    /// (e.g. to smoke-test a new profile). Can be used for debugging and is optional
+    #[arg(short, long)]
+    pub limit: Option<u64>,
+
+    /// Disable triggering the indexer after sync ended successfully
+    #[arg(value_enum, short, long, default_value = "false")]
+    pub disable_index: bool,
+
+    /// Trigger the indexing of the written products after every sync chunk of the import,
+    /// so they become consistent in the storefront progressively during long imports.
+    /// The indexing at the end skips the product indexer then
+    #[arg(long, conflicts_with = "disable_index")]
+    pub index_per_chunk: bool,
+
+    // Verbose output, used for debugging
+    // #[arg(short, long, action = ArgAction::SetTrue)]
+    // verbose: bool,
+    /// How many requests can be "in-flight" at the same time
+    #[arg(short, long, default_value = in_flight_limit_default_as_string())]
+    pub in_flight_limit: usize,
+
+    /// Sync the chunks of the import strictly in file order (with a single sync thread),
+    /// for entities where the write order matters, e.g. category positions
+    #[arg(long)]
+    pub ordered: bool,
+
+    /// Maximum number of tries a request is executed on a recoverable failure (1..=255),
+    /// overrides the `retry.retries` of the profile [default: 10]
+    #[arg(short, long)]
+    pub try_count: Option<NonZeroU8>,
+
+    /// Number of retries after a failed try, overrides `--try-count` and the `retry.retries` of the profile
+    #[arg(long, conflicts_with = "try_count")]
+    pub retries: Option<u8>,
+
+    /// How the delay between tries of failed requests grows, overrides the `retry.backoff` of the profile
+    #[arg(value_enum, long)]
+    pub backoff: Option<BackoffStrategy>,
+
+    /// Timeout in seconds for a whole request, 0 disables it for long-running requests
+    #[arg(long, default_value = "15")]
+    pub timeout: u64,
+
+    /// Timeout in seconds for establishing a connection to the shop
+    #[arg(long, default_value = "10")]
+    pub connect_timeout: u64,
+
+    /// Maximum idle connections kept open per host for reuse.
+    /// By default every request opens a new connection, which avoids issues with long-running requests
+    #[arg(long, default_value = "0")]
+    pub pool_max_idle: usize,
+
+    /// Interval in seconds of TCP keep-alive probes on open connections
+    #[arg(long)]
+    pub keep_alive: Option<u64>,
+
+    /// Use HTTP/2 for all requests (the shop needs to support it)
+    #[arg(long)]
+    pub http2: bool,
+
+    /// Approximate memory ceiling in MB for the import, which sizes the amount of rows
+    /// loaded into memory at once based on the row sizes
+    #[arg(long)]
+    pub max_memory: Option<usize>,
+
+    /// Maximum size in MB of a single response (e.g. an export page), 0 disables the limit
+    #[arg(long, default_value = "512")]
+    pub max_response_size: u64,
+
+    /// Path to write a JSON report of the run to (e.g. with the script warnings)
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+
+    /// URL to POST a JSON summary of the finished (or failed) run to, with the per-row errors
+    /// and the ids of the written entities, e.g. to reconcile failures in other systems
+    #[arg(long, value_parser = parse_url)]
+    pub webhook: Option<String>,
+
+    /// Path of a JSON file with the progress of the run (rows done, errors so far and ETA),
+    /// which is updated every few seconds, e.g. for monitoring agents
+    #[arg(long)]
+    pub status_file: Option<PathBuf>,
+
+    /// Export without counting the entities first, pages are requested until all are processed.
+    /// Useful for big tables, where counting times out
+    #[arg(long)]
+    pub skip_count: bool,
+
+    /// Continue the export if a page still fails after all tries,
+    /// the failed pages are listed in the summary
+    #[arg(long)]
+    pub skip_failed_pages: bool,
+
+    /// Suppress entities on export, which were already written on a previous page
+    /// (they can shift between pages if entities are written during the export)
+    #[arg(long)]
+    pub dedupe: bool,
+
+    /// Only export the products visible in the sales channel with this name,
+    /// overrides the `sales_channel` of the profile
+    #[arg(long)]
+    pub sales_channel: Option<String>,
+
+    /// Only export the products of the dynamic product group (product stream) with this name,
+    /// overrides the `product_stream` of the profile
+    #[arg(long)]
+    pub product_stream: Option<String>,
+
+    /// Export one file per language of the shop (named like `products.de-DE.csv`),
+    /// with the translated fields in that language
+    #[arg(long)]
+    pub split_by_language: bool,
+
+    /// End the lines of the exported file with CRLF (`\r\n`) instead of LF, e.g. for tools on Windows
+    #[arg(long)]
+    pub crlf: bool,
+
+    /// Compress the exported file with gzip (e.g. `products.csv.gz`)
+    #[arg(long)]
+    pub gzip: bool,
+
+    /// Write a manifest next to the exported file (`<file>.manifest.json`) with the row count,
+    /// the SHA-256 of the file, the profile, the shop URL and the time of the export,
+    /// so consumers of the file can verify it after the transfer
+    #[arg(long)]
+    pub manifest: bool,
+
+    /// Previous export of the same profile, only the rows which were added, changed or removed
+    /// since then are exported, with the type of the change in the `_change` column
+    #[arg(long)]
+    pub baseline: Option<PathBuf>,
+
+    /// Warn about columns of the file that aren't used by any mapping on import
+    /// and about mappings without any value on export
+    #[arg(long)]
+    pub warn_unused: bool,
+
+    /// Collect statistics of the mapped columns on import (nulls, distinct values, lengths
+    /// and type conversion failures), which are shown in the summary and the report
+    #[arg(long)]
+    pub column_stats: bool,
+
+    /// Only validate the import on the server without writing anything: every chunk is sent
+    /// in a request which is rolled back, and the errors of the server are collected per row
+    #[arg(long)]
+    pub validate_remote: bool,
+
+    /// Confirm the deletion of the entities of the file with `-m delete`, which is refused without it
+    #[arg(long)]
+    pub yes: bool,
+
+    /// Skip the validation of the profile mappings against the entity schema,
+    /// e.g. for fields of plugins that are missing in the schema
+    #[arg(long)]
+    pub no_validate: bool,
+
+    /// Skip the client-side check of the imported values against the length and pattern
+    /// constraints of the OpenAPI schema
+    #[arg(long)]
+    pub no_schema_constraints: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Args)]
+pub struct PreviewArgs {
+    /// Path to profile.yaml, or the name of a profile in ./profiles or of a default profile (like `default_product`)
+    #[arg(short, long)]
+    pub profile: PathBuf,
+
+    /// Additional mapping like `file_column=entity_path[:type]` (type is string, number or boolean),
+    /// which overrides the mapping of the same column in the profile. Can be used multiple times
+    #[arg(long = "map", value_name = "MAPPING")]
+    pub map: Vec<EntityPathMapping>,
+
+    /// Path to data file, imports can also be read from stdin (`-`) or an `http(s)://` URL
+    #[arg(short, long)]
+    pub file: PathBuf,
+
+    /// Format of the data file
+    #[arg(value_enum, long, default_value = "csv")]
+    pub format: FileFormat,
+
+    /// Amount of rows to preview
+    #[arg(short, long, default_value = "5")]
+    pub rows: usize,
+
+    /// Skip the validation of the profile mappings against the entity schema
+    #[arg(long)]
+    pub no_validate: bool,
+}
+
+impl From<PreviewArgs> for SyncArgs {
+    /// Import arguments with the defaults of the `sync` command
+    fn from(args: PreviewArgs) -> Self {
+        Self {
+            mode: SyncMode::Import,
+            profile: args.profile,
+            map: args.map,
+            retry_file: None,
+            max_duration: None,
+            resume: false,
+            active_hours: None,
+            skip_synced_chunks: false,
+            lock_wait: None,
+            no_lock: false,
+            file: args.file,
+            format: args.format,
+            limit: Some(args.rows as u64),
+            disable_index: true,
+            index_per_chunk: false,
+            in_flight_limit: DEFAULT_IN_FLIGHT,
+            ordered: false,
+            try_count: None,
+            retries: None,
+            backoff: None,
+            timeout: 15,
+            connect_timeout: 10,
+            pool_max_idle: 0,
+            keep_alive: None,
+            http2: false,
+            max_memory: None,
+            max_response_size: 512,
+            report: None,
+            webhook: None,
+            status_file: None,
+            skip_count: false,
+            skip_failed_pages: false,
+            dedupe: false,
+            sales_channel: None,
+            product_stream: None,
+            split_by_language: false,
+            crlf: false,
+            gzip: false,
+            manifest: false,
+            baseline: None,
+            warn_unused: false,
+            column_stats: false,
+            validate_remote: false,
+            yes: false,
+            no_validate: args.no_validate,
+            no_schema_constraints: false,
+        }
+    }
 }
 
 pub const DEFAULT_IN_FLIGHT: usize = 10;
 
+pub const DEFAULT_TRY_COUNT: NonZeroU8 = NonZeroU8::new(10).unwrap();
+
+impl SyncArgs {
+    /// Tries of failed requests, the arguments take precedence over the `retry.retries` of the profile
+    pub fn get_try_count(&self, profile_retries: Option<u8>) -> NonZeroU8 {
+        let retries_to_tries = |retries| NonZeroU8::MIN.saturating_add(retries);
+        self.retries
+            .map(retries_to_tries)
+            .or(self.try_count)
+            .or(profile_retries.map(retries_to_tries))
+            .unwrap_or(DEFAULT_TRY_COUNT)
+    }
+}
+
+/// Parse a duration like `90s`, `55m`, `2h` or `1h30m`
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let mut total = Duration::ZERO;
+    let mut number = String::new();
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let seconds_per_unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            c => return Err(format!("unknown unit '{c}', expected h, m or s")),
+        };
+        if number.is_empty() {
+            return Err(format!("missing number before '{c}'"));
+        }
+        let too_long = || "duration is too long".to_string();
+        let amount: u64 = number.parse().map_err(|_| too_long())?;
+        let seconds = amount.checked_mul(seconds_per_unit).ok_or_else(too_long)?;
+        total = total
+            .checked_add(Duration::from_secs(seconds))
+            .ok_or_else(too_long)?;
+        number.clear();
+    }
+
+    if !number.is_empty() || total.is_zero() {
+        return Err(format!(
+            "invalid duration '{value}', expected e.g. 55m, 2h or 1h30m"
+        ));
+    }
+
+    Ok(total)
+}
+
+/// Daily time window of the local time like `22:00-06:00` (`--active-hours`),
+/// the end is excluded and windows can span midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ActiveHours {
+    /// Time until the window opens again, `None` within the window
+    pub fn get_wait(&self, now: NaiveTime) -> Option<Duration> {
+        let is_active = if self.start < self.end {
+            self.start <= now && now < self.end
+        } else {
+            self.start <= now || now < self.end
+        };
+        if is_active {
+            return None;
+        }
+
+        const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+        let seconds = i64::from(self.start.num_seconds_from_midnight())
+            - i64::from(now.num_seconds_from_midnight());
+        Some(Duration::from_secs(
+            seconds.rem_euclid(SECONDS_PER_DAY) as u64
+        ))
+    }
+
+    pub fn start(&self) -> NaiveTime {
+        self.start
+    }
+}
+
+impl FromStr for ActiveHours {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse_time = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| format!("invalid time '{time}', expected a time like 22:00"))
+        };
+        let (start, end) = value.split_once('-').ok_or_else(|| {
+            format!("invalid window '{value}', expected a window like 22:00-06:00")
+        })?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            return Err(format!("the window '{value}' is empty"));
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+/// Validate the URL while parsing the arguments, but keep it as a string (`Url` is big)
+fn parse_url(value: &str) -> Result<String, String> {
+    reqwest::Url::parse(value)
+        .map(|_| value.to_string())
+        .map_err(|e| e.to_string())
+}
+
 fn in_flight_limit_default_as_string() -> String {
     DEFAULT_IN_FLIGHT.to_string()
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SyncMode {
     Import,
     Export,
+    /// delete the entities of the file (identified by their primary keys), like tag assignments
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FileFormat {
+    /// values separated by semicolons
+    Csv,
+    /// values separated by tabs
+    Tsv,
+    /// values padded to the `width` of their mapping, without separators
+    FixedWidth,
+    /// columnar parquet file (export only), needs the feature `parquet`
+    Parquet,
+}
+
+impl FileFormat {
+    pub fn delimiter(self) -> Option<u8> {
+        match self {
+            FileFormat::Csv => Some(b';'),
+            FileFormat::Tsv => Some(b'\t'),
+            FileFormat::FixedWidth | FileFormat::Parquet => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -128,16 +701,237 @@ mod tests {
         assert_eq!(
             cli,
             Cli {
-                command: Commands::Sync {
+                command: Commands::Sync(Box::new(SyncArgs {
                     mode: SyncMode::Import,
                     profile: "my_profile.yaml".into(),
+                    map: vec![],
                     file: "./output.csv".into(),
+                    retry_file: None,
+                    max_duration: None,
+                    resume: false,
+                    active_hours: None,
+                    skip_synced_chunks: false,
+                    lock_wait: None,
+                    no_lock: false,
+                    format: FileFormat::Csv,
                     limit: None,
                     disable_index: false,
+                    index_per_chunk: false,
                     in_flight_limit: DEFAULT_IN_FLIGHT,
-                    try_count: NonZeroU8::new(10).unwrap()
-                },
+                    ordered: false,
+                    try_count: None,
+                    retries: None,
+                    backoff: None,
+                    timeout: 15,
+                    connect_timeout: 10,
+                    pool_max_idle: 0,
+                    keep_alive: None,
+                    http2: false,
+                    max_memory: None,
+                    max_response_size: 512,
+                    report: None,
+                    webhook: None,
+                    status_file: None,
+                    skip_count: false,
+                    skip_failed_pages: false,
+                    dedupe: false,
+                    sales_channel: None,
+                    product_stream: None,
+                    split_by_language: false,
+                    crlf: false,
+                    gzip: false,
+                    manifest: false,
+                    baseline: None,
+                    warn_unused: false,
+                    column_stats: false,
+                    validate_remote: false,
+                    yes: false,
+                    no_validate: false,
+                    no_schema_constraints: false,
+                })),
+                read_only: false,
+                user_agent: None,
+                correlation_id: None,
             }
         );
     }
+
+    #[test]
+    fn preview_args_use_sync_defaults() {
+        let args = vec![
+            "sw-sync-cli",
+            "preview",
+            "--profile",
+            "my_profile.yaml",
+            "--file",
+            "./input.csv",
+        ];
+        let Commands::Preview(preview_args) = Cli::try_parse_from(args).unwrap().command else {
+            panic!("expected preview command");
+        };
+        assert_eq!(preview_args.rows, 5);
+
+        let args = vec![
+            "sw-sync-cli",
+            "sync",
+            "-m",
+            "import",
+            "--profile",
+            "my_profile.yaml",
+            "--file",
+            "./input.csv",
+            "--limit",
+            "5",
+            "--disable-index",
+        ];
+        let Commands::Sync(sync_args) = Cli::try_parse_from(args).unwrap().command else {
+            panic!("expected sync command");
+        };
+        assert_eq!(SyncArgs::from(preview_args), *sync_args);
+    }
+
+    #[test]
+    fn global_read_only_flag() {
+        let cli = Cli::try_parse_from(["sw-sync-cli", "index", "--read-only"]).unwrap();
+        assert!(cli.read_only);
+        let cli = Cli::try_parse_from(["sw-sync-cli", "--read-only", "index"]).unwrap();
+        assert!(cli.read_only);
+    }
+
+    #[test]
+    fn commands_sending_requests() {
+        let command = |args: &[&str]| {
+            Cli::try_parse_from(["sw-sync-cli"].iter().chain(args))
+                .unwrap()
+                .command
+        };
+        assert!(command(&["index"]).sends_requests());
+        assert!(command(&["sync", "-m", "export", "-p", "a.yaml", "-f", "a.csv"]).sends_requests());
+        assert!(!command(&["copy-profiles"]).sends_requests());
+        assert!(!command(&["sample", "-p", "a.yaml", "-f", "a.csv"]).sends_requests());
+    }
+
+    #[test]
+    fn try_count_precedence() {
+        let sync_args = |args: &[&str]| {
+            let cli = Cli::try_parse_from(
+                [
+                    "sw-sync-cli",
+                    "sync",
+                    "-m",
+                    "import",
+                    "-p",
+                    "p.yaml",
+                    "-f",
+                    "f.csv",
+                ]
+                .iter()
+                .chain(args),
+            )
+            .unwrap();
+            let Commands::Sync(args) = cli.command else {
+                unreachable!("parsed as sync command");
+            };
+            args
+        };
+
+        assert_eq!(sync_args(&[]).get_try_count(None), DEFAULT_TRY_COUNT);
+        assert_eq!(sync_args(&[]).get_try_count(Some(4)).get(), 5);
+        // an explicit argument wins over the profile
+        assert_eq!(
+            sync_args(&["--try-count", "2"])
+                .get_try_count(Some(4))
+                .get(),
+            2
+        );
+        assert_eq!(
+            sync_args(&["--retries", "0"]).get_try_count(Some(4)).get(),
+            1
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("55m"), Ok(Duration::from_secs(55 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1h30m15s"), Ok(Duration::from_secs(5415)));
+        assert!(parse_duration("55").is_err());
+        assert!(parse_duration("0m").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert_eq!(
+            parse_duration("9999999999999999h"),
+            Err("duration is too long".to_string())
+        );
+        assert_eq!(
+            parse_duration("99999999999999999999s"),
+            Err("duration is too long".to_string())
+        );
+    }
+
+    #[test]
+    fn active_hours() {
+        let time = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+        let night: ActiveHours = "22:00-06:00".parse().unwrap();
+        assert_eq!(night.get_wait(time("23:30")), None);
+        assert_eq!(night.get_wait(time("05:59")), None);
+        assert_eq!(
+            night.get_wait(time("06:00")),
+            Some(Duration::from_secs(16 * 60 * 60))
+        );
+        assert_eq!(
+            night.get_wait(time("21:45")),
+            Some(Duration::from_secs(15 * 60))
+        );
+
+        let lunch: ActiveHours = "12:00 - 13:30".parse().unwrap();
+        assert_eq!(lunch.get_wait(time("12:00")), None);
+        assert_eq!(
+            lunch.get_wait(time("13:30")),
+            Some(Duration::from_secs(22 * 60 * 60 + 30 * 60))
+        );
+
+        assert!("22:00".parse::<ActiveHours>().is_err());
+        assert!("22:00-25:00".parse::<ActiveHours>().is_err());
+        assert!("06:00-06:00".parse::<ActiveHours>().is_err());
+    }
+
+    #[test]
+    fn repeated_map_args() {
+        let args = vec![
+            "sw-sync-cli",
+            "preview",
+            "--profile",
+            "default_product",
+            "--file",
+            "./input.csv",
+            "--map",
+            "ean=ean",
+            "--map",
+            "weight=weight:number",
+        ];
+        let Commands::Preview(preview_args) = Cli::try_parse_from(args).unwrap().command else {
+            panic!("expected preview command");
+        };
+        assert_eq!(
+            preview_args
+                .map
+                .iter()
+                .map(|m| m.entity_path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["ean", "weight"]
+        );
+
+        let args = vec![
+            "sw-sync-cli",
+            "preview",
+            "--profile",
+            "default_product",
+            "--file",
+            "./input.csv",
+            "--map",
+            "ean",
+        ];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
 }
@@ -5,11 +5,19 @@
 //!
 //! Utilizes <https://serde.rs/>
 
-use crate::api::filter::{CriteriaFilter, CriteriaSorting};
+use crate::api::filter::{Criteria, CriteriaFilter, CriteriaSorting};
+use crate::paths::{find_config_file, get_config_dir, get_long_path};
 use anyhow::Context;
+use csv::StringRecord;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::path::Path;
+use std::collections::{BTreeMap, HashSet};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Folder of the profiles copied by `copy-profiles`, profiles can be referenced by their name in it
+pub const PROFILES_DIR: &str = "./profiles";
 
 pub const DEFAULT_PROFILES: &[(&str, &str)] = &[
     (
@@ -24,6 +32,10 @@ pub const DEFAULT_PROFILES: &[(&str, &str)] = &[
         "default_customer.yaml",
         include_str!("../profiles/default_customer.yaml"),
     ),
+    (
+        "default_customer_tag.yaml",
+        include_str!("../profiles/default_customer_tag.yaml"),
+    ),
     (
         "default_media.yaml",
         include_str!("../profiles/default_media.yaml"),
@@ -36,14 +48,30 @@ pub const DEFAULT_PROFILES: &[(&str, &str)] = &[
         "default_order.yaml",
         include_str!("../profiles/default_order.yaml"),
     ),
+    (
+        "default_order_tag.yaml",
+        include_str!("../profiles/default_order_tag.yaml"),
+    ),
     (
         "default_product.yaml",
         include_str!("../profiles/default_product.yaml"),
     ),
+    (
+        "default_product_stock.yaml",
+        include_str!("../profiles/default_product_stock.yaml"),
+    ),
+    (
+        "default_product_tag.yaml",
+        include_str!("../profiles/default_product_tag.yaml"),
+    ),
     (
         "default_product_variants.yaml",
         include_str!("../profiles/default_product_variants.yaml"),
     ),
+    (
+        "default_promotion.yaml",
+        include_str!("../profiles/default_promotion.yaml"),
+    ),
     (
         "default_promotion_code.yaml",
         include_str!("../profiles/default_promotion_code.yaml"),
@@ -71,12 +99,82 @@ pub struct Credentials {
 
 impl Credentials {
     pub fn read_credentials() -> anyhow::Result<Self> {
-        let serialized_credentials = std::fs::read_to_string("./.credentials.toml")
+        let path = find_config_file(".credentials.toml");
+        let serialized_credentials = std::fs::read_to_string(&path)
             .context("No .credentials.toml found. Call command auth first.")?;
+        // they may be the user-wide credentials of the config directory
+        println!("using the credentials of {path:?}");
 
         let credentials: Self = toml::from_str(&serialized_credentials)?;
         Ok(credentials)
     }
+
+    /// Path of the credentials of an environment: either the path of a credentials file
+    /// or the name of an environment (like `staging`), which was authenticated with `auth --env staging`
+    pub fn get_env_path(env: &str) -> anyhow::Result<PathBuf> {
+        let path = PathBuf::from(env);
+        if path.is_file() {
+            return Ok(path);
+        }
+
+        Self::validate_env_name(env)?;
+        Ok(find_config_file(&format!(".credentials.{env}.toml")))
+    }
+
+    /// Environment names become part of the credentials filename,
+    /// so they may only contain `[A-Za-z0-9_-]` (no path separators)
+    pub fn validate_env_name(env: &str) -> anyhow::Result<()> {
+        if env.is_empty()
+            || !env
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            anyhow::bail!(
+                "invalid environment name '{env}': only letters, digits, '_' and '-' are allowed"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Credentials of the integration of an installed app, from the confirmation payload
+    /// of the app registration (`{"apiKey": "...", "secretKey": "...", "shopUrl": "...", ...}`)
+    pub fn read_app_confirmation(path: &Path) -> anyhow::Result<Self> {
+        let serialized_confirmation = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read app confirmation {path:?}"))?;
+        Self::parse_app_confirmation(&serialized_confirmation)
+            .with_context(|| format!("invalid app confirmation {path:?}"))
+    }
+
+    fn parse_app_confirmation(serialized_confirmation: &str) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AppConfirmation {
+            api_key: String,
+            secret_key: String,
+            #[serde(default)]
+            shop_url: String,
+        }
+
+        let confirmation: AppConfirmation = serde_json::from_str(serialized_confirmation)?;
+        Ok(Self {
+            base_url: confirmation.shop_url,
+            access_key_id: confirmation.api_key,
+            access_key_secret: confirmation.secret_key,
+        })
+    }
+
+    pub fn read_env_credentials(env: &str) -> anyhow::Result<Self> {
+        let path = Self::get_env_path(env)?;
+        let serialized_credentials = std::fs::read_to_string(&path).with_context(|| {
+            format!("No credentials of environment '{env}' found at {path:?}. Call command auth --env {env} first.")
+        })?;
+        println!("using the credentials of environment '{env}' of {path:?}");
+
+        let credentials: Self = toml::from_str(&serialized_credentials)
+            .with_context(|| format!("invalid credentials file {path:?}"))?;
+        Ok(credentials)
+    }
 }
 
 #[derive(Debug, Default, Eq, PartialEq, Deserialize)]
@@ -95,6 +193,96 @@ pub struct Profile {
 
     pub mappings: Vec<Mapping>,
 
+    /// Values (like ids) shared by the scripts (`get_constant("name")`)
+    /// and the mapping defaults (`${constants.name}`)
+    #[serde(default)]
+    pub constants: BTreeMap<String, serde_json::Value>,
+
+    /// Names of the (compiled in) Rust transformers, which are applied to every entity
+    #[serde(default = "Vec::new")]
+    pub transformers: Vec<String>,
+
+    /// Prepend the `_id`, `_version_id`, `_updated_at` and `_checksum` columns on export
+    #[serde(default)]
+    pub meta_columns: bool,
+
+    /// Only export the products, which are visible in the sales channel with this name
+    pub sales_channel: Option<String>,
+
+    /// Only export the products of the dynamic product group (product stream) with this name
+    pub product_stream: Option<String>,
+
+    /// Download the generated documents (e.g. invoices) of exported orders into a folder
+    pub documents: Option<DocumentOptions>,
+
+    /// Whether the prices of imported orders are written as they are (e.g. historical orders of a migration)
+    /// or recalculated by the server after the import (e.g. orders of an ongoing sync)
+    #[serde(default)]
+    pub order_prices: OrderPrices,
+
+    /// Adjustments of the exported header line, e.g. for the header names required by other tools
+    #[serde(default)]
+    pub header: HeaderOptions,
+
+    /// How values are protected from being interpreted as numbers, e.g. product numbers like `0012345`
+    #[serde(default)]
+    pub string_quoting: StringQuoting,
+
+    /// How null (or missing) values are written on export, defaults to `null`
+    pub null_value: Option<String>,
+
+    /// Backend for the serialize / deserialize logic
+    #[serde(default)]
+    pub script_engine: ScriptEngine,
+
+    /// Path to the module with the serialize / deserialize functions, if `script_engine` is `wasm`
+    pub wasm_module: Option<PathBuf>,
+
+    /// Sandbox limits for the Rhai scripts
+    #[serde(default)]
+    pub script_limits: ScriptLimits,
+
+    /// How `get_language_by_iso` / `get_currency_by_iso` handle codes which don't exist in the shop
+    #[serde(default)]
+    pub iso_codes: IsoCodeOptions,
+
+    /// Amount of entities per sync request on import, defaults to 500.
+    /// Bigger requests are faster for minimal payloads, like stock updates
+    pub sync_chunk_size: Option<NonZeroUsize>,
+
+    /// Maximum of sync requests of this entity in parallel on import, below the `--in-flight-limit`.
+    /// Avoids deadlocks of entities which write the same rows concurrently (like variants of the same product)
+    pub max_concurrent_chunks: Option<NonZeroUsize>,
+
+    /// Additional entity paths which the serialize script or the transformers read on export.
+    /// Only the mapped fields are requested from the API, for profiles with scripts or transformers
+    /// that only happens if the fields they need are listed here
+    #[serde(default)]
+    pub export_fields: Vec<String>,
+
+    /// Size limit of the payload of a single entity on import, oversized rows are skipped
+    /// instead of failing the whole sync request
+    pub payload_limit: Option<PayloadLimit>,
+
+    /// File column with the action of every row on import (`upsert` or `delete`), for files which mix both.
+    /// Rows with an empty cell are upserted, the deleted rows only need their primary keys
+    pub action_column: Option<String>,
+
+    /// Lines of the imported file starting with this character (like `#`) are skipped,
+    /// e.g. `# generated at ...` lines of supplier feeds
+    pub comment_char: Option<char>,
+
+    /// Rows of the imported file without any value (like `;;;`) are skipped, instead of being imported as empty rows
+    #[serde(default)]
+    pub skip_blank_rows: bool,
+
+    /// How the ids of imported rows without an `id` are generated
+    pub id_strategy: Option<IdStrategy>,
+
+    /// Tries of failed requests (export pages and deadlocks on import)
+    #[serde(default)]
+    pub retry: RetryOptions,
+
     #[serde(default = "String::new")]
     pub serialize_script: String,
 
@@ -103,19 +291,411 @@ pub struct Profile {
 }
 
 impl Profile {
+    pub fn get_null_value(&self) -> &str {
+        self.null_value.as_deref().unwrap_or("null")
+    }
+
+    /// The `comment_char` as a byte for the CSV reader
+    pub fn get_comment_byte(&self) -> anyhow::Result<Option<u8>> {
+        match self.comment_char {
+            Some(comment_char) if !comment_char.is_ascii() => {
+                anyhow::bail!("the comment_char '{comment_char}' of the profile needs to be an ASCII character")
+            }
+            comment_char => Ok(comment_char.map(|c| c as u8)),
+        }
+    }
+
+    pub fn get_sync_chunk_size(&self) -> usize {
+        self.sync_chunk_size
+            .map_or(Criteria::MAX_LIMIT, NonZeroUsize::get)
+    }
+
+    /// File columns read by the profile itself instead of a mapping,
+    /// the `action_column` and the column of the `take-from-column` id strategy
+    pub fn get_row_columns(&self) -> Vec<&str> {
+        let id_column = match &self.id_strategy {
+            Some(IdStrategy::TakeFromColumn { column }) => Some(column.as_str()),
+            Some(IdStrategy::RandomUuid | IdStrategy::DeterministicFromKeyFields { .. }) | None => {
+                None
+            }
+        };
+
+        self.action_column
+            .as_deref()
+            .into_iter()
+            .chain(id_column)
+            .collect()
+    }
+
+    /// Amount of sync requests in parallel on import, exports always use the in-flight limit
+    pub fn get_concurrent_chunks(&self, in_flight_limit: usize) -> usize {
+        self.max_concurrent_chunks
+            .map_or(in_flight_limit, |max| max.get().min(in_flight_limit))
+    }
+
+    /// Read the profile from the path or by its name (like `default_product`),
+    /// see [`Profile::read_serialized_profile`]
     pub fn read_profile(profile_path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let config_profiles_dir = get_config_dir().map(|dir| dir.join("profiles"));
+        let mut profiles_dirs = vec![Path::new(PROFILES_DIR)];
+        profiles_dirs.extend(config_profiles_dir.as_deref());
         let serialized_profile =
-            std::fs::read_to_string(profile_path).context("Provided profile file not found")?;
+            Self::read_serialized_profile(profile_path.as_ref(), &profiles_dirs)?;
 
-        let profile: Self = serde_yaml::from_str(&serialized_profile)?;
+        let mut profile: Self = serde_yaml::from_str(&serialized_profile)?;
+        profile.resolve_constants()?;
         Ok(profile)
     }
+
+    /// Read the profile file at the path. If it doesn't exist and the path is only a name
+    /// (with or without `.yaml`), the profile is looked up in the `profiles_dirs` first
+    /// (e.g. `./profiles` and the one in the config directory) and then in the default profiles embedded in the binary
+    fn read_serialized_profile(
+        profile_path: &Path,
+        profiles_dirs: &[&Path],
+    ) -> anyhow::Result<String> {
+        if profile_path.exists() {
+            return std::fs::read_to_string(get_long_path(profile_path))
+                .with_context(|| format!("failed to read profile {profile_path:?}"));
+        }
+
+        let is_name = profile_path.components().count() == 1;
+        let file_name = match profile_path.extension() {
+            Some(_) => profile_path.to_path_buf(),
+            None => profile_path.with_extension("yaml"),
+        };
+        if is_name {
+            for profiles_dir in profiles_dirs {
+                let local_path = profiles_dir.join(&file_name);
+                if local_path.exists() {
+                    return std::fs::read_to_string(get_long_path(&local_path))
+                        .with_context(|| format!("failed to read profile {local_path:?}"));
+                }
+            }
+
+            if let Some((_, content)) = DEFAULT_PROFILES
+                .iter()
+                .find(|(name, _)| Path::new(name) == file_name)
+            {
+                return Ok(content.to_string());
+            }
+        }
+
+        anyhow::bail!(
+            "Provided profile {profile_path:?} not found, neither as file nor as name of a profile in {profiles_dirs:?} or of a default profile (see `copy-profiles --list`)"
+        )
+    }
+
+    /// Replace the mappings of the same file columns with the given mappings (`--map`)
+    /// or append them, if the profile has no mapping for the column yet.
+    /// Mappings of the scripts can't be replaced, the scripts would miss their keys
+    pub fn apply_mapping_overrides(
+        &mut self,
+        overrides: &[EntityPathMapping],
+    ) -> anyhow::Result<()> {
+        for mapping in overrides {
+            let existing = self
+                .mappings
+                .iter_mut()
+                .find(|m| m.get_file_column() == mapping.file_column);
+            match existing {
+                Some(Mapping::ByScript(script_mapping)) => {
+                    anyhow::bail!(
+                        "the column '{}' is mapped to the key '{}' of the scripts, it can't be overridden with --map",
+                        script_mapping.file_column,
+                        script_mapping.key
+                    );
+                }
+                Some(existing) => {
+                    println!(
+                        "overriding the mapping of column '{}' with '{}'",
+                        mapping.file_column, mapping.entity_path
+                    );
+                    *existing = Mapping::ByPath(Box::new(mapping.clone()));
+                }
+                None => self
+                    .mappings
+                    .push(Mapping::ByPath(Box::new(mapping.clone()))),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve the relative paths of the `base64_file` mappings from the directory of the imported file
+    pub fn set_base64_dir(&mut self, dir: &Path) {
+        for mapping in &mut self.mappings {
+            if let Mapping::ByPath(by_path) = mapping {
+                if by_path.base64_file {
+                    by_path.base64_dir = Some(dir.to_path_buf());
+                }
+            }
+        }
+    }
+
+    /// Replace the `${constants.name}` placeholders in the mapping defaults with their values
+    fn resolve_constants(&mut self) -> anyhow::Result<()> {
+        const PLACEHOLDER_START: &str = "${constants.";
+
+        for mapping in &mut self.mappings {
+            let Mapping::ByPath(path_mapping) = mapping else {
+                continue;
+            };
+            let EntityPathMapping {
+                default: Some(default),
+                file_column,
+                ..
+            } = path_mapping.as_mut()
+            else {
+                continue;
+            };
+
+            let mut resolved = String::with_capacity(default.len());
+            let mut rest = default.as_str();
+            while let Some(start) = rest.find(PLACEHOLDER_START) {
+                resolved.push_str(&rest[..start]);
+                let name_start = start + PLACEHOLDER_START.len();
+                let name_end = rest[name_start..]
+                    .find('}')
+                    .map(|end| name_start + end)
+                    .with_context(|| {
+                        format!(
+                            "unclosed constant placeholder in default of column '{file_column}'"
+                        )
+                    })?;
+                let name = &rest[name_start..name_end];
+
+                match self.constants.get(name).with_context(|| {
+                    format!("unknown constant '{name}' in default of column '{file_column}'")
+                })? {
+                    serde_json::Value::String(value) => resolved.push_str(value),
+                    value => resolved.push_str(&value.to_string()),
+                }
+                rest = &rest[name_end + 1..];
+            }
+            resolved.push_str(rest);
+
+            *default = resolved;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct DocumentOptions {
+    /// folder of the downloaded documents, created if it doesn't exist
+    pub folder: PathBuf,
+    /// technical names of the document types to download (e.g. `invoice`), all types if empty
+    #[serde(default)]
+    pub types: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptEngine {
+    #[default]
+    Rhai,
+    Wasm,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderPrices {
+    /// write the monetary values (prices, taxes, shipping costs) of the file as they are
+    #[default]
+    Verbatim,
+    /// recalculate the prices and taxes of the written orders with the current rules of the shop
+    Recalculate,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StringQuoting {
+    /// only quote values in the file if necessary (e.g. if they contain the delimiter)
+    #[default]
+    Necessary,
+    /// quote all values in the exported file
+    Always,
+    /// prefix numeric-looking text values with an apostrophe on export (like spreadsheet applications),
+    /// which is removed again on import
+    Apostrophe,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HeaderOptions {
+    /// case of the exported column names
+    pub case: HeaderCase,
+    /// static prefix of every exported column name (except the meta columns)
+    pub prefix: String,
+}
+
+impl HeaderOptions {
+    /// Exported name of a column with the given header
+    pub fn format(&self, header: &str) -> String {
+        let header = match self.case {
+            HeaderCase::Unchanged => header.to_owned(),
+            HeaderCase::SnakeCase => to_snake_case(header),
+            HeaderCase::Uppercase => header.to_uppercase(),
+            HeaderCase::Lowercase => header.to_lowercase(),
+        };
+
+        format!("{}{header}", self.prefix)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderCase {
+    #[default]
+    Unchanged,
+    /// e.g. `Gross Price` or `grossPrice` becomes `gross_price`
+    SnakeCase,
+    Uppercase,
+    Lowercase,
+}
+
+fn to_snake_case(header: &str) -> String {
+    let mut snake_case = String::with_capacity(header.len());
+    let mut previous_lowercase = false;
+    for c in header.chars() {
+        if c.is_alphanumeric() {
+            if c.is_uppercase() && previous_lowercase {
+                snake_case.push('_');
+            }
+            previous_lowercase = c.is_lowercase() || c.is_numeric();
+            snake_case.extend(c.to_lowercase());
+        } else {
+            if !snake_case.is_empty() && !snake_case.ends_with('_') {
+                snake_case.push('_');
+            }
+            previous_lowercase = false;
+        }
+    }
+
+    snake_case.trim_end_matches('_').to_owned()
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ScriptLimits {
+    /// Maximum number of operations of a single script run, `0` means unlimited
+    pub max_operations: u64,
+    /// Maximum depth of nested function calls
+    pub max_call_levels: usize,
+    /// Maximum execution time of a single script run (one row) in milliseconds, `0` means unlimited
+    pub timeout_ms: u64,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 0,
+            max_call_levels: 64,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct IsoCodeOptions {
+    /// Codes of the file replaced by codes of the shop, e.g. `de-AT: de-DE` or `CHF: EUR`
+    pub mapping: BTreeMap<String, String>,
+    /// What happens with codes which aren't in the shop (and have no entry in `mapping`)
+    pub unknown: UnknownIsoCode,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownIsoCode {
+    /// an empty id (with a printed warning), the behavior before this option existed
+    #[default]
+    Empty,
+    /// fail the row
+    Error,
+    /// use the system default language / the default currency
+    Default,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum IdStrategy {
+    /// a new random id, so every import creates new entities
+    RandomUuid,
+    /// derived from the values of the entity paths (e.g. `productNumber`),
+    /// so the same row updates the same entity on every import
+    DeterministicFromKeyFields { key_fields: Vec<String> },
+    /// the value of a file column (a uuid with or without dashes), which isn't mapped to the `id`
+    TakeFromColumn { column: String },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct PayloadLimit {
+    /// Maximum size of the JSON payload of a single entity in bytes
+    pub max_bytes: NonZeroUsize,
+    /// Fields which are removed from oversized payloads (in this order) until they fit,
+    /// e.g. `description` or nested like `translations.de-DE.description`
+    #[serde(default)]
+    pub strip_fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RetryOptions {
+    /// Number of retries after a failed try, only used without the `--try-count` and `--retries` arguments
+    pub retries: Option<u8>,
+    /// How the delay grows between the tries
+    pub backoff: BackoffStrategy,
+    /// Base delay between the tries in milliseconds
+    pub delay_ms: u64,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            retries: None,
+            backoff: BackoffStrategy::Linear,
+            delay_ms: 1000,
+        }
+    }
+}
+
+impl RetryOptions {
+    /// Upper bound of the delay, so exponential backoff doesn't stall the sync
+    const MAX_DELAY: Duration = Duration::from_secs(60);
+
+    /// Delay after the given (1-based) failed try
+    pub fn get_delay(&self, tries: u8) -> Duration {
+        let delay = Duration::from_millis(self.delay_ms);
+        let delay = match self.backoff {
+            BackoffStrategy::Fixed => delay,
+            BackoffStrategy::Linear => delay.saturating_mul(u32::from(tries)),
+            BackoffStrategy::Exponential => {
+                delay.saturating_mul(2u32.saturating_pow(u32::from(tries.saturating_sub(1))))
+            }
+        };
+
+        delay.min(Self::MAX_DELAY)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackoffStrategy {
+    /// always wait the base delay
+    Fixed,
+    /// wait the base delay times the number of failed tries
+    Linear,
+    /// double the delay after every failed try
+    Exponential,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
 #[serde(untagged)]
 pub enum Mapping {
-    ByPath(EntityPathMapping),
+    ByPath(Box<EntityPathMapping>),
     ByScript(EntityScriptMapping),
 }
 
@@ -126,13 +706,284 @@ impl Mapping {
             Mapping::ByScript(m) => &m.file_column,
         }
     }
+
+    /// Column name in the exported header line, which is the `file_column` if no `header` is set
+    pub fn get_header(&self) -> &str {
+        let header = match self {
+            Mapping::ByPath(m) => &m.header,
+            Mapping::ByScript(m) => &m.header,
+        };
+
+        header.as_deref().unwrap_or_else(|| self.get_file_column())
+    }
+
+    /// Columns of the imported file, which are read by the mapping
+    pub fn get_import_columns(&self) -> Vec<&str> {
+        match self {
+            Mapping::ByPath(path_mapping) => match &path_mapping.concat {
+                Some(concat) => concat.columns.iter().map(String::as_str).collect(),
+                None => vec![&path_mapping.file_column],
+            },
+            Mapping::ByScript(script_mapping) => vec![&script_mapping.file_column],
+        }
+    }
+
+    pub fn get_width(&self) -> Option<usize> {
+        match self {
+            Mapping::ByPath(m) => m.width,
+            Mapping::ByScript(m) => m.width,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
 pub struct EntityPathMapping {
     pub file_column: String,
     pub entity_path: String,
     pub column_type: Option<ColumnType>,
+    /// locale code (e.g. `de-DE`) of the translation to read / write for a translatable field
+    pub language: Option<String>,
+    /// iso code (e.g. `EUR`) of the currency to read / write for a field of a price, like `price.gross`
+    pub currency: Option<String>,
+    /// used on import if the cell is empty, can reference constants with `${constants.name}`
+    pub default: Option<String>,
+    /// on import, how empty cells are handled (by default they are skipped, except for string columns)
+    pub empty_as: Option<EmptyAs>,
+    /// on import null values (e.g. `null` cells) are written to clear the field, instead of being left out
+    #[serde(default)]
+    pub write_null: bool,
+    /// on import the cell can't be empty
+    #[serde(default)]
+    pub required: bool,
+    /// on import the value can be null (empty cell or `null`)
+    #[serde(default = "default_true")]
+    pub allow_null: bool,
+    /// on import the (non-empty) cell must be one of these values
+    #[serde(default)]
+    pub allowed_values: Vec<String>,
+    /// compare the cells case-insensitive with the `allowed_values`
+    #[serde(default)]
+    pub ignore_case: bool,
+    /// validate the entity path against the entity schema, can be disabled e.g. for plugin fields
+    #[serde(default = "default_true")]
+    pub validate: bool,
+    /// column name on export, if it should differ from the `file_column`
+    pub header: Option<String>,
+    /// width of the column in characters, required for the fixed-width format
+    pub width: Option<usize>,
+    /// on import longer strings are truncated to this amount of characters
+    pub max_length: Option<usize>,
+    /// on import the cell contains the path of a local file, whose content is written base64 encoded
+    /// (only into text and JSON fields, like custom fields)
+    #[serde(default)]
+    pub base64_file: bool,
+    /// joins the values of a wildcard path (like `categories[*].name`) or an id list field
+    /// (like `categoryIds`) on export and splits id lists on import, defaults to `|`
+    pub separator: Option<String>,
+    /// on import the cell value is resolved into the id of the referenced record
+    pub lookup: Option<Lookup>,
+    /// on export the value is formatted, e.g. rounded to decimal places or with a date format
+    pub format: Option<ValueFormat>,
+    /// on import the value is combined of multiple file columns (e.g. first and last name),
+    /// instead of being read from the `file_column`
+    pub concat: Option<ConcatColumns>,
+    /// on import only a part of the file column is used (e.g. the first name of a full name)
+    pub split: Option<SplitColumn>,
+    /// fields of every element of a to-many association (e.g. the prices), the elements are joined
+    /// by the `separator` and their fields (in the order of these mappings) by the `field_separator`,
+    /// separators inside the values are escaped with a backslash
+    #[serde(default)]
+    pub mappings: Vec<NestedMapping>,
+    /// between the fields of an element of the nested `mappings`, defaults to `:`
+    pub field_separator: Option<String>,
+    /// many-to-many association path written by an id list field (e.g. `categories` of `categoryIds`),
+    /// resolved from the entity schema
+    #[serde(skip)]
+    pub id_list_association: Option<String>,
+    /// directory of the imported file, relative `base64_file` paths are resolved from it
+    #[serde(skip)]
+    pub base64_dir: Option<PathBuf>,
+}
+
+impl EntityPathMapping {
+    /// Whether the imported file has the `id_column` of the lookup instead of the `file_column`,
+    /// the ids of that column are imported as they are
+    pub fn has_lookup_ids(&self, headers: &StringRecord) -> bool {
+        let Some(id_column) = self.lookup.as_ref().and_then(|l| l.id_column.as_deref()) else {
+            return false;
+        };
+
+        !headers.iter().any(|header| header == self.file_column)
+            && headers.iter().any(|header| header == id_column)
+    }
+
+    /// Column of the imported file with the values of this mapping
+    pub fn get_import_column(&self, headers: &StringRecord) -> &str {
+        match &self.lookup {
+            Some(Lookup {
+                id_column: Some(id_column),
+                ..
+            }) if self.has_lookup_ids(headers) => id_column,
+            _ => &self.file_column,
+        }
+    }
+
+    /// Entity paths of the fields of the nested `mappings`, e.g. `prices.ruleId`
+    pub fn get_nested_paths(&self) -> impl Iterator<Item = String> + '_ {
+        self.mappings
+            .iter()
+            .map(|nested| format!("{}.{}", self.entity_path, nested.entity_path))
+    }
+}
+
+/// Parses a mapping of the `--map` argument like `file_column=entity_path` or `file_column=entity_path:type`
+impl FromStr for EntityPathMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (file_column, rest) = value.split_once('=').with_context(|| {
+            format!("mapping '{value}' should look like 'file_column=entity_path[:type]'")
+        })?;
+        let (entity_path, column_type) = match rest.rsplit_once(':') {
+            Some((entity_path, column_type)) => {
+                let column_type = match column_type {
+                    "string" => ColumnType::String,
+                    "number" => ColumnType::Number,
+                    "boolean" => ColumnType::Boolean,
+                    column_type => anyhow::bail!(
+                        "unknown column type '{column_type}' in mapping '{value}', expected string, number or boolean"
+                    ),
+                };
+                (entity_path, Some(column_type))
+            }
+            None => (rest, None),
+        };
+
+        let file_column = file_column.trim();
+        let entity_path = entity_path.trim();
+        if file_column.is_empty() || entity_path.is_empty() {
+            anyhow::bail!("mapping '{value}' needs a file column and an entity path");
+        }
+
+        Ok(Self {
+            file_column: file_column.to_string(),
+            entity_path: entity_path.to_string(),
+            column_type,
+            ..Default::default()
+        })
+    }
+}
+
+impl Default for EntityPathMapping {
+    fn default() -> Self {
+        Self {
+            file_column: String::new(),
+            entity_path: String::new(),
+            column_type: None,
+            language: None,
+            currency: None,
+            default: None,
+            empty_as: None,
+            write_null: false,
+            required: false,
+            allow_null: true,
+            allowed_values: vec![],
+            ignore_case: false,
+            validate: true,
+            header: None,
+            width: None,
+            max_length: None,
+            base64_file: false,
+            separator: None,
+            lookup: None,
+            format: None,
+            concat: None,
+            split: None,
+            mappings: vec![],
+            field_separator: None,
+            id_list_association: None,
+            base64_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct Lookup {
+    /// entity of the referenced records, e.g. `product_manufacturer`
+    pub entity: String,
+    /// field of the referenced records, which is compared with the cell value, e.g. `name`
+    pub field: String,
+    /// create a record for values which can't be resolved, instead of failing the row
+    #[serde(default)]
+    pub create_missing: bool,
+    /// additional fields of created records, e.g. the `groupId` of property options
+    #[serde(default)]
+    pub create_with: BTreeMap<String, serde_json::Value>,
+    /// column with the ids of the referenced records, which is imported without lookup
+    /// if the file has it instead of the `file_column` (e.g. files of an older profile version)
+    pub id_column: Option<String>,
+}
+
+/// Field of the elements of a to-many association (`mappings` of a mapping)
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct NestedMapping {
+    /// path inside the element, e.g. `ruleId` or `media.url`
+    pub entity_path: String,
+    pub column_type: Option<ColumnType>,
+}
+
+/// File columns which are joined into one value on import
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct ConcatColumns {
+    pub columns: Vec<String>,
+    /// between the values, defaults to a space. Empty cells are left out
+    #[serde(default = "default_space")]
+    pub separator: String,
+}
+
+/// Part of a file column, which is used on import
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct SplitColumn {
+    /// defaults to a space
+    #[serde(default = "default_space")]
+    pub separator: String,
+    /// position of the part, starting at 0. Missing parts are empty
+    pub index: usize,
+    /// maximum amount of parts, the last one contains the rest of the value
+    /// (e.g. `2` to split a full name into the first name and all other names)
+    pub parts: Option<NonZeroUsize>,
+}
+
+/// Formatting of exported values, each option only applies to values of its type
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+pub struct ValueFormat {
+    /// decimal places of numbers, e.g. `2` for `19.90`
+    pub decimals: Option<usize>,
+    /// strftime format of dates, e.g. `%d.%m.%Y`
+    pub date_format: Option<String>,
+    /// written instead of `true`
+    pub true_label: Option<String>,
+    /// written instead of `false`
+    pub false_label: Option<String>,
+}
+
+fn default_space() -> String {
+    " ".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyAs {
+    /// clear the field by writing null
+    Null,
+    /// write an empty string
+    EmptyString,
+    /// leave the field untouched
+    Skip,
 }
 
 #[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
@@ -141,6 +992,10 @@ pub struct EntityScriptMapping {
     /// used as an identifier inside the script
     pub key: String,
     pub column_type: Option<ColumnType>,
+    /// column name on export, if it should differ from the `file_column`
+    pub header: Option<String>,
+    /// width of the column in characters, required for the fixed-width format
+    pub width: Option<usize>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
@@ -155,7 +1010,160 @@ pub enum ColumnType {
 mod tests {
     use super::*;
     use crate::api::{CurrencyList, Entity, IsoLanguageList};
-    use crate::data::{prepare_scripting_environment, validate_paths_for_entity};
+    use crate::data::{
+        get_associations, get_invalid_associations, prepare_scripting_environment,
+        resolve_id_lists, validate_paths_for_entity, validate_script_keys,
+    };
+
+    #[test]
+    fn validate_env_name() {
+        assert!(Credentials::validate_env_name("staging").is_ok());
+        assert!(Credentials::validate_env_name("prod_EU-2").is_ok());
+        for env in ["", "../x", "a/b", "a\\b", "..", "stag ing"] {
+            assert!(Credentials::validate_env_name(env).is_err(), "{env:?}");
+        }
+        assert!(Credentials::get_env_path("../staging").is_err());
+    }
+
+    #[test]
+    fn parse_app_confirmation() {
+        let credentials = Credentials::parse_app_confirmation(
+            r#"{
+                "apiKey": "SWIAEXAMPLE",
+                "secretKey": "secret",
+                "timestamp": "1727000000",
+                "shopUrl": "https://shop.example.com",
+                "shopId": "sHoPiD"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(credentials.base_url, "https://shop.example.com");
+        assert_eq!(credentials.access_key_id, "SWIAEXAMPLE");
+        assert_eq!(credentials.access_key_secret, "secret");
+
+        assert!(Credentials::parse_app_confirmation(r#"{ "apiKey": "SWIAEXAMPLE" }"#).is_err());
+    }
+
+    #[test]
+    fn parse_mapping_override() {
+        let mapping: EntityPathMapping = "ean=ean".parse().unwrap();
+        assert_eq!(
+            mapping,
+            EntityPathMapping {
+                file_column: "ean".to_string(),
+                entity_path: "ean".to_string(),
+                ..Default::default()
+            }
+        );
+
+        let mapping: EntityPathMapping = "weight=customFields.weight:number".parse().unwrap();
+        assert_eq!(mapping.file_column, "weight");
+        assert_eq!(mapping.entity_path, "customFields.weight");
+        assert_eq!(mapping.column_type, Some(ColumnType::Number));
+
+        assert!("ean".parse::<EntityPathMapping>().is_err());
+        assert!("=ean".parse::<EntityPathMapping>().is_err());
+        assert!("ean=ean:date"
+            .parse::<EntityPathMapping>()
+            .is_err_and(|e| e.to_string().contains("unknown column type 'date'")));
+    }
+
+    #[test]
+    fn apply_mapping_overrides() {
+        let mut profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "name"
+    entity_path: "name"
+  - file_column: "stock"
+    entity_path: "stock"
+  - file_column: "weight"
+    key: "weight"
+"#,
+        )
+        .unwrap();
+
+        profile
+            .apply_mapping_overrides(&[
+                "stock=stock:number".parse().unwrap(),
+                "ean=ean".parse().unwrap(),
+            ])
+            .unwrap();
+
+        let mappings: Vec<(&str, &str)> = profile
+            .mappings
+            .iter()
+            .map(|m| match m {
+                Mapping::ByPath(m) => (m.file_column.as_str(), m.entity_path.as_str()),
+                Mapping::ByScript(m) => (m.file_column.as_str(), "<script>"),
+            })
+            .collect();
+        assert_eq!(
+            mappings,
+            vec![
+                ("name", "name"),
+                ("stock", "stock"),
+                ("weight", "<script>"),
+                ("ean", "ean")
+            ]
+        );
+
+        let error = profile
+            .apply_mapping_overrides(&["weight=weight:number".parse().unwrap()])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the column 'weight' is mapped to the key 'weight' of the scripts, it can't be overridden with --map"
+        );
+    }
+
+    #[test]
+    fn read_profile_by_name() {
+        let default_product = DEFAULT_PROFILES
+            .iter()
+            .find(|(name, _)| *name == "default_product.yaml")
+            .unwrap()
+            .1;
+        let missing_dir = Path::new("./does-not-exist");
+
+        // embedded default profiles, with and without extension
+        for name in ["default_product", "default_product.yaml"] {
+            assert_eq!(
+                Profile::read_serialized_profile(Path::new(name), &[missing_dir]).unwrap(),
+                default_product
+            );
+        }
+
+        // the local profiles folder takes precedence
+        let profiles_dir = std::env::temp_dir().join("sw-sync-cli-read-profile-by-name");
+        std::fs::create_dir_all(&profiles_dir).unwrap();
+        std::fs::write(profiles_dir.join("default_product.yaml"), "entity: product").unwrap();
+        assert_eq!(
+            Profile::read_serialized_profile(Path::new("default_product"), &[&profiles_dir])
+                .unwrap(),
+            "entity: product"
+        );
+        std::fs::remove_dir_all(&profiles_dir).unwrap();
+
+        // existing paths are read directly
+        assert_eq!(
+            Profile::read_serialized_profile(
+                Path::new("./profiles/default_product.yaml"),
+                &[missing_dir]
+            )
+            .unwrap(),
+            default_product
+        );
+
+        assert!(Profile::read_serialized_profile(Path::new("unknown"), &[missing_dir]).is_err());
+        // paths aren't resolved as names
+        assert!(Profile::read_serialized_profile(
+            Path::new("./other/default_product.yaml"),
+            &[missing_dir]
+        )
+        .is_err());
+    }
 
     #[test]
     fn all_default_profiles_should_be_included() {
@@ -202,24 +1210,139 @@ mod tests {
 
         // run through all included default profiles and verify them
         for (profile_filename, profile_content) in DEFAULT_PROFILES {
-            let profile: Profile = serde_yaml::from_str(&profile_content).expect(&format!(
-                "failed to parse default profile '{profile_filename}'"
-            ));
+            let mut profile: Profile = serde_yaml::from_str(profile_content)
+                .unwrap_or_else(|_| panic!("failed to parse default profile '{profile_filename}'"));
 
-            validate_paths_for_entity(&profile.entity, &profile.mappings, &api_schema).expect(
-                &format!("failed to validate entity path's for default profile {profile_filename}"),
+            validate_paths_for_entity(&profile.entity, &profile.mappings, &api_schema)
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "failed to validate entity path's for default profile {profile_filename}"
+                    )
+                });
+
+            resolve_id_lists(&mut profile, &api_schema);
+            let associations = get_associations(&profile, &api_schema);
+            let invalid_associations = get_invalid_associations(
+                &profile.entity,
+                &associations,
+                &profile.mappings,
+                &api_schema,
+            );
+            assert!(
+                invalid_associations.is_empty(),
+                "invalid associations of default profile {profile_filename}: {invalid_associations:?}"
             );
 
+            validate_script_keys(&profile).unwrap_or_else(|e| {
+                panic!("failed to validate script keys of default profile {profile_filename}: {e}")
+            });
+
             // compile serialize & deserialize scripts
             prepare_scripting_environment(
                 &profile.serialize_script,
                 &profile.deserialize_script,
                 IsoLanguageList::default(),
                 CurrencyList::default(),
+                &profile.constants,
+                profile.script_limits,
             )
-            .expect(&format!(
-                "failed to compile scripts in default profile {profile_filename}"
-            ));
+            .unwrap_or_else(|_| {
+                panic!("failed to compile scripts in default profile {profile_filename}")
+            });
         }
     }
+
+    #[test]
+    fn retry_delays_by_backoff_strategy() {
+        let retry = |backoff| RetryOptions {
+            retries: None,
+            backoff,
+            delay_ms: 500,
+        };
+        let delays = |retry: RetryOptions| {
+            (1..=4)
+                .map(|t| retry.get_delay(t).as_millis())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            delays(retry(BackoffStrategy::Fixed)),
+            vec![500, 500, 500, 500]
+        );
+        assert_eq!(
+            delays(retry(BackoffStrategy::Linear)),
+            vec![500, 1000, 1500, 2000]
+        );
+        assert_eq!(
+            delays(retry(BackoffStrategy::Exponential)),
+            vec![500, 1000, 2000, 4000]
+        );
+        assert_eq!(
+            retry(BackoffStrategy::Exponential).get_delay(255),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn sync_chunk_size_defaults_to_max_limit() {
+        let profile: Profile = serde_yaml::from_str("entity: product\nmappings: []").unwrap();
+        assert_eq!(profile.get_sync_chunk_size(), Criteria::MAX_LIMIT);
+
+        let profile: Profile =
+            serde_yaml::from_str(include_str!("../profiles/default_product_stock.yaml")).unwrap();
+        assert_eq!(profile.get_sync_chunk_size(), 2000);
+    }
+
+    #[test]
+    fn concurrent_chunks_below_in_flight_limit() {
+        let profile: Profile = serde_yaml::from_str("entity: product\nmappings: []").unwrap();
+        assert_eq!(profile.get_concurrent_chunks(8), 8);
+
+        let profile: Profile =
+            serde_yaml::from_str("entity: product\nmappings: []\nmax_concurrent_chunks: 2")
+                .unwrap();
+        assert_eq!(profile.get_concurrent_chunks(8), 2);
+        assert_eq!(profile.get_concurrent_chunks(1), 1);
+    }
+
+    #[test]
+    fn resolve_constants_in_defaults() {
+        let mut profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+constants:
+  taxId: "abc"
+  stock: 10
+mappings:
+  - file_column: "tax id"
+    entity_path: "taxId"
+    default: "${constants.taxId}"
+  - file_column: "stock"
+    entity_path: "stock"
+    default: "${constants.stock}0"
+  - file_column: "name"
+    entity_path: "name"
+"#,
+        )
+        .unwrap();
+        profile.resolve_constants().unwrap();
+
+        let defaults: Vec<_> = profile
+            .mappings
+            .iter()
+            .map(|m| match m {
+                Mapping::ByPath(m) => m.default.as_deref(),
+                Mapping::ByScript(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(defaults, vec![Some("abc"), Some("100"), None]);
+
+        profile.mappings = vec![Mapping::ByPath(Box::new(EntityPathMapping {
+            file_column: "tax id".to_string(),
+            entity_path: "taxId".to_string(),
+            default: Some("${constants.unknown}".to_string()),
+            ..Default::default()
+        }))];
+        assert!(profile.resolve_constants().is_err());
+    }
 }
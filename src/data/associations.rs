@@ -0,0 +1,408 @@
+//! Associations (and fields) which need to be loaded on export for the mapped entity paths
+
+use crate::api::Entity;
+use crate::config_file::{Mapping, Profile};
+use crate::data::documents::DOCUMENTS_ASSOCIATION;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// All associations needed for the export, the ones of the profile plus the associations
+/// traversed by the entity paths of the mappings and by the fields of the `equals` filters
+/// (e.g. `manufacturer` for a filter on `manufacturer.name`)
+pub fn get_associations(profile: &Profile, api_schema: &Entity) -> HashSet<String> {
+    let mut associations = profile.associations.clone();
+    if profile.documents.is_some() {
+        associations.insert(DOCUMENTS_ASSOCIATION.to_string());
+    }
+    for field in profile.filter.iter().flat_map(|f| f.get_equals_fields()) {
+        if let Some(association) = get_association_path(&profile.entity, field, api_schema) {
+            associations.insert(association);
+        }
+    }
+    for mapping in &profile.mappings {
+        let Mapping::ByPath(by_path) = mapping else {
+            continue;
+        };
+
+        let association = get_association_path(&profile.entity, &by_path.entity_path, api_schema);
+
+        // translated fields are read from the translations of the requested language
+        if by_path.language.is_some() {
+            let translations = match &association {
+                Some(association) => format!("{association}.translations.language.locale"),
+                None => "translations.language.locale".to_owned(),
+            };
+            associations.insert(translations);
+        }
+
+        if let Some(association) = association {
+            associations.insert(association);
+        }
+        for nested_path in by_path.get_nested_paths() {
+            if let Some(association) =
+                get_association_path(&profile.entity, &nested_path, api_schema)
+            {
+                associations.insert(association);
+            }
+        }
+    }
+
+    associations
+}
+
+/// Association path (e.g. `tax.country`), which needs to be loaded for an entity path (e.g. `tax.country.name`).
+///
+/// The path is followed through the entity schema as long as the fields are associations,
+/// so fields like JSON objects (e.g. `customFields.color`) end the association path.
+/// Optional chaining markers (`manufacturer?.media?.url`) and wildcards (`categories[*].name`) are removed.
+pub fn get_association_path(
+    entity: &str,
+    entity_path: &str,
+    api_schema: &Entity,
+) -> Option<String> {
+    let mut current_entity = entity;
+    let mut association_path: Vec<&str> = vec![];
+
+    let mut fields = entity_path
+        .split('.')
+        .map(|f| f.trim_end_matches('?').trim_end_matches("[*]"));
+    // the last part is always the field itself
+    let _field = fields.next_back();
+    for field in fields {
+        let property = api_schema
+            .get(current_entity)
+            .and_then(|e| e.get("properties"))
+            .and_then(|p| p.get(field));
+        let Some(property) = property else {
+            break;
+        };
+        if property.get("type").and_then(|t| t.as_str()) != Some("association") {
+            break;
+        }
+        let Some(association_entity) = property.get("entity").and_then(|e| e.as_str()) else {
+            break;
+        };
+
+        association_path.push(field);
+        current_entity = association_entity;
+    }
+
+    if association_path.is_empty() {
+        None
+    } else {
+        Some(association_path.join("."))
+    }
+}
+
+/// Fields of the export response per entity (the `includes` of the criteria), so only the fields
+/// referenced by the mappings are sent by the API, instead of the whole (maybe very wide) entities.
+///
+/// Scripts and transformers can access any field of the entity, so profiles using them need to list
+/// the fields they read in `export_fields`. Otherwise (and with `documents`) all fields are exported,
+/// which is signaled by an empty map
+pub fn get_includes(profile: &Profile, api_schema: &Entity) -> BTreeMap<String, BTreeSet<String>> {
+    let uses_entity = !profile.serialize_script.is_empty()
+        || profile.wasm_module.is_some()
+        || !profile.transformers.is_empty();
+    if profile.documents.is_some() || (uses_entity && profile.export_fields.is_empty()) {
+        return BTreeMap::new();
+    }
+
+    let mut paths: Vec<String> = vec!["id".to_string()];
+    if profile.meta_columns {
+        paths.extend(["versionId".to_string(), "updatedAt".to_string()]);
+    }
+    paths.extend(profile.export_fields.iter().cloned());
+    for mapping in &profile.mappings {
+        let Mapping::ByPath(by_path) = mapping else {
+            continue;
+        };
+        // translated fields are read from the translation with the locale code of the language
+        if by_path.language.is_some() {
+            let (parent, field) = match by_path.entity_path.rsplit_once('.') {
+                Some((parent, field)) => (format!("{parent}."), field),
+                None => (String::new(), by_path.entity_path.as_str()),
+            };
+            paths.push(format!("{parent}translations.{field}"));
+            paths.push(format!("{parent}translations.language.locale.code"));
+        } else {
+            paths.push(by_path.entity_path.clone());
+        }
+        paths.extend(by_path.get_nested_paths());
+    }
+
+    let mut includes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for path in paths {
+        let mut current_entity = profile.entity.as_str();
+        for field in path
+            .split('.')
+            .map(|f| f.trim_end_matches('?').trim_end_matches("[*]"))
+        {
+            includes
+                .entry(current_entity.to_string())
+                .or_default()
+                .insert(field.to_string());
+
+            let property = api_schema
+                .get(current_entity)
+                .and_then(|e| e.get("properties"))
+                .and_then(|p| p.get(field));
+            let association_entity = property
+                .filter(|p| p.get("type").and_then(Value::as_str) == Some("association"))
+                .and_then(|p| p.get("entity"))
+                .and_then(Value::as_str);
+            // the rest of the path is inside the field (e.g. JSON like `customFields.color`)
+            let Some(association_entity) = association_entity else {
+                break;
+            };
+            current_entity = association_entity;
+        }
+    }
+
+    includes
+}
+
+/// Resolve the many-to-many associations of the mappings to id list fields like `categoryIds`
+pub fn resolve_id_lists(profile: &mut Profile, api_schema: &Entity) {
+    for mapping in &mut profile.mappings {
+        if let Mapping::ByPath(by_path) = mapping {
+            by_path.id_list_association =
+                get_id_list_association(&profile.entity, &by_path.entity_path, api_schema);
+        }
+    }
+}
+
+/// Many-to-many association path of an id list field (e.g. `categories` of `categoryIds`).
+///
+/// These `*Ids` fields are maintained by the shop and can't be written,
+/// so on import the ids are written as the association (`categories: [{ id }]`) instead.
+/// The association is the `many_to_many` field named by the plural of the id list (`tagIds` -> `tags`)
+pub fn get_id_list_association(
+    entity: &str,
+    entity_path: &str,
+    api_schema: &Entity,
+) -> Option<String> {
+    let get_property = |entity: &str, field: &str| {
+        api_schema
+            .get(entity)
+            .and_then(|e| e.get("properties"))
+            .and_then(|p| p.get(field))
+    };
+
+    let (parent_path, field) = match entity_path.rsplit_once('.') {
+        Some((parent_path, field)) => (Some(parent_path), field),
+        None => (None, entity_path),
+    };
+
+    let mut current_entity = entity;
+    for parent in parent_path.into_iter().flat_map(|p| p.split('.')) {
+        let property = get_property(current_entity, parent.trim_end_matches('?'))?;
+        if property.get("type").and_then(Value::as_str) != Some("association") {
+            return None;
+        }
+        current_entity = property.get("entity").and_then(Value::as_str)?;
+    }
+
+    let field = field.trim_end_matches('?');
+    let singular = field.strip_suffix("Ids")?;
+    if get_property(current_entity, field)?
+        .get("type")
+        .and_then(Value::as_str)
+        != Some("json_list")
+    {
+        return None;
+    }
+
+    let plurals = [
+        Some(format!("{singular}s")),
+        singular.strip_suffix('y').map(|stem| format!("{stem}ies")),
+    ];
+    let association = plurals.into_iter().flatten().find(|plural| {
+        get_property(current_entity, plural)
+            .and_then(|p| p.get("relation"))
+            .and_then(Value::as_str)
+            == Some("many_to_many")
+    })?;
+
+    Some(match parent_path {
+        Some(parent_path) => format!("{parent_path}.{association}"),
+        None => association,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_schema() -> Entity {
+        let raw_schema_content =
+            std::fs::read_to_string("./fixtures/entity-schema-2024-08-01.json")
+                .expect("failed to read entity-schema fixture");
+        serde_json::from_str(&raw_schema_content).expect("failed to parse entity-schema fixture")
+    }
+
+    #[test]
+    fn association_paths_by_schema() {
+        let api_schema = fixture_schema();
+        let association_path = |path| get_association_path("product", path, &api_schema);
+
+        assert_eq!(association_path("productNumber"), None);
+        assert_eq!(association_path("tax.taxRate"), Some("tax".to_string()));
+        assert_eq!(
+            association_path("manufacturer?.media?.url"),
+            Some("manufacturer.media".to_string())
+        );
+        assert_eq!(
+            association_path("cover.media.alt"),
+            Some("cover.media".to_string())
+        );
+        assert_eq!(
+            association_path("categories[*].media?.url"),
+            Some("categories.media".to_string())
+        );
+        // JSON fields aren't associations
+        assert_eq!(association_path("customFields.color"), None);
+        assert_eq!(association_path("price.DEFAULT.gross"), None);
+        assert_eq!(
+            association_path("manufacturer.customFields.color"),
+            Some("manufacturer".to_string())
+        );
+    }
+
+    #[test]
+    fn id_list_associations_by_schema() {
+        let api_schema = fixture_schema();
+        let id_list = |entity, path| get_id_list_association(entity, path, &api_schema);
+
+        assert_eq!(
+            id_list("product", "categoryIds"),
+            Some("categories".to_string())
+        );
+        assert_eq!(id_list("product", "tagIds"), Some("tags".to_string()));
+        assert_eq!(id_list("product", "optionIds"), Some("options".to_string()));
+        assert_eq!(
+            id_list("product", "propertyIds"),
+            Some("properties".to_string())
+        );
+        assert_eq!(
+            id_list("product", "parent?.categoryIds"),
+            Some("parent?.categories".to_string())
+        );
+        assert_eq!(id_list("customer", "tagIds"), Some("tags".to_string()));
+        // id lists without a many-to-many association and other fields
+        assert_eq!(id_list("customer", "vatIds"), None);
+        assert_eq!(id_list("product", "categories"), None);
+        assert_eq!(id_list("product", "productNumber"), None);
+    }
+
+    #[test]
+    fn associations_of_profile() {
+        let api_schema = fixture_schema();
+        let profile: Profile = serde_yaml::from_str(
+            r"
+entity: product
+associations:
+  - 'visibilities'
+filter:
+  - type: 'multi'
+    operator: 'or'
+    queries:
+      - type: 'equals'
+        field: 'manufacturer.name'
+        value: 'shopware AG'
+      - type: 'equalsAny'
+        field: 'tax.name'
+        value: ['Standard rate']
+  - type: 'contains'
+    field: 'cover.media.alt'
+    value: 'shirt'
+mappings:
+  - file_column: 'id'
+    entity_path: 'id'
+  - file_column: 'name (german)'
+    entity_path: 'name'
+    language: 'de-DE'
+  - file_column: 'manufacturer name'
+    entity_path: 'manufacturer?.name'
+    language: 'de-DE'
+  - file_column: 'color'
+    entity_path: 'customFields.color'
+",
+        )
+        .unwrap();
+
+        let mut associations = get_associations(&profile, &api_schema)
+            .into_iter()
+            .collect::<Vec<_>>();
+        associations.sort_unstable();
+        assert_eq!(
+            associations,
+            vec![
+                "manufacturer",
+                "manufacturer.translations.language.locale",
+                "tax",
+                "translations.language.locale",
+                "visibilities",
+            ]
+        );
+    }
+
+    #[test]
+    fn includes_of_mapped_fields() {
+        let api_schema = fixture_schema();
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "number"
+    entity_path: "productNumber"
+  - file_column: "manufacturer"
+    entity_path: "manufacturer?.name"
+  - file_column: "categories"
+    entity_path: "categories[*].name"
+  - file_column: "color"
+    entity_path: "customFields.color"
+  - file_column: "name (german)"
+    entity_path: "name"
+    language: "de-DE"
+"#,
+        )
+        .unwrap();
+
+        let includes = get_includes(&profile, &api_schema);
+        let fields = |entity: &str| {
+            includes[entity]
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            fields("product"),
+            vec![
+                "categories",
+                "customFields",
+                "id",
+                "manufacturer",
+                "productNumber",
+                "translations"
+            ]
+        );
+        assert_eq!(fields("product_manufacturer"), vec!["name"]);
+        assert_eq!(fields("category"), vec!["name"]);
+        assert_eq!(fields("product_translation"), vec!["language", "name"]);
+        assert_eq!(fields("language"), vec!["locale"]);
+        assert_eq!(fields("locale"), vec!["code"]);
+
+        // scripts can read any field, unless they are listed
+        let with_script = Profile {
+            serialize_script: "row.stock = entity.stock;".to_string(),
+            ..profile
+        };
+        assert!(get_includes(&with_script, &api_schema).is_empty());
+        let with_export_fields = Profile {
+            export_fields: vec!["stock".to_string()],
+            ..with_script
+        };
+        assert!(get_includes(&with_export_fields, &api_schema)["product"].contains("stock"));
+    }
+}
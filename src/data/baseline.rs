@@ -0,0 +1,236 @@
+//! Delta exports (`--baseline previous.csv`): only the rows which were added, changed or removed
+//! since a previous export of the same profile are written, with the type of the change in the first column
+
+use crate::config_file::{Mapping, Profile};
+use anyhow::Context;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// Column with the type of the change, which is prepended to the delta export
+pub const CHANGE_COLUMN: &str = "_change";
+
+/// Maximum amount of duplicate keys listed in the error of the baseline
+const MAX_LISTED_DUPLICATES: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Changed,
+    Removed,
+}
+
+impl Change {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Changed => "changed",
+            Change::Removed => "removed",
+        }
+    }
+}
+
+/// Rows of the previous export by their key (the id of the entity)
+#[derive(Debug)]
+pub struct Baseline {
+    key_column: usize,
+    /// in file order, rows are taken out once they are compared
+    rows: Vec<Option<Vec<String>>>,
+    positions: HashMap<String, usize>,
+}
+
+impl Baseline {
+    /// Read the previous export, it needs the same header as the current export.
+    /// Rows without a key can't be matched with the exported rows, they are skipped
+    pub fn read(
+        path: &Path,
+        delimiter: u8,
+        profile: &Profile,
+        header: &[String],
+    ) -> anyhow::Result<Self> {
+        let key_column = get_key_column(profile)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .from_path(path)
+            .with_context(|| format!("failed to open baseline {path:?}"))?;
+        if reader.headers()?.iter().ne(header.iter()) {
+            anyhow::bail!("the header of the baseline {path:?} doesn't match the export, it needs to be a full export of the same profile");
+        }
+
+        let mut rows = vec![];
+        let mut positions = HashMap::new();
+        let mut rows_without_key = 0;
+        let mut duplicate_keys = BTreeSet::new();
+        for record in reader.records() {
+            let row: Vec<String> = record
+                .with_context(|| format!("failed to read baseline {path:?}"))?
+                .iter()
+                .map(str::to_owned)
+                .collect();
+            if row[key_column].is_empty() {
+                rows_without_key += 1;
+                continue;
+            }
+            // otherwise the earlier row couldn't be matched and would be reported as removed
+            match positions.entry(row[key_column].clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(rows.len());
+                    rows.push(Some(row));
+                }
+                Entry::Occupied(entry) => {
+                    duplicate_keys.insert(entry.key().clone());
+                }
+            }
+        }
+        if !duplicate_keys.is_empty() {
+            let listed: Vec<&str> = duplicate_keys
+                .iter()
+                .take(MAX_LISTED_DUPLICATES)
+                .map(String::as_str)
+                .collect();
+            anyhow::bail!(
+                "the ids of the baseline {path:?} aren't unique ({} duplicates): {}{}",
+                duplicate_keys.len(),
+                listed.join(", "),
+                if duplicate_keys.len() > MAX_LISTED_DUPLICATES {
+                    ", ..."
+                } else {
+                    ""
+                }
+            );
+        }
+        if rows_without_key > 0 {
+            println!("Warning: {rows_without_key} rows of the baseline {path:?} have no id, they are ignored");
+        }
+
+        Ok(Self {
+            key_column,
+            rows,
+            positions,
+        })
+    }
+
+    /// Compare an exported row with the row of the same key in the baseline,
+    /// `None` if it's unchanged. Rows without a key are always added
+    pub fn compare(&mut self, row: &[String]) -> Option<Change> {
+        let key = &row[self.key_column];
+        if key.is_empty() {
+            return Some(Change::Added);
+        }
+        let Some(position) = self.positions.remove(key) else {
+            return Some(Change::Added);
+        };
+
+        match self.rows[position].take() {
+            Some(previous) if previous == row => None,
+            _ => Some(Change::Changed),
+        }
+    }
+
+    /// Rows of the baseline which weren't exported anymore
+    pub fn into_removed(self) -> impl Iterator<Item = Vec<String>> {
+        self.rows.into_iter().flatten()
+    }
+}
+
+/// Column with the id of the entities: the `_id` meta-column or the mapping of the entity path `id`
+fn get_key_column(profile: &Profile) -> anyhow::Result<usize> {
+    if profile.meta_columns {
+        return Ok(0);
+    }
+
+    profile
+        .mappings
+        .iter()
+        .position(|mapping| matches!(mapping, Mapping::ByPath(m) if m.entity_path == "id"))
+        .context("--baseline needs the ids of the exported entities: add a mapping of the entity path 'id' or enable meta_columns")
+}
+
+/// Prepend the column of the change
+pub fn get_delta_header(header: &[String]) -> Vec<String> {
+    std::iter::once(CHANGE_COLUMN.to_string())
+        .chain(header.iter().cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_with_baseline() {
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "name"
+    entity_path: "name"
+  - file_column: "id"
+    entity_path: "id"
+"#,
+        )
+        .unwrap();
+        let header = vec!["name".to_string(), "id".to_string()];
+        let path = std::env::temp_dir().join("sw-sync-cli-compare-with-baseline.csv");
+        std::fs::write(&path, "name;id\nShirt;a\nShoe;b\nHat;c\nCap;\nBelt;\n").unwrap();
+
+        let mut baseline = Baseline::read(&path, b';', &profile, &header).unwrap();
+        let row = |name: &str, id: &str| vec![name.to_string(), id.to_string()];
+        assert_eq!(baseline.compare(&row("Shirt", "a")), None);
+        assert_eq!(baseline.compare(&row("Boot", "b")), Some(Change::Changed));
+        assert_eq!(baseline.compare(&row("Sock", "d")), Some(Change::Added));
+        // rows without a key don't match each other
+        assert_eq!(baseline.compare(&row("Cap", "")), Some(Change::Added));
+        assert_eq!(
+            baseline.into_removed().collect::<Vec<_>>(),
+            [row("Hat", "c")]
+        );
+
+        let other_header = vec!["title".to_string(), "id".to_string()];
+        assert!(Baseline::read(&path, b';', &profile, &other_header).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let profile = Profile {
+            mappings: profile.mappings[..1].to_vec(),
+            ..profile
+        };
+        assert!(get_key_column(&profile).is_err());
+        assert_eq!(
+            get_delta_header(&header),
+            ["_change".to_string(), "name".to_string(), "id".to_string()]
+        );
+    }
+
+    #[test]
+    fn duplicate_keys_fail_the_baseline() {
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "id"
+    entity_path: "id"
+  - file_column: "name"
+    entity_path: "name"
+"#,
+        )
+        .unwrap();
+        let header = vec!["id".to_string(), "name".to_string()];
+        let path = std::env::temp_dir().join("sw-sync-cli-duplicate-baseline-keys.csv");
+        std::fs::write(
+            &path,
+            "id;name
+a;Shirt
+b;Shoe
+a;Shirt
+",
+        )
+        .unwrap();
+
+        let error = Baseline::read(&path, b';', &profile, &header).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("the ids of the baseline {path:?} aren't unique (1 duplicates): a")
+        );
+    }
+}
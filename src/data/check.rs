@@ -0,0 +1,642 @@
+//! Readiness check of a profile against the configured shop (`check`),
+//! e.g. as a gate of a release pipeline before a new feed is enabled
+
+use crate::api::{SwClient, DEFAULT_CURRENCY};
+use crate::cli::{FileFormat, SyncMode};
+use crate::config_file::{EntityPathMapping, Lookup, Mapping, Profile, UnknownIsoCode};
+use crate::data::associations::{get_associations, resolve_id_lists};
+use crate::data::import::is_blank_row;
+use crate::data::lookup::{get_lookup_key, search_existing_ids, LookupMatch};
+use crate::data::transform::get_mapping_cell;
+use crate::data::validate::{
+    get_invalid_associations, validate_paths_for_entity, validate_script_keys,
+};
+use anyhow::Context;
+use csv::StringRecord;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Maximum amount of listed values in the messages of the report
+const MAX_LISTED_VALUES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Passed,
+    Warning,
+    Failed,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ReadinessReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl ReadinessReport {
+    fn add(&mut self, name: impl Into<String>, status: CheckStatus, message: impl Into<String>) {
+        self.checks.push(CheckResult {
+            name: name.into(),
+            status,
+            message: message.into(),
+        });
+    }
+
+    /// Ready if no check failed, warnings are only listed
+    pub fn is_ready(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Failed)
+    }
+}
+
+impl Display for ReadinessReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Passed => "passed",
+                CheckStatus::Warning => "warning",
+                CheckStatus::Failed => "FAILED",
+            };
+            writeln!(f, "[{status}] {}: {}", check.name, check.message)?;
+        }
+
+        let count = |status| self.checks.iter().filter(|c| c.status == status).count();
+        write!(
+            f,
+            "{} ({} passed, {} warnings, {} failed)",
+            if self.is_ready() {
+                "Ready"
+            } else {
+                "Not ready"
+            },
+            count(CheckStatus::Passed),
+            count(CheckStatus::Warning),
+            count(CheckStatus::Failed)
+        )
+    }
+}
+
+/// Check the profile against the shop: the paths of the mappings, the lookups
+/// (resolving the values of the first `rows` rows of the `sample` file), the referenced
+/// languages and currencies and the privileges of the integration needed for the `mode`
+pub fn check_profile(
+    profile: &mut Profile,
+    sw_client: &dyn SwClient,
+    mode: SyncMode,
+    sample: Option<&Path>,
+    format: FileFormat,
+    rows: usize,
+) -> anyhow::Result<ReadinessReport> {
+    let mut report = ReadinessReport::default();
+
+    let api_schema = sw_client.entity_schema()?;
+    resolve_id_lists(profile, &api_schema);
+    let associations = get_associations(profile, &api_schema);
+    let paths = validate_paths_for_entity(&profile.entity, &profile.mappings, &api_schema)
+        .and_then(|()| validate_script_keys(profile));
+    match paths {
+        Ok(()) => report.add(
+            "paths",
+            CheckStatus::Passed,
+            format!(
+                "{} mappings and {} associations exist in the entity schema",
+                profile.mappings.len(),
+                associations.len()
+            ),
+        ),
+        Err(e) => report.add("paths", CheckStatus::Failed, format!("{e:#}")),
+    }
+    // only used on export, where the API ignores unknown associations
+    let invalid_associations = get_invalid_associations(
+        &profile.entity,
+        &associations,
+        &profile.mappings,
+        &api_schema,
+    );
+    if !invalid_associations.is_empty() {
+        report.add(
+            "associations",
+            CheckStatus::Warning,
+            format!("{invalid_associations:?} don't exist in the entity schema, they are ignored on export"),
+        );
+    }
+
+    let sample_values = match sample {
+        Some(path) => read_sample_values(profile, path, format, rows)?,
+        None => HashMap::new(),
+    };
+    for (file_column, lookup) in get_lookups(profile) {
+        let name = format!("lookup of '{file_column}'");
+        let has_field = api_schema
+            .get(&lookup.entity)
+            .and_then(|e| e.pointer(&format!("/properties/{}", lookup.field)))
+            .is_some();
+        if !has_field {
+            let message = format!(
+                "{}.{} not found in the entity schema",
+                lookup.entity, lookup.field
+            );
+            report.add(name, CheckStatus::Failed, message);
+            continue;
+        }
+
+        let Some(values) = sample_values.get(file_column) else {
+            report.add(
+                name,
+                CheckStatus::Passed,
+                format!("{}.{} exists", lookup.entity, lookup.field),
+            );
+            continue;
+        };
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+        let ids = match search_existing_ids(lookup, &values, sw_client) {
+            Ok(ids) => ids,
+            Err(e) => {
+                report.add(name, CheckStatus::Failed, format!("{e:#}"));
+                continue;
+            }
+        };
+
+        let unresolved: Vec<&str> = values
+            .iter()
+            .copied()
+            .filter(|value| !ids.contains_key(&get_lookup_key(value)))
+            .collect();
+        let ambiguous: Vec<&str> = values
+            .into_iter()
+            .filter(|value| {
+                matches!(
+                    ids.get(&get_lookup_key(value)),
+                    Some(LookupMatch::Ambiguous(_))
+                )
+            })
+            .collect();
+        if !ambiguous.is_empty() {
+            let message = format!(
+                "{} sampled values match several records, their rows will fail: {}",
+                ambiguous.len(),
+                list_values(&ambiguous)
+            );
+            report.add(name, CheckStatus::Warning, message);
+            continue;
+        }
+        let (status, message) = match (unresolved.len(), lookup.create_missing) {
+            (0, _) => (
+                CheckStatus::Passed,
+                format!("all {} sampled values were found", ids.len()),
+            ),
+            (count, true) => (
+                CheckStatus::Passed,
+                format!(
+                    "{count} sampled values will be created: {}",
+                    list_values(&unresolved)
+                ),
+            ),
+            (count, false) => (
+                CheckStatus::Warning,
+                format!(
+                    "{count} sampled values can't be resolved: {}",
+                    list_values(&unresolved)
+                ),
+            ),
+        };
+        report.add(name, status, message);
+    }
+
+    let languages = get_referenced_languages(profile);
+    if !languages.is_empty() {
+        let available = sw_client.get_languages()?.data;
+        check_iso_codes(&mut report, "languages", profile, &languages, &available);
+    }
+    let currencies = get_referenced_currencies(profile);
+    if !currencies.is_empty() {
+        let available = sw_client.get_currencies()?.data;
+        check_iso_codes(&mut report, "currencies", profile, &currencies, &available);
+    }
+
+    match sw_client.get_total(&profile.entity, &[]) {
+        Ok(total) => report.add(
+            "read access",
+            CheckStatus::Passed,
+            format!("{total} {} entities can be read", profile.entity),
+        ),
+        Err(e) => report.add("read access", CheckStatus::Failed, e.to_string()),
+    }
+
+    let required = get_required_privileges(profile, mode);
+    match sw_client.get_integration_privileges() {
+        Ok(None) => report.add(
+            "privileges",
+            CheckStatus::Passed,
+            "the integration is an administrator",
+        ),
+        Ok(Some(privileges)) => {
+            let missing: Vec<&str> = required
+                .iter()
+                .filter(|privilege| !privileges.contains(*privilege))
+                .map(String::as_str)
+                .collect();
+            if missing.is_empty() {
+                report.add(
+                    "privileges",
+                    CheckStatus::Passed,
+                    format!("the integration has all {} needed privileges", required.len()),
+                );
+            } else {
+                report.add(
+                    "privileges",
+                    CheckStatus::Failed,
+                    format!("the integration misses the privileges {missing:?}"),
+                );
+            }
+        }
+        Err(e) => report.add(
+            "privileges",
+            CheckStatus::Warning,
+            format!("the privileges of the integration can't be read (this needs the privilege 'integration:read'): {e}"),
+        ),
+    }
+
+    Ok(report)
+}
+
+/// Lookup mappings by their file column
+fn get_lookups(profile: &Profile) -> Vec<(&str, &Lookup)> {
+    profile
+        .mappings
+        .iter()
+        .filter_map(|mapping| match mapping {
+            Mapping::ByPath(by_path) => by_path
+                .lookup
+                .as_ref()
+                .map(|lookup| (by_path.file_column.as_str(), lookup)),
+            Mapping::ByScript(_) => None,
+        })
+        .collect()
+}
+
+/// Distinct values of the lookup columns in the first rows of the sample file, by file column
+fn read_sample_values(
+    profile: &Profile,
+    path: &Path,
+    format: FileFormat,
+    rows: usize,
+) -> anyhow::Result<HashMap<String, BTreeSet<String>>> {
+    let delimiter = format
+        .delimiter()
+        .context("sample files can only be read as csv or tsv")?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .comment(profile.get_comment_byte()?)
+        .from_path(path)
+        .with_context(|| format!("failed to read the sample file {path:?}"))?;
+    let headers = reader.headers()?.clone();
+
+    // the lookups of which the sample has all columns read on import (e.g. every column of a `concat`)
+    let lookup_mappings: Vec<&EntityPathMapping> = profile
+        .mappings
+        .iter()
+        .filter_map(|mapping| match mapping {
+            Mapping::ByPath(by_path)
+                if by_path.lookup.is_some()
+                    && !by_path.has_lookup_ids(&headers)
+                    && mapping
+                        .get_import_columns()
+                        .iter()
+                        .all(|column| headers.iter().any(|header| header == *column)) =>
+            {
+                Some(by_path.as_ref())
+            }
+            Mapping::ByPath(_) | Mapping::ByScript(_) => None,
+        })
+        .collect();
+
+    let mut values: HashMap<String, BTreeSet<String>> = lookup_mappings
+        .iter()
+        .map(|mapping| (mapping.file_column.clone(), BTreeSet::new()))
+        .collect();
+    for record in reader.byte_records().take(rows) {
+        let record = record?;
+        if profile.skip_blank_rows && is_blank_row(&record) {
+            continue;
+        }
+        let record = StringRecord::from_byte_record(record)?;
+        for mapping in &lookup_mappings {
+            let cell = get_mapping_cell(mapping, &headers, &record)?;
+            let value = cell.trim();
+            if !value.is_empty() && !value.eq_ignore_ascii_case("null") {
+                values
+                    .entry(mapping.file_column.clone())
+                    .or_default()
+                    .insert(value.to_string());
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+/// Locale codes of the translations written by the mappings and of the scripts (`get_language_by_iso("de-DE")`)
+fn get_referenced_languages(profile: &Profile) -> BTreeSet<String> {
+    let mut languages: BTreeSet<String> = profile
+        .mappings
+        .iter()
+        .filter_map(|mapping| match mapping {
+            Mapping::ByPath(by_path) => by_path.language.clone(),
+            Mapping::ByScript(_) => None,
+        })
+        .collect();
+    languages.extend(get_script_literals(profile, "get_language_by_iso"));
+
+    languages
+}
+
+/// Currency codes of the mappings and the scripts (`get_currency_by_iso("EUR")`)
+fn get_referenced_currencies(profile: &Profile) -> BTreeSet<String> {
+    let mut currencies: BTreeSet<String> = profile
+        .mappings
+        .iter()
+        .filter_map(|mapping| match mapping {
+            Mapping::ByPath(by_path) => by_path.currency.clone(),
+            Mapping::ByScript(_) => None,
+        })
+        .filter(|currency| currency != DEFAULT_CURRENCY)
+        .collect();
+    currencies.extend(get_script_literals(profile, "get_currency_by_iso"));
+
+    currencies
+}
+
+/// String literals passed to a script function, codes of variables can't be checked
+fn get_script_literals(profile: &Profile, function: &str) -> BTreeSet<String> {
+    let call = format!("{function}(\"");
+    [&profile.serialize_script, &profile.deserialize_script]
+        .into_iter()
+        .flat_map(|script| {
+            script.match_indices(&call).filter_map(|(index, _)| {
+                script[index + call.len()..]
+                    .split_once('"')
+                    .map(|(literal, _)| literal.to_string())
+            })
+        })
+        .collect()
+}
+
+/// Check that the codes (after the `iso_codes.mapping` of the profile) exist in the shop
+fn check_iso_codes(
+    report: &mut ReadinessReport,
+    name: &str,
+    profile: &Profile,
+    codes: &BTreeSet<String>,
+    available: &HashMap<String, String>,
+) {
+    let missing: Vec<&str> = codes
+        .iter()
+        .map(|code| {
+            profile
+                .iso_codes
+                .mapping
+                .get(code)
+                .map_or(code.as_str(), String::as_str)
+        })
+        .filter(|code| !available.contains_key(*code))
+        .collect();
+    if missing.is_empty() {
+        report.add(
+            name,
+            CheckStatus::Passed,
+            format!("{codes:?} exist in the shop"),
+        );
+        return;
+    }
+
+    let mut available: Vec<&String> = available.keys().collect();
+    available.sort_unstable();
+    let message = format!("{missing:?} don't exist in the shop, available: {available:?}");
+    match profile.iso_codes.unknown {
+        // rows fall back to the default of the shop
+        UnknownIsoCode::Default | UnknownIsoCode::Empty => {
+            report.add(name, CheckStatus::Warning, message)
+        }
+        UnknownIsoCode::Error => report.add(name, CheckStatus::Failed, message),
+    }
+}
+
+/// Privileges (`entity:operation`) of the integration, which the sync in the mode needs
+fn get_required_privileges(profile: &Profile, mode: SyncMode) -> BTreeSet<String> {
+    let entity = &profile.entity;
+    let mut privileges = BTreeSet::from([format!("{entity}:read")]);
+    match mode {
+        SyncMode::Import => {
+            privileges.insert(format!("{entity}:create"));
+            privileges.insert(format!("{entity}:update"));
+            if profile.action_column.is_some() {
+                privileges.insert(format!("{entity}:delete"));
+            }
+        }
+        SyncMode::Delete => {
+            privileges.insert(format!("{entity}:delete"));
+        }
+        SyncMode::Export => {}
+    }
+
+    for (_, lookup) in get_lookups(profile) {
+        privileges.insert(format!("{}:read", lookup.entity));
+        if lookup.create_missing && mode == SyncMode::Import {
+            privileges.insert(format!("{}:create", lookup.entity));
+        }
+    }
+
+    privileges
+}
+
+fn list_values(values: &[&str]) -> String {
+    let mut list = format!("{:?}", &values[..values.len().min(MAX_LISTED_VALUES)]);
+    if values.len() > MAX_LISTED_VALUES {
+        list.push_str(&format!(" and {} more", values.len() - MAX_LISTED_VALUES));
+    }
+
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_profile() -> Profile {
+        serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "name"
+    entity_path: "name"
+  - file_column: "name (en)"
+    entity_path: "name"
+    language: "en-GB"
+  - file_column: "manufacturer"
+    entity_path: "manufacturerId"
+    lookup:
+      entity: "product_manufacturer"
+      field: "name"
+      create_missing: true
+  - file_column: "tax"
+    entity_path: "taxId"
+    lookup:
+      entity: "tax"
+      field: "name"
+deserialize_script: |
+  entity.languageId = get_language_by_iso("de-DE");
+  entity.currencyId = get_currency_by_iso("USD");
+  entity.other = get_currency_by_iso(row.currency);
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn required_privileges() {
+        let profile = get_test_profile();
+
+        assert_eq!(
+            get_required_privileges(&profile, SyncMode::Import),
+            BTreeSet::from(
+                [
+                    "product:read",
+                    "product:create",
+                    "product:update",
+                    "product_manufacturer:read",
+                    "product_manufacturer:create",
+                    "tax:read",
+                ]
+                .map(str::to_string)
+            )
+        );
+        assert_eq!(
+            get_required_privileges(&profile, SyncMode::Delete),
+            BTreeSet::from(
+                [
+                    "product:read",
+                    "product:delete",
+                    "product_manufacturer:read",
+                    "tax:read",
+                ]
+                .map(str::to_string)
+            )
+        );
+
+        let profile = Profile {
+            action_column: Some("action".to_string()),
+            ..profile
+        };
+        assert!(get_required_privileges(&profile, SyncMode::Import).contains("product:delete"));
+    }
+
+    #[test]
+    fn sample_values_of_concat_lookups() {
+        let mut profile = get_test_profile();
+        profile.mappings.push(
+            serde_yaml::from_str(
+                r#"
+file_column: "manufacturer (full name)"
+entity_path: "manufacturerId"
+lookup:
+  entity: "product_manufacturer"
+  field: "name"
+concat:
+  columns: ["brand", "suffix"]
+"#,
+            )
+            .unwrap(),
+        );
+        let path = std::env::temp_dir().join("sw-sync-cli-check-sample-values.csv");
+        std::fs::write(
+            &path,
+            "name;manufacturer;brand;suffix\nCap;shopware;Acme;Inc.\nShirt;NULL;Acme;\n",
+        )
+        .unwrap();
+
+        let values = read_sample_values(&profile, &path, FileFormat::Csv, 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            values,
+            HashMap::from([
+                (
+                    "manufacturer".to_string(),
+                    BTreeSet::from(["shopware".to_string()])
+                ),
+                (
+                    "manufacturer (full name)".to_string(),
+                    BTreeSet::from(["Acme".to_string(), "Acme Inc.".to_string()])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn referenced_iso_codes() {
+        let mut profile = get_test_profile();
+
+        assert_eq!(
+            get_referenced_languages(&profile),
+            BTreeSet::from(["de-DE".to_string(), "en-GB".to_string()])
+        );
+        assert_eq!(
+            get_referenced_currencies(&profile),
+            BTreeSet::from(["USD".to_string()])
+        );
+
+        profile.iso_codes.mapping = [("en-GB".to_string(), "en-US".to_string())].into();
+        profile.iso_codes.unknown = UnknownIsoCode::Error;
+        let available = HashMap::from([
+            ("de-DE".to_string(), "a".to_string()),
+            ("en-GB".to_string(), "b".to_string()),
+        ]);
+        let mut report = ReadinessReport::default();
+        check_iso_codes(
+            &mut report,
+            "languages",
+            &profile,
+            &get_referenced_languages(&profile),
+            &available,
+        );
+        assert_eq!(report.checks[0].status, CheckStatus::Failed);
+        assert!(report.checks[0]
+            .message
+            .contains(r#"["en-US"] don't exist"#));
+        assert!(!report.is_ready());
+
+        profile.iso_codes.unknown = UnknownIsoCode::Default;
+        check_iso_codes(
+            &mut report,
+            "languages",
+            &profile,
+            &BTreeSet::from(["en-GB".to_string()]),
+            &available,
+        );
+        assert_eq!(report.checks[1].status, CheckStatus::Warning);
+    }
+
+    #[test]
+    fn report_output() {
+        let mut report = ReadinessReport::default();
+        report.add("paths", CheckStatus::Passed, "ok");
+        report.add("privileges", CheckStatus::Warning, "can't be read");
+        assert!(report.is_ready());
+
+        assert_eq!(
+            report.to_string(),
+            "[passed] paths: ok\n[warning] privileges: can't be read\nReady (1 passed, 1 warnings, 0 failed)"
+        );
+        assert_eq!(
+            list_values(&["a"; 12]),
+            r#"["a", "a", "a", "a", "a", "a", "a", "a", "a", "a"] and 2 more"#
+        );
+    }
+}
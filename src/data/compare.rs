@@ -0,0 +1,411 @@
+//! Differences between the entities of two shops (`compare`), e.g. to confirm the parity after a staging refresh
+
+use crate::api::filter::{Criteria, CriteriaSorting, CriteriaSortingOrder};
+use crate::api::{Entity, SwClient};
+use crate::config_file::{EntityPathMapping, Mapping, Profile};
+use crate::data::transform::{is_wildcard_path, EntityPath};
+use anyhow::Context;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// Maximum amount of entities listed per kind of difference, the counts include all of them
+const MAX_LISTED: usize = 50;
+
+/// Values of the compared fields of every entity of a shop, by the value of the key field
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// amount of entities in the shop
+    pub count: usize,
+    pub entities: BTreeMap<String, Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ShopComparison {
+    pub source_count: usize,
+    pub target_count: usize,
+    pub only_in_source: Vec<String>,
+    pub only_in_target: Vec<String>,
+    /// differing fields of the entities existing in both shops, by key
+    pub changed: BTreeMap<String, Vec<FieldChange>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldChange {
+    /// file column of the mapping
+    pub field: String,
+    pub source: serde_json::Value,
+    pub target: serde_json::Value,
+}
+
+impl ShopComparison {
+    pub fn is_empty(&self) -> bool {
+        self.source_count == self.target_count
+            && self.only_in_source.is_empty()
+            && self.only_in_target.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+/// Entity path mappings of the profile which are compared, mappings of the scripts are left out
+pub fn get_compared_mappings<'a>(
+    profile: &'a Profile,
+    ignore: &[String],
+) -> Vec<&'a EntityPathMapping> {
+    profile
+        .mappings
+        .iter()
+        .filter_map(|mapping| match mapping {
+            Mapping::ByPath(mapping) => Some(mapping.as_ref()),
+            Mapping::ByScript(_) => None,
+        })
+        .filter(|mapping| {
+            !ignore.contains(&mapping.entity_path) && !ignore.contains(&mapping.file_column)
+        })
+        .collect()
+}
+
+/// Read all entities of the profile (with its filter) from the shop, sorted by id
+pub fn fetch_snapshot(
+    profile: &Profile,
+    associations: &HashSet<String>,
+    key: &str,
+    mappings: &[&EntityPathMapping],
+    sw_client: &dyn SwClient,
+) -> anyhow::Result<Snapshot> {
+    let mut snapshot = Snapshot::default();
+    let mut duplicate_keys = BTreeSet::new();
+    let mut page = 1;
+    loop {
+        let mut criteria = Criteria {
+            page,
+            limit: Some(Criteria::MAX_LIMIT),
+            sort: vec![CriteriaSorting {
+                field: "id".to_string(),
+                order: CriteriaSortingOrder::Ascending,
+            }],
+            filter: profile.filter.clone(),
+            ..Default::default()
+        };
+        for association in associations {
+            criteria.add_association(association);
+        }
+
+        let response = sw_client.list(&profile.entity, &criteria)?;
+        let page_size = response.data.len();
+        for entity in response.data {
+            let key_value = entity
+                .get_by_path(key)
+                .filter(|value| !value.is_null())
+                .with_context(|| format!("an entity has no value for the key '{key}'"))?;
+            let key_value = match key_value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+
+            snapshot.count += 1;
+            // otherwise the values of one of the entities would be compared at random
+            match snapshot.entities.entry(key_value) {
+                Entry::Vacant(entry) => {
+                    entry.insert(get_compared_values(&entity, mappings));
+                }
+                Entry::Occupied(entry) => {
+                    duplicate_keys.insert(entry.key().clone());
+                }
+            }
+        }
+
+        if page_size < Criteria::MAX_LIMIT {
+            break;
+        }
+        page += 1;
+    }
+
+    if !duplicate_keys.is_empty() {
+        let listed: Vec<&str> = duplicate_keys
+            .iter()
+            .take(MAX_LISTED)
+            .map(String::as_str)
+            .collect();
+        anyhow::bail!(
+            "the values of the key '{key}' aren't unique ({} duplicates): {}{}; compare by a unique field with --key",
+            duplicate_keys.len(),
+            listed.join(", "),
+            if duplicate_keys.len() > MAX_LISTED { ", ..." } else { "" }
+        );
+    }
+
+    Ok(snapshot)
+}
+
+/// Values of the mappings, like they are exported. Fields which don't exist are null
+fn get_compared_values(entity: &Entity, mappings: &[&EntityPathMapping]) -> Vec<serde_json::Value> {
+    mappings
+        .iter()
+        .map(|mapping| {
+            if is_wildcard_path(&mapping.entity_path) {
+                let values = entity.get_all_by_path(&mapping.entity_path);
+                return values.map_or(serde_json::Value::Null, |values| {
+                    values.into_iter().cloned().collect()
+                });
+            }
+
+            let value = match &mapping.language {
+                Some(language) => entity.get_translated_by_path(&mapping.entity_path, language),
+                None => entity.get_by_path(&mapping.entity_path),
+            };
+            value.cloned().unwrap_or_default()
+        })
+        .collect()
+}
+
+pub fn compare_snapshots(
+    mappings: &[&EntityPathMapping],
+    source: &Snapshot,
+    target: &Snapshot,
+) -> ShopComparison {
+    let mut comparison = ShopComparison {
+        source_count: source.count,
+        target_count: target.count,
+        ..Default::default()
+    };
+
+    for (key, source_values) in &source.entities {
+        let Some(target_values) = target.entities.get(key) else {
+            comparison.only_in_source.push(key.clone());
+            continue;
+        };
+
+        let changes: Vec<FieldChange> = mappings
+            .iter()
+            .zip(source_values.iter().zip(target_values))
+            .filter(|(_, (source, target))| !is_same_value(source, target))
+            .map(|(mapping, (source, target))| FieldChange {
+                field: mapping.file_column.clone(),
+                source: source.clone(),
+                target: target.clone(),
+            })
+            .collect();
+        if !changes.is_empty() {
+            comparison.changed.insert(key.clone(), changes);
+        }
+    }
+    comparison.only_in_target = target
+        .entities
+        .keys()
+        .filter(|key| !source.entities.contains_key(*key))
+        .cloned()
+        .collect();
+
+    comparison
+}
+
+/// Numbers are compared by their value, so `10` and `10.0` are the same
+fn is_same_value(a: &serde_json::Value, b: &serde_json::Value) -> bool {
+    match (a, b) {
+        (serde_json::Value::Number(a), serde_json::Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (serde_json::Value::Array(a), serde_json::Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| is_same_value(a, b))
+        }
+        (serde_json::Value::Object(a), serde_json::Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| is_same_value(a, b)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+impl Display for ShopComparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Entities in the source shop: {}, in the target shop: {}",
+            self.source_count, self.target_count
+        )?;
+
+        let list = |f: &mut Formatter<'_>, title: &str, keys: &[String]| -> std::fmt::Result {
+            if keys.is_empty() {
+                return Ok(());
+            }
+            writeln!(f, "{title} ({}):", keys.len())?;
+            for key in keys.iter().take(MAX_LISTED) {
+                writeln!(f, "  {key}")?;
+            }
+            if keys.len() > MAX_LISTED {
+                writeln!(f, "  ... and {} more", keys.len() - MAX_LISTED)?;
+            }
+            Ok(())
+        };
+        list(f, "Only in the source shop", &self.only_in_source)?;
+        list(f, "Only in the target shop", &self.only_in_target)?;
+
+        if !self.changed.is_empty() {
+            writeln!(f, "Different in both shops ({}):", self.changed.len())?;
+            for (key, changes) in self.changed.iter().take(MAX_LISTED) {
+                writeln!(f, "  {key}")?;
+                for change in changes {
+                    writeln!(
+                        f,
+                        "    {}: {} (source) != {} (target)",
+                        change.field, change.source, change.target
+                    )?;
+                }
+            }
+            if self.changed.len() > MAX_LISTED {
+                writeln!(f, "  ... and {} more", self.changed.len() - MAX_LISTED)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeShop;
+    use serde_json::json;
+    use std::path::Path;
+
+    #[test]
+    fn compare_shop_snapshots() {
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "id"
+    entity_path: "id"
+  - file_column: "product number"
+    entity_path: "productNumber"
+  - file_column: "stock"
+    entity_path: "stock"
+  - file_column: "manufacturer"
+    entity_path: "manufacturer?.name"
+  - file_column: "category names"
+    entity_path: "categories[*].name"
+  - file_column: "script value"
+    key: "script_value"
+"#,
+        )
+        .unwrap();
+        let mappings = get_compared_mappings(&profile, &["id".to_string()]);
+        assert_eq!(mappings.len(), 4);
+
+        let entities = |entities: Vec<serde_json::Value>| {
+            let mut snapshot = Snapshot::default();
+            for entity in entities {
+                let entity: Entity = serde_json::from_value(entity).unwrap();
+                snapshot.count += 1;
+                snapshot.entities.insert(
+                    entity["productNumber"].as_str().unwrap().to_string(),
+                    get_compared_values(&entity, &mappings),
+                );
+            }
+            snapshot
+        };
+        let source = entities(vec![
+            json!({"id": "a", "productNumber": "SW-1", "stock": 10, "manufacturer": null, "categories": [{"name": "Shirts"}]}),
+            json!({"id": "b", "productNumber": "SW-2", "stock": 1.0, "manufacturer": {"name": "shopware"}, "categories": []}),
+            json!({"id": "c", "productNumber": "SW-3", "stock": 0, "manufacturer": null, "categories": []}),
+        ]);
+        let target = entities(vec![
+            json!({"id": "x", "productNumber": "SW-1", "stock": 12, "manufacturer": {"name": "shopware"}, "categories": [{"name": "Shirts"}]}),
+            json!({"id": "y", "productNumber": "SW-2", "stock": 1, "manufacturer": {"name": "shopware"}, "categories": []}),
+            json!({"id": "z", "productNumber": "SW-4", "stock": 0, "manufacturer": null, "categories": []}),
+        ]);
+
+        let comparison = compare_snapshots(&mappings, &source, &target);
+        assert_eq!(
+            comparison,
+            ShopComparison {
+                source_count: 3,
+                target_count: 3,
+                only_in_source: vec!["SW-3".to_string()],
+                only_in_target: vec!["SW-4".to_string()],
+                changed: BTreeMap::from([(
+                    "SW-1".to_string(),
+                    vec![
+                        FieldChange {
+                            field: "stock".to_string(),
+                            source: json!(10),
+                            target: json!(12),
+                        },
+                        FieldChange {
+                            field: "manufacturer".to_string(),
+                            source: json!(null),
+                            target: json!("shopware"),
+                        },
+                    ]
+                )]),
+            }
+        );
+        assert!(!comparison.is_empty());
+        assert_eq!(
+            comparison.to_string(),
+            "Entities in the source shop: 3, in the target shop: 3\n\
+             Only in the source shop (1):\n  SW-3\n\
+             Only in the target shop (1):\n  SW-4\n\
+             Different in both shops (1):\n  SW-1\n    stock: 10 (source) != 12 (target)\n    \
+             manufacturer: null (source) != \"shopware\" (target)\n"
+        );
+
+        assert!(compare_snapshots(&mappings, &source, &source).is_empty());
+    }
+
+    #[test]
+    fn fetch_snapshots_of_two_shops() {
+        let source = FakeShop::new();
+        let target = FakeShop::with_entities(json!({
+            "product": [
+                {"id": "b", "productNumber": "SW-1", "stock": 10.0, "name": "T-Shirt"},
+                {"id": "c", "productNumber": "SW-2", "stock": 3, "name": "Hoodie"},
+            ]
+        }));
+        let profile = Profile::read_profile(Path::new("default_product")).unwrap();
+        let mappings = get_compared_mappings(&profile, &[]);
+        let mappings: Vec<_> = mappings
+            .into_iter()
+            .filter(|m| ["productNumber", "stock", "name"].contains(&m.entity_path.as_str()))
+            .collect();
+
+        let fetch = |shop: &FakeShop| {
+            fetch_snapshot(&profile, &HashSet::new(), "productNumber", &mappings, shop).unwrap()
+        };
+        let comparison = compare_snapshots(&mappings, &fetch(&source), &fetch(&target));
+        assert_eq!(comparison.source_count, 2);
+        assert_eq!(comparison.target_count, 2);
+        assert!(comparison.only_in_source.is_empty() && comparison.only_in_target.is_empty());
+        assert_eq!(
+            comparison.changed,
+            BTreeMap::from([(
+                "SW-2".to_string(),
+                vec![FieldChange {
+                    field: "stock".to_string(),
+                    source: json!(0),
+                    target: json!(3),
+                }]
+            )])
+        );
+    }
+
+    #[test]
+    fn duplicate_keys_fail_the_snapshot() {
+        let shop = FakeShop::with_entities(json!({
+            "product": [
+                {"id": "a", "productNumber": "SW-1", "stock": 1},
+                {"id": "b", "productNumber": "SW-1", "stock": 2},
+                {"id": "c", "productNumber": "SW-2", "stock": 3},
+            ]
+        }));
+        let profile = Profile::read_profile(Path::new("default_product")).unwrap();
+        let mappings = get_compared_mappings(&profile, &[]);
+
+        let error = fetch_snapshot(&profile, &HashSet::new(), "productNumber", &mappings, &shop)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the values of the key 'productNumber' aren't unique (1 duplicates): SW-1; compare by a unique field with --key"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Client-side validation of the imported values against the constraints of the OpenAPI schema
+//! of the shop (`maxLength` and `pattern` of string fields, like the pattern of ids),
+//! can be disabled with `--no-schema-constraints`
+
+use crate::api::Entity;
+use crate::config_file::{Mapping, Profile};
+use crate::data::report::RowError;
+use crate::data::transform::{get_translation_entity_path, is_wildcard_path, EntityPath};
+use regex::Regex;
+
+#[derive(Debug)]
+pub struct SchemaConstraint {
+    /// path of the value in the deserialized entity
+    entity_path: String,
+    max_length: Option<usize>,
+    pattern: Option<Regex>,
+}
+
+/// Collect the constraints of the fields written by the entity path mappings from the OpenAPI schema
+/// (the entity schema has none), the associations of the paths are followed in the entity schema.
+/// Fields without constraints (or missing in the schemas) are left out
+pub fn get_schema_constraints(
+    profile: &Profile,
+    api_schema: &Entity,
+    openapi_schema: &serde_json::Value,
+) -> Vec<SchemaConstraint> {
+    let mut constraints = vec![];
+    for mapping in &profile.mappings {
+        let Mapping::ByPath(mapping) = mapping else {
+            continue;
+        };
+        if is_wildcard_path(&mapping.entity_path) || mapping.id_list_association.is_some() {
+            continue;
+        }
+        let Some(field) = get_field_schema(
+            &profile.entity,
+            &mapping.entity_path,
+            api_schema,
+            openapi_schema,
+        ) else {
+            continue;
+        };
+
+        let max_length = field
+            .get("maxLength")
+            .and_then(serde_json::Value::as_u64)
+            .map(|max_length| max_length as usize);
+        let pattern = field
+            .get("pattern")
+            .and_then(serde_json::Value::as_str)
+            .and_then(|pattern| match Regex::new(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    println!(
+                        "Warning: the pattern of the field '{}' isn't checked, it's not supported: {e}",
+                        mapping.entity_path
+                    );
+                    None
+                }
+            });
+        if max_length.is_none() && pattern.is_none() {
+            continue;
+        }
+
+        let entity_path = match &mapping.language {
+            Some(language) => get_translation_entity_path(&mapping.entity_path, language),
+            None => mapping.entity_path.clone(),
+        };
+        constraints.push(SchemaConstraint {
+            entity_path,
+            max_length,
+            pattern,
+        });
+    }
+
+    constraints
+}
+
+/// OpenAPI schema of the field an entity path points to, following the associations
+fn get_field_schema<'a>(
+    entity: &str,
+    entity_path: &str,
+    api_schema: &Entity,
+    openapi_schema: &'a serde_json::Value,
+) -> Option<&'a serde_json::Value> {
+    let mut entity = entity;
+    let mut parts = entity_path.split('.').peekable();
+    while let Some(part) = parts.next() {
+        let field = part.trim_end_matches('?');
+        if parts.peek().is_none() {
+            return get_openapi_properties(openapi_schema, entity)?.get(field);
+        }
+        entity = api_schema
+            .get(entity)?
+            .get("properties")?
+            .get(field)?
+            .get("entity")?
+            .as_str()?;
+    }
+
+    None
+}
+
+/// Properties of the OpenAPI schema of an entity,
+/// which is named in PascalCase (`product_manufacturer` is `ProductManufacturer`)
+fn get_openapi_properties<'a>(
+    openapi_schema: &'a serde_json::Value,
+    entity: &str,
+) -> Option<&'a serde_json::Map<String, serde_json::Value>> {
+    let schema_name: String = entity
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    let schema = openapi_schema
+        .get("components")?
+        .get("schemas")?
+        .get(schema_name)?;
+
+    match schema.get("properties") {
+        Some(properties) => properties.as_object(),
+        // schemas composed of several parts
+        None => schema
+            .get("allOf")?
+            .as_array()?
+            .iter()
+            .find_map(|part| part.get("properties")?.as_object()),
+    }
+}
+
+/// First violated constraint of the deserialized row, only string values are checked
+pub fn check_schema_constraints(
+    constraints: &[SchemaConstraint],
+    entity: &Entity,
+    row_index: usize,
+) -> Option<RowError> {
+    for constraint in constraints {
+        let Some(serde_json::Value::String(value)) = entity.get_by_path(&constraint.entity_path)
+        else {
+            continue;
+        };
+
+        let message = match (constraint.max_length, &constraint.pattern) {
+            (Some(max_length), _) if value.chars().count() > max_length => format!(
+                "value is longer than the maximum of {max_length} characters of the API schema"
+            ),
+            (_, Some(pattern)) if !pattern.is_match(value) => {
+                format!("value doesn't match the pattern '{pattern}' of the API schema")
+            }
+            _ => continue,
+        };
+
+        return Some(RowError {
+            row_index,
+            pointer: format!(
+                "/{}",
+                constraint.entity_path.replace('?', "").replace('.', "/")
+            ),
+            message,
+            value: Some(serde_json::to_string(value).unwrap_or_default()),
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_file::EntityPathMapping;
+    use serde_json::json;
+
+    #[test]
+    fn check_constraints_of_schema() {
+        let api_schema = json!({
+            "product": { "properties": {
+                "manufacturer": { "type": "association", "entity": "product_manufacturer" },
+            } },
+        });
+        let openapi_schema = json!({ "components": { "schemas": {
+            "Product": { "properties": {
+                "productNumber": { "type": "string", "maxLength": 6 },
+                "ean": { "type": "string", "pattern": "^[0-9]+$" },
+                "stock": { "type": "integer", "format": "int64" },
+            } },
+            "ProductManufacturer": { "allOf": [
+                { "required": ["name"] },
+                { "properties": { "name": { "type": "string", "maxLength": 3 } } },
+            ] },
+        } } });
+        let profile = Profile {
+            entity: "product".to_string(),
+            mappings: vec![
+                mapping("productNumber", None),
+                mapping("ean", None),
+                mapping("stock", None),
+                mapping("manufacturer?.name", Some("de-DE")),
+            ],
+            ..Default::default()
+        };
+
+        let constraints =
+            get_schema_constraints(&profile, api_schema.as_object().unwrap(), &openapi_schema);
+        assert_eq!(constraints.len(), 3);
+        assert_eq!(
+            constraints[2].entity_path,
+            "manufacturer?.translations.de-DE.name"
+        );
+
+        let check = |entity: serde_json::Value| {
+            check_schema_constraints(&constraints, entity.as_object().unwrap(), 4)
+        };
+        assert_eq!(
+            check(json!({ "productNumber": "SW1000", "ean": "4006381", "stock": 12345678 })),
+            None
+        );
+        assert_eq!(
+            check(json!({ "productNumber": "SW10000" })),
+            Some(RowError {
+                row_index: 4,
+                pointer: "/productNumber".to_string(),
+                message: "value is longer than the maximum of 6 characters of the API schema"
+                    .to_string(),
+                value: Some("\"SW10000\"".to_string()),
+            })
+        );
+        assert_eq!(
+            check(json!({ "ean": "40-06" })).map(|e| e.message),
+            Some("value doesn't match the pattern '^[0-9]+$' of the API schema".to_string())
+        );
+        assert_eq!(
+            check(json!({ "manufacturer": { "translations": { "de-DE": { "name": "Käse" } } } }))
+                .map(|e| e.pointer),
+            Some("/manufacturer/translations/de-DE/name".to_string())
+        );
+    }
+
+    #[test]
+    fn constraints_of_shop_schemas() {
+        let api_schema: Entity = serde_json::from_str(
+            &std::fs::read_to_string("./fixtures/entity-schema-2024-08-01.json").unwrap(),
+        )
+        .unwrap();
+        let openapi_schema: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string("./fixtures/openapi3-excerpt.json").unwrap(),
+        )
+        .unwrap();
+        let profile = Profile {
+            entity: "product".to_string(),
+            mappings: vec![
+                mapping("productNumber", None),
+                mapping("manufacturerId", None),
+                mapping("manufacturer?.mediaId", None),
+                mapping("name", Some("de-DE")),
+            ],
+            ..Default::default()
+        };
+
+        // only the ids have a pattern in the schema of the shop
+        let constraints = get_schema_constraints(&profile, &api_schema, &openapi_schema);
+        let paths: Vec<&str> = constraints.iter().map(|c| c.entity_path.as_str()).collect();
+        assert_eq!(paths, ["manufacturerId", "manufacturer?.mediaId"]);
+
+        let check = |entity: serde_json::Value| {
+            check_schema_constraints(&constraints, entity.as_object().unwrap(), 0)
+        };
+        assert_eq!(
+            check(json!({
+                "productNumber": "SW10000",
+                "manufacturerId": "0190e5c6a2d07b4e9d2b3d0c3f1a3a04",
+            })),
+            None
+        );
+        assert_eq!(
+            check(json!({ "manufacturer": { "mediaId": "logo.png" } })).map(|e| e.pointer),
+            Some("/manufacturer/mediaId".to_string())
+        );
+    }
+
+    fn mapping(entity_path: &str, language: Option<&str>) -> Mapping {
+        Mapping::ByPath(Box::new(EntityPathMapping {
+            entity_path: entity_path.to_string(),
+            language: language.map(str::to_string),
+            ..Default::default()
+        }))
+    }
+}
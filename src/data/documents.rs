@@ -0,0 +1,182 @@
+//! Download of the generated documents (invoices, delivery notes, ...) of exported orders,
+//! configured with the profile option `documents`.
+//!
+//! Every document is written into the folder as `<document type>_<document number>_<document id>.<file type>`,
+//! the id keeps documents of the same number apart (e.g. a regenerated invoice)
+//! and the paths are available in the `documentFiles` field of the order (joined by `|`)
+
+use crate::api::{Entity, SwClient};
+use crate::config_file::{DocumentOptions, Profile};
+use anyhow::Context;
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Field of the order with the paths of the downloaded documents
+const DOCUMENT_FILES_FIELD: &str = "documentFiles";
+
+/// Association needed to download the documents of an order
+pub const DOCUMENTS_ASSOCIATION: &str = "documents.documentType";
+
+/// Check the profile and create the folder of the documents
+pub fn prepare_documents_folder(profile: &Profile) -> anyhow::Result<()> {
+    let Some(options) = &profile.documents else {
+        return Ok(());
+    };
+    if profile.entity != "order" {
+        anyhow::bail!(
+            "documents can only be downloaded for orders, but the profile entity is '{}'",
+            profile.entity
+        );
+    }
+
+    std::fs::create_dir_all(&options.folder)
+        .with_context(|| format!("failed to create documents folder {:?}", options.folder))
+}
+
+/// Download the documents of the order and add their paths to the `documentFiles` field
+pub fn download_documents(
+    order: &mut Entity,
+    options: &DocumentOptions,
+    sw_client: &dyn SwClient,
+) -> anyhow::Result<()> {
+    let mut files = vec![];
+    let documents = match order.get("documents") {
+        Some(Value::Array(documents)) => documents.as_slice(),
+        _ => &[],
+    };
+
+    for document in documents {
+        if !is_requested_type(document, options) {
+            continue;
+        }
+        let (Some(id), Some(deep_link_code)) = (
+            document.get("id").and_then(Value::as_str),
+            document.get("deepLinkCode").and_then(Value::as_str),
+        ) else {
+            continue;
+        };
+
+        let path = get_document_path(document, options);
+        let content = sw_client
+            .download_document(id, deep_link_code)
+            .with_context(|| format!("failed to download document {id}"))?;
+        std::fs::write(&path, content)
+            .with_context(|| format!("failed to write document {path:?}"))?;
+
+        files.push(path.to_string_lossy().into_owned());
+    }
+
+    files.sort_unstable();
+    order.insert(
+        DOCUMENT_FILES_FIELD.to_string(),
+        Value::String(files.join("|")),
+    );
+    Ok(())
+}
+
+fn is_requested_type(document: &Value, options: &DocumentOptions) -> bool {
+    if options.types.is_empty() {
+        return true;
+    }
+
+    document
+        .pointer("/documentType/technicalName")
+        .and_then(Value::as_str)
+        .is_some_and(|technical_name| options.types.iter().any(|t| t == technical_name))
+}
+
+/// Path of the document in the folder, named by the document type, number and id.
+/// The number alone isn't unique, documents of different types or a regenerated document can share it
+fn get_document_path(document: &Value, options: &DocumentOptions) -> PathBuf {
+    let id = document
+        .get("id")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let name = match document
+        .get("documentNumber")
+        .and_then(Value::as_str)
+        .filter(|number| !number.is_empty())
+    {
+        Some(number) => format!("{number}_{id}"),
+        None => id.to_string(),
+    };
+    let extension = document
+        .get("fileType")
+        .and_then(Value::as_str)
+        .unwrap_or("pdf");
+    let technical_name = document
+        .pointer("/documentType/technicalName")
+        .and_then(Value::as_str)
+        .unwrap_or("document");
+
+    // only keep safe characters, the number is free text of the shop
+    let file_name: String = format!("{technical_name}_{name}.{extension}")
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    options.folder.join(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn document_types_and_paths() {
+        let options = DocumentOptions {
+            folder: PathBuf::from("./documents"),
+            types: vec!["invoice".to_string()],
+        };
+        let invoice = json!({
+            "id": "a".repeat(32),
+            "documentNumber": "1000/2024",
+            "fileType": "pdf",
+            "documentType": { "technicalName": "invoice" }
+        });
+        let delivery_note = json!({
+            "id": "b".repeat(32),
+            "documentType": { "technicalName": "delivery_note" }
+        });
+
+        assert!(is_requested_type(&invoice, &options));
+        assert!(!is_requested_type(&delivery_note, &options));
+        assert!(is_requested_type(
+            &delivery_note,
+            &DocumentOptions {
+                types: vec![],
+                ..options.clone()
+            }
+        ));
+
+        assert_eq!(
+            get_document_path(&invoice, &options),
+            PathBuf::from(format!(
+                "./documents/invoice_1000_2024_{}.pdf",
+                "a".repeat(32)
+            ))
+        );
+        assert_eq!(
+            get_document_path(&delivery_note, &options),
+            PathBuf::from(format!("./documents/delivery_note_{}.pdf", "b".repeat(32)))
+        );
+
+        // same number of another document
+        let regenerated_invoice = json!({
+            "id": "c".repeat(32),
+            "documentNumber": "1000/2024",
+            "documentType": { "technicalName": "invoice" }
+        });
+        assert_ne!(
+            get_document_path(&regenerated_invoice, &options),
+            get_document_path(&invoice, &options)
+        );
+    }
+}
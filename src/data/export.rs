@@ -1,11 +1,31 @@
 //! Everything related to exporting data out of shopware
 
-use crate::api::filter::Criteria;
-use crate::api::{Entity, SwListResponse};
+use crate::api::filter::{
+    Criteria, CriteriaFilter, CriteriaSorting, CriteriaSortingOrder, LogicOperator,
+};
+use crate::api::{Entity, SwClient, SwListResponse};
+use crate::cli::FileFormat;
+use crate::config_file::{Mapping, Profile, StringQuoting};
+use crate::data::baseline::{get_delta_header, Baseline, Change};
+use crate::data::documents::download_documents;
+use crate::data::lookup::resolve_lookup_values;
+use crate::data::manifest::write_manifest;
+use crate::data::parquet::{ParquetFileWriter, ParquetSchema, RowGroup};
+use crate::data::transform::script::take_script_time;
 use crate::data::transform::serialize_entity;
+use crate::data::ChunkMetrics;
 use crate::SyncContext;
+use anyhow::Context;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
 use std::cmp;
-use std::sync::Arc;
+use std::collections::{HashSet, VecDeque};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 pub fn export(context: Arc<SyncContext>) -> anyhow::Result<()> {
     if !context.associations.is_empty() {
@@ -16,74 +36,295 @@ pub fn export(context: Arc<SyncContext>) -> anyhow::Result<()> {
         println!("Using filter: {:#?}", context.profile.filter);
     }
 
-    if !context.profile.sort.is_empty() {
+    if context.profile.sort.is_empty() {
+        println!("Using default sort: id ASC");
+    } else {
         println!("Using sort: {:#?}", context.profile.sort);
     }
 
-    // retrieve total entity count from shopware and calculate chunk count
-    let mut total = context
-        .sw_client
-        .get_total(&context.profile.entity, &context.profile.filter)?;
+    let (chunk_limit, chunk_count) = if context.skip_count {
+        // page until an empty / incomplete page is returned, because counting can time out on big tables
+        let chunk_limit = match context.limit {
+            Some(limit) => cmp::min(
+                Criteria::MAX_LIMIT,
+                usize::try_from(limit).unwrap_or(Criteria::MAX_LIMIT),
+            ),
+            None => Criteria::MAX_LIMIT,
+        };
+        let chunk_count = context
+            .limit
+            .map(|limit| limit.div_ceil(chunk_limit as u64));
+        println!(
+            "Reading entity '{}' with chunk limit {} without counting, until all pages are processed",
+            context.profile.entity, chunk_limit
+        );
 
-    if total == 0 {
-        return Err(anyhow::anyhow!("No entities found for export."));
-    }
+        (chunk_limit, chunk_count)
+    } else {
+        // retrieve total entity count from shopware and calculate chunk count
+        let mut total = context
+            .sw_client
+            .get_total(&context.profile.entity, &context.profile.filter)?;
 
-    if let Some(limit) = context.limit {
-        total = cmp::min(limit, total);
-    }
+        if total == 0 {
+            return Err(anyhow::anyhow!("No entities found for export."));
+        }
 
-    let chunk_limit = cmp::min(
-        Criteria::MAX_LIMIT,
-        usize::try_from(total).expect("64 bit system wide pointers or values smaller than usize"),
-    );
-    let chunk_count = total.div_ceil(chunk_limit as u64);
-    println!(
-        "Reading {} of entity '{}' with chunk limit {}, resulting in {} chunks to be processed",
-        total, context.profile.entity, chunk_limit, chunk_count
-    );
+        if let Some(limit) = context.limit {
+            total = cmp::min(limit, total);
+        }
+        let _ = context.total_rows.set(total as usize);
+
+        let chunk_limit = cmp::min(
+            Criteria::MAX_LIMIT,
+            usize::try_from(total)
+                .expect("64 bit system wide pointers or values smaller than usize"),
+        );
+        let chunk_count = total.div_ceil(chunk_limit as u64);
+        println!(
+            "Reading {} of entity '{}' with chunk limit {}, resulting in {} chunks to be processed",
+            total, context.profile.entity, chunk_limit, chunk_count
+        );
+
+        (chunk_limit, Some(chunk_count))
+    };
 
     // spawn writer thread
+    let parquet_schema = match context.format {
+        FileFormat::Parquet => Some(ParquetSchema::new(
+            &context.profile,
+            &get_header_line(&context.profile),
+        )?),
+        _ => None,
+    };
+    // read before the file is created, the baseline can be the previous version of the same file
+    let baseline = match (&context.baseline, context.format.delimiter()) {
+        (Some(path), Some(delimiter)) => Some(Baseline::read(
+            path,
+            delimiter,
+            &context.profile,
+            &get_header_line(&context.profile),
+        )?),
+        _ => None,
+    };
+    let file_writer = FileWriter::create(&context, parquet_schema.as_ref())?;
     let (writer_tx, rx) = std::sync::mpsc::channel();
     let context_clone = Arc::clone(&context);
-    let writer = std::thread::spawn(move || write_to_file_worker(rx, &context_clone));
-
-    // Spawn a thread into the thread pool (rayon) for each chunk.
-    // fails on first encountered error
-    rayon::scope_fifo(|s| {
-        for i in 0..chunk_count {
-            let context = Arc::clone(&context);
-            let writer_tx = std::sync::mpsc::Sender::clone(&writer_tx);
-            s.spawn_fifo(move |_| {
-                // Unwrap on failure is fine here for now:
-                // if something goes wrong during export, this will panic the thread
-                // and that panic will bubble up to the main thread
-                // We might re-evaluate this with the ticket: ToDo NEXT-37312
-
-                let page = i + 1;
-                println!("processing page {page}...");
-
-                let response = send_request(page, chunk_limit, &context).unwrap();
-                let result = process_response(page, chunk_limit, response, &context).unwrap();
-
-                // submit data to file writer thread
-                writer_tx.send(result).unwrap();
-                println!("processed page {page}");
-            });
-        }
-        drop(writer_tx);
-    });
+    let writer =
+        std::thread::spawn(move || write_to_file_worker(rx, file_writer, baseline, &context_clone));
 
-    // wait for the writer thread to finish writing to the CSV file
+    // Without a known chunk count, pages are requested in batches of the in-flight limit,
+    // until a page with fewer entities than the chunk limit marks the end
+    let batch_size = match chunk_count {
+        Some(chunk_count) => chunk_count,
+        None => context.in_flight_limit as u64,
+    };
+    let reached_end = AtomicBool::new(false);
+    let first_error = Mutex::new(None);
+    let mut next_page = 1;
+    while !reached_end.load(Ordering::Relaxed)
+        && chunk_count.is_none_or(|chunk_count| next_page <= chunk_count)
+    {
+        let batch_end = match chunk_count {
+            Some(chunk_count) => cmp::min(next_page + batch_size, chunk_count + 1),
+            None => next_page + batch_size,
+        };
+
+        // Spawn a thread into the thread pool (rayon) for each chunk.
+        // fails on first encountered error (after all spawned pages are finished)
+        rayon::scope_fifo(|s| {
+            for page in next_page..batch_end {
+                let context = Arc::clone(&context);
+                let writer_tx = std::sync::mpsc::Sender::clone(&writer_tx);
+                let reached_end = &reached_end;
+                let first_error = &first_error;
+                let parquet_schema = parquet_schema.as_ref();
+                s.spawn_fifo(move |_| {
+                    println!("processing page {page}...");
+
+                    let mut metrics = ChunkMetrics {
+                        chunk: page as usize,
+                        ..Default::default()
+                    };
+                    let start = Instant::now();
+                    let response =
+                        send_request_with_retries(page, chunk_limit, &context, &mut metrics);
+                    metrics.request = start.elapsed();
+
+                    let result = match response {
+                        Ok(response) => {
+                            if response.data.len() < chunk_limit {
+                                reached_end.store(true, Ordering::Relaxed);
+                            }
+                            metrics.rows = response.data.len();
+                            let start = Instant::now();
+                            take_script_time();
+                            let result = process_response(
+                                page,
+                                chunk_limit,
+                                response,
+                                &context,
+                                parquet_schema,
+                            );
+                            metrics.transform = start.elapsed();
+                            metrics.script = take_script_time();
+                            result
+                        }
+                        Err(e) if context.skip_failed_pages => {
+                            println!("Warning: skipping page {page}, because it failed: {e:#}");
+                            context.failed_pages.lock().unwrap().push(page);
+                            // the writer still needs the page to keep the order
+                            Ok((page, ExportedPage::Rows(vec![])))
+                        }
+                        Err(e) => Err(e.context(format!("failed to export page {page}"))),
+                    };
+                    context.chunk_metrics.lock().unwrap().push(metrics);
+
+                    match result {
+                        Ok(result) => {
+                            // submit data to file writer thread
+                            // (only fails if the writer already stopped with an error, which is returned later)
+                            let _ = writer_tx.send(result);
+                            println!("processed page {page}");
+                        }
+                        Err(e) => {
+                            first_error.lock().unwrap().get_or_insert(e);
+                            reached_end.store(true, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        next_page = batch_end;
+    }
+    drop(writer_tx);
+
+    // wait for the writer thread to finish writing to the CSV file, also if a page failed
     // Safety:
     // it's fine to unwrap here, because failure would mean a panic inside the thread,
     // thus panicking the main thread is acceptable
     // Note: we still handle the returned result gracefully and bubble up the error in that case
-    writer.join().unwrap()?;
+    let written_rows = writer.join().unwrap();
+    let written_rows = match first_error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => written_rows,
+    };
+    let written_rows = match written_rows {
+        Ok(written_rows) => written_rows,
+        Err(e) => {
+            // an incomplete file could be mistaken for a complete export
+            if let Err(remove_error) = context.sink.remove(&context.file) {
+                println!(
+                    "Warning: failed to remove the incomplete file {:?}: {remove_error:#}",
+                    context.file
+                );
+            }
+            return Err(e);
+        }
+    };
+
+    if context.manifest {
+        let path = write_manifest(&context, written_rows)?;
+        println!("wrote manifest {path:?}");
+    }
 
     Ok(())
 }
 
+/// Export one file per language of the shop (`--split-by-language`), named by the locale code
+/// like `products.de-DE.csv`, each with the translated fields of that language.
+/// Returns the context again for the summary of the run
+pub fn export_by_language(mut context: SyncContext) -> anyhow::Result<SyncContext> {
+    let has_translations = context
+        .api_schema
+        .get(&context.profile.entity)
+        .and_then(|e| e.pointer("/properties/translations"))
+        .is_some();
+    if !has_translations {
+        anyhow::bail!(
+            "entity '{}' has no translations, it can't be exported by language",
+            context.profile.entity
+        );
+    }
+
+    let mut languages: Vec<(String, String)> = context
+        .sw_client
+        .get_languages()?
+        .data
+        .into_iter()
+        .collect();
+    languages.sort_unstable();
+
+    let sw_client = context.sw_client.clone();
+    let file = context.file.clone();
+    let mut empty_columns: Option<Vec<String>> = None;
+    for (locale, language_id) in languages {
+        context.sw_client = sw_client.with_language(language_id);
+        context.file = get_language_file_path(&file, &locale);
+        println!("Exporting language '{locale}' into {:?}", context.file);
+
+        let shared_context = Arc::new(context);
+        export(Arc::clone(&shared_context))?;
+        let Some(language_context) = Arc::into_inner(shared_context) else {
+            anyhow::bail!("the context is still used after the export of language '{locale}'");
+        };
+        context = language_context;
+
+        // the columns are only reported as empty, if they are empty in the files of all languages
+        let language_empty_columns = std::mem::take(context.empty_columns.get_mut().unwrap());
+        empty_columns = Some(match empty_columns {
+            Some(empty_columns) => empty_columns
+                .into_iter()
+                .filter(|column| language_empty_columns.contains(column))
+                .collect(),
+            None => language_empty_columns,
+        });
+    }
+
+    *context.empty_columns.get_mut().unwrap() = empty_columns.unwrap_or_default();
+    context.sw_client = sw_client;
+    context.file = file;
+    Ok(context)
+}
+
+/// Path of the file of a language, the locale code is added before the extension
+fn get_language_file_path(file: &Path, locale: &str) -> PathBuf {
+    let mut file_name = file.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{locale}"));
+    if let Some(extension) = file.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+
+    file.with_file_name(file_name)
+}
+
+/// Request a page, a failed request is tried again up to `try_count` times
+fn send_request_with_retries(
+    page: u64,
+    chunk_limit: usize,
+    context: &SyncContext,
+    metrics: &mut ChunkMetrics,
+) -> anyhow::Result<SwListResponse<Entity>> {
+    let mut tries = 1;
+    loop {
+        match send_request(page, chunk_limit, context) {
+            Ok(response) => return Ok(response),
+            Err(e) if tries < context.try_count.get() => {
+                println!(
+                    "request of page {page} failed (try {tries} of {}): {e:#}; retry initialized",
+                    context.try_count
+                );
+                std::thread::sleep(context.profile.retry.get_delay(tries));
+                tries += 1;
+                metrics.retries += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 fn send_request(
     page: u64,
     chunk_limit: usize,
@@ -92,8 +333,9 @@ fn send_request(
     let mut criteria = Criteria {
         page,
         limit: Some(chunk_limit),
-        sort: context.profile.sort.clone(),
+        sort: get_sorting(&context.profile.sort),
         filter: context.profile.filter.clone(),
+        includes: context.includes.clone(),
         ..Default::default()
     };
 
@@ -106,33 +348,397 @@ fn send_request(
     Ok(response)
 }
 
+/// Version id of the live data, see `Defaults::LIVE_VERSION` of Shopware
+const LIVE_VERSION_ID: &str = "0fa91ce3e96a4bc2be4bd9ce752c3425";
+
+/// Versioned entities (like orders) also contain the drafts of every edit in the administration,
+/// so only the live version is exported, unless the profile filters by the `versionId` itself
+pub fn add_live_version_filter(profile: &mut Profile, api_schema: &Entity) {
+    let is_versioned = api_schema
+        .get(&profile.entity)
+        .and_then(|e| e.pointer("/properties/versionId/flags/primary_key"))
+        .and_then(serde_json::Value::as_bool)
+        .unwrap_or(false);
+
+    if is_versioned && !profile.filter.iter().any(|f| f.uses_field("versionId")) {
+        profile.filter.push(CriteriaFilter::Equals {
+            field: "versionId".to_string(),
+            value: serde_json::Value::String(LIVE_VERSION_ID.to_string()),
+        });
+    }
+}
+
+/// Limit the export of products to the ones visible in the sales channel (by its name).
+///
+/// Variants without own visibilities inherit the ones of their parent, but the filter
+/// only matches the own visibilities. The number of these left out variants is
+/// reported as a warning and returned.
+pub fn add_sales_channel_filter(
+    profile: &mut Profile,
+    sales_channel: &str,
+    sw_client: &dyn SwClient,
+) -> anyhow::Result<u64> {
+    if profile.entity != "product" {
+        anyhow::bail!(
+            "only products can be exported for a sales channel, but the profile entity is '{}'",
+            profile.entity
+        );
+    }
+
+    let sales_channels = sw_client.get_sales_channels()?;
+    let sales_channel_id = sales_channels.get(sales_channel).with_context(|| {
+        format!(
+            "sales channel '{sales_channel}' not found, available: {:?}",
+            sales_channels.keys().collect::<Vec<_>>()
+        )
+    })?;
+
+    let mut inherited_filter = profile.filter.clone();
+    inherited_filter.extend([
+        CriteriaFilter::Not {
+            operator: LogicOperator::And,
+            queries: vec![CriteriaFilter::Equals {
+                field: "parentId".to_string(),
+                value: serde_json::Value::Null,
+            }],
+        },
+        CriteriaFilter::Equals {
+            field: "visibilities.id".to_string(),
+            value: serde_json::Value::Null,
+        },
+        CriteriaFilter::Equals {
+            field: "parent.visibilities.salesChannelId".to_string(),
+            value: serde_json::Value::String(sales_channel_id.clone()),
+        },
+    ]);
+    let dropped_variants = sw_client.get_total(&profile.entity, &inherited_filter)?;
+    if dropped_variants > 0 {
+        println!(
+            "Warning: {dropped_variants} variants are left out of the export, they only inherit the visibility in sales channel '{sales_channel}' from their parent product"
+        );
+    }
+
+    profile.filter.push(CriteriaFilter::Equals {
+        field: "visibilities.salesChannelId".to_string(),
+        value: serde_json::Value::String(sales_channel_id.clone()),
+    });
+    Ok(dropped_variants)
+}
+
+/// Sorting of the profile or by `id` if there is none, because pagination needs a deterministic order.
+/// Otherwise entities written during the export can shift between pages and be duplicated / missing
+fn get_sorting(profile_sort: &[CriteriaSorting]) -> Vec<CriteriaSorting> {
+    if profile_sort.is_empty() {
+        vec![CriteriaSorting {
+            field: "id".to_string(),
+            order: CriteriaSortingOrder::Ascending,
+        }]
+    } else {
+        profile_sort.to_vec()
+    }
+}
+
+/// Serialized row together with the id of its entity
+struct ExportedRow {
+    id: Option<String>,
+    columns: Vec<String>,
+}
+
+/// Rows of a page for the file writer
+enum ExportedPage {
+    Rows(Vec<ExportedRow>),
+    /// rows already encoded by the page worker (parquet), with the columns which got any value
+    RowGroup {
+        row_group: RowGroup,
+        columns_with_values: Vec<bool>,
+    },
+}
+
 fn process_response(
     page: u64,
     chunk_limit: usize,
     response: SwListResponse<Entity>,
     context: &SyncContext,
-) -> anyhow::Result<(u64, Vec<Vec<String>>)> {
+    parquet_schema: Option<&ParquetSchema>,
+) -> anyhow::Result<(u64, ExportedPage)> {
+    let mut entities: Vec<Entity> = Vec::with_capacity(chunk_limit);
     let mut rows: Vec<Vec<String>> = Vec::with_capacity(chunk_limit);
 
-    for entity in response.data {
-        let row = serialize_entity(&entity, &context.profile, &context.scripting_environment)?;
+    let first_row_index = (page as usize - 1) * chunk_limit;
+    for (index, mut entity) in response.data.into_iter().enumerate() {
+        if let Some(documents) = &context.profile.documents {
+            download_documents(&mut entity, documents, context.sw_client.as_ref())?;
+        }
+
+        let Some(row) = serialize_entity(
+            &entity,
+            first_row_index + index,
+            &context.profile,
+            &context.scripting_environment,
+            &context.transformers,
+        )?
+        else {
+            // skipped by the serialize script
+            continue;
+        };
+
+        entities.push(entity);
         rows.push(row);
     }
 
-    Ok((page, rows))
+    resolve_lookup_values(&mut rows, context)?;
+
+    let rows: Vec<ExportedRow> = entities
+        .into_iter()
+        .zip(rows)
+        .map(|(entity, mut row)| {
+            if context.profile.meta_columns {
+                row.splice(0..0, get_meta_columns(&entity, &row));
+            }
+
+            ExportedRow {
+                id: entity
+                    .get("id")
+                    .and_then(|id| id.as_str())
+                    .map(str::to_owned),
+                columns: row,
+            }
+        })
+        .collect();
+
+    let Some(parquet_schema) = parquet_schema else {
+        return Ok((page, ExportedPage::Rows(rows)));
+    };
+
+    // encoding the columns in the page worker keeps the writer thread from becoming the bottleneck
+    let mut columns_with_values = vec![false; rows.first().map_or(0, |row| row.columns.len())];
+    if context.warn_unused {
+        for row in &rows {
+            mark_columns_with_values(&mut columns_with_values, &row.columns);
+        }
+    }
+    let columns: Vec<&[String]> = rows.iter().map(|row| row.columns.as_slice()).collect();
+    let row_group = parquet_schema.encode(&columns)?;
+
+    Ok((
+        page,
+        ExportedPage::RowGroup {
+            row_group,
+            columns_with_values,
+        },
+    ))
+}
+
+/// Exported text file, optionally compressed (`--gzip`)
+enum OutputFile {
+    Plain(Box<dyn Write + Send + Sync>),
+    Gzip(GzEncoder<Box<dyn Write + Send + Sync>>),
+}
+
+impl OutputFile {
+    fn create(context: &SyncContext) -> anyhow::Result<Self> {
+        let file = context.sink.create(&context.file)?;
+        if context.gzip {
+            Ok(Self::Gzip(GzEncoder::new(file, Compression::default())))
+        } else {
+            Ok(Self::Plain(file))
+        }
+    }
+
+    /// Write the end of the compressed stream, which is required for a valid gzip file
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::Plain(mut file) => file.flush()?,
+            Self::Gzip(encoder) => {
+                encoder.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for OutputFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Plain(_) => f.write_str("OutputFile::Plain"),
+            Self::Gzip(_) => f.write_str("OutputFile::Gzip"),
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Writes the exported rows in the format of the file
+enum FileWriter {
+    Delimited(Box<csv::Writer<OutputFile>>),
+    /// every value is padded (or truncated) to the width of its column
+    FixedWidth {
+        writer: BufWriter<OutputFile>,
+        widths: Vec<usize>,
+        line_ending: &'static str,
+    },
+    /// the rows are encoded into row groups by the page workers, so only whole row groups are written
+    Parquet(Box<ParquetFileWriter>),
+}
+
+impl FileWriter {
+    fn create(
+        context: &SyncContext,
+        parquet_schema: Option<&ParquetSchema>,
+    ) -> anyhow::Result<Self> {
+        if let Some(parquet_schema) = parquet_schema {
+            let writer = ParquetFileWriter::create(&context.file, parquet_schema)?;
+            return Ok(Self::Parquet(Box::new(writer)));
+        }
+
+        let Some(delimiter) = context.format.delimiter() else {
+            return Ok(Self::FixedWidth {
+                widths: get_column_widths(&context.profile)?,
+                writer: BufWriter::new(OutputFile::create(context)?),
+                line_ending: if context.crlf { "\r\n" } else { "\n" },
+            });
+        };
+
+        let quote_style = match context.profile.string_quoting {
+            StringQuoting::Always => csv::QuoteStyle::Always,
+            StringQuoting::Necessary | StringQuoting::Apostrophe => csv::QuoteStyle::Necessary,
+        };
+        let terminator = if context.crlf {
+            csv::Terminator::CRLF
+        } else {
+            csv::Terminator::Any(b'\n')
+        };
+        let csv_writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .quote_style(quote_style)
+            .terminator(terminator)
+            .from_writer(OutputFile::create(context)?);
+
+        Ok(Self::Delimited(Box::new(csv_writer)))
+    }
+
+    fn write_record(&mut self, record: &[String]) -> anyhow::Result<()> {
+        match self {
+            Self::Delimited(csv_writer) => csv_writer.write_record(record)?,
+            Self::FixedWidth {
+                writer,
+                widths,
+                line_ending,
+            } => {
+                writer.write_all(format_fixed_width(record, widths, line_ending).as_bytes())?;
+            }
+            Self::Parquet(_) => unreachable!("parquet rows are only written as row groups"),
+        }
+
+        Ok(())
+    }
+
+    fn write_header(&mut self, header: &[String]) -> anyhow::Result<()> {
+        match self {
+            // the column names are part of the parquet schema
+            Self::Parquet(_) => Ok(()),
+            _ => self.write_record(header),
+        }
+    }
+
+    fn write_row_group(&mut self, row_group: RowGroup) -> anyhow::Result<()> {
+        match self {
+            Self::Parquet(writer) => writer.append(row_group),
+            _ => unreachable!("row groups are only encoded for parquet files"),
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::Delimited(csv_writer) => csv_writer.into_inner()?.finish()?,
+            Self::FixedWidth { writer, .. } => writer.into_inner()?.finish()?,
+            Self::Parquet(writer) => writer.close()?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Widths of the meta-columns in the fixed-width format (uuids, RFC 3339 timestamp and SHA-256 hex digest)
+const META_COLUMN_WIDTHS: [usize; 4] = [32, 32, 29, 64];
+
+/// Widths of all columns for the fixed-width format, which need to be defined for every mapping
+fn get_column_widths(profile: &Profile) -> anyhow::Result<Vec<usize>> {
+    let missing_widths: Vec<&str> = profile
+        .mappings
+        .iter()
+        .filter(|mapping| mapping.get_width().is_none())
+        .map(|mapping| mapping.get_file_column())
+        .collect();
+    if !missing_widths.is_empty() {
+        anyhow::bail!(
+            "the fixed-width format requires a width for every mapping, missing for the columns {missing_widths:?}"
+        );
+    }
+
+    let mut widths = vec![];
+    if profile.meta_columns {
+        widths.extend(META_COLUMN_WIDTHS);
+    }
+    widths.extend(profile.mappings.iter().filter_map(Mapping::get_width));
+
+    Ok(widths)
+}
+
+/// Line with every value padded with spaces (or truncated) to the width of its column
+fn format_fixed_width(record: &[String], widths: &[usize], line_ending: &str) -> String {
+    let mut line = String::with_capacity(widths.iter().sum::<usize>() + line_ending.len());
+    for (value, &width) in record.iter().zip(widths) {
+        // line breaks would break the layout
+        let value = value.replace(['\r', '\n'], " ");
+        line.push_str(&format!("{value:<width$.width$}"));
+    }
+    line.push_str(line_ending);
+
+    line
 }
 
-#[allow(clippy::type_complexity)]
 fn write_to_file_worker(
-    rx: std::sync::mpsc::Receiver<(u64, Vec<Vec<String>>)>,
+    rx: std::sync::mpsc::Receiver<(u64, ExportedPage)>,
+    mut file_writer: FileWriter,
+    mut baseline: Option<Baseline>,
     context: &SyncContext,
-) -> anyhow::Result<()> {
-    let mut csv_writer = csv::WriterBuilder::new()
-        .delimiter(b';')
-        .from_path(&context.file)?;
-
+) -> anyhow::Result<usize> {
     // writer header line
-    csv_writer.write_record(get_header_line(context))?;
+    let header = get_header_line(&context.profile);
+    match baseline {
+        Some(_) => file_writer.write_header(&get_delta_header(&header))?,
+        None => file_writer.write_header(&header)?,
+    }
+    let mut changes = [0; 3];
+
+    // which columns got any value, to find mappings without values (`--warn-unused`)
+    let mut columns_with_values = vec![false; get_header_line(&context.profile).len()];
+    // exported rows, including the unchanged rows of a delta export
+    let mut exported_rows = 0;
+    // rows of a delta export which are the same in the baseline and aren't written
+    let mut unchanged_rows = 0;
+    // data rows in the file, e.g. only the changes of a delta export
+    let mut file_rows = 0;
+
+    // ids of the written entities, to suppress entities which shifted between pages
+    let mut written_ids = context.dedupe.then(WrittenIds::default);
+    let mut duplicates = 0;
 
     // buffer incoming (page, chunk) messages, to process them in order
     let mut buffer = vec![];
@@ -140,7 +746,7 @@ fn write_to_file_worker(
     while let Ok(msg) = rx.recv() {
         buffer.push(msg);
 
-        buffer.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        buffer.sort_unstable_by_key(|m| std::cmp::Reverse(m.0));
         loop {
             match buffer.last() {
                 Some(m) if m.0 == next_page => {}
@@ -148,27 +754,545 @@ fn write_to_file_worker(
             }
 
             // got the next page, so write it
-            let (page, rows) = buffer.remove(buffer.len() - 1);
+            let (page, exported_page) = buffer.remove(buffer.len() - 1);
             println!("writing page {page}");
 
+            let rows = match exported_page {
+                ExportedPage::Rows(rows) => rows,
+                ExportedPage::RowGroup {
+                    row_group,
+                    columns_with_values: page_columns_with_values,
+                } => {
+                    if context.warn_unused {
+                        for (has_value, page_has_value) in
+                            columns_with_values.iter_mut().zip(page_columns_with_values)
+                        {
+                            *has_value |= page_has_value;
+                        }
+                        exported_rows += row_group.rows();
+                    }
+
+                    file_rows += row_group.rows();
+                    file_writer.write_row_group(row_group)?;
+                    next_page += 1;
+                    continue;
+                }
+            };
+            if let Some(written_ids) = &mut written_ids {
+                written_ids.start_page();
+            }
             for row in rows {
-                csv_writer.write_record(row)?;
+                if let (Some(written_ids), Some(id)) = (&mut written_ids, &row.id) {
+                    if !written_ids.insert(id) {
+                        duplicates += 1;
+                        continue;
+                    }
+                }
+
+                if context.warn_unused {
+                    mark_columns_with_values(&mut columns_with_values, &row.columns);
+                    exported_rows += 1;
+                }
+
+                match &mut baseline {
+                    Some(baseline) => {
+                        let Some(change) = baseline.compare(&row.columns) else {
+                            unchanged_rows += 1;
+                            continue;
+                        };
+                        changes[change as usize] += 1;
+                        file_writer.write_record(&get_delta_row(change, row.columns))?;
+                    }
+                    None => file_writer.write_record(&row.columns)?,
+                }
+                file_rows += 1;
             }
             next_page += 1;
         }
     }
 
-    csv_writer.flush()?;
+    if let Some(baseline) = baseline {
+        for row in baseline.into_removed() {
+            changes[Change::Removed as usize] += 1;
+            file_rows += 1;
+            file_writer.write_record(&get_delta_row(Change::Removed, row))?;
+        }
+        println!(
+            "delta to baseline: {} added, {} changed, {} removed rows ({unchanged_rows} unchanged rows aren't written)",
+            changes[0], changes[1], changes[2]
+        );
+    }
 
-    Ok(())
+    file_writer.finish()?;
+    if exported_rows > 0 {
+        *context.empty_columns.lock().unwrap() =
+            get_empty_columns(&context.profile, &columns_with_values);
+    }
+    // summed up over the files of `--split-by-language`
+    context
+        .suppressed_duplicates
+        .fetch_add(duplicates, Ordering::Relaxed);
+
+    Ok(file_rows)
 }
 
-fn get_header_line(context: &SyncContext) -> Vec<String> {
+/// Row of the delta export, with the type of the change in the first column
+fn get_delta_row(change: Change, mut row: Vec<String>) -> Vec<String> {
+    row.insert(0, change.as_str().to_string());
+    row
+}
+
+fn mark_columns_with_values(columns_with_values: &mut [bool], row: &[String]) {
+    for (has_value, value) in columns_with_values.iter_mut().zip(row) {
+        *has_value |= !value.is_empty();
+    }
+}
+
+/// Columns of entity path mappings, which didn't have a value in any exported row
+fn get_empty_columns(profile: &Profile, columns_with_values: &[bool]) -> Vec<String> {
+    let meta_columns = if profile.meta_columns {
+        META_COLUMNS.len()
+    } else {
+        0
+    };
+
+    profile
+        .mappings
+        .iter()
+        .zip(&columns_with_values[meta_columns..])
+        .filter(|(mapping, has_value)| matches!(mapping, Mapping::ByPath(_)) && !**has_value)
+        .map(|(mapping, _)| mapping.get_file_column().to_owned())
+        .collect()
+}
+
+/// Ids of the entities written on the last pages of the export, to suppress duplicates (`--dedupe`).
+/// Entities only shift by the number of concurrently created / deleted entities,
+/// so the ids of older pages are dropped to keep the memory bounded for big exports
+#[derive(Debug, Default)]
+struct WrittenIds {
+    pages: VecDeque<HashSet<u128>>,
+}
+
+impl WrittenIds {
+    /// Number of pages of which the ids are kept (up to 4000 entities with the maximum page size)
+    const PAGES: usize = 8;
+
+    fn start_page(&mut self) {
+        let mut page = match self.pages.len() {
+            Self::PAGES => self.pages.pop_front().expect("is full"),
+            _ => HashSet::new(),
+        };
+        page.clear();
+        self.pages.push_back(page);
+    }
+
+    /// Returns false if the entity was already written
+    fn insert(&mut self, id: &str) -> bool {
+        let key = get_id_key(id);
+        if self.pages.iter().any(|page| page.contains(&key)) {
+            return false;
+        }
+
+        self.pages
+            .back_mut()
+            .expect("ids are only inserted after a page is started")
+            .insert(key)
+    }
+}
+
+/// Compact key of an entity id for the duplicate detection,
+/// uuids (32 hex characters) are stored in 16 bytes instead of a whole string,
+/// other ids by the first 16 bytes of their SHA-256 hash
+fn get_id_key(id: &str) -> u128 {
+    if id.len() == 32 {
+        if let Ok(key) = u128::from_str_radix(id, 16) {
+            return key;
+        }
+    }
+
+    let hash = Sha256::digest(id.as_bytes());
+    u128::from_be_bytes(hash[..16].try_into().expect("SHA-256 hash has 32 bytes"))
+}
+
+/// Column names of the meta-columns, which are prepended if `meta_columns` is enabled in the profile
+pub(super) const META_COLUMNS: [&str; 4] = ["_id", "_version_id", "_updated_at", "_checksum"];
+
+/// Meta-columns that serve as stable anchors for later comparisons of exports:
+/// id, version id and last update of the entity plus a SHA-256 checksum of the mapped row values
+fn get_meta_columns(entity: &Entity, row: &[String]) -> Vec<String> {
+    let field = |name: &str| match entity.get(name) {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    };
+
+    let mut hasher = Sha256::new();
+    for value in row {
+        hasher.update(value.as_bytes());
+        // unit separator, so ["ab", "c"] and ["a", "bc"] result in different checksums
+        hasher.update([0x1f]);
+    }
+    let checksum = format!("{:x}", hasher.finalize());
+
+    vec![
+        field("id"),
+        field("versionId"),
+        field("updatedAt"),
+        checksum,
+    ]
+}
+
+fn get_header_line(profile: &Profile) -> Vec<String> {
     let mut columns = vec![];
 
-    for mapping in &context.profile.mappings {
-        columns.push(mapping.get_file_column().to_owned());
+    if profile.meta_columns {
+        columns.extend(META_COLUMNS.iter().map(|c| c.to_string()));
+    }
+
+    for mapping in &profile.mappings {
+        columns.push(profile.header.format(mapping.get_header()));
     }
 
     columns
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs::File;
+    use std::io::Read;
+
+    #[test]
+    fn default_sorting_by_id() {
+        assert_eq!(
+            get_sorting(&[]),
+            vec![CriteriaSorting {
+                field: "id".to_string(),
+                order: CriteriaSortingOrder::Ascending,
+            }]
+        );
+
+        let profile_sort = vec![CriteriaSorting {
+            field: "name".to_string(),
+            order: CriteriaSortingOrder::Descending,
+        }];
+        assert_eq!(get_sorting(&profile_sort), profile_sort);
+    }
+
+    #[test]
+    fn live_version_filter_of_versioned_entities() {
+        let api_schema: Entity = serde_json::from_str(
+            &std::fs::read_to_string("./fixtures/entity-schema-2024-08-01.json").unwrap(),
+        )
+        .unwrap();
+        let profile = |entity: &str, filter| Profile {
+            entity: entity.to_string(),
+            filter,
+            ..Default::default()
+        };
+        let live_version_filter = CriteriaFilter::Equals {
+            field: "versionId".to_string(),
+            value: json!(LIVE_VERSION_ID),
+        };
+
+        let mut order = profile("order", vec![]);
+        add_live_version_filter(&mut order, &api_schema);
+        assert_eq!(order.filter, vec![live_version_filter]);
+
+        let mut customer = profile("customer", vec![]);
+        add_live_version_filter(&mut customer, &api_schema);
+        assert_eq!(customer.filter, vec![]);
+
+        // the profile can export other versions
+        let draft_filter = vec![CriteriaFilter::EqualsAny {
+            field: "versionId".to_string(),
+            value: vec![json!("a".repeat(32))],
+        }];
+        let mut drafts = profile("order", draft_filter.clone());
+        add_live_version_filter(&mut drafts, &api_schema);
+        assert_eq!(drafts.filter, draft_filter);
+    }
+
+    #[test]
+    fn sales_channel_filter_reports_inherited_variants() {
+        let mut server = mockito::Server::new();
+        let _auth = server
+            .mock("POST", "/api/oauth/token")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"access_token": "access_token"}"#)
+            .create();
+        let _sales_channels = server
+            .mock("POST", "/api/search/sales-channel")
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "total": 1,
+                    "data": [{ "id": "storefront-id", "name": "Storefront" }],
+                    "aggregations": { "count": { "count": 1 } },
+                })
+                .to_string(),
+            )
+            .create();
+        let inherited_variants = server
+            .mock("POST", "/api/search/product")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "filter": [
+                    { "type": "equals", "field": "active", "value": true },
+                    { "type": "not", "operator": "and", "queries": [
+                        { "type": "equals", "field": "parentId", "value": null }
+                    ] },
+                    { "type": "equals", "field": "visibilities.id", "value": null },
+                    {
+                        "type": "equals",
+                        "field": "parent.visibilities.salesChannelId",
+                        "value": "storefront-id"
+                    },
+                ]
+            })))
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"aggregations": {"count": {"count": 2}}}"#)
+            .create();
+        let sw_client = crate::api::SwHttpClient::new(
+            crate::config_file::Credentials {
+                base_url: server.url(),
+                access_key_id: "access_key_id".to_string(),
+                access_key_secret: "access_key_secret".to_string(),
+            },
+            crate::api::SwClientOptions::default(),
+        )
+        .unwrap();
+
+        let active_filter = CriteriaFilter::Equals {
+            field: "active".to_string(),
+            value: json!(true),
+        };
+        let mut profile = Profile {
+            entity: "product".to_string(),
+            filter: vec![active_filter.clone()],
+            ..Default::default()
+        };
+        let dropped = add_sales_channel_filter(&mut profile, "Storefront", &sw_client).unwrap();
+        inherited_variants.assert();
+        assert_eq!(dropped, 2);
+        assert_eq!(
+            profile.filter,
+            vec![
+                active_filter,
+                CriteriaFilter::Equals {
+                    field: "visibilities.salesChannelId".to_string(),
+                    value: json!("storefront-id"),
+                },
+            ]
+        );
+
+        assert!(
+            add_sales_channel_filter(&mut profile, "Headless", &sw_client)
+                .is_err_and(|e| e.to_string().contains("'Headless' not found"))
+        );
+        let mut category = Profile {
+            entity: "category".to_string(),
+            ..Default::default()
+        };
+        assert!(add_sales_channel_filter(&mut category, "Storefront", &sw_client).is_err());
+    }
+
+    #[test]
+    fn language_file_paths() {
+        assert_eq!(
+            get_language_file_path(Path::new("./export/products.csv"), "de-DE"),
+            PathBuf::from("./export/products.de-DE.csv")
+        );
+        assert_eq!(
+            get_language_file_path(Path::new("products"), "en-GB"),
+            PathBuf::from("products.en-GB")
+        );
+    }
+
+    #[test]
+    fn custom_header_line() {
+        let profile: Profile = serde_yaml::from_str(
+            r"
+entity: product
+meta_columns: true
+header:
+  case: snake_case
+  prefix: 'sw_'
+mappings:
+  - file_column: 'productNumber'
+    entity_path: 'productNumber'
+  - file_column: 'gross price'
+    key: 'gross_price'
+  - file_column: 'name'
+    entity_path: 'name'
+    header: 'Product Title'
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_header_line(&profile),
+            vec![
+                "_id",
+                "_version_id",
+                "_updated_at",
+                "_checksum",
+                "sw_product_number",
+                "sw_gross_price",
+                "sw_product_title"
+            ]
+        );
+    }
+
+    #[test]
+    fn fixed_width_columns() {
+        let profile: Profile = serde_yaml::from_str(
+            r"
+entity: product
+mappings:
+  - file_column: 'product number'
+    entity_path: 'productNumber'
+    width: 8
+  - file_column: 'gross price'
+    key: 'gross_price'
+    width: 5
+",
+        )
+        .unwrap();
+        let widths = get_column_widths(&profile).unwrap();
+        assert_eq!(widths, vec![8, 5]);
+
+        assert_eq!(
+            format_fixed_width(
+                &["SW10001".to_string(), "1234.56".to_string()],
+                &widths,
+                "\n"
+            ),
+            "SW10001 1234.\n"
+        );
+        assert_eq!(
+            format_fixed_width(&["Größe\n2".to_string(), String::new()], &widths, "\r\n"),
+            "Größe 2      \r\n"
+        );
+
+        let profile: Profile = serde_yaml::from_str(
+            r"
+entity: product
+mappings:
+  - file_column: 'product number'
+    entity_path: 'productNumber'
+",
+        )
+        .unwrap();
+        assert!(get_column_widths(&profile).is_err());
+    }
+
+    #[test]
+    fn empty_columns_of_export() {
+        let profile: Profile = serde_yaml::from_str(
+            r"
+entity: product
+meta_columns: true
+mappings:
+  - file_column: 'id'
+    entity_path: 'id'
+  - file_column: 'ean'
+    entity_path: 'ean'
+  - file_column: 'gross price'
+    key: 'gross_price'
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_empty_columns(&profile, &[true, true, true, true, true, false, false]),
+            vec!["ean"]
+        );
+    }
+
+    #[test]
+    fn gzip_output_file() {
+        let path = std::env::temp_dir().join("sw-sync-cli-gzip-output-file.csv.gz");
+        let file = File::create(&path).unwrap();
+        let mut csv_writer =
+            csv::WriterBuilder::new()
+                .delimiter(b';')
+                .from_writer(OutputFile::Gzip(GzEncoder::new(
+                    Box::new(file),
+                    Compression::default(),
+                )));
+        csv_writer.write_record(["id", "name"]).unwrap();
+        csv_writer.write_record(["a", "Shirt"]).unwrap();
+        csv_writer.into_inner().unwrap().finish().unwrap();
+
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "id;name\na;Shirt\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn id_keys() {
+        assert_eq!(
+            get_id_key("0190b7c5e0a67a4c8d7e3c4f2b1a0f9e"),
+            0x0190b7c5e0a67a4c8d7e3c4f2b1a0f9e
+        );
+        assert_ne!(
+            get_id_key("0190b7c5e0a67a4c8d7e3c4f2b1a0f9e"),
+            get_id_key("0190b7c5e0a67a4c8d7e3c4f2b1a0f9f")
+        );
+        assert_eq!(get_id_key("not a uuid"), get_id_key("not a uuid"));
+        assert_ne!(get_id_key("not a uuid"), get_id_key("not a uuid!"));
+        // short hex ids aren't mixed up with uuids of the same value
+        assert_ne!(get_id_key("1"), get_id_key("01"));
+        assert_ne!(get_id_key("1"), 1);
+    }
+
+    #[test]
+    fn written_ids_of_last_pages() {
+        let mut written_ids = WrittenIds::default();
+        written_ids.start_page();
+        assert!(written_ids.insert("a"));
+        assert!(!written_ids.insert("a"));
+
+        // duplicates on the following pages are found
+        written_ids.start_page();
+        assert!(!written_ids.insert("a"));
+        assert!(written_ids.insert("b"));
+
+        // the ids of older pages are dropped
+        for _ in 0..WrittenIds::PAGES {
+            written_ids.start_page();
+        }
+        assert_eq!(written_ids.pages.len(), WrittenIds::PAGES);
+        assert!(written_ids.insert("a"));
+    }
+
+    #[test]
+    fn meta_columns_of_entity() {
+        let entity: Entity = serde_json::from_value(json!({
+            "id": "0191b1b3f8d47b5e8b2e3b6c5e5d4c3a",
+            "updatedAt": null,
+            "name": "shopware"
+        }))
+        .unwrap();
+        let row = vec!["shopware".to_string(), "42".to_string()];
+
+        let columns = get_meta_columns(&entity, &row);
+
+        assert_eq!(columns.len(), META_COLUMNS.len());
+        assert_eq!(columns[0], "0191b1b3f8d47b5e8b2e3b6c5e5d4c3a");
+        assert_eq!(columns[1], "");
+        assert_eq!(columns[2], "");
+        assert_eq!(columns[3].len(), 64);
+
+        // checksum only changes with the row content
+        assert_eq!(columns[3], get_meta_columns(&Entity::new(), &row)[3]);
+        assert_ne!(
+            columns[3],
+            get_meta_columns(&entity, &["shopware4".to_string(), "2".to_string()])[3]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Ids of imported rows without an `id` (`id_strategy` of the profile),
+//! so profiles don't need their own conventions in scripts
+
+use crate::api::Entity;
+use crate::config_file::IdStrategy;
+use crate::data::transform::EntityPath;
+use anyhow::Context;
+use csv::StringRecord;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Add the id of the strategy to the entity, if it has none yet
+pub fn apply_id_strategy(
+    strategy: &IdStrategy,
+    entity_name: &str,
+    entity: &mut Entity,
+    headers: &StringRecord,
+    row: &StringRecord,
+) -> anyhow::Result<()> {
+    if has_id(entity) {
+        return Ok(());
+    }
+
+    let id = match strategy {
+        IdStrategy::RandomUuid => Uuid::new_v4().simple().to_string(),
+        IdStrategy::DeterministicFromKeyFields { key_fields } => {
+            get_key_fields_id(entity_name, entity, key_fields)?
+        }
+        IdStrategy::TakeFromColumn { column } => {
+            let value = headers
+                .iter()
+                .position(|header| header == column)
+                .and_then(|index| row.get(index))
+                .with_context(|| format!("Can't find the id column '{column}' in CSV headers"))?;
+            Uuid::try_parse(value.trim())
+                .with_context(|| {
+                    format!("the value '{value}' of the id column '{column}' is not a valid id")
+                })?
+                .simple()
+                .to_string()
+        }
+    };
+    entity.insert("id".to_string(), Value::String(id));
+
+    Ok(())
+}
+
+fn has_id(entity: &Entity) -> bool {
+    match entity.get("id") {
+        Some(Value::String(id)) => !id.is_empty(),
+        Some(Value::Null) | None => false,
+        Some(_) => true,
+    }
+}
+
+/// Id in the format of shopware (32 hex characters), which is the same for the same values of the key fields
+fn get_key_fields_id(
+    entity_name: &str,
+    entity: &Entity,
+    key_fields: &[String],
+) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(entity_name.as_bytes());
+    for key_field in key_fields {
+        let value = match entity.get_by_path(key_field) {
+            Some(Value::String(value)) if !value.is_empty() => value.clone(),
+            Some(Value::Null) | Some(Value::String(_)) | None => {
+                anyhow::bail!("the key field '{key_field}' of the id_strategy has no value")
+            }
+            Some(value) => value.to_string(),
+        };
+        hasher.update([0x1f]);
+        hasher.update(value.as_bytes());
+    }
+
+    Ok(format!("{:x}", hasher.finalize())[..32].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply(strategy: &IdStrategy, entity: Value, row: Vec<&str>) -> anyhow::Result<Value> {
+        let Value::Object(mut entity) = entity else {
+            unreachable!();
+        };
+        let headers = StringRecord::from(vec!["number", "uuid"]);
+        apply_id_strategy(
+            strategy,
+            "product",
+            &mut entity,
+            &headers,
+            &StringRecord::from(row),
+        )?;
+        Ok(entity["id"].clone())
+    }
+
+    #[test]
+    fn generate_ids() {
+        let row = vec!["SW-1", "0190E4A5-3C0A-7C5E-8E6B-1A2B3C4D5E6F"];
+
+        let random = apply(&IdStrategy::RandomUuid, json!({}), row.clone()).unwrap();
+        assert_eq!(random.as_str().unwrap().len(), 32);
+        assert_ne!(
+            apply(&IdStrategy::RandomUuid, json!({}), row.clone()).unwrap(),
+            random
+        );
+        // existing ids are kept
+        assert_eq!(
+            apply(&IdStrategy::RandomUuid, json!({"id": "a"}), row.clone()).unwrap(),
+            json!("a")
+        );
+
+        let deterministic = IdStrategy::DeterministicFromKeyFields {
+            key_fields: vec!["productNumber".to_string(), "stock".to_string()],
+        };
+        let entity = json!({"productNumber": "SW-1", "stock": 5});
+        let id = apply(&deterministic, entity.clone(), row.clone()).unwrap();
+        assert_eq!(id.as_str().unwrap().len(), 32);
+        assert_eq!(
+            apply(&deterministic, entity.clone(), row.clone()).unwrap(),
+            id
+        );
+        assert_ne!(
+            apply(
+                &deterministic,
+                json!({"productNumber": "SW-2", "stock": 5}),
+                row.clone()
+            )
+            .unwrap(),
+            id
+        );
+        assert!(apply(
+            &deterministic,
+            json!({"productNumber": "SW-1"}),
+            row.clone()
+        )
+        .is_err());
+
+        let from_column = IdStrategy::TakeFromColumn {
+            column: "uuid".to_string(),
+        };
+        assert_eq!(
+            apply(&from_column, json!({"id": null}), row.clone()).unwrap(),
+            json!("0190e4a53c0a7c5e8e6b1a2b3c4d5e6f")
+        );
+        assert!(apply(&from_column, json!({}), vec!["SW-1", "not an id"]).is_err());
+    }
+}
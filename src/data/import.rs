@@ -1,156 +1,1277 @@
 //! Everything related to import data into shopware
 
-use crate::api::filter::Criteria;
-use crate::api::{Entity, SwApiError, SwError, SwErrorBody, SyncAction};
+use crate::api::{
+    parse_error_pointer, Entity, SwApiError, SwError, SwErrorBody, SyncAction, SyncResult,
+};
+use crate::config_file::OrderPrices;
+use crate::data::constraints::check_schema_constraints;
+use crate::data::ids::apply_id_strategy;
+use crate::data::lookup::resolve_lookups;
+use crate::data::sanitize::{
+    limit_payload_size, remove_write_protected_fields, retain_primary_keys,
+};
+use crate::data::storage::is_local_file;
 use crate::data::transform::deserialize_row;
+use crate::data::transform::plugins::InvalidRow;
+use crate::data::transform::script::take_script_time;
+use crate::data::{
+    get_unmapped_columns, validate_file_columns, validate_row_columns, ChunkMetrics,
+    ColumnStatistics, RowError,
+};
 use crate::SyncContext;
-use anyhow::{anyhow, Context};
-use csv::StringRecord;
+use anyhow::Context;
+use crossbeam_channel::{Receiver, Sender};
+use csv::{ByteRecord, StringRecord};
 use itertools::Itertools;
-use std::sync::Arc;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Buffer size of the CSV reader, bigger than the default to reduce the amount of read calls on big files
+const CSV_READ_BUFFER_CAPACITY: usize = 1024 * 1024;
+
+/// Estimated factor between the raw size of a CSV row and its memory usage during processing
+/// (raw record, deserialized entity and request payload)
+const ROW_MEMORY_FACTOR: usize = 4;
+
+/// With a memory ceiling (`--max-memory`) the raw size of the rows decides the file chunk window,
+/// this only caps it at 8 times the default window (2 sync chunks per in-flight request).
+/// Narrow rows (like stock updates) can use much bigger windows then, but the window stays bounded
+/// for tiny rows, whose fixed per-row overhead (record bounds, row index and entity map)
+/// isn't covered by the raw size
+const MAX_MEMORY_WINDOW_FACTOR: usize = 16;
 
 pub fn import(context: Arc<SyncContext>) -> anyhow::Result<()> {
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .delimiter(b';')
-        .from_path(&context.file)?;
+    let mut csv_reader = open_file(&context)?;
     let headers = csv_reader.headers()?.clone();
-    let chunked_iter = csv_reader
-        .into_records()
-        .enumerate()
-        // limit how much CSV rows get loaded into memory at once (one file chunk)
-        .chunks(Criteria::MAX_LIMIT * context.in_flight_limit * 2);
+    validate_file_columns(&headers, &context.profile.mappings)?;
+    validate_row_columns(&headers, &context.profile)?;
+    if context.profile.action_column.is_some() && context.sync_action != SyncAction::Upsert {
+        anyhow::bail!("the action_column of the profile can only be used with the mode import");
+    }
+    if context.warn_unused {
+        let mut unmapped_columns = get_unmapped_columns(&headers, &context.profile.mappings);
+        let row_columns = context.profile.get_row_columns();
+        unmapped_columns.retain(|column| !row_columns.contains(&column.as_str()));
+        *context.unmapped_columns.lock().unwrap() = unmapped_columns;
+    }
+    // a resumed import (`--resume`) continues at the row of the checkpoint,
+    // with `--limit` only the first rows of the file are imported
+    let mut records = FileRecords {
+        reader: csv_reader,
+        buffer: ByteRecord::new(),
+        next_index: 0,
+        start_row: context.start_row,
+        row_limit: get_row_limit(&context),
+        skip_blank_rows: context.profile.skip_blank_rows,
+        data_rows: 0,
+        read_rows: 0,
+        finished: false,
+    };
+    let total_rows = &context.total_rows;
+
+    let sync_chunk_size = context.profile.get_sync_chunk_size();
+    // limit how much CSV rows get loaded into memory at once (one file chunk)
+    // with a memory ceiling the window is sized by the actual row sizes instead,
+    // so narrow rows can use bigger windows
+    let max_rows = match context.max_memory {
+        None => sync_chunk_size * context.in_flight_limit * 2,
+        Some(_) => sync_chunk_size * context.in_flight_limit * MAX_MEMORY_WINDOW_FACTOR,
+    };
+    // the next file chunk is read while the current one is processed,
+    // so the ceiling is split between the two chunks in memory
+    let max_bytes = context.max_memory.map(|m| m / ROW_MEMORY_FACTOR / 2);
 
     // process one big file chunk of a potentially big CSV file at a time
-    for file_chunk in &chunked_iter {
-        let file_chunk: Vec<(usize, Result<StringRecord, csv::Error>)> = file_chunk.collect();
-        let first_index = file_chunk.first().map_or(0, |t| t.0);
-        let last_index = file_chunk.last().map_or(0, |t| t.0);
-        let chunk_length = file_chunk.len();
+    std::thread::scope(|s| {
+        // Reading from disk (e.g. slow network storage) overlaps with the sync of the current chunk,
+        // so the API isn't idle while the next chunk is read (double buffering).
+        // The rendezvous channel makes the reader wait with the next chunk until the current one is done.
+        let (chunk_tx, chunk_rx) = mpsc::sync_channel(0);
+        s.spawn(move || loop {
+            let file_chunk = read_file_chunk(&mut records, max_rows, max_bytes);
+            if records.finished {
+                // counted while reading, for the ETA of the status file
+                let _ = total_rows.set(records.read_rows);
+            }
+            if file_chunk.is_empty() || chunk_tx.send(file_chunk).is_err() {
+                break;
+            }
+        });
+
+        for file_chunk in chunk_rx {
+            let first_index = file_chunk.first().map_or(0, |t| t.0);
+            let last_index = file_chunk.last().map_or(0, |t| t.0);
+            let chunk_length = file_chunk.len();
+
+            if is_past_deadline(&context) {
+                stop_before_row(&context, first_index);
+                break;
+            }
+
+            println!("file chunk {first_index}..={last_index} (size={chunk_length}) was read from CSV into memory");
+            process_file_chunk(&headers, file_chunk, &context)?;
+            println!("file chunk {first_index}..={last_index} (size={chunk_length}) finished and cleared from memory");
+        }
+
+        Ok(())
+    })
+}
+
+/// Data rows of the imported file with their row index.
+/// Rows are read as raw bytes, because reading is sequential and the UTF-8 validation
+/// is expensive for big files. The validation happens later in the parallel deserialize stage.
+/// Every row is parsed into the same reused buffer, only the imported rows are copied out of it
+/// (into an exactly sized record), so skipped rows don't allocate at all
+struct FileRecords<R> {
+    reader: csv::Reader<R>,
+    buffer: ByteRecord,
+    next_index: usize,
+    /// rows before it are skipped (`--resume`)
+    start_row: usize,
+    /// amount of rows which are read at most (`--limit`), skipped blank rows don't count
+    row_limit: usize,
+    skip_blank_rows: bool,
+    /// rows read so far (without the skipped blank rows), including the rows before the `start_row`
+    data_rows: usize,
+    /// imported rows read so far
+    read_rows: usize,
+    /// whether the end of the file (or the `--limit`) is reached
+    finished: bool,
+}
+
+impl<R: Read> Iterator for FileRecords<R> {
+    type Item = (usize, Result<ByteRecord, csv::Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.data_rows < self.row_limit {
+            let index = self.next_index;
+            self.next_index += 1;
+            match self.reader.read_byte_record(&mut self.buffer) {
+                Ok(false) => break,
+                Ok(true) => {}
+                Err(e) => {
+                    self.data_rows += 1;
+                    self.read_rows += 1;
+                    return Some((index, Err(e)));
+                }
+            }
 
-        println!("file chunk {first_index}..={last_index} (size={chunk_length}) was read from CSV into memory");
-        process_file_chunk(&headers, file_chunk, &context)?;
-        println!("file chunk {first_index}..={last_index} (size={chunk_length}) finished and cleared from memory");
+            if self.skip_blank_rows && is_blank_row(&self.buffer) {
+                continue;
+            }
+            self.data_rows += 1;
+            if index < self.start_row {
+                continue;
+            }
+            self.read_rows += 1;
+            return Some((index, Ok(self.buffer.clone())));
+        }
+
+        self.finished = true;
+        None
+    }
+}
+
+/// Maximum amount of imported rows of the file (`--limit`)
+fn get_row_limit(context: &SyncContext) -> usize {
+    context.limit.map_or(usize::MAX, |limit| {
+        usize::try_from(limit).unwrap_or(usize::MAX)
+    })
+}
+
+/// Payloads of rows which failed after all tries because of transient errors (like an outage of the shop),
+/// written next to the imported file to sync them again later with `--retry-file`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetryFile {
+    pub entity: String,
+    pub action: SyncAction,
+    /// the imported file
+    pub file: PathBuf,
+    pub rows: Vec<RetryRow>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryRow {
+    pub row_index: usize,
+    /// action of the row, retry files without it use the action of the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<SyncAction>,
+    /// error of the last try
+    pub error: String,
+    pub payload: Entity,
+}
+
+/// Path of the retry file of an imported file, e.g. `products.csv.retry.json`
+pub fn get_retry_file_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".retry.json");
+    PathBuf::from(path)
+}
+
+/// Write the rows which failed because of transient errors to the retry file,
+/// returns its path if there were any
+pub fn write_retry_file(context: &SyncContext) -> anyhow::Result<Option<PathBuf>> {
+    // files which aren't local (like stdin) get a retry file of the entity in the working directory
+    let path = if is_local_file(&context.file) {
+        get_retry_file_path(&context.file)
+    } else {
+        get_retry_file_path(Path::new(&context.profile.entity))
+    };
+
+    let mut rows = context.retry_rows.lock().unwrap().clone();
+    if rows.is_empty() {
+        // the retry file of a previous run is outdated once all rows (or all of its rows) were synced,
+        // a resumed or stopped run didn't sync all rows, so it's kept
+        let synced_all_rows = context.start_row == 0
+            && context.resume_row.lock().unwrap().is_none()
+            && !context.validate_remote;
+        if synced_all_rows && path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to remove the outdated retry file {path:?}"))?;
+            println!("Removed the retry file {path:?} of a previous run, all rows were synced");
+        }
+        return Ok(None);
     }
+    rows.sort_by_key(|row| row.row_index);
+
+    let retry_file = RetryFile {
+        entity: context.profile.entity.clone(),
+        action: context.sync_action,
+        file: context.file.clone(),
+        rows,
+    };
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("failed to create retry file {path:?}"))?;
+    serde_json::to_writer(BufWriter::new(file), &retry_file)
+        .with_context(|| format!("failed to write retry file {path:?}"))?;
+
+    Ok(Some(path))
+}
+
+/// Sync the payloads of a retry file again, instead of the rows of the imported file
+pub fn import_retry_file(context: Arc<SyncContext>, path: &Path) -> anyhow::Result<()> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("failed to open retry file {path:?}"))?;
+    let retry_file: RetryFile = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to read retry file {path:?}"))?;
+    if retry_file.entity != context.profile.entity || retry_file.action != context.sync_action {
+        anyhow::bail!(
+            "the retry file contains {:?} payloads of the entity '{}', which doesn't match the profile and mode",
+            retry_file.action,
+            retry_file.entity
+        );
+    }
+    println!(
+        "retrying {} rows of {:?}",
+        retry_file.rows.len(),
+        retry_file.file
+    );
+
+    let (sync_tx, sync_rx) = crossbeam_channel::bounded::<SequencedChunk>(context.in_flight_limit);
+
+    std::thread::scope(|thread_scope| {
+        // the chunks are sent in order, so they don't need a reorder window
+        spawn_sync_stage(thread_scope, sync_rx, None, &context);
+
+        let chunks = retry_file
+            .rows
+            .into_iter()
+            .chunks(context.profile.get_sync_chunk_size());
+        for (sequence, rows) in (&chunks).into_iter().enumerate() {
+            let (row_indices, actions, entities): (Vec<usize>, Vec<SyncAction>, Vec<Entity>) = rows
+                .map(|row| {
+                    let action = row.action.unwrap_or(context.sync_action);
+                    (row.row_index, action, row.payload)
+                })
+                .multiunzip();
+            let metrics = ChunkMetrics {
+                chunk: *row_indices.first().unwrap_or(&0),
+                rows: row_indices.len(),
+                ..Default::default()
+            };
+
+            let chunk = SequencedChunk {
+                sequence,
+                chunk: Some(DeserializedChunk {
+                    row_indices,
+                    actions,
+                    entities,
+                    metrics,
+                }),
+            };
+            if sync_tx.send(chunk).is_err() {
+                // all sync threads stopped, their panic is propagated by the thread scope
+                break;
+            }
+        }
+
+        // close the channel, so the sync stage finishes after processing the remaining chunks
+        drop(sync_tx);
+    });
 
     Ok(())
 }
 
+/// Whether the maximum duration of the run is reached (`--max-duration`),
+/// then no more chunks are started
+fn is_past_deadline(context: &SyncContext) -> bool {
+    context
+        .deadline
+        .is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// Longest sleep while waiting for the active hours, so the deadline is still checked in between
+const MAX_ACTIVE_HOURS_SLEEP: Duration = Duration::from_secs(60);
+
+/// Pause outside the active hours (`--active-hours`), until they start again or the deadline is reached
+fn wait_for_active_hours(context: &SyncContext) {
+    let Some(active_hours) = &context.active_hours else {
+        return;
+    };
+
+    let mut paused = false;
+    while let Some(wait) = active_hours.get_wait(chrono::Local::now().time()) {
+        if is_past_deadline(context) {
+            return;
+        }
+        if !paused {
+            println!(
+                "outside of the active hours, the import pauses until {} (in {}m)",
+                active_hours.start().format("%H:%M"),
+                wait.as_secs().div_ceil(60)
+            );
+            paused = true;
+        }
+        std::thread::sleep(wait.min(MAX_ACTIVE_HOURS_SLEEP));
+    }
+}
+
+/// Stop the import before the row (or an earlier one), a resumed import continues there.
+/// Chunks are synced in parallel, so rows after it might be synced already and are synced again
+fn stop_before_row(context: &SyncContext, row_index: usize) {
+    let mut resume_row = context.resume_row.lock().unwrap();
+    *resume_row = Some(resume_row.map_or(row_index, |row| row.min(row_index)));
+}
+
+/// Position of an import, which stopped after the maximum duration (`--max-duration`),
+/// and the hashes of the synced chunks (`--skip-synced-chunks`)
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    entity: String,
+    /// the first row, which wasn't synced yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_row: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    synced_chunks: Vec<String>,
+}
+
+/// Path of the checkpoint file of an imported file, e.g. `products.csv.checkpoint.json`
+fn get_checkpoint_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".checkpoint.json");
+    PathBuf::from(path)
+}
+
+/// Row of the checkpoint of the imported file, where a resumed import (`--resume`) starts
+pub fn read_checkpoint(file: &Path, entity: &str) -> anyhow::Result<usize> {
+    let path = get_checkpoint_path(file);
+    let checkpoint = std::fs::File::open(&path)
+        .with_context(|| format!("no checkpoint {path:?} found to resume the import"))?;
+
+    parse_checkpoint(checkpoint, &path, entity)?
+        .next_row
+        .with_context(|| format!("the checkpoint {path:?} has no stopped import to resume"))
+}
+
+fn parse_checkpoint(file: std::fs::File, path: &Path, entity: &str) -> anyhow::Result<Checkpoint> {
+    let checkpoint: Checkpoint = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to read checkpoint {path:?}"))?;
+    if checkpoint.entity != entity {
+        anyhow::bail!(
+            "the checkpoint {path:?} belongs to an import of '{}' instead of '{entity}'",
+            checkpoint.entity
+        );
+    }
+
+    Ok(checkpoint)
+}
+
+/// Content hashes of the sync chunks, which were synced completely (`--skip-synced-chunks`).
+/// Chunks with the hash of a previous run are skipped, so running the same file twice doesn't write anything again
+#[derive(Debug, Default)]
+pub struct SyncedChunks {
+    /// from the checkpoint of the previous run
+    previous: HashSet<String>,
+    /// synced (or skipped) by this run
+    current: Mutex<BTreeSet<String>>,
+    skipped: AtomicUsize,
+}
+
+impl SyncedChunks {
+    /// Read the hashes of the checkpoint of the imported file, if it exists
+    pub fn read(file: &Path, entity: &str) -> anyhow::Result<Self> {
+        let path = get_checkpoint_path(file);
+        let previous = match std::fs::File::open(&path) {
+            Ok(checkpoint) => parse_checkpoint(checkpoint, &path, entity)?
+                .synced_chunks
+                .into_iter()
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to open checkpoint {path:?}")),
+        };
+
+        Ok(Self {
+            previous,
+            ..Default::default()
+        })
+    }
+
+    /// Whether a previous run synced the chunk already, then it's kept for the next run
+    fn was_synced(&self, hash: &str) -> bool {
+        if !self.previous.contains(hash) {
+            return false;
+        }
+
+        self.current.lock().unwrap().insert(hash.to_owned());
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    fn add(&self, hash: String) {
+        self.current.lock().unwrap().insert(hash);
+    }
+
+    /// Amount of chunks, which were skipped because a previous run synced them
+    pub fn skipped(&self) -> usize {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Hashes for the checkpoint, a resumed import keeps the ones of the previous run,
+    /// because it started in the middle of the file
+    fn get_hashes(&self, keep_previous: bool) -> Vec<String> {
+        let mut hashes = self.current.lock().unwrap().clone();
+        if keep_previous {
+            hashes.extend(self.previous.iter().cloned());
+        }
+
+        hashes.into_iter().collect()
+    }
+}
+
+/// SHA-256 of the payload of a sync operation
+fn get_chunk_hash(entity: &str, action: SyncAction, entities: &[Entity]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(entity.as_bytes());
+    hasher.update(format!("\n{action:?}\n").as_bytes());
+    for entity in entities {
+        hasher.update(serde_json::to_vec(entity).unwrap_or_default());
+        hasher.update(b"\n");
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Write the checkpoint of an import, which stopped after the maximum duration
+/// or remove the checkpoint of a resumed import, which finished. Returns the path of a written checkpoint
+pub fn update_checkpoint(context: &SyncContext) -> anyhow::Result<Option<PathBuf>> {
+    let path = get_checkpoint_path(&context.file);
+    let next_row = *context.resume_row.lock().unwrap();
+    let synced_chunks = context
+        .synced_chunks
+        .as_ref()
+        .map(|synced_chunks| synced_chunks.get_hashes(context.start_row > 0))
+        .unwrap_or_default();
+    if next_row.is_none() && synced_chunks.is_empty() {
+        if context.start_row > 0 || (context.synced_chunks.is_some() && path.exists()) {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to remove checkpoint {path:?}"))?;
+        }
+        return Ok(None);
+    }
+
+    let checkpoint = Checkpoint {
+        entity: context.profile.entity.clone(),
+        next_row,
+        synced_chunks,
+    };
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("failed to create checkpoint {path:?}"))?;
+    serde_json::to_writer_pretty(file, &checkpoint)
+        .with_context(|| format!("failed to write checkpoint {path:?}"))?;
+
+    Ok(next_row.map(|_| path))
+}
+
+/// Read the next file chunk, which ends after `max_rows` rows
+/// or (if provided) as soon as the raw size of the rows reaches `max_bytes`
+fn read_file_chunk(
+    records: &mut impl Iterator<Item = (usize, Result<ByteRecord, csv::Error>)>,
+    max_rows: usize,
+    max_bytes: Option<usize>,
+) -> Vec<(usize, Result<ByteRecord, csv::Error>)> {
+    let mut file_chunk = vec![];
+    let mut chunk_bytes = 0;
+
+    for (index, record) in records.by_ref() {
+        if let Ok(record) = &record {
+            chunk_bytes += record.as_slice().len();
+        }
+        file_chunk.push((index, record));
+
+        if file_chunk.len() >= max_rows || max_bytes.is_some_and(|max| chunk_bytes >= max) {
+            break;
+        }
+    }
+
+    file_chunk
+}
+
+/// Message of the deserialize stage to the sync stage, every sync chunk of the file chunk is sent,
+/// so the ordered sync stage (`--ordered`) never waits for a chunk that doesn't come
+struct SequencedChunk {
+    /// position of the sync chunk in the file chunk
+    sequence: usize,
+    /// not available if the chunk failed to deserialize or the deadline was reached before
+    chunk: Option<DeserializedChunk>,
+}
+
+/// Sends the deserialized chunk to the sync stage when dropped,
+/// so its sequence also arrives there if the deserialize task returns early or panics
+struct ChunkSender {
+    sequence: usize,
+    chunk: Option<DeserializedChunk>,
+    sync_tx: Sender<SequencedChunk>,
+}
+
+impl Drop for ChunkSender {
+    fn drop(&mut self) {
+        // blocks if the sync stage is busy, which limits the deserialized chunks in memory.
+        // Fails only if all sync threads stopped, their panic is propagated by the thread scope
+        let _ = self.sync_tx.send(SequencedChunk {
+            sequence: self.sequence,
+            chunk: self.chunk.take(),
+        });
+    }
+}
+
+/// Limits how far the deserialization runs ahead of the ordered sync stage (`--ordered`).
+/// The chunks deserialized after a slow one are held back by the sync stage,
+/// without the window they would pile up in memory until the slow chunk is done
+struct ReorderWindow {
+    /// sequence of the next chunk to be synced
+    next_sequence: Mutex<usize>,
+    advanced: Condvar,
+    size: usize,
+}
+
+impl ReorderWindow {
+    fn new(size: usize) -> Self {
+        Self {
+            next_sequence: Mutex::new(0),
+            advanced: Condvar::new(),
+            size: size.max(1),
+        }
+    }
+
+    /// Block until the chunk with the sequence is inside the window
+    fn wait_for(&self, sequence: usize) {
+        let mut next_sequence = self.next_sequence.lock().unwrap();
+        while sequence >= *next_sequence + self.size {
+            next_sequence = self.advanced.wait(next_sequence).unwrap();
+        }
+    }
+
+    fn advance(&self) {
+        *self.next_sequence.lock().unwrap() += 1;
+        self.advanced.notify_all();
+    }
+}
+
+/// A sync chunk that was deserialized and is ready to be sent to shopware
+struct DeserializedChunk {
+    row_indices: Vec<usize>,
+    /// action of every row, they only differ with an `action_column`
+    actions: Vec<SyncAction>,
+    entities: Vec<Entity>,
+    metrics: ChunkMetrics,
+}
+
 fn process_file_chunk(
     headers: &StringRecord,
-    file_chunk: Vec<(usize, Result<StringRecord, csv::Error>)>,
+    file_chunk: Vec<(usize, Result<ByteRecord, csv::Error>)>,
     context: &Arc<SyncContext>,
 ) -> anyhow::Result<()> {
-    rayon::scope_fifo(|s| {
-        // split the big file_chunk into smaller chunks that fit in single sync requests
-        // and iterate over them, spawning a processing tasks for each sync chunk
-        let chunked_iter = file_chunk.into_iter().chunks(Criteria::MAX_LIMIT);
-        for chunk in &chunked_iter {
-            let (row_indices, records_chunk): (Vec<usize>, Vec<Result<StringRecord, csv::Error>>) =
-                chunk.unzip();
-            let first_index = *row_indices.first().unwrap_or(&0);
-            let last_index = *row_indices.last().unwrap_or(&0);
-            let chunk_length = records_chunk.len();
-
-            let context_clone = Arc::clone(context);
-            let headers = &headers;
-            s.spawn_fifo(move |_| {
-                println!("sync chunk {first_index}..={last_index} (size={chunk_length}) is now being deserialized");
-                let entity_chunk = match deserialize_chunk(headers, first_index, records_chunk, &context_clone) {
-                    Ok(chunk) => chunk,
-                    Err(e) => {
-                        println!("sync chunk {first_index}..={last_index} (size={chunk_length}) failed to deserialize:\n{e:#}");
+    // The CPU bound deserialization (rayon pool) and the network bound sync requests (own threads)
+    // run as separate pipeline stages, connected by a bounded channel.
+    // That way the network stays busy while scripts are executed for the following chunks.
+    let (sync_tx, sync_rx) = crossbeam_channel::bounded::<SequencedChunk>(context.in_flight_limit);
+    // the chunks which arrive before a slow one are held back by the ordered sync stage,
+    // the window limits them to the amount of in-flight chunks
+    let window = context
+        .ordered
+        .then(|| Arc::new(ReorderWindow::new(context.in_flight_limit)));
+
+    std::thread::scope(|thread_scope| {
+        spawn_sync_stage(thread_scope, sync_rx, window.clone(), context);
+
+        // deserialize stage
+        rayon::scope_fifo(|s| {
+            // split the big file_chunk into smaller chunks that fit in single sync requests
+            // and iterate over them, spawning a deserialize task for each sync chunk
+            let chunked_iter = file_chunk
+                .into_iter()
+                .chunks(context.profile.get_sync_chunk_size());
+            for (sequence, chunk) in (&chunked_iter).into_iter().enumerate() {
+                let (file_indices, records_chunk): (
+                    Vec<usize>,
+                    Vec<Result<ByteRecord, csv::Error>>,
+                ) = chunk.unzip();
+                let first_index = *file_indices.first().unwrap_or(&0);
+                let last_index = *file_indices.last().unwrap_or(&0);
+                let chunk_length = records_chunk.len();
+
+                let context_clone = Arc::clone(context);
+                let headers = &headers;
+                let sync_tx = sync_tx.clone();
+                let window = window.clone();
+                s.spawn_fifo(move |_| {
+                    let mut sender = ChunkSender {
+                        sequence,
+                        chunk: None,
+                        sync_tx,
+                    };
+                    // the tasks are started in sequence order, so the waiting ones never block the chunk the window waits for
+                    if let Some(window) = &window {
+                        window.wait_for(sequence);
+                    }
+                    if is_past_deadline(&context_clone) {
+                        stop_before_row(&context_clone, first_index);
                         return;
                     }
-                };
+                    println!("sync chunk {first_index}..={last_index} (size={chunk_length}) is now being deserialized");
+                    // deleting or validating must not create missing lookup values
+                    let allow_create = context_clone.sync_action == SyncAction::Upsert
+                        && !context_clone.validate_remote;
+                    let start = Instant::now();
+                    take_script_time();
+                    let (row_indices, actions, entities) = match deserialize_chunk(headers, first_index, records_chunk, &context_clone, allow_create) {
+                        Ok(chunk) => chunk.into_iter().multiunzip(),
+                        Err(e) => {
+                            println!("sync chunk {first_index}..={last_index} (size={chunk_length}) failed to deserialize:\n{e:#}");
+                            return;
+                        }
+                    };
+
+                    let metrics = ChunkMetrics {
+                        chunk: first_index,
+                        rows: chunk_length,
+                        transform: start.elapsed(),
+                        script: take_script_time(),
+                        ..Default::default()
+                    };
+
+                    // sent once the sender is dropped
+                    sender.chunk = Some(DeserializedChunk {
+                        row_indices,
+                        actions,
+                        entities,
+                        metrics,
+                    });
+                });
+            }
+        });
+
+        // close the channel, so the sync stage finishes after processing the remaining chunks
+        drop(sync_tx);
+    });
+
+    Ok(())
+}
+
+/// Spawn the threads of the sync stage, with `--ordered` a single thread syncs the chunks in file order.
+/// Every thread owns a receiver, so a panicking thread doesn't affect the others
+/// and the channel is closed for the senders once all of them stopped
+fn spawn_sync_stage<'scope, 'env>(
+    thread_scope: &'scope std::thread::Scope<'scope, 'env>,
+    sync_rx: Receiver<SequencedChunk>,
+    window: Option<Arc<ReorderWindow>>,
+    context: &'env Arc<SyncContext>,
+) {
+    let sync_threads = if context.ordered {
+        1
+    } else {
+        context
+            .profile
+            .get_concurrent_chunks(context.in_flight_limit)
+    };
+    for _ in 0..sync_threads {
+        let sync_rx = sync_rx.clone();
+        let window = window.clone();
+        thread_scope.spawn(move || {
+            if context.ordered {
+                run_ordered_sync_stage(sync_rx, window.as_deref(), context);
+            } else {
+                run_sync_stage(sync_rx, context);
+            }
+        });
+    }
+}
+
+/// Sync the chunks in the order they are deserialized, by multiple threads in parallel.
+/// Ends once all senders are dropped, so there is no more work
+fn run_sync_stage(sync_rx: Receiver<SequencedChunk>, context: &Arc<SyncContext>) {
+    for chunk in sync_rx.into_iter().filter_map(|sequenced| sequenced.chunk) {
+        sync_deserialized_chunk(chunk, context);
+    }
+}
+
+/// Sync the chunks strictly in file order, by a single thread (`--ordered`).
+/// Chunks which are deserialized early are held back until all previous chunks are synced,
+/// the window is advanced after every chunk to let the deserialization continue.
+fn run_ordered_sync_stage(
+    sync_rx: Receiver<SequencedChunk>,
+    window: Option<&ReorderWindow>,
+    context: &Arc<SyncContext>,
+) {
+    for_each_in_sequence(
+        sync_rx
+            .into_iter()
+            .map(|sequenced| (sequenced.sequence, sequenced.chunk)),
+        |chunk| {
+            if let Some(chunk) = chunk {
+                sync_deserialized_chunk(chunk, context);
+            }
+            if let Some(window) = window {
+                window.advance();
+            }
+        },
+    );
+}
+
+/// Call `f` for the items ordered by their sequence number (starting at 0),
+/// items which arrive early are held back until all previous ones arrived.
+/// Gaps in the sequence (e.g. of chunks which failed to deserialize) are skipped at the end.
+fn for_each_in_sequence<T>(items: impl IntoIterator<Item = (usize, T)>, mut f: impl FnMut(T)) {
+    let mut pending = BTreeMap::new();
+    let mut next_sequence = 0;
 
-                println!("sync chunk {first_index}..={last_index} (size={chunk_length}) is now being synced to shopware");
-                if let Err(e) = sync_chunk(&row_indices, entity_chunk, &context_clone) {
-                    println!("sync chunk {first_index}..={last_index} (size={chunk_length}) failed to be synced over API:\n{e}");
+    for (sequence, item) in items {
+        pending.insert(sequence, item);
+        while let Some(item) = pending.remove(&next_sequence) {
+            f(item);
+            next_sequence += 1;
+        }
+    }
+
+    for item in pending.into_values() {
+        f(item);
+    }
+}
+
+fn sync_deserialized_chunk(chunk: DeserializedChunk, context: &Arc<SyncContext>) {
+    let DeserializedChunk {
+        row_indices,
+        actions,
+        entities,
+        mut metrics,
+        ..
+    } = chunk;
+
+    wait_for_active_hours(context);
+    if is_past_deadline(context) {
+        // the row indices are empty if all rows of the chunk were skipped
+        stop_before_row(context, metrics.chunk);
+        return;
+    }
+
+    let first_index = *row_indices.first().unwrap_or(&0);
+    let last_index = *row_indices.last().unwrap_or(&0);
+    let chunk_length = entities.len();
+
+    if entities.is_empty() {
+        // all rows of the chunk were skipped
+        context.chunk_metrics.lock().unwrap().push(metrics);
+        return;
+    }
+
+    println!("sync chunk {first_index}..={last_index} (size={chunk_length}) is now being synced to shopware");
+    for (action, row_indices, entities) in split_by_action(row_indices, actions, entities) {
+        let hash = context
+            .synced_chunks
+            .as_ref()
+            .map(|_| get_chunk_hash(&context.profile.entity, action, &entities));
+        if let (Some(synced_chunks), Some(hash)) = (&context.synced_chunks, &hash) {
+            if synced_chunks.was_synced(hash) {
+                println!("sync chunk {first_index}..={last_index} (size={chunk_length}) was already synced by a previous run and is skipped");
+                continue;
+            }
+        }
+
+        match sync_chunk(&row_indices, entities, action, context, &mut metrics) {
+            Ok(true) => {
+                if let (Some(synced_chunks), Some(hash)) = (&context.synced_chunks, hash) {
+                    synced_chunks.add(hash);
                 }
-            });
+            }
+            Ok(false) => {}
+            Err(e) => {
+                println!("sync chunk {first_index}..={last_index} (size={chunk_length}) failed to be synced over API:\n{e}");
+            }
         }
+    }
+    context.chunk_metrics.lock().unwrap().push(metrics);
+}
 
-        Ok(())
-    })
+/// Split the rows of a chunk into one sync operation per run of consecutive rows with the same action
+/// (e.g. of an `action_column`), so the operations are applied in file order
+fn split_by_action(
+    row_indices: Vec<usize>,
+    actions: Vec<SyncAction>,
+    entities: Vec<Entity>,
+) -> Vec<(SyncAction, Vec<usize>, Vec<Entity>)> {
+    let mut operations: Vec<(SyncAction, Vec<usize>, Vec<Entity>)> = vec![];
+    for ((row_index, action), entity) in row_indices.into_iter().zip(actions).zip(entities) {
+        match operations.last_mut().filter(|(a, ..)| *a == action) {
+            Some((_, row_indices, entities)) => {
+                row_indices.push(row_index);
+                entities.push(entity);
+            }
+            None => operations.push((action, vec![row_index], vec![entity])),
+        }
+    }
+
+    operations
+}
+
+/// Deserialize the first rows of the file into the payloads, which would be sent on import
+pub fn preview(context: &Arc<SyncContext>, rows: usize) -> anyhow::Result<Vec<Entity>> {
+    let mut csv_reader = open_file(context)?;
+    let headers = csv_reader.headers()?.clone();
+    validate_file_columns(&headers, &context.profile.mappings)?;
+    validate_row_columns(&headers, &context.profile)?;
+
+    let records = csv_reader
+        .into_byte_records()
+        .filter(|record| {
+            !context.profile.skip_blank_rows || !record.as_ref().is_ok_and(is_blank_row)
+        })
+        .take(rows)
+        .collect();
+    let entities = deserialize_chunk(&headers, 0, records, context, false)?;
+
+    Ok(entities.into_iter().map(|(_, _, entity)| entity).collect())
+}
+
+/// Reader of the imported file, which skips the comment lines of the profile
+fn open_file(context: &SyncContext) -> anyhow::Result<csv::Reader<Box<dyn Read + Send>>> {
+    let csv_reader = csv::ReaderBuilder::new()
+        .delimiter(get_delimiter(context)?)
+        .comment(context.profile.get_comment_byte()?)
+        .buffer_capacity(CSV_READ_BUFFER_CAPACITY)
+        .from_reader(context.source.open(&context.file)?);
+
+    Ok(csv_reader)
+}
+
+/// Row without any value (like `;;;`), e.g. at the end of supplier feeds
+pub(super) fn is_blank_row(record: &ByteRecord) -> bool {
+    record.iter().all(|value| value.trim_ascii().is_empty())
+}
+
+fn get_delimiter(context: &SyncContext) -> anyhow::Result<u8> {
+    context
+        .format
+        .delimiter()
+        .context("fixed-width and parquet files are only supported for export")
 }
 
 fn deserialize_chunk(
     headers: &StringRecord,
     first_index: usize,
-    records_chunk: Vec<Result<StringRecord, csv::Error>>,
+    records_chunk: Vec<Result<ByteRecord, csv::Error>>,
     context: &Arc<SyncContext>,
-) -> anyhow::Result<Vec<Entity>> {
-    let mut entities: Vec<Entity> = Vec::with_capacity(Criteria::MAX_LIMIT);
+    allow_create: bool,
+) -> anyhow::Result<Vec<(usize, SyncAction, Entity)>> {
+    let action_column = match &context.profile.action_column {
+        Some(column) => Some(
+            headers
+                .iter()
+                .position(|header| header == column)
+                .with_context(|| {
+                    format!("Can't find the action column '{column}' in CSV headers")
+                })?,
+        ),
+        None => None,
+    };
+    let mut entities = Vec::with_capacity(records_chunk.len());
+    let mut removed_fields = BTreeSet::new();
+    let mut column_stats = context
+        .column_stats
+        .as_ref()
+        .map(|_| ColumnStatistics::new(headers, &context.profile.mappings));
+
+    let mut records = Vec::with_capacity(records_chunk.len());
     for (record_counter, record) in records_chunk.into_iter().enumerate() {
         let record = record?; // fail on first CSV read failure
+        let record = StringRecord::from_byte_record(record)
+            .with_context(|| format!("row {} is not valid UTF-8", record_counter + first_index))?;
+        if let Some(column_stats) = &mut column_stats {
+            column_stats.add_row(&record);
+        }
+        records.push(record);
+    }
+
+    let lookup_errors = resolve_lookups(headers, &mut records, first_index, context, allow_create)?;
+    let failed_lookups: HashSet<usize> = lookup_errors.iter().map(|e| e.row_index).collect();
+    for error in lookup_errors {
+        println!(
+            "row {} will be skipped: {} {}",
+            error.row_index, error.pointer, error.message
+        );
+        context.skipped_rows.lock().unwrap().push(error);
+    }
 
-        let entity = deserialize_row(
+    for (record_counter, record) in records.iter().enumerate() {
+        let row_index = record_counter + first_index;
+        if failed_lookups.contains(&row_index) {
+            continue;
+        }
+        let mut entity = match deserialize_row(
             headers,
-            &record,
+            record,
+            row_index,
             &context.profile,
             &context.scripting_environment,
-        )
-        .with_context(|| format!("error in row {}", record_counter + first_index))?;
+            &context.transformers,
+        ) {
+            Ok(entity) => entity,
+            Err(e) => {
+                let Some(invalid) = e.downcast_ref::<InvalidRow>() else {
+                    return Err(e.context(format!("error in row {row_index}")));
+                };
+                println!(
+                    "row {row_index} will be skipped: {} {}",
+                    invalid.pointer, invalid.message
+                );
+                context.skipped_rows.lock().unwrap().push(RowError {
+                    row_index,
+                    pointer: invalid.pointer.clone(),
+                    message: invalid.message.clone(),
+                    value: None,
+                });
+                continue;
+            }
+        };
+        if let Some(id_strategy) = &context.profile.id_strategy {
+            apply_id_strategy(
+                id_strategy,
+                &context.profile.entity,
+                &mut entity,
+                headers,
+                record,
+            )
+            .with_context(|| format!("error in row {row_index}"))?;
+        }
+
+        let action = match action_column {
+            Some(column) => parse_action(&record[column])
+                .with_context(|| format!("error in row {row_index}"))?,
+            None => context.sync_action,
+        };
+        if action == SyncAction::Delete {
+            retain_primary_keys(&context.profile.entity, &mut entity, &context.api_schema);
+            entities.push((row_index, action, entity));
+            continue;
+        }
+
+        if let Some(error) =
+            check_schema_constraints(&context.schema_constraints, &entity, row_index)
+        {
+            println!(
+                "row {row_index} will be skipped: {} {}",
+                error.pointer, error.message
+            );
+            context.skipped_rows.lock().unwrap().push(error);
+            continue;
+        }
+
+        removed_fields.extend(remove_write_protected_fields(
+            &context.profile.entity,
+            &mut entity,
+            &context.api_schema,
+        ));
+
+        if let Some(payload_limit) = &context.profile.payload_limit {
+            match limit_payload_size(&mut entity, payload_limit) {
+                Ok(stripped) if !stripped.is_empty() => {
+                    println!("Warning: removed the fields {stripped:?} from the oversized payload of row {row_index}");
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    println!("row {row_index} will be skipped: {e}");
+                    context.skipped_rows.lock().unwrap().push(RowError {
+                        row_index,
+                        pointer: String::new(),
+                        message: e.to_string(),
+                        value: None,
+                    });
+                    continue;
+                }
+            }
+        }
+
+        entities.push((row_index, action, entity));
+    }
 
-        entities.push(entity);
+    if let (Some(total), Some(column_stats)) = (&context.column_stats, column_stats) {
+        total.lock().unwrap().merge(column_stats);
+    }
+
+    if !removed_fields.is_empty() {
+        println!("Warning: removed write-protected fields from the payloads of the sync chunk starting at row {first_index}, they can't be imported: {removed_fields:?}");
     }
 
     Ok(entities)
 }
 
+/// Value of the action column of a row, empty cells are upserted
+fn parse_action(value: &str) -> anyhow::Result<SyncAction> {
+    match value.trim().to_lowercase().as_str() {
+        "" | "upsert" => Ok(SyncAction::Upsert),
+        "delete" => Ok(SyncAction::Delete),
+        value => anyhow::bail!("unknown action '{value}', expected 'upsert' or 'delete'"),
+    }
+}
+
 fn sync_chunk(
     row_indices: &[usize],
     mut chunk: Vec<Entity>,
+    action: SyncAction,
     context: &Arc<SyncContext>,
-) -> anyhow::Result<()> {
-    if let Ok(()) = attempt_chunk_sync_with_retries(row_indices, &mut chunk, context) {
-        return Ok(());
+    metrics: &mut ChunkMetrics,
+) -> anyhow::Result<bool> {
+    if context.validate_remote {
+        return validate_chunk_remote(row_indices, &chunk, action, context, metrics)
+            .map(|()| false);
+    }
+
+    let chunk_length = chunk.len();
+    let mut row_indices = row_indices.to_vec();
+    let error = match attempt_chunk_sync_with_retries(
+        &mut row_indices,
+        &mut chunk,
+        action,
+        context,
+        metrics,
+    ) {
+        // invalid rows are removed from the chunk, then it wasn't synced completely
+        Ok(()) => return Ok(row_indices.len() == chunk_length),
+        Err(error) => error,
+    };
+
+    if is_transient_error(&error) {
+        // e.g. an outage of the shop, the single row import would fail as well
+        println!("chunk import failed after all tries, its remaining {} rows are added to the retry file:\n{error:#}", chunk.len());
+        add_retry_rows(&row_indices, chunk, action, &error, context);
+        return Ok(false);
     }
 
     println!("chunk import failed; starting with single row import to filter faulty rows");
 
-    for (entity, index) in chunk.into_iter().zip(row_indices.iter()) {
-        match attempt_chunk_sync_with_retries(row_indices, &mut vec![entity], context) {
+    for (entity, index) in chunk.into_iter().zip(row_indices) {
+        let mut single_row = vec![entity];
+        let mut single_index = vec![index];
+        match attempt_chunk_sync_with_retries(
+            &mut single_index,
+            &mut single_row,
+            action,
+            context,
+            metrics,
+        ) {
             Ok(_) => {}
+            Err(error) if is_transient_error(&error) => {
+                println!("entry at row {index} failed after all tries and is added to the retry file:\n{error:#}");
+                add_retry_rows(&single_index, single_row, action, &error, context);
+            }
             Err(error) => {
                 println!("{error:?}");
                 println!("invalid entry at row {index} will be skipped");
+                context.failed_rows.lock().unwrap().push(RowError {
+                    row_index: index,
+                    pointer: String::new(),
+                    message: format!("{error:#}"),
+                    value: None,
+                });
             }
         }
     }
 
+    Ok(false)
+}
+
+/// All tries of a chunk failed because of deadlocks
+#[derive(Debug, Error)]
+#[error("max try count reached")]
+struct TriesExhausted;
+
+/// Errors which aren't caused by the payload, like network errors, timeouts, overloaded servers
+/// (5xx or 429 responses) or repeated deadlocks. Rejected payloads and all other errors are permanent
+fn is_transient_error(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<SwApiError>() {
+        Some(SwApiError::Server(status, _)) => {
+            status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+        }
+        Some(SwApiError::Request(_) | SwApiError::Timeout(_)) => true,
+        Some(_) => false,
+        None => error.is::<TriesExhausted>(),
+    }
+}
+
+/// Remember the rows for the retry file, they are listed as failed rows in the report as well
+fn add_retry_rows(
+    row_indices: &[usize],
+    chunk: Vec<Entity>,
+    action: SyncAction,
+    error: &anyhow::Error,
+    context: &SyncContext,
+) {
+    let message = format!("{error:#}");
+    let mut failed_rows = context.failed_rows.lock().unwrap();
+    let mut retry_rows = context.retry_rows.lock().unwrap();
+    for (row_index, payload) in row_indices.iter().zip(chunk) {
+        failed_rows.push(RowError {
+            row_index: *row_index,
+            pointer: String::new(),
+            message: message.clone(),
+            value: None,
+        });
+        retry_rows.push(RetryRow {
+            row_index: *row_index,
+            action: Some(action),
+            error: message.clone(),
+            payload,
+        });
+    }
+}
+
+/// Collect the errors of the server for the rows of the chunk, without writing it (`--validate-remote`)
+fn validate_chunk_remote(
+    row_indices: &[usize],
+    chunk: &[Entity],
+    action: SyncAction,
+    context: &Arc<SyncContext>,
+    metrics: &mut ChunkMetrics,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let errors = context
+        .sw_client
+        .validate_sync(&context.profile.entity, action, chunk);
+    metrics.request += start.elapsed();
+    let errors = errors?;
+
+    let row_errors = get_row_errors(errors, row_indices)?;
+
+    println!(
+        "server validation of the sync chunk starting at row {} found {} errors",
+        row_indices.first().unwrap_or(&0),
+        row_errors.len()
+    );
+    context
+        .remote_validation_errors
+        .lock()
+        .unwrap()
+        .extend(row_errors);
+
     Ok(())
 }
 
+/// Errors of the server per row, the entries of the error pointers are the positions in the chunk
+fn get_row_errors(errors: Vec<SwError>, row_indices: &[usize]) -> anyhow::Result<Vec<RowError>> {
+    let mut row_errors = Vec::with_capacity(errors.len());
+    for error in errors {
+        match &error {
+            SwError::WriteError { source, .. } => {
+                let (entry, pointer) = parse_error_pointer(&source.pointer)?;
+                let row_index = *row_indices
+                    .get(entry)
+                    .context("error pointer should have a entry in row_indices")?;
+                row_errors.push(RowError {
+                    row_index,
+                    pointer: pointer.to_string(),
+                    message: error.get_message(),
+                    value: error.get_value(),
+                });
+            }
+            SwError::GenericError { code, .. } => {
+                println!("server validation error [{code}]: {}", error.get_message())
+            }
+        }
+    }
+
+    Ok(row_errors)
+}
+
+/// Invalid entries are removed from the chunk (and their row indices) on write errors,
+/// so on failure the chunk only contains the remaining entries
 fn attempt_chunk_sync_with_retries(
-    row_indices: &[usize],
+    row_indices: &mut Vec<usize>,
     chunk: &mut Vec<Entity>,
+    action: SyncAction,
     context: &Arc<SyncContext>,
+    metrics: &mut ChunkMetrics,
 ) -> anyhow::Result<()> {
     let mut try_count = context.try_count.get();
     loop {
         if try_count == 0 {
-            return Err(anyhow!("max try count reached"));
+            return Err(TriesExhausted.into());
         }
 
-        let (error_status, error_body) =
-            match context
-                .sw_client
-                .sync(&context.profile.entity, SyncAction::Upsert, chunk)
-            {
-                Ok(()) => {
-                    return Ok(());
+        let start = Instant::now();
+        let result = context
+            .sw_client
+            .sync(&context.profile.entity, action, chunk);
+        metrics.request += start.elapsed();
+        let (error_status, error_body) = match result {
+            Ok(sync_result) => {
+                if context.index_per_chunk {
+                    index_written_products(&sync_result, context);
                 }
-                Err(SwApiError::Server(error_status, error_body)) => (error_status, error_body),
-                Err(e) => {
-                    return Err(e.into());
+                if context.profile.order_prices == OrderPrices::Recalculate {
+                    recalculate_written_orders(&sync_result, row_indices, chunk, context);
                 }
-            };
+                if let Some(sync_results) = &context.sync_results {
+                    sync_results.lock().unwrap().add(sync_result);
+                }
+                return Ok(());
+            }
+            Err(SwApiError::Server(error_status, error_body)) => (error_status, error_body),
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
 
         match error_body {
             body if body.check_for_error_code(SwError::ERROR_CODE_DEADLOCK) => {
                 println!("deadlock occurred; retry initialized");
                 try_count = try_count.saturating_sub(1);
+                metrics.retries += 1;
+                // no need to wait after the last try
+                if try_count > 0 {
+                    std::thread::sleep(
+                        context
+                            .profile
+                            .retry
+                            .get_delay(context.try_count.get() - try_count),
+                    );
+                }
             }
             ref body
                 if body
@@ -159,13 +1280,14 @@ fn attempt_chunk_sync_with_retries(
                     .any(|e| matches!(e, SwError::WriteError { .. })) =>
             {
                 println!("write error occurred; retry initialized");
-                remove_invalid_entries_from_chunk(row_indices, chunk, body);
+                remove_invalid_entries_from_chunk(row_indices, chunk, body, context);
 
                 if chunk.is_empty() {
                     return Ok(());
                 }
 
                 try_count = try_count.saturating_sub(1);
+                metrics.retries += 1;
             }
             body => {
                 return Err(SwApiError::Server(error_status, body).into());
@@ -176,20 +1298,90 @@ fn attempt_chunk_sync_with_retries(
     }
 }
 
-fn remove_invalid_entries_from_chunk(
+/// Trigger the indexing of the written products right away (`--index-per-chunk`),
+/// so they become consistent in the storefront during long imports
+fn index_written_products(sync_result: &SyncResult, context: &SyncContext) {
+    let ids = get_written_ids(sync_result);
+    if ids.is_empty() {
+        return;
+    }
+
+    if let Err(e) = context.sw_client.index_products(&ids) {
+        println!(
+            "Warning: failed to trigger the indexing of {} written products: {e}",
+            ids.len()
+        );
+    }
+}
+
+/// Ids of the written entities of a sync request. Versioned entities (like products and orders)
+/// are written with their composite primary key `{"id": ..., "versionId": ...}`, all others with their id
+fn get_written_ids(sync_result: &SyncResult) -> Vec<&str> {
+    sync_result
+        .written
+        .iter()
+        .filter_map(|written| match written {
+            serde_json::Value::Object(primary_key) => {
+                primary_key.get("id").and_then(serde_json::Value::as_str)
+            }
+            written => written.as_str(),
+        })
+        .collect()
+}
+
+/// Recalculate the prices and taxes of the written orders (`order_prices: "recalculate"`),
+/// orders which can't be recalculated are failed rows and fail the import at the end
+fn recalculate_written_orders(
+    sync_result: &SyncResult,
     row_indices: &[usize],
+    chunk: &[Entity],
+    context: &SyncContext,
+) {
+    for order_id in get_written_ids(sync_result) {
+        let Err(e) = context.sw_client.recalculate_order(order_id) else {
+            continue;
+        };
+        context
+            .failed_recalculations
+            .fetch_add(1, Ordering::Relaxed);
+
+        let message = format!("failed to recalculate the prices of order {order_id}: {e}");
+        let row_index = chunk
+            .iter()
+            .position(|entity| {
+                entity.get("id").and_then(serde_json::Value::as_str) == Some(order_id)
+            })
+            .and_then(|position| row_indices.get(position));
+        match row_index {
+            Some(row_index) => context.failed_rows.lock().unwrap().push(RowError {
+                row_index: *row_index,
+                pointer: String::new(),
+                message,
+                value: None,
+            }),
+            // e.g. orders without an id in the file, which got their id from the server
+            None => println!("Warning: {message}"),
+        }
+    }
+}
+
+fn remove_invalid_entries_from_chunk(
+    row_indices: &mut Vec<usize>,
     chunk: &mut Vec<Entity>,
     error_body: &SwErrorBody,
+    context: &SyncContext,
 ) {
     let mut to_be_removed = vec![];
     for err in &error_body.errors {
-        let (source, detail) = match err {
-            SwError::WriteError { source, detail, .. } => (source, detail),
-            err => {
-                println!("{:?}", err);
+        let source = match err {
+            SwError::WriteError { source, .. } => source,
+            SwError::GenericError { code, .. } => {
+                println!("server error [{code}]: {}", err.get_message());
                 continue;
             }
         };
+        let message = err.get_message();
+        let value = err.get_value();
 
         const PREFIX: &str = "/write_data/";
         let (entry_str, remaining_pointer) = &source.pointer[PREFIX.len()..]
@@ -199,7 +1391,7 @@ fn remove_invalid_entries_from_chunk(
             .parse()
             .expect("error pointer should contain usize");
 
-        let row_index = row_indices
+        let row_index = *row_indices
             .get(entry)
             .expect("error pointer should have a entry in row_indices");
         let row_line_number = row_index + 2;
@@ -207,12 +1399,22 @@ fn remove_invalid_entries_from_chunk(
             .get(entry)
             .expect("error pointer should have a entry in chunk");
         println!(
-            "server validation error on (CSV) line {}: {} Remaining pointer '{}' failed payload:\n{}",
+            "server validation error on (CSV) line {}: {}{} Remaining pointer '{}' failed payload:\n{}",
             row_line_number,
-            detail,
+            message,
+            value
+                .as_ref()
+                .map(|value| format!(", got {value}"))
+                .unwrap_or_default(),
             remaining_pointer,
             serde_json::to_string_pretty(&row).unwrap(),
         );
+        context.failed_rows.lock().unwrap().push(RowError {
+            row_index,
+            pointer: format!("/{remaining_pointer}"),
+            message,
+            value,
+        });
         to_be_removed.push(entry);
     }
 
@@ -224,5 +1426,457 @@ fn remove_invalid_entries_from_chunk(
 
     for index in to_be_removed {
         chunk.remove(index);
+        row_indices.remove(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        for_each_in_sequence, get_checkpoint_path, get_chunk_hash, get_retry_file_path,
+        get_row_errors, get_written_ids, is_blank_row, is_transient_error, parse_action,
+        read_checkpoint, read_file_chunk, split_by_action, FileRecords, ReorderWindow, RetryFile,
+        SyncedChunks, TriesExhausted,
+    };
+    use crate::api::{SwApiError, SwError, SwErrorBody, SyncAction, SyncResult};
+    use crate::config_file::Profile;
+    use anyhow::anyhow;
+    use csv::{ByteRecord, StringRecord};
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn read_file_chunk_limits() {
+        let data = "id;name\n1;short\n2;a much much longer name\n3;x\n4;y\n5;z\n";
+        let reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(data.as_bytes());
+        let mut records = reader.into_byte_records().enumerate();
+
+        // row count limit
+        let chunk = read_file_chunk(&mut records, 1, None);
+        assert_eq!(chunk.iter().map(|c| c.0).collect::<Vec<_>>(), vec![0]);
+
+        // byte limit is reached by the long row
+        let chunk = read_file_chunk(&mut records, 100, Some(10));
+        assert_eq!(chunk.iter().map(|c| c.0).collect::<Vec<_>>(), vec![1]);
+
+        let chunk = read_file_chunk(&mut records, 100, Some(10));
+        assert_eq!(chunk.iter().map(|c| c.0).collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        let chunk = read_file_chunk(&mut records, 100, Some(10));
+        assert!(chunk.is_empty());
+    }
+
+    #[test]
+    fn items_in_sequence() {
+        let mut items = vec![];
+        for_each_in_sequence(
+            vec![(2, 'c'), (0, 'a'), (3, 'd'), (1, 'b'), (6, 'g'), (5, 'f')],
+            |item| items.push(item),
+        );
+        // the item with sequence 4 is missing
+        assert_eq!(items, vec!['a', 'b', 'c', 'd', 'f', 'g']);
+    }
+
+    #[test]
+    fn reorder_window_waits_for_the_sync_stage() {
+        let window = std::sync::Arc::new(ReorderWindow::new(2));
+        // inside the window
+        window.wait_for(1);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let waiting = std::thread::spawn({
+            let window = std::sync::Arc::clone(&window);
+            move || {
+                window.wait_for(2);
+                tx.send(()).unwrap();
+            }
+        });
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+        window.advance();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiting.join().unwrap();
+    }
+
+    #[test]
+    fn written_ids_of_sync_responses() {
+        // `data` of a sync response, orders are written with their composite primary key
+        let written = json!([
+            { "id": "0190e5c6a2d07b4e9d2b3d0c3f1a4001", "versionId": "0fa91ce3e96a4bc2be4bd9ce752c3425" },
+            "0190e5c6a2d07b4e9d2b3d0c3f1a4002",
+            { "versionId": "0fa91ce3e96a4bc2be4bd9ce752c3425" },
+        ]);
+        let sync_result = SyncResult {
+            written: serde_json::from_value(written).unwrap(),
+            deleted: vec![],
+        };
+
+        assert_eq!(
+            get_written_ids(&sync_result),
+            [
+                "0190e5c6a2d07b4e9d2b3d0c3f1a4001",
+                "0190e5c6a2d07b4e9d2b3d0c3f1a4002"
+            ]
+        );
+    }
+
+    #[test]
+    fn transient_errors() {
+        let server_error =
+            |status| anyhow!(SwApiError::Server(status, SwErrorBody { errors: vec![] },));
+        assert!(!is_transient_error(&server_error(StatusCode::BAD_REQUEST)));
+        assert!(!is_transient_error(&server_error(
+            StatusCode::UNPROCESSABLE_ENTITY
+        )));
+        assert!(is_transient_error(&server_error(
+            StatusCode::SERVICE_UNAVAILABLE
+        )));
+        assert!(is_transient_error(&server_error(
+            StatusCode::TOO_MANY_REQUESTS
+        )));
+        assert!(is_transient_error(&anyhow!(TriesExhausted)));
+        assert!(is_transient_error(&anyhow!(SwApiError::Timeout(
+            "/api/_action/sync".to_string()
+        ))));
+        assert!(!is_transient_error(&anyhow!(SwApiError::ReadOnly(
+            "/api/_action/sync".to_string()
+        ))));
+        assert!(!is_transient_error(&anyhow!("failed to read the payload")));
+    }
+
+    #[test]
+    fn retry_file_format() {
+        assert_eq!(
+            get_retry_file_path(Path::new("./import/products.csv")),
+            PathBuf::from("./import/products.csv.retry.json")
+        );
+
+        let retry_file: RetryFile = serde_json::from_value(json!({
+            "entity": "product",
+            "action": "upsert",
+            "file": "./products.csv",
+            "rows": [
+                { "row_index": 3, "error": "operation timed out", "payload": { "id": "a", "stock": 5 } }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(retry_file.action, SyncAction::Upsert);
+        assert_eq!(retry_file.rows[0].row_index, 3);
+        assert_eq!(retry_file.rows[0].payload["stock"], json!(5));
+    }
+
+    #[test]
+    fn split_chunk_by_action() {
+        let entity = |id: &str| json!({ "id": id }).as_object().unwrap().clone();
+        let operations = split_by_action(
+            vec![4, 5, 6, 7, 8],
+            vec![
+                SyncAction::Delete,
+                SyncAction::Delete,
+                SyncAction::Upsert,
+                SyncAction::Delete,
+                SyncAction::Upsert,
+            ],
+            vec![
+                entity("a"),
+                entity("b"),
+                entity("a"),
+                entity("a"),
+                entity("c"),
+            ],
+        );
+
+        // delete(a) -> upsert(a) -> delete(a) must end with `a` deleted
+        assert_eq!(
+            operations,
+            [
+                (
+                    SyncAction::Delete,
+                    vec![4, 5],
+                    vec![entity("a"), entity("b")]
+                ),
+                (SyncAction::Upsert, vec![6], vec![entity("a")]),
+                (SyncAction::Delete, vec![7], vec![entity("a")]),
+                (SyncAction::Upsert, vec![8], vec![entity("c")]),
+            ]
+        );
+
+        assert_eq!(parse_action(" Delete").unwrap(), SyncAction::Delete);
+        assert_eq!(parse_action("").unwrap(), SyncAction::Upsert);
+        assert!(parse_action("remove").is_err());
+    }
+
+    #[test]
+    fn chunk_hashes() {
+        let entities = vec![
+            json!({ "id": "a", "stock": 1 })
+                .as_object()
+                .unwrap()
+                .clone(),
+            json!({ "id": "b", "stock": 2 })
+                .as_object()
+                .unwrap()
+                .clone(),
+        ];
+
+        let hash = get_chunk_hash("product", SyncAction::Upsert, &entities);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            hash,
+            get_chunk_hash("product", SyncAction::Upsert, &entities)
+        );
+        assert_ne!(
+            hash,
+            get_chunk_hash("product", SyncAction::Delete, &entities)
+        );
+        assert_ne!(
+            hash,
+            get_chunk_hash("category", SyncAction::Upsert, &entities)
+        );
+        assert_ne!(
+            hash,
+            get_chunk_hash("product", SyncAction::Upsert, &entities[..1])
+        );
+    }
+
+    #[test]
+    fn read_checkpoints() {
+        let dir = std::env::temp_dir().join("sw-sync-cli-read-checkpoints");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("products.csv");
+        assert_eq!(
+            get_checkpoint_path(&file),
+            dir.join("products.csv.checkpoint.json")
+        );
+
+        assert!(read_checkpoint(&file, "product")
+            .is_err_and(|e| e.to_string().contains("no checkpoint")));
+
+        std::fs::write(
+            get_checkpoint_path(&file),
+            r#"{ "entity": "product", "next_row": 1500 }"#,
+        )
+        .unwrap();
+        assert_eq!(read_checkpoint(&file, "product").unwrap(), 1500);
+        assert!(read_checkpoint(&file, "category").is_err());
+        assert!(SyncedChunks::read(&file, "product")
+            .unwrap()
+            .previous
+            .is_empty());
+
+        // a finished import with --skip-synced-chunks only has the hashes
+        let hash = get_chunk_hash("product", SyncAction::Upsert, &[]);
+        std::fs::write(
+            get_checkpoint_path(&file),
+            format!(r#"{{ "entity": "product", "synced_chunks": ["{hash}"] }}"#),
+        )
+        .unwrap();
+        assert!(read_checkpoint(&file, "product")
+            .is_err_and(|e| e.to_string().contains("no stopped import")));
+        let synced_chunks = SyncedChunks::read(&file, "product").unwrap();
+        assert!(synced_chunks.was_synced(&hash));
+        assert!(!synced_chunks.was_synced("other"));
+        synced_chunks.add("new".to_string());
+        assert_eq!(synced_chunks.skipped(), 1);
+        assert_eq!(synced_chunks.get_hashes(false).len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skip_comments_and_blank_rows() {
+        let profile: Profile =
+            serde_yaml::from_str("entity: product\nmappings: []\ncomment_char: '#'").unwrap();
+        let data = "# generated at 2024-05-01\nid;stock\na;1\n\n# b;2\n;\n c ; \n ; \n";
+        let rows: Vec<ByteRecord> = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .comment(profile.get_comment_byte().unwrap())
+            .from_reader(data.as_bytes())
+            .into_byte_records()
+            .map(Result::unwrap)
+            .filter(|record| !is_blank_row(record))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ByteRecord::from(vec!["a", "1"]),
+                ByteRecord::from(vec![" c ", " "])
+            ]
+        );
+
+        let profile: Profile =
+            serde_yaml::from_str("entity: product\nmappings: []\ncomment_char: '§'").unwrap();
+        assert!(profile.get_comment_byte().is_err());
+    }
+
+    #[test]
+    fn file_records() {
+        let data = "id;stock\na;1\n;\nb;2\nc;3\nd;4\n";
+        let records = |start_row, row_limit, skip_blank_rows| {
+            let reader = csv::ReaderBuilder::new()
+                .delimiter(b';')
+                .from_reader(data.as_bytes());
+            FileRecords {
+                reader,
+                buffer: ByteRecord::new(),
+                next_index: 0,
+                start_row,
+                row_limit,
+                skip_blank_rows,
+                data_rows: 0,
+                read_rows: 0,
+                finished: false,
+            }
+            .map(|(index, record)| (index, record.unwrap()))
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            records(0, usize::MAX, true),
+            [
+                (0, ByteRecord::from(vec!["a", "1"])),
+                (2, ByteRecord::from(vec!["b", "2"])),
+                (3, ByteRecord::from(vec!["c", "3"])),
+                (4, ByteRecord::from(vec!["d", "4"])),
+            ]
+        );
+        assert_eq!(
+            records(1, 3, false),
+            [
+                (1, ByteRecord::from(vec!["", ""])),
+                (2, ByteRecord::from(vec!["b", "2"])),
+            ]
+        );
+        // skipped blank rows don't count against the limit, the rows before the start row do
+        assert_eq!(
+            records(0, 3, true),
+            [
+                (0, ByteRecord::from(vec!["a", "1"])),
+                (2, ByteRecord::from(vec!["b", "2"])),
+                (3, ByteRecord::from(vec!["c", "3"])),
+            ]
+        );
+        assert_eq!(
+            records(2, 3, true),
+            [
+                (2, ByteRecord::from(vec!["b", "2"])),
+                (3, ByteRecord::from(vec!["c", "3"])),
+            ]
+        );
+
+        // the imported rows are counted while reading, for the status file
+        let mut records = FileRecords {
+            reader: csv::ReaderBuilder::new()
+                .delimiter(b';')
+                .from_reader(data.as_bytes()),
+            buffer: ByteRecord::new(),
+            next_index: 0,
+            start_row: 1,
+            row_limit: usize::MAX,
+            skip_blank_rows: true,
+            data_rows: 0,
+            read_rows: 0,
+            finished: false,
+        };
+        assert_eq!(records.by_ref().take(2).count(), 2);
+        assert!(!records.finished);
+        assert_eq!(records.by_ref().count(), 1);
+        assert!(records.finished);
+        assert_eq!(records.read_rows, 3);
+    }
+
+    /// Compares reading a big CSV file with `StringRecord`s (validated on read),
+    /// a new `ByteRecord` per row (validated later in parallel) and the reused buffer of `FileRecords`.
+    /// Run it with `cargo test --release -- --ignored --nocapture bench_csv_reading`,
+    /// for 2M rows (~250 MB) it measured 0.89s, 0.80s and 0.58s
+    #[test]
+    #[ignore]
+    fn bench_csv_reading() {
+        let mut data = String::from("id;name;description;stock\n");
+        for i in 0..2_000_000 {
+            data.push_str(&format!(
+                "{i:032x};Product {i};<p>A long description with some <b>html</b> for product {i}</p>;{i}\n"
+            ));
+        }
+        let reader = || {
+            csv::ReaderBuilder::new()
+                .delimiter(b';')
+                .buffer_capacity(super::CSV_READ_BUFFER_CAPACITY)
+                .from_reader(data.as_bytes())
+        };
+
+        let start = Instant::now();
+        let rows: Vec<StringRecord> = reader().into_records().map(Result::unwrap).collect();
+        println!(
+            "StringRecord: read {} rows in {:.3}s",
+            rows.len(),
+            start.elapsed().as_secs_f32()
+        );
+        drop(rows);
+
+        let start = Instant::now();
+        let rows: Vec<ByteRecord> = reader().into_byte_records().map(Result::unwrap).collect();
+        println!(
+            "ByteRecord per row: read {} rows in {:.3}s",
+            rows.len(),
+            start.elapsed().as_secs_f32()
+        );
+        drop(rows);
+
+        let start = Instant::now();
+        let records = FileRecords {
+            reader: reader(),
+            buffer: ByteRecord::new(),
+            next_index: 0,
+            start_row: 0,
+            row_limit: usize::MAX,
+            skip_blank_rows: true,
+            data_rows: 0,
+            read_rows: 0,
+            finished: false,
+        };
+        let rows: Vec<ByteRecord> = records.map(|(_, record)| record.unwrap()).collect();
+        println!(
+            "reused ByteRecord buffer: read {} rows in {:.3}s",
+            rows.len(),
+            start.elapsed().as_secs_f32()
+        );
+    }
+
+    #[test]
+    fn row_errors_of_partially_failed_chunk() {
+        let write_error = |pointer: &str| -> SwError {
+            serde_json::from_value(json!({
+                "code": "c1051bb4-d103-4f74-8988-acbcafc7fdc3",
+                "status": "400",
+                "detail": "This value should not be blank.",
+                "template": "This value should not be blank.",
+                "source": { "pointer": pointer },
+            }))
+            .unwrap()
+        };
+        // rows 6 and 7 were skipped before, so the chunk entries aren't the row offsets
+        let row_indices = [5, 8, 9];
+
+        let row_errors = get_row_errors(
+            vec![
+                write_error("/write_data/2/name"),
+                write_error("/write_data/0/stock"),
+            ],
+            &row_indices,
+        )
+        .unwrap();
+        let rows: Vec<(usize, &str)> = row_errors
+            .iter()
+            .map(|e| (e.row_index, e.pointer.as_str()))
+            .collect();
+        assert_eq!(rows, vec![(9, "/name"), (5, "/stock")]);
+
+        assert!(get_row_errors(vec![write_error("/write_data/3/name")], &row_indices).is_err());
+        assert!(get_row_errors(vec![], &row_indices).unwrap().is_empty());
     }
 }
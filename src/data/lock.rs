@@ -0,0 +1,113 @@
+//! Lock of an entity of a shop during imports, so overlapping runs (e.g. a cron job and a manual run)
+//! don't write the same entity concurrently, which ends in deadlocks on the server.
+//!
+//! The lock is an OS file lock on a file in the temp directory, it's released by the OS
+//! when the process ends in any way.
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Interval of the attempts to get the lock, while waiting for the other run
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub struct RunLock {
+    /// the lock is held as long as the file is open
+    _file: File,
+}
+
+impl RunLock {
+    /// Lock the entity of the shop, waiting at most `wait` for another run to finish
+    /// (without waiting, it fails right away)
+    pub fn acquire(base_url: &str, entity: &str, wait: Option<Duration>) -> anyhow::Result<Self> {
+        let path = get_lock_path(base_url, entity);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("failed to open lock file {path:?}"))?;
+
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("failed to lock {path:?}"))
+                }
+            }
+
+            let owner = get_owner(&mut file);
+            match wait {
+                Some(wait) if start.elapsed() < wait => {
+                    if !waiting {
+                        println!("waiting for another run on '{entity}' of {base_url} ({owner}) to finish...");
+                        waiting = true;
+                    }
+                    std::thread::sleep(RETRY_INTERVAL);
+                }
+                _ => anyhow::bail!(
+                    "another run is already syncing '{entity}' of {base_url} ({owner}), wait for it to finish or use --lock-wait to queue this run"
+                ),
+            }
+        }
+
+        // the owner is shown to the runs which can't get the lock
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "pid {}", std::process::id())?;
+        file.flush()?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// Lock file of the entity of a shop in the temp directory
+fn get_lock_path(base_url: &str, entity: &str) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(base_url.trim_end_matches('/').as_bytes());
+    hasher.update(b"\n");
+    hasher.update(entity.as_bytes());
+    let hash = format!("{:x}", hasher.finalize());
+
+    std::env::temp_dir().join(format!("sw-sync-cli-{}.lock", &hash[..16]))
+}
+
+fn get_owner(file: &mut File) -> String {
+    let mut owner = String::new();
+    if file.rewind().is_err() || file.read_to_string(&mut owner).is_err() || owner.is_empty() {
+        return "unknown process".to_string();
+    }
+
+    owner
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_entity_of_shop() {
+        let base_url = "https://lock-test.example.com";
+        let lock = RunLock::acquire(base_url, "product", None).unwrap();
+
+        assert!(RunLock::acquire(base_url, "product", None).is_err_and(|e| e
+            .to_string()
+            .contains(&format!("(pid {})", std::process::id()))));
+        assert!(RunLock::acquire(base_url, "product", Some(Duration::from_millis(10))).is_err());
+        // other entities and shops aren't locked
+        let other_entity = RunLock::acquire(base_url, "category", None).unwrap();
+        let other_shop = RunLock::acquire("https://other.example.com", "product", None).unwrap();
+
+        drop(lock);
+        RunLock::acquire(&format!("{base_url}/"), "product", None).unwrap();
+        drop((other_entity, other_shop));
+    }
+}
@@ -0,0 +1,471 @@
+//! Lookups resolve readable values of the imported file (like a manufacturer name)
+//! into the ids of the referenced records, configured per mapping:
+//! ```yaml
+//! - file_column: "manufacturer name"
+//!   entity_path: "manufacturerId"
+//!   lookup:
+//!     entity: "product_manufacturer"
+//!     field: "name"
+//!     create_missing: true
+//! ```
+//!
+//! On export the ids are replaced with the values of the referenced records again.
+//!
+//! Values are compared case-insensitively and without trailing spaces, like the collation of the database does,
+//! so "ACME" finds the record "Acme". Values matching several records fail their rows, because the id would be arbitrary.
+
+use crate::api::filter::{Criteria, CriteriaFilter};
+use crate::api::{Entity, SwClient, SwListResponse, SyncAction};
+use crate::config_file::{Lookup, Mapping};
+use crate::data::RowError;
+use crate::SyncContext;
+use anyhow::Context;
+use csv::StringRecord;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Records found for a looked up value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum LookupMatch {
+    Id(String),
+    /// the value isn't unique, these records have it
+    Ambiguous(Vec<String>),
+}
+
+impl LookupMatch {
+    fn add(&mut self, id: &str) {
+        match self {
+            Self::Id(existing) if existing == id => {}
+            Self::Id(existing) => *self = Self::Ambiguous(vec![existing.clone(), id.to_owned()]),
+            Self::Ambiguous(ids) => ids.push(id.to_owned()),
+        }
+    }
+}
+
+/// Key of a looked up value, compared like the case-insensitive collation of the database,
+/// which is used by the `equalsAny` filter of the search
+pub(super) fn get_lookup_key(value: &str) -> String {
+    value.trim_end().to_lowercase()
+}
+
+/// Ids of the already resolved values (and the values of the ids on export), shared by all sync chunks
+#[derive(Debug, Default)]
+pub struct LookupCache {
+    /// ids by the key of the looked up value, per entity and field
+    ids: Mutex<HashMap<(String, String), HashMap<String, LookupMatch>>>,
+    /// looked up values by the id, per entity and field
+    values: Mutex<HashMap<(String, String), HashMap<String, String>>>,
+}
+
+impl LookupCache {
+    fn get(&self, lookup: &Lookup, value: &str) -> Option<LookupMatch> {
+        self.ids
+            .lock()
+            .unwrap()
+            .get(&(lookup.entity.clone(), lookup.field.clone()))
+            .and_then(|ids| ids.get(&get_lookup_key(value)))
+            .cloned()
+    }
+
+    fn insert(&self, lookup: &Lookup, ids: HashMap<String, LookupMatch>) {
+        self.ids
+            .lock()
+            .unwrap()
+            .entry((lookup.entity.clone(), lookup.field.clone()))
+            .or_default()
+            .extend(ids);
+    }
+
+    fn get_value(&self, lookup: &Lookup, id: &str) -> Option<String> {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&(lookup.entity.clone(), lookup.field.clone()))
+            .and_then(|values| values.get(id))
+            .cloned()
+    }
+
+    fn insert_values(&self, lookup: &Lookup, values: HashMap<String, String>) {
+        self.values
+            .lock()
+            .unwrap()
+            .entry((lookup.entity.clone(), lookup.field.clone()))
+            .or_default()
+            .extend(values);
+    }
+}
+
+/// Replace the values of all lookup columns in the records with the ids of the referenced records.
+///
+/// The values, which aren't cached yet, are searched with a single request per column
+/// and created if `create_missing` is enabled (unless `allow_create` is false, e.g. for a preview,
+/// where they get the id they would be created with).
+/// Returns the errors of rows with values matching several records, these rows need to be skipped
+pub fn resolve_lookups(
+    headers: &StringRecord,
+    records: &mut [StringRecord],
+    first_index: usize,
+    context: &SyncContext,
+    allow_create: bool,
+) -> anyhow::Result<Vec<RowError>> {
+    let mut row_errors = vec![];
+    for mapping in &context.profile.mappings {
+        let Mapping::ByPath(path_mapping) = mapping else {
+            continue;
+        };
+        let Some(lookup) = &path_mapping.lookup else {
+            continue;
+        };
+        if path_mapping.has_lookup_ids(headers) {
+            // the file has the ids already
+            continue;
+        }
+        if path_mapping.concat.is_some() || path_mapping.split.is_some() {
+            anyhow::bail!(
+                "the lookup of column '{}' can't be combined with concat or split",
+                path_mapping.file_column
+            );
+        }
+        let column_index = headers
+            .iter()
+            .position(|header| header == path_mapping.file_column)
+            .with_context(|| {
+                format!(
+                    "Can't find column '{}' in CSV headers",
+                    path_mapping.file_column
+                )
+            })?;
+
+        let uncached: BTreeSet<&str> = records
+            .iter()
+            .filter_map(|record| record.get(column_index))
+            .filter(|value| !is_empty_value(value))
+            .filter(|value| context.lookup_cache.get(lookup, value).is_none())
+            .collect();
+        if !uncached.is_empty() {
+            let uncached: Vec<&str> = uncached.into_iter().collect();
+            let ids = search_ids(lookup, &uncached, context, allow_create)?;
+            context.lookup_cache.insert(lookup, ids);
+        }
+
+        for (record_counter, record) in records.iter_mut().enumerate() {
+            let Some(value) = record.get(column_index) else {
+                continue;
+            };
+            if is_empty_value(value) {
+                continue;
+            }
+
+            let row_index = record_counter + first_index;
+            let id = match context.lookup_cache.get(lookup, value) {
+                Some(LookupMatch::Id(id)) => id,
+                Some(LookupMatch::Ambiguous(ids)) => {
+                    row_errors.push(RowError {
+                        row_index,
+                        pointer: format!(
+                            "/{}",
+                            path_mapping.entity_path.replace('?', "").replace('.', "/")
+                        ),
+                        message: format!(
+                            "the {} '{value}' of the lookup isn't unique, it matches the {} records {ids:?}",
+                            lookup.field, lookup.entity
+                        ),
+                        value: Some(serde_json::to_string(value).unwrap_or_default()),
+                    });
+                    continue;
+                }
+                None => anyhow::bail!(
+                    "error in row {row_index}: no {} found with the {} '{value}' (column \"{}\"), use create_missing to create it",
+                    lookup.entity,
+                    lookup.field,
+                    path_mapping.file_column
+                ),
+            };
+            *record = replace_field(record, column_index, &id);
+        }
+    }
+
+    Ok(row_errors)
+}
+
+/// Replace the ids in the lookup columns of exported rows with the values of the referenced records
+/// (the reverse of the import), so the exported file can be imported again.
+/// The columns of the rows need to be in the order of the mappings
+pub fn resolve_lookup_values(
+    rows: &mut [Vec<String>],
+    context: &SyncContext,
+) -> anyhow::Result<()> {
+    for (column_index, mapping) in context.profile.mappings.iter().enumerate() {
+        let Mapping::ByPath(path_mapping) = mapping else {
+            continue;
+        };
+        let Some(lookup) = &path_mapping.lookup else {
+            continue;
+        };
+
+        let uncached: BTreeSet<&str> = rows
+            .iter()
+            .filter_map(|row| row.get(column_index))
+            .map(String::as_str)
+            .filter(|id| !is_empty_value(id))
+            .filter(|id| context.lookup_cache.get_value(lookup, id).is_none())
+            .collect();
+        if !uncached.is_empty() {
+            let uncached: Vec<&str> = uncached.into_iter().collect();
+            let values = search_values(lookup, &uncached, context)?;
+            context.lookup_cache.insert_values(lookup, values);
+        }
+
+        for row in rows.iter_mut() {
+            let Some(cell) = row.get_mut(column_index) else {
+                continue;
+            };
+            // ids of deleted records are kept, like a failed lookup on import
+            if let Some(value) = context.lookup_cache.get_value(lookup, cell) {
+                *cell = value;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Search the looked up field values of the ids
+fn search_values(
+    lookup: &Lookup,
+    ids: &[&str],
+    context: &SyncContext,
+) -> anyhow::Result<HashMap<String, String>> {
+    let mut values = HashMap::with_capacity(ids.len());
+    search_records(
+        lookup,
+        "id",
+        ids,
+        context.sw_client.as_ref(),
+        |value, id| {
+            values.insert(id.to_owned(), value.to_owned());
+        },
+    )?;
+
+    Ok(values)
+}
+
+fn is_empty_value(value: &str) -> bool {
+    value.trim().is_empty() || value.eq_ignore_ascii_case("null")
+}
+
+/// Search the ids of the values and create the missing records (if enabled)
+fn search_ids(
+    lookup: &Lookup,
+    values: &[&str],
+    context: &SyncContext,
+    allow_create: bool,
+) -> anyhow::Result<HashMap<String, LookupMatch>> {
+    let mut ids = search_existing_ids(lookup, values, context.sw_client.as_ref())?;
+    if !lookup.create_missing {
+        return Ok(ids);
+    }
+
+    // values only differing in case are created once
+    let mut missing_keys = HashSet::new();
+    let missing: Vec<&str> = values
+        .iter()
+        .copied()
+        .filter(|value| {
+            let key = get_lookup_key(value);
+            !ids.contains_key(&key) && missing_keys.insert(key)
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(ids);
+    }
+
+    let payloads: Vec<Entity> = missing
+        .iter()
+        .map(|value| get_create_payload(lookup, value))
+        .collect();
+    if allow_create {
+        println!(
+            "creating {} missing {} records for the lookup of '{}': {missing:?}",
+            missing.len(),
+            lookup.entity,
+            lookup.field
+        );
+        context
+            .sw_client
+            .sync(&lookup.entity, SyncAction::Upsert, &payloads)
+            .with_context(|| format!("failed to create the missing {} records", lookup.entity))?;
+    } else {
+        println!(
+            "the missing {} records {missing:?} would be created on import",
+            lookup.entity
+        );
+    }
+
+    for (value, payload) in missing.into_iter().zip(payloads) {
+        if let Some(serde_json::Value::String(id)) = payload.get("id") {
+            ids.insert(get_lookup_key(value), LookupMatch::Id(id.clone()));
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Search the ids of the values by their key (see `get_lookup_key`), values without a record are left out
+pub(super) fn search_existing_ids(
+    lookup: &Lookup,
+    values: &[&str],
+    sw_client: &dyn SwClient,
+) -> anyhow::Result<HashMap<String, LookupMatch>> {
+    let mut ids = HashMap::with_capacity(values.len());
+    search_records(lookup, &lookup.field, values, sw_client, |value, id| {
+        add_match(&mut ids, value, id);
+    })?;
+
+    Ok(ids)
+}
+
+/// Search the records of the lookup entity, whose `filter_field` is one of the values,
+/// and pass the looked up field value and the id of every found record to `on_record`
+fn search_records(
+    lookup: &Lookup,
+    filter_field: &str,
+    values: &[&str],
+    sw_client: &dyn SwClient,
+    mut on_record: impl FnMut(&str, &str),
+) -> anyhow::Result<()> {
+    for chunk in values.chunks(Criteria::MAX_LIMIT) {
+        let criteria = Criteria {
+            limit: Some(Criteria::MAX_LIMIT),
+            filter: vec![CriteriaFilter::EqualsAny {
+                field: filter_field.to_string(),
+                value: chunk.iter().map(|v| serde_json::json!(v)).collect(),
+            }],
+            fields: vec!["id".to_string(), lookup.field.clone()],
+            ..Default::default()
+        };
+
+        let response: SwListResponse<Entity> = sw_client
+            .list(&lookup.entity, &criteria)
+            .with_context(|| format!("failed to look up {}.{}", lookup.entity, lookup.field))?;
+        for record in response.data {
+            let value = record.get(&lookup.field).and_then(|v| v.as_str());
+            let id = record.get("id").and_then(|v| v.as_str());
+            if let (Some(value), Some(id)) = (value, id) {
+                on_record(value, id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn add_match(ids: &mut HashMap<String, LookupMatch>, value: &str, id: &str) {
+    match ids.entry(get_lookup_key(value)) {
+        Entry::Occupied(mut entry) => entry.get_mut().add(id),
+        Entry::Vacant(entry) => {
+            entry.insert(LookupMatch::Id(id.to_owned()));
+        }
+    }
+}
+
+/// Payload of a missing record, the id is derived from the value,
+/// so concurrent sync chunks (or later runs) create the same record
+fn get_create_payload(lookup: &Lookup, value: &str) -> Entity {
+    let mut hasher = Sha256::new();
+    hasher.update(lookup.entity.as_bytes());
+    hasher.update([0x1f]);
+    hasher.update(lookup.field.as_bytes());
+    hasher.update([0x1f]);
+    hasher.update(value.as_bytes());
+    let id = format!("{:x}", hasher.finalize())[..32].to_string();
+
+    let mut payload: Entity = lookup
+        .create_with
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    payload.insert("id".to_string(), serde_json::Value::String(id));
+    payload.insert(
+        lookup.field.clone(),
+        serde_json::Value::String(value.to_owned()),
+    );
+
+    payload
+}
+
+fn replace_field(record: &StringRecord, index: usize, value: &str) -> StringRecord {
+    record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| if i == index { value } else { field })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn create_payload_of_missing_record() {
+        let lookup: Lookup = serde_yaml::from_str(
+            r"
+entity: 'property_group_option'
+field: 'name'
+create_missing: true
+create_with:
+  groupId: '0190b7c5e0a67a4c8d7e3c4f2b1a0f9e'
+",
+        )
+        .unwrap();
+
+        let payload = get_create_payload(&lookup, "red");
+        let id = payload["id"].as_str().unwrap().to_string();
+        assert_eq!(id.len(), 32);
+        assert_eq!(
+            serde_json::Value::Object(payload),
+            json!({
+                "id": id,
+                "name": "red",
+                "groupId": "0190b7c5e0a67a4c8d7e3c4f2b1a0f9e"
+            })
+        );
+
+        // same value results in the same record
+        assert_eq!(get_create_payload(&lookup, "red")["id"], json!(id));
+        assert_ne!(get_create_payload(&lookup, "blue")["id"], json!(id));
+    }
+
+    #[test]
+    fn lookup_matches() {
+        let mut ids = HashMap::new();
+        add_match(&mut ids, "Acme", "a");
+        add_match(&mut ids, "Shopware", "b");
+        // the search matches case-insensitively
+        add_match(&mut ids, "SHOPWARE ", "c");
+        add_match(&mut ids, "shopware", "d");
+        add_match(&mut ids, "acme", "a");
+
+        assert_eq!(ids.len(), 2);
+        assert_eq!(
+            ids[&get_lookup_key("ACME")],
+            LookupMatch::Id("a".to_string())
+        );
+        assert_eq!(
+            ids[&get_lookup_key("shopware")],
+            LookupMatch::Ambiguous(vec!["b".to_string(), "c".to_string(), "d".to_string()])
+        );
+    }
+
+    #[test]
+    fn replace_lookup_field() {
+        let record = StringRecord::from(vec!["1", "Acme", "3"]);
+        assert_eq!(
+            replace_field(&record, 1, "0190b7c5e0a67a4c8d7e3c4f2b1a0f9e"),
+            StringRecord::from(vec!["1", "0190b7c5e0a67a4c8d7e3c4f2b1a0f9e", "3"])
+        );
+    }
+}
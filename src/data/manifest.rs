@@ -0,0 +1,86 @@
+//! Manifest of an exported file (`--manifest`), so consumers of transferred feeds can verify
+//! that the file is complete and unchanged
+
+use crate::SyncContext;
+use anyhow::Context;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    /// name of the exported file, the manifest is written next to it
+    file: String,
+    profile: &'a Path,
+    entity: &'a str,
+    shop_url: &'a str,
+    created_at: String,
+    /// data rows of the file (without the header)
+    rows: usize,
+    /// hex digest of the file as written (after the compression)
+    sha256: String,
+    gzip: bool,
+}
+
+/// Write the manifest of the exported file, returns its path
+pub fn write_manifest(context: &SyncContext, rows: usize) -> anyhow::Result<PathBuf> {
+    let manifest = Manifest {
+        file: context
+            .file
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        profile: &context.profile_path,
+        entity: &context.profile.entity,
+        shop_url: context.sw_client.base_url(),
+        created_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        rows,
+        sha256: get_file_digest(&context.file)?,
+        gzip: context.gzip,
+    };
+
+    let path = get_manifest_path(&context.file);
+    std::fs::write(&path, serde_json::to_vec_pretty(&manifest)?)
+        .with_context(|| format!("failed to write manifest {path:?}"))?;
+
+    Ok(path)
+}
+
+/// Path of the manifest, like `products.csv.manifest.json` for `products.csv`
+fn get_manifest_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".manifest.json");
+    PathBuf::from(path)
+}
+
+/// SHA-256 of the file content as hex digest
+fn get_file_digest(path: &Path) -> anyhow::Result<String> {
+    let mut file = File::open(path).with_context(|| format!("failed to read {path:?}"))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_of_file() {
+        let path = std::env::temp_dir().join("sw-sync-cli-manifest-of-file.csv");
+        std::fs::write(&path, "abc").unwrap();
+
+        assert_eq!(
+            get_file_digest(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            get_manifest_path(&path),
+            std::env::temp_dir().join("sw-sync-cli-manifest-of-file.csv.manifest.json")
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+//! Cleanup of media, which isn't used by any entity anymore (e.g. after products were removed)
+
+use crate::api::filter::{Criteria, CriteriaFilter, LogicOperator};
+use crate::api::{Entity, SwClient, SyncAction};
+use anyhow::Context;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// Associations of the media entity, which belong to the media itself and don't count as usage
+const OWN_ASSOCIATIONS: [&str; 3] = ["translations", "thumbnails", "tags"];
+
+/// Component of the administration for custom fields, which reference a media
+const MEDIA_FIELD_COMPONENT: &str = "sw-media-field";
+
+/// Filters for media without any usage: every to-many association of the media
+/// (like `productMedia` or `categories`) has to be empty
+pub fn get_unused_media_filters(api_schema: &Entity) -> anyhow::Result<Vec<CriteriaFilter>> {
+    let properties = api_schema
+        .get("media")
+        .and_then(|e| e.get("properties"))
+        .and_then(|p| p.as_object())
+        .context("media entity not found in the entity schema")?;
+
+    let filters = properties
+        .iter()
+        .filter(|(name, _)| !OWN_ASSOCIATIONS.contains(&name.as_str()))
+        .filter(|(_, property)| {
+            matches!(
+                property.get("relation").and_then(|r| r.as_str()),
+                Some("one_to_many" | "many_to_many" | "one_to_one")
+            )
+        })
+        .map(|(name, _)| CriteriaFilter::Equals {
+            field: format!("{name}.id"),
+            value: serde_json::Value::Null,
+        })
+        .collect();
+
+    Ok(filters)
+}
+
+/// Ids referenced in JSON fields, which aren't associations: the config of CMS slots and themes
+/// and the values of media custom fields. Every id of these fields is collected,
+/// so the media ids are included without knowing the structure of every slot or theme config
+fn get_referenced_media_ids(
+    sw_client: &dyn SwClient,
+    api_schema: &Entity,
+) -> anyhow::Result<HashSet<String>> {
+    let mut ids = HashSet::new();
+    collect_ids(
+        sw_client,
+        api_schema,
+        "cms_slot",
+        &["config"],
+        vec![],
+        &mut ids,
+    )
+    .context("failed to read the config of the CMS slots")?;
+    collect_ids(
+        sw_client,
+        api_schema,
+        "theme",
+        &["baseConfig", "configValues"],
+        vec![],
+        &mut ids,
+    )
+    .context("failed to read the config of the themes")?;
+
+    for (entity, fields) in get_media_custom_fields(sw_client)? {
+        if !api_schema.contains_key(&entity) {
+            continue;
+        }
+        // only the entities with a value in one of the media custom fields
+        let filter = CriteriaFilter::Multi {
+            operator: LogicOperator::Or,
+            queries: fields
+                .iter()
+                .map(|field| CriteriaFilter::Not {
+                    operator: LogicOperator::And,
+                    queries: vec![CriteriaFilter::Equals {
+                        field: format!("customFields.{field}"),
+                        value: serde_json::Value::Null,
+                    }],
+                })
+                .collect(),
+        };
+        collect_ids(
+            sw_client,
+            api_schema,
+            &entity,
+            &["customFields"],
+            vec![filter],
+            &mut ids,
+        )
+        .with_context(|| format!("failed to read the media custom fields of {entity}"))?;
+    }
+
+    Ok(ids)
+}
+
+/// Names of the media custom fields per entity of their custom field sets
+fn get_media_custom_fields(
+    sw_client: &dyn SwClient,
+) -> anyhow::Result<BTreeMap<String, BTreeSet<String>>> {
+    let criteria = Criteria {
+        limit: Some(Criteria::MAX_LIMIT),
+        associations: [(
+            "customFieldSet".to_string(),
+            Criteria {
+                associations: [("relations".to_string(), Criteria::default())].into(),
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+
+    let mut fields: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for_each_page(sw_client, "custom_field", criteria, |custom_field| {
+        let config = &custom_field["config"];
+        let is_media_field = config["componentName"] == MEDIA_FIELD_COMPONENT
+            || config["customFieldType"] == "media";
+        let Some(name) = custom_field["name"].as_str().filter(|_| is_media_field) else {
+            return;
+        };
+        let relations = custom_field
+            .pointer("/customFieldSet/relations")
+            .and_then(serde_json::Value::as_array);
+        for entity in relations
+            .into_iter()
+            .flatten()
+            .filter_map(|relation| relation["entityName"].as_str())
+        {
+            fields
+                .entry(entity.to_string())
+                .or_default()
+                .insert(name.to_string());
+        }
+    })
+    .context("failed to read the custom fields")?;
+
+    Ok(fields)
+}
+
+/// Collect the ids in the JSON fields of all (filtered) entities, including their translations
+fn collect_ids(
+    sw_client: &dyn SwClient,
+    api_schema: &Entity,
+    entity: &str,
+    fields: &[&str],
+    filter: Vec<CriteriaFilter>,
+    ids: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    let fields: BTreeSet<String> = fields.iter().map(|field| field.to_string()).collect();
+    let translation_entity = format!("{entity}_translation");
+    let has_translations = api_schema.contains_key(&translation_entity);
+
+    let mut includes = BTreeMap::from([(entity.to_string(), fields.clone())]);
+    let mut associations = BTreeMap::new();
+    if has_translations {
+        includes
+            .get_mut(entity)
+            .expect("includes of the entity")
+            .insert("translations".to_string());
+        includes.insert(translation_entity, fields);
+        associations.insert("translations".to_string(), Criteria::default());
+    }
+    let criteria = Criteria {
+        limit: Some(Criteria::MAX_LIMIT),
+        filter,
+        associations,
+        includes,
+        ..Default::default()
+    };
+
+    for_each_page(sw_client, entity, criteria, |record| {
+        add_ids(&record, ids);
+    })
+}
+
+fn for_each_page(
+    sw_client: &dyn SwClient,
+    entity: &str,
+    mut criteria: Criteria,
+    mut f: impl FnMut(serde_json::Value),
+) -> anyhow::Result<()> {
+    let limit = criteria.limit.unwrap_or(Criteria::MAX_LIMIT);
+    loop {
+        let response = sw_client.list(entity, &criteria)?;
+        let count = response.data.len();
+        response
+            .data
+            .into_iter()
+            .map(serde_json::Value::Object)
+            .for_each(&mut f);
+        if count < limit {
+            return Ok(());
+        }
+        criteria.page += 1;
+    }
+}
+
+/// Add all strings of the JSON value, which look like ids (32 hex characters)
+fn add_ids(value: &serde_json::Value, ids: &mut HashSet<String>) {
+    match value {
+        serde_json::Value::String(s) if is_id(s) => {
+            ids.insert(s.to_lowercase());
+        }
+        serde_json::Value::Array(values) => values.iter().for_each(|v| add_ids(v, ids)),
+        serde_json::Value::Object(object) => object.values().for_each(|v| add_ids(v, ids)),
+        _ => {}
+    }
+}
+
+fn is_id(value: &str) -> bool {
+    value.len() == 32 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Delete all unused media (optionally only of one media folder) in sync chunks,
+/// returns the amount of deleted media or without `force` the amount of media, which would be deleted
+pub fn cleanup_media(
+    sw_client: &dyn SwClient,
+    folder: Option<&str>,
+    force: bool,
+) -> anyhow::Result<u64> {
+    let api_schema = sw_client.entity_schema()?;
+    let referenced_ids = get_referenced_media_ids(sw_client, &api_schema)?;
+    let filters = get_cleanup_filters(&api_schema, folder, &referenced_ids)?;
+
+    let total = sw_client.get_total("media", &filters)?;
+    println!("found {total} unused media");
+    if !force || total == 0 {
+        return Ok(total);
+    }
+
+    let criteria = Criteria {
+        limit: Some(Criteria::MAX_LIMIT),
+        filter: filters,
+        includes: [("media".to_string(), BTreeSet::from(["id".to_string()]))].into(),
+        ..Default::default()
+    };
+
+    let mut deleted = 0;
+    loop {
+        // deleted media doesn't match anymore, so the first page always contains the next chunk
+        let response = sw_client.list("media", &criteria)?;
+        if response.data.is_empty() {
+            break;
+        }
+
+        let payload: Vec<Entity> = response
+            .data
+            .iter()
+            .filter_map(|media| media.get("id"))
+            .map(|id| Entity::from_iter([("id".to_string(), id.clone())]))
+            .collect();
+        sw_client
+            .sync("media", SyncAction::Delete, &payload)
+            .with_context(|| format!("failed to delete media after {deleted} were deleted"))?;
+
+        deleted += payload.len() as u64;
+        println!("deleted {deleted} of {total} unused media");
+        // media which became unused in the meantime is left for the next run
+        if deleted >= total {
+            break;
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Filters of the media to be deleted: unused by associations and not referenced in JSON fields
+fn get_cleanup_filters(
+    api_schema: &Entity,
+    folder: Option<&str>,
+    referenced_ids: &HashSet<String>,
+) -> anyhow::Result<Vec<CriteriaFilter>> {
+    let mut filters = get_unused_media_filters(api_schema)?;
+    if let Some(folder) = folder {
+        filters.push(CriteriaFilter::Equals {
+            field: "mediaFolder.name".to_string(),
+            value: json!(folder),
+        });
+    }
+    if !referenced_ids.is_empty() {
+        let mut referenced_ids: Vec<&String> = referenced_ids.iter().collect();
+        referenced_ids.sort_unstable();
+        filters.push(CriteriaFilter::Not {
+            operator: LogicOperator::And,
+            queries: vec![CriteriaFilter::EqualsAny {
+                field: "id".to_string(),
+                value: referenced_ids.into_iter().map(|id| json!(id)).collect(),
+            }],
+        });
+    }
+
+    Ok(filters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeShop;
+
+    #[test]
+    fn unused_media_filters() {
+        let api_schema: Entity = serde_json::from_str(
+            &std::fs::read_to_string("./fixtures/entity-schema-2024-08-01.json").unwrap(),
+        )
+        .unwrap();
+
+        let filters = get_unused_media_filters(&api_schema).unwrap();
+        let fields: Vec<&str> = filters
+            .iter()
+            .map(|filter| match filter {
+                CriteriaFilter::Equals { field, value } => {
+                    assert!(value.is_null());
+                    field.as_str()
+                }
+                filter => panic!("unexpected filter {filter:?}"),
+            })
+            .collect();
+
+        assert!(fields.contains(&"productMedia.id"));
+        assert!(fields.contains(&"categories.id"));
+        assert!(fields.contains(&"themeMedia.id"));
+        // own associations and many to one associations aren't usages
+        assert!(!fields.contains(&"thumbnails.id"));
+        assert!(!fields.contains(&"translations.id"));
+        assert!(!fields.contains(&"mediaFolder.id"));
+
+        assert!(get_unused_media_filters(&Entity::new()).is_err());
+    }
+
+    #[test]
+    fn media_referenced_in_json_fields() {
+        let sw_client = FakeShop::with_entities(json!({
+            "cms_slot": [{
+                "config": { "media": { "source": "static", "value": "0190e5c6a2d07b4e9d2b3d0c3f1a3a01" } },
+                "translations": [{
+                    "config": { "sliderItems": { "source": "static", "value": [
+                        { "mediaId": "0190E5C6A2D07B4E9D2B3D0C3F1A3A02", "url": null }
+                    ] } }
+                }]
+            }],
+            "theme": [{
+                "baseConfig": null,
+                "configValues": { "sw-logo-desktop": { "value": "0190e5c6a2d07b4e9d2b3d0c3f1a3a03" } }
+            }],
+            "custom_field": [
+                {
+                    "name": "brand_logo",
+                    "config": { "componentName": "sw-media-field" },
+                    "customFieldSet": { "relations": [{ "entityName": "product_manufacturer" }] }
+                },
+                {
+                    "name": "brand_note",
+                    "config": { "componentName": "sw-field" },
+                    "customFieldSet": { "relations": [{ "entityName": "product" }] }
+                }
+            ],
+            "product_manufacturer": [{ "customFields": { "brand_logo": "0190e5c6a2d07b4e9d2b3d0c3f1a3a04" } }],
+            // not searched, the custom field isn't a media field
+            "product": [{ "customFields": { "brand_note": "0190e5c6a2d07b4e9d2b3d0c3f1a3a05" } }],
+        }));
+        let api_schema = sw_client.entity_schema().unwrap();
+
+        let ids = get_referenced_media_ids(&sw_client, &api_schema).unwrap();
+        assert_eq!(
+            ids,
+            HashSet::from(
+                [
+                    "0190e5c6a2d07b4e9d2b3d0c3f1a3a01",
+                    "0190e5c6a2d07b4e9d2b3d0c3f1a3a02",
+                    "0190e5c6a2d07b4e9d2b3d0c3f1a3a03",
+                    "0190e5c6a2d07b4e9d2b3d0c3f1a3a04",
+                ]
+                .map(String::from)
+            )
+        );
+
+        let filters = get_cleanup_filters(&api_schema, Some("Product Media"), &ids).unwrap();
+        let [.., folder, referenced] = filters.as_slice() else {
+            panic!("folder and referenced filters expected");
+        };
+        assert_eq!(
+            folder,
+            &CriteriaFilter::Equals {
+                field: "mediaFolder.name".to_string(),
+                value: json!("Product Media"),
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(referenced).unwrap(),
+            json!({
+                "type": "not",
+                "operator": "and",
+                "queries": [{
+                    "type": "equalsAny",
+                    "field": "id",
+                    "value": [
+                        "0190e5c6a2d07b4e9d2b3d0c3f1a3a01",
+                        "0190e5c6a2d07b4e9d2b3d0c3f1a3a02",
+                        "0190e5c6a2d07b4e9d2b3d0c3f1a3a03",
+                        "0190e5c6a2d07b4e9d2b3d0c3f1a3a04",
+                    ]
+                }]
+            })
+        );
+    }
+}
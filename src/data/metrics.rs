@@ -0,0 +1,145 @@
+//! Timings of the processed chunks (sync chunks on import, pages on export),
+//! to compare the throughput of runs, e.g. after changing a profile
+
+use serde::{Serialize, Serializer};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct ChunkMetrics {
+    /// index of the first row of the sync chunk on import, the page on export
+    pub chunk: usize,
+    pub rows: usize,
+    /// time to deserialize (import) or serialize (export) the rows, including the scripts and lookups
+    #[serde(serialize_with = "serialize_millis")]
+    pub transform: Duration,
+    /// time spent in the scripts (and the wasm module)
+    #[serde(serialize_with = "serialize_millis")]
+    pub script: Duration,
+    /// time of all requests of the chunk, including the retries
+    #[serde(serialize_with = "serialize_millis")]
+    pub request: Duration,
+    pub retries: usize,
+}
+
+/// Durations are written as milliseconds to the report
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+/// Percentile breakdown of one metric over all chunks
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Percentiles<T> {
+    pub p50: T,
+    pub p90: T,
+    pub p99: T,
+    pub max: T,
+}
+
+impl<T: Copy + Ord> Percentiles<T> {
+    /// Returns `None` without values
+    pub fn of(mut values: Vec<T>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+
+        Some(Self {
+            p50: nearest_rank(&values, 50),
+            p90: nearest_rank(&values, 90),
+            p99: nearest_rank(&values, 99),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+/// Percentile of sorted values by the nearest-rank method
+fn nearest_rank<T: Copy>(sorted: &[T], percentile: usize) -> T {
+    let rank = (percentile * sorted.len()).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+/// Printable lines of the percentiles of every metric
+pub fn summarize(metrics: &[ChunkMetrics]) -> Vec<String> {
+    let durations: [(&str, Vec<Duration>); 3] = [
+        ("transform", metrics.iter().map(|m| m.transform).collect()),
+        ("script", metrics.iter().map(|m| m.script).collect()),
+        ("request", metrics.iter().map(|m| m.request).collect()),
+    ];
+
+    let mut lines = vec![];
+    for (name, values) in durations {
+        if let Some(p) = Percentiles::of(values) {
+            lines.push(format!(
+                "{name}: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                p.p50, p.p90, p.p99, p.max
+            ));
+        }
+    }
+    if let Some(retries) = Percentiles::of(metrics.iter().map(|m| m.retries).collect()) {
+        let total: usize = metrics.iter().map(|m| m.retries).sum();
+        lines.push(format!(
+            "retries: {total} in total, p50 {}, p90 {}, p99 {}, max {}",
+            retries.p50, retries.p90, retries.p99, retries.max
+        ));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_by_nearest_rank() {
+        let values: Vec<usize> = (1..=100).rev().collect();
+        let percentiles = Percentiles::of(values).unwrap();
+        assert_eq!(
+            percentiles,
+            Percentiles {
+                p50: 50,
+                p90: 90,
+                p99: 99,
+                max: 100
+            }
+        );
+
+        let single = Percentiles::of(vec![7]).unwrap();
+        assert_eq!((single.p50, single.p99, single.max), (7, 7, 7));
+
+        assert!(Percentiles::<usize>::of(vec![]).is_none());
+    }
+
+    #[test]
+    fn summarize_chunks() {
+        let metrics = vec![
+            ChunkMetrics {
+                chunk: 0,
+                rows: 2,
+                transform: Duration::from_millis(20),
+                script: Duration::from_millis(10),
+                request: Duration::from_millis(300),
+                retries: 0,
+            },
+            ChunkMetrics {
+                chunk: 2,
+                rows: 1,
+                transform: Duration::from_millis(40),
+                script: Duration::from_millis(30),
+                request: Duration::from_millis(100),
+                retries: 2,
+            },
+        ];
+
+        assert_eq!(
+            summarize(&metrics),
+            vec![
+                "transform: p50 20ms, p90 40ms, p99 40ms, max 40ms",
+                "script: p50 10ms, p90 30ms, p99 30ms, max 30ms",
+                "request: p50 100ms, p90 300ms, p99 300ms, max 300ms",
+                "retries: 2 in total, p50 0, p90 2, p99 2, max 2",
+            ]
+        );
+        assert!(summarize(&[]).is_empty());
+    }
+}
@@ -1,11 +1,59 @@
+mod associations;
+mod baseline;
+mod check;
+mod compare;
+mod constraints;
+mod documents;
 mod export;
+mod ids;
 mod import;
+mod lock;
+mod lookup;
+mod manifest;
+mod media;
+mod metrics;
+mod parquet;
+mod product_stream;
+mod report;
+mod sample;
+mod sanitize;
+mod schema_diff;
+mod stats;
+mod status;
+mod storage;
 mod transform;
 mod validate;
 
 // reexport the important functions / structs as part of this module
-pub use export::export;
-pub use import::import;
+pub use associations::{get_associations, get_includes, resolve_id_lists};
+pub use check::check_profile;
+pub use compare::{compare_snapshots, fetch_snapshot, get_compared_mappings};
+pub use constraints::{get_schema_constraints, SchemaConstraint};
+pub use documents::prepare_documents_folder;
+pub use export::{add_live_version_filter, add_sales_channel_filter, export, export_by_language};
+pub use import::{
+    import, import_retry_file, preview, read_checkpoint, update_checkpoint, write_retry_file,
+    RetryRow, SyncedChunks,
+};
+pub use lock::RunLock;
+pub use lookup::LookupCache;
+pub use media::cleanup_media;
+pub use metrics::ChunkMetrics;
+pub use product_stream::add_product_stream_filter;
+pub use report::{send_webhook, RowError, SyncFailure, SyncReport, SyncedIds, WebhookPayload};
+pub use sample::write_sample;
+pub use schema_diff::diff_schemas;
+pub use stats::ColumnStatistics;
+pub use status::with_status_file;
+#[cfg(any(test, feature = "testing"))]
+pub use storage::InMemory;
+pub use storage::{get_sink, get_source, is_local_file, DataSink, DataSource};
+pub(crate) use transform::is_wildcard_path;
+pub use transform::plugins::{get_transformers, RowTransformer};
 pub use transform::script::prepare_scripting_environment;
 pub use transform::script::ScriptingEnvironment;
-pub use validate::validate_paths_for_entity;
+pub use transform::wasm::WasmModule;
+pub use validate::{
+    get_invalid_associations, get_unmapped_columns, validate_base64_files, validate_file_columns,
+    validate_paths_for_entity, validate_row_columns, validate_script_keys,
+};
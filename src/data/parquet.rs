@@ -0,0 +1,317 @@
+//! Parquet export (feature `parquet`) for the ingestion into data warehouses
+//!
+//! Every exported page becomes its own row group. The page workers encode the column chunks
+//! of their page in parallel, the writer thread only appends the encoded row groups in page order
+//! and writes the footer with the metadata of all row groups at the end.
+
+use crate::config_file::Profile;
+use std::path::Path;
+
+#[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+#[derive(Clone)]
+pub struct ParquetSchema {
+    #[cfg(feature = "parquet")]
+    arrow: arrow_schema::SchemaRef,
+    #[cfg(feature = "parquet")]
+    parquet: std::sync::Arc<parquet::schema::types::SchemaDescriptor>,
+    #[cfg(feature = "parquet")]
+    properties: parquet::file::properties::WriterPropertiesPtr,
+    /// strings are exported with a leading apostrophe (`string_quoting: apostrophe`), which is removed again
+    #[cfg(feature = "parquet")]
+    strip_text_marker: bool,
+    /// serialized null values (`null_value` of the profile), they are written as parquet nulls
+    #[cfg(feature = "parquet")]
+    null_value: String,
+}
+
+/// Encoded column chunks of one page
+#[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+pub struct RowGroup {
+    rows: usize,
+    #[cfg(feature = "parquet")]
+    columns: Vec<parquet::arrow::arrow_writer::ArrowColumnChunk>,
+}
+
+#[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+pub struct ParquetFileWriter {
+    #[cfg(feature = "parquet")]
+    writer: parquet::file::writer::SerializedFileWriter<std::fs::File>,
+}
+
+impl RowGroup {
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl ParquetSchema {
+    /// Schema with a nullable column per exported column (with the header as name),
+    /// `number` columns are stored as doubles, `boolean` columns as booleans and all others as strings
+    pub fn new(profile: &Profile, header: &[String]) -> anyhow::Result<Self> {
+        use crate::config_file::{ColumnType, Mapping, StringQuoting};
+        use arrow_schema::{DataType, Field, Schema};
+        use parquet::basic::Compression;
+        use parquet::file::properties::WriterProperties;
+
+        // meta-columns are prepended to the mappings
+        let meta_columns = header.len() - profile.mappings.len();
+        let fields: Vec<Field> = header
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let column_type = index
+                    .checked_sub(meta_columns)
+                    .and_then(|index| match &profile.mappings[index] {
+                        Mapping::ByPath(m) => m.column_type.as_ref(),
+                        Mapping::ByScript(m) => m.column_type.as_ref(),
+                    });
+                let data_type = match column_type {
+                    Some(ColumnType::Number) => DataType::Float64,
+                    Some(ColumnType::Boolean) => DataType::Boolean,
+                    Some(ColumnType::String) | None => DataType::Utf8,
+                };
+
+                Field::new(name, data_type, true)
+            })
+            .collect();
+
+        let arrow = std::sync::Arc::new(Schema::new(fields));
+        let parquet = parquet::arrow::arrow_to_parquet_schema(&arrow)?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+
+        Ok(Self {
+            arrow,
+            parquet: std::sync::Arc::new(parquet),
+            properties: std::sync::Arc::new(properties),
+            strip_text_marker: profile.string_quoting == StringQuoting::Apostrophe,
+            null_value: profile.get_null_value().to_string(),
+        })
+    }
+
+    /// Encode the serialized rows of a page into a row group, empty and null values become null
+    pub fn encode(&self, rows: &[&[String]]) -> anyhow::Result<RowGroup> {
+        use anyhow::Context;
+        use arrow_array::{ArrayRef, BooleanArray, Float64Array, StringArray};
+        use arrow_schema::DataType;
+        use parquet::arrow::arrow_writer::{compute_leaves, get_column_writers};
+
+        let mut writers = get_column_writers(&self.parquet, &self.properties, &self.arrow)?;
+        let mut leaf_writers = writers.iter_mut();
+        for (index, field) in self.arrow.fields().iter().enumerate() {
+            let values = rows.iter().map(|row| {
+                row.get(index)
+                    .map(String::as_str)
+                    .filter(|v| !v.is_empty() && *v != self.null_value)
+            });
+
+            let array: ArrayRef = match field.data_type() {
+                DataType::Float64 => std::sync::Arc::new(
+                    values
+                        .map(|value| value.map(str::parse::<f64>).transpose())
+                        .collect::<Result<Float64Array, _>>()
+                        .with_context(|| {
+                            format!(
+                                "column '{}' contains a value which isn't a number",
+                                field.name()
+                            )
+                        })?,
+                ),
+                DataType::Boolean => std::sync::Arc::new(
+                    values
+                        .map(|value| value.map(str::parse::<bool>).transpose())
+                        .collect::<Result<BooleanArray, _>>()
+                        .with_context(|| {
+                            format!(
+                                "column '{}' contains a value which isn't a boolean",
+                                field.name()
+                            )
+                        })?,
+                ),
+                _ => std::sync::Arc::new(
+                    values
+                        .map(|value| match value {
+                            Some(value) if self.strip_text_marker => {
+                                Some(value.strip_prefix('\'').unwrap_or(value))
+                            }
+                            value => value,
+                        })
+                        .collect::<StringArray>(),
+                ),
+            };
+
+            for leaf in compute_leaves(field, &array)? {
+                leaf_writers
+                    .next()
+                    .context("parquet schema has fewer leaf columns than the export")?
+                    .write(&leaf)?;
+            }
+        }
+
+        let columns = writers
+            .into_iter()
+            .map(|writer| writer.close())
+            .collect::<Result<_, _>>()?;
+
+        Ok(RowGroup {
+            rows: rows.len(),
+            columns,
+        })
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl ParquetFileWriter {
+    pub fn create(path: &Path, schema: &ParquetSchema) -> anyhow::Result<Self> {
+        let writer = parquet::file::writer::SerializedFileWriter::new(
+            std::fs::File::create(path)?,
+            schema.parquet.root_schema_ptr(),
+            std::sync::Arc::clone(&schema.properties),
+        )?;
+
+        Ok(Self { writer })
+    }
+
+    /// Append an encoded row group, empty ones (e.g. of skipped pages) are left out
+    pub fn append(&mut self, row_group: RowGroup) -> anyhow::Result<()> {
+        if row_group.rows == 0 {
+            return Ok(());
+        }
+
+        let mut row_group_writer = self.writer.next_row_group()?;
+        for column in row_group.columns {
+            column.append_to_row_group(&mut row_group_writer)?;
+        }
+        row_group_writer.close()?;
+
+        Ok(())
+    }
+
+    /// Write the footer, the file is only readable afterward
+    pub fn close(self) -> anyhow::Result<()> {
+        self.writer.close()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+impl ParquetSchema {
+    pub fn new(_profile: &Profile, _header: &[String]) -> anyhow::Result<Self> {
+        anyhow::bail!("the format 'parquet' is not available in this build, it needs to be compiled with the feature 'parquet'")
+    }
+
+    pub fn encode(&self, _rows: &[&[String]]) -> anyhow::Result<RowGroup> {
+        unreachable!("parquet schema can't be created without the feature 'parquet'")
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+impl ParquetFileWriter {
+    pub fn create(_path: &Path, _schema: &ParquetSchema) -> anyhow::Result<Self> {
+        unreachable!("parquet schema can't be created without the feature 'parquet'")
+    }
+
+    pub fn append(&mut self, _row_group: RowGroup) -> anyhow::Result<()> {
+        unreachable!("parquet schema can't be created without the feature 'parquet'")
+    }
+
+    pub fn close(self) -> anyhow::Result<()> {
+        unreachable!("parquet schema can't be created without the feature 'parquet'")
+    }
+}
+
+#[cfg(all(test, feature = "parquet"))]
+mod tests {
+    use super::*;
+    use arrow_array::{Array, BooleanArray, Float64Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn write_row_groups() {
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "name"
+    entity_path: "name"
+  - file_column: "stock"
+    entity_path: "stock"
+    column_type: "number"
+  - file_column: "active"
+    entity_path: "active"
+    column_type: "boolean"
+"#,
+        )
+        .unwrap();
+        let header = vec![
+            "name".to_string(),
+            "stock".to_string(),
+            "active".to_string(),
+        ];
+        let schema = ParquetSchema::new(&profile, &header).unwrap();
+
+        let rows = |values: &[[&str; 3]]| -> Vec<Vec<String>> {
+            values
+                .iter()
+                .map(|row| row.iter().map(|v| v.to_string()).collect())
+                .collect()
+        };
+        let first_page = rows(&[["Shirt", "10", "true"], ["Shoe", "2.5", "false"]]);
+        let second_page = rows(&[["", "", ""], ["null", "null", "null"]]);
+        // row groups can be encoded in any order / thread
+        let encode = |rows: &[Vec<String>]| {
+            schema
+                .encode(&rows.iter().map(Vec::as_slice).collect::<Vec<_>>())
+                .unwrap()
+        };
+        let second_row_group = encode(&second_page);
+        let first_row_group = encode(&first_page);
+
+        let path = std::env::temp_dir().join("sw-sync-cli-write-row-groups.parquet");
+        let mut writer = ParquetFileWriter::create(&path, &schema).unwrap();
+        writer.append(first_row_group).unwrap();
+        writer.append(encode(&[])).unwrap();
+        writer.append(second_row_group).unwrap();
+        writer.close().unwrap();
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let batches: Vec<_> = reader.build().unwrap().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let column = |index: usize| batches.iter().map(move |batch| batch.column(index));
+        let names: Vec<_> = column(0)
+            .flat_map(|c| c.as_any().downcast_ref::<StringArray>().unwrap().iter())
+            .collect();
+        assert_eq!(names, [Some("Shirt"), Some("Shoe"), None, None]);
+        let stocks: Vec<_> = column(1)
+            .flat_map(|c| c.as_any().downcast_ref::<Float64Array>().unwrap().iter())
+            .collect();
+        assert_eq!(stocks, [Some(10.0), Some(2.5), None, None]);
+        let active: Vec<_> = column(2)
+            .flat_map(|c| c.as_any().downcast_ref::<BooleanArray>().unwrap().iter())
+            .collect();
+        assert_eq!(active, [Some(true), Some(false), None, None]);
+
+        // with a custom null_value the text "null" is a regular string
+        let profile = Profile {
+            null_value: Some("NULL".to_string()),
+            ..profile
+        };
+        let schema = ParquetSchema::new(&profile, &header).unwrap();
+        assert!(schema
+            .encode(&[&["null".to_string(), "NULL".to_string(), "NULL".to_string()]])
+            .is_ok());
+        assert!(schema
+            .encode(&[&["NULL".to_string(), "null".to_string(), "NULL".to_string()]])
+            .is_err_and(|e| e.to_string().contains("'stock'")));
+
+        assert!(schema
+            .encode(&[&["Shirt".to_string(), "many".to_string(), String::new()]])
+            .is_err_and(|e| e.to_string().contains("'stock'")));
+    }
+}
@@ -0,0 +1,159 @@
+//! Export of the products of a dynamic product group (`product_stream`),
+//! by converting the filters of the stream into the criteria filters of the export
+
+use crate::api::filter::{Criteria, CriteriaFilter};
+use crate::api::{Entity, SwClient, SwListResponse};
+use crate::config_file::Profile;
+use anyhow::Context;
+use serde_json::Value;
+
+/// Limit the export of products to the ones matching the product stream (by its name)
+pub fn add_product_stream_filter(
+    profile: &mut Profile,
+    product_stream: &str,
+    sw_client: &dyn SwClient,
+) -> anyhow::Result<()> {
+    if profile.entity != "product" {
+        anyhow::bail!(
+            "only products can be exported for a product stream, but the profile entity is '{}'",
+            profile.entity
+        );
+    }
+
+    let criteria = Criteria {
+        limit: Some(1),
+        filter: vec![CriteriaFilter::Equals {
+            field: "name".to_string(),
+            value: Value::String(product_stream.to_string()),
+        }],
+        fields: vec![
+            "id".to_string(),
+            "name".to_string(),
+            "apiFilter".to_string(),
+        ],
+        ..Default::default()
+    };
+    let response: SwListResponse<Entity> = sw_client.list("product_stream", &criteria)?;
+    let stream = response
+        .data
+        .into_iter()
+        .next()
+        .with_context(|| format!("product stream '{product_stream}' not found"))?;
+
+    let Some(Value::Array(api_filter)) = stream.get("apiFilter") else {
+        anyhow::bail!(
+            "product stream '{product_stream}' has no filters yet, it might need to be indexed first"
+        );
+    };
+    for filter in api_filter {
+        let filter = convert_stream_filter(filter.clone()).with_context(|| {
+            format!("failed to convert the filter {filter} of product stream '{product_stream}'")
+        })?;
+        profile.filter.push(filter);
+    }
+
+    Ok(())
+}
+
+/// Convert a filter of the stream (`apiFilter`) into a criteria filter.
+///
+/// The stream filters differ from the ones of the API criteria in a few places:
+/// the fields are prefixed with the entity (`product.active`), the operators are uppercase
+/// and the values of `equalsAny` are joined by `|`
+fn convert_stream_filter(mut filter: Value) -> anyhow::Result<CriteriaFilter> {
+    normalize_stream_filter(&mut filter);
+    Ok(serde_json::from_value(filter)?)
+}
+
+fn normalize_stream_filter(filter: &mut Value) {
+    let Some(object) = filter.as_object_mut() else {
+        return;
+    };
+
+    if let Some(Value::String(field)) = object.get_mut("field") {
+        if let Some(stripped) = field.strip_prefix("product.") {
+            *field = stripped.to_string();
+        }
+    }
+
+    if let Some(Value::String(operator)) = object.get_mut("operator") {
+        *operator = operator.to_lowercase();
+    }
+
+    if object.get("type").and_then(Value::as_str) == Some("equalsAny") {
+        if let Some(Value::String(values)) = object.get("value") {
+            let values = values
+                .split('|')
+                .map(|value| Value::String(value.to_string()))
+                .collect();
+            object.insert("value".to_string(), Value::Array(values));
+        }
+    }
+
+    if let Some(Value::Array(queries)) = object.get_mut("queries") {
+        queries.iter_mut().for_each(normalize_stream_filter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::filter::{LogicOperator, RangeParameters};
+    use serde_json::json;
+
+    #[test]
+    fn convert_filters_of_stream() {
+        let filter = convert_stream_filter(json!({
+            "type": "multi",
+            "operator": "OR",
+            "queries": [
+                {
+                    "type": "multi",
+                    "operator": "AND",
+                    "queries": [
+                        { "type": "equals", "field": "product.active", "value": "1" },
+                        { "type": "equalsAny", "field": "product.manufacturerId", "value": "a|b" },
+                        {
+                            "type": "range",
+                            "field": "product.stock",
+                            "parameters": { "gte": 10 }
+                        }
+                    ]
+                }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            filter,
+            CriteriaFilter::Multi {
+                operator: LogicOperator::Or,
+                queries: vec![CriteriaFilter::Multi {
+                    operator: LogicOperator::And,
+                    queries: vec![
+                        CriteriaFilter::Equals {
+                            field: "active".to_string(),
+                            value: json!("1"),
+                        },
+                        CriteriaFilter::EqualsAny {
+                            field: "manufacturerId".to_string(),
+                            value: vec![json!("a"), json!("b")],
+                        },
+                        CriteriaFilter::Range {
+                            field: "stock".to_string(),
+                            parameters: serde_json::from_value::<RangeParameters>(
+                                json!({ "gte": 10 })
+                            )
+                            .unwrap(),
+                        },
+                    ],
+                }],
+            }
+        );
+
+        assert!(
+            convert_stream_filter(json!({ "type": "until", "field": "product.releaseDate" }))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,655 @@
+//! Summary of a sync run, printed at the end and optionally written as JSON (`--report`)
+//! or sent to a webhook (`--webhook`)
+
+use crate::api::SyncResult;
+use crate::cli::{SyncArgs, SyncMode};
+use crate::config_file::RetryOptions;
+use crate::data::metrics::{self, ChunkMetrics};
+use crate::data::stats::ColumnStats;
+use crate::SyncContext;
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU8;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// Maximum amount of warnings listed in the printed summary, the report file contains all of them
+const MAX_PRINTED_WARNINGS: usize = 20;
+
+/// Maximum amount of written (and deleted) ids kept for the report, the ones of bigger imports are only counted
+const MAX_REPORTED_IDS: usize = 100_000;
+
+#[derive(Debug, Default, Serialize)]
+pub struct SyncReport {
+    pub file: PathBuf,
+    /// emitted by the scripts with `warn(message)`
+    pub script_warnings: Vec<RowWarning>,
+    /// pages of the export which failed and were skipped (`--skip-failed-pages`)
+    pub failed_pages: Vec<u64>,
+    /// entities which were already written on a previous page of the export (`--dedupe`)
+    pub suppressed_duplicates: usize,
+    /// columns of the imported file, which aren't used by any mapping (`--warn-unused`)
+    pub unmapped_columns: Vec<String>,
+    /// entity path columns of the export, which didn't have a value in any row (`--warn-unused`)
+    pub empty_columns: Vec<String>,
+    /// statistics of the mapped columns of the import (`--column-stats`)
+    pub column_stats: Vec<ColumnStats>,
+    /// errors of the server for the rows of the import (`--validate-remote`)
+    pub remote_validation_errors: Vec<RowError>,
+    /// rows of the import which were skipped, e.g. because their payload is too large
+    pub skipped_rows: Vec<RowError>,
+    /// rows of the import which were rejected by the server
+    pub failed_rows: Vec<RowError>,
+    /// amount of the entities written by the import (created or updated)
+    pub written_count: usize,
+    /// ids of the entities written by the import, limited to the first `MAX_REPORTED_IDS`
+    pub written_ids: Vec<serde_json::Value>,
+    /// amount of the entities deleted by the import (`--mode delete`)
+    pub deleted_count: usize,
+    /// ids of the entities deleted by the import, limited to the first `MAX_REPORTED_IDS`
+    pub deleted_ids: Vec<serde_json::Value>,
+    /// timings of the sync chunks (import) or pages (export)
+    pub chunk_metrics: Vec<ChunkMetrics>,
+    /// row where the import stopped after the maximum duration, it's continued there with `--resume`
+    pub resume_row: Option<usize>,
+}
+
+/// Ids of the entities written and deleted by the import, collected for the report (`--report` or `--webhook`)
+#[derive(Debug, Default)]
+pub struct SyncedIds {
+    written: Vec<serde_json::Value>,
+    written_count: usize,
+    deleted: Vec<serde_json::Value>,
+    deleted_count: usize,
+}
+
+impl SyncedIds {
+    pub fn add(&mut self, result: SyncResult) {
+        self.written_count += result.written.len();
+        self.deleted_count += result.deleted.len();
+        let remaining = MAX_REPORTED_IDS.saturating_sub(self.written.len());
+        self.written
+            .extend(result.written.into_iter().take(remaining));
+        let remaining = MAX_REPORTED_IDS.saturating_sub(self.deleted.len());
+        self.deleted
+            .extend(result.deleted.into_iter().take(remaining));
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct RowWarning {
+    pub row_index: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct RowError {
+    pub row_index: usize,
+    /// JSON pointer to the invalid field in the payload of the row, e.g. `/name`
+    pub pointer: String,
+    pub message: String,
+    /// offending value, as formatted by the server (e.g. `"abc"` or `null`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+impl SyncReport {
+    /// Collect the report data of a finished run
+    pub fn collect(context: &SyncContext) -> Self {
+        Self {
+            file: context.file.clone(),
+            script_warnings: context.scripting_environment.take_warnings(),
+            failed_pages: {
+                let mut failed_pages = context.failed_pages.lock().unwrap().clone();
+                failed_pages.sort_unstable();
+                failed_pages
+            },
+            suppressed_duplicates: context.suppressed_duplicates.load(Ordering::Relaxed),
+            unmapped_columns: context.unmapped_columns.lock().unwrap().clone(),
+            empty_columns: context.empty_columns.lock().unwrap().clone(),
+            column_stats: context
+                .column_stats
+                .as_ref()
+                .map(|stats| stats.lock().unwrap().clone().into_columns())
+                .unwrap_or_default(),
+            remote_validation_errors: {
+                let mut errors = context.remote_validation_errors.lock().unwrap().clone();
+                errors.sort_by_key(|error| error.row_index);
+                errors
+            },
+            skipped_rows: {
+                let mut skipped_rows = context.skipped_rows.lock().unwrap().clone();
+                skipped_rows.sort_by_key(|row| row.row_index);
+                skipped_rows
+            },
+            failed_rows: {
+                let mut failed_rows = context.failed_rows.lock().unwrap().clone();
+                failed_rows.sort_by_key(|row| row.row_index);
+                failed_rows
+            },
+            written_count: context
+                .sync_results
+                .as_ref()
+                .map_or(0, |results| results.lock().unwrap().written_count),
+            written_ids: context
+                .sync_results
+                .as_ref()
+                .map(|results| results.lock().unwrap().written.clone())
+                .unwrap_or_default(),
+            deleted_count: context
+                .sync_results
+                .as_ref()
+                .map_or(0, |results| results.lock().unwrap().deleted_count),
+            deleted_ids: context
+                .sync_results
+                .as_ref()
+                .map(|results| results.lock().unwrap().deleted.clone())
+                .unwrap_or_default(),
+            chunk_metrics: {
+                let mut chunk_metrics = context.chunk_metrics.lock().unwrap().clone();
+                chunk_metrics.sort_by_key(|metrics| metrics.chunk);
+                chunk_metrics
+            },
+            resume_row: *context.resume_row.lock().unwrap(),
+        }
+    }
+
+    pub fn print_summary(&self) {
+        if !self.failed_pages.is_empty() {
+            println!(
+                "Warning: {} pages failed and are missing in the export: {:?}",
+                self.failed_pages.len(),
+                self.failed_pages
+            );
+        }
+
+        if self.suppressed_duplicates > 0 {
+            println!(
+                "Suppressed {} duplicated entities, which shifted between pages during the export",
+                self.suppressed_duplicates
+            );
+        }
+
+        if !self.unmapped_columns.is_empty() {
+            println!(
+                "Warning: the columns {:?} of the file aren't used by any mapping",
+                self.unmapped_columns
+            );
+        }
+
+        if !self.empty_columns.is_empty() {
+            println!(
+                "Warning: the columns {:?} didn't have a value in any exported row, check the entity paths of their mappings",
+                self.empty_columns
+            );
+        }
+
+        if !self.column_stats.is_empty() {
+            println!("Column statistics:");
+            for stats in &self.column_stats {
+                let lengths = match (stats.min_length, stats.max_length) {
+                    (Some(min), Some(max)) => format!("{min}..={max}"),
+                    _ => "-".to_string(),
+                };
+                println!(
+                    "- {}: {} nulls, ~{} distinct values, length {}, {} type conversion failures",
+                    stats.column,
+                    stats.nulls,
+                    stats.distinct_estimate,
+                    lengths,
+                    stats.cast_failures
+                );
+            }
+        }
+
+        if !self.chunk_metrics.is_empty() {
+            println!("Timings of {} chunks:", self.chunk_metrics.len());
+            for line in metrics::summarize(&self.chunk_metrics) {
+                println!("- {line}");
+            }
+        }
+
+        if !self.skipped_rows.is_empty() {
+            println!("Warning: {} rows were skipped:", self.skipped_rows.len());
+            print_row_errors(&self.skipped_rows);
+        }
+
+        if !self.failed_rows.is_empty() {
+            println!(
+                "Warning: {} rows were rejected by the server:",
+                self.failed_rows.len()
+            );
+            print_row_errors(&self.failed_rows);
+        }
+
+        if !self.remote_validation_errors.is_empty() {
+            let rows: HashSet<usize> = self
+                .remote_validation_errors
+                .iter()
+                .map(|error| error.row_index)
+                .collect();
+            println!(
+                "The server validation found {} errors in {} rows:",
+                self.remote_validation_errors.len(),
+                rows.len()
+            );
+            print_row_errors(&self.remote_validation_errors);
+        }
+
+        if self.script_warnings.is_empty() {
+            return;
+        }
+
+        println!("Scripts emitted {} warnings:", self.script_warnings.len());
+        for warning in self.script_warnings.iter().take(MAX_PRINTED_WARNINGS) {
+            println!("- row {}: {}", warning.row_index, warning.message);
+        }
+        if self.script_warnings.len() > MAX_PRINTED_WARNINGS {
+            println!(
+                "... and {} more (use --report to get all of them)",
+                self.script_warnings.len() - MAX_PRINTED_WARNINGS
+            );
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("failed to create report file {path:?}"))?;
+        serde_json::to_writer_pretty(file, self)
+            .with_context(|| format!("failed to write report file {path:?}"))?;
+
+        Ok(())
+    }
+}
+
+fn print_row_errors(errors: &[RowError]) {
+    let lines = get_aggregated_lines(errors);
+    for line in lines.iter().take(MAX_PRINTED_WARNINGS) {
+        println!("- {line}");
+    }
+    if lines.len() > MAX_PRINTED_WARNINGS {
+        println!(
+            "... and {} more (use --report to get all of them)",
+            lines.len() - MAX_PRINTED_WARNINGS
+        );
+    }
+}
+
+/// Lines of the row errors, identical errors (same field and message) of multiple rows
+/// are aggregated into one line, in the order of their first appearance
+fn get_aggregated_lines(errors: &[RowError]) -> Vec<String> {
+    /// Maximum amount of rows listed in an aggregated line
+    const MAX_LISTED_ROWS: usize = 5;
+
+    // groups in order of their first error, indexed by (pointer, message)
+    let mut groups: Vec<(&str, &str, Vec<&RowError>)> = vec![];
+    let mut group_indices: HashMap<(&str, &str), usize> = HashMap::new();
+    for error in errors {
+        let key = (error.pointer.as_str(), error.message.as_str());
+        match group_indices.get(&key) {
+            Some(&index) => groups[index].2.push(error),
+            None => {
+                group_indices.insert(key, groups.len());
+                groups.push((key.0, key.1, vec![error]));
+            }
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(pointer, message, group)| {
+            let field = if pointer.is_empty() {
+                String::new()
+            } else {
+                format!(" {pointer}")
+            };
+
+            match group.as_slice() {
+                [error] => {
+                    let value = match &error.value {
+                        Some(value) => format!(", got {value}"),
+                        None => String::new(),
+                    };
+                    format!("row {}{field}: {message}{value}", error.row_index)
+                }
+                _ => {
+                    let rows: Vec<String> = group
+                        .iter()
+                        .take(MAX_LISTED_ROWS)
+                        .map(|error| error.row_index.to_string())
+                        .collect();
+                    let more = match group.len().checked_sub(MAX_LISTED_ROWS) {
+                        Some(more) if more > 0 => format!(" and {more} more"),
+                        _ => String::new(),
+                    };
+                    format!(
+                        "{} rows{field}: {message} (rows {}{more})",
+                        group.len(),
+                        rows.join(", ")
+                    )
+                }
+            }
+        })
+        .collect()
+}
+
+/// Error which stopped a sync run, with the report collected until then
+#[derive(Debug)]
+pub struct SyncFailure {
+    pub error: anyhow::Error,
+    /// not available if the run failed before it started, e.g. because of an invalid profile
+    pub report: Option<Box<SyncReport>>,
+}
+
+impl From<anyhow::Error> for SyncFailure {
+    fn from(error: anyhow::Error) -> Self {
+        Self {
+            error,
+            report: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Success,
+    /// stopped after the maximum duration (`--max-duration`)
+    Resumable,
+    Failed,
+}
+
+/// Body of the request to the webhook (`--webhook`) at the end of a run
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    pub status: RunStatus,
+    pub mode: SyncMode,
+    pub profile: &'a Path,
+    pub file: &'a Path,
+    /// error which stopped the run
+    pub error: Option<String>,
+    /// collected until the error on failed runs, not available if the run failed before it started
+    pub report: Option<&'a SyncReport>,
+}
+
+impl<'a> WebhookPayload<'a> {
+    pub fn new(args: &'a SyncArgs, result: &'a Result<SyncReport, SyncFailure>) -> Self {
+        let (status, error, report) = match result {
+            Ok(report) if report.resume_row.is_some() => (RunStatus::Resumable, None, Some(report)),
+            Ok(report) => (RunStatus::Success, None, Some(report)),
+            Err(failure) => (
+                RunStatus::Failed,
+                Some(format!("{:#}", failure.error)),
+                failure.report.as_deref(),
+            ),
+        };
+
+        Self {
+            status,
+            mode: args.mode,
+            profile: &args.profile,
+            file: &args.file,
+            error,
+            report,
+        }
+    }
+}
+
+/// POST the payload to the webhook, retrying on network errors and unsuccessful responses
+pub fn send_webhook(
+    url: &str,
+    payload: &WebhookPayload,
+    timeout: Option<Duration>,
+    try_count: NonZeroU8,
+    retry: &RetryOptions,
+) -> anyhow::Result<()> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .build()?;
+
+    let mut tries = 0;
+    loop {
+        tries += 1;
+        let error = match client.post(url).json(payload).send() {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => anyhow!("webhook responded with status {}", response.status()),
+            Err(e) => anyhow!(e),
+        };
+
+        if tries >= try_count.get() {
+            return Err(error.context(format!("failed after {tries} tries")));
+        }
+        println!("sending the summary to the webhook failed; retry initialized: {error:#}");
+        std::thread::sleep(retry.get_delay(tries));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{FileFormat, PreviewArgs};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn report_as_json() {
+        let report = SyncReport {
+            file: PathBuf::from("./products.csv"),
+            script_warnings: vec![RowWarning {
+                row_index: 4,
+                message: "negative stock".to_string(),
+            }],
+            failed_pages: vec![3],
+            suppressed_duplicates: 2,
+            unmapped_columns: vec!["comment".to_string()],
+            empty_columns: vec![],
+            column_stats: vec![],
+            remote_validation_errors: vec![RowError {
+                row_index: 7,
+                pointer: "/name".to_string(),
+                message: "This value should not be blank.".to_string(),
+                value: None,
+            }],
+            skipped_rows: vec![],
+            failed_rows: vec![RowError {
+                row_index: 9,
+                pointer: "/stock".to_string(),
+                message: "This value should be of type int.".to_string(),
+                value: None,
+            }],
+            written_count: 2,
+            written_ids: vec![json!("a"), json!("b")],
+            deleted_count: 0,
+            deleted_ids: vec![],
+            chunk_metrics: vec![ChunkMetrics {
+                chunk: 0,
+                rows: 500,
+                transform: Duration::from_millis(120),
+                script: Duration::from_millis(80),
+                request: Duration::from_micros(1500),
+                retries: 1,
+            }],
+            resume_row: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "file": "./products.csv",
+                "script_warnings": [
+                    { "row_index": 4, "message": "negative stock" }
+                ],
+                "failed_pages": [3],
+                "suppressed_duplicates": 2,
+                "unmapped_columns": ["comment"],
+                "empty_columns": [],
+                "column_stats": [],
+                "remote_validation_errors": [
+                    { "row_index": 7, "pointer": "/name", "message": "This value should not be blank." }
+                ],
+                "skipped_rows": [],
+                "failed_rows": [
+                    { "row_index": 9, "pointer": "/stock", "message": "This value should be of type int." }
+                ],
+                "written_count": 2,
+                "written_ids": ["a", "b"],
+                "deleted_count": 0,
+                "deleted_ids": [],
+                "chunk_metrics": [
+                    { "chunk": 0, "rows": 500, "transform": 120.0, "script": 80.0, "request": 1.5, "retries": 1 }
+                ],
+                "resume_row": null
+            })
+        );
+    }
+
+    #[test]
+    fn aggregate_row_errors() {
+        let error = |row_index, pointer: &str, message: &str, value: Option<&str>| RowError {
+            row_index,
+            pointer: pointer.to_string(),
+            message: message.to_string(),
+            value: value.map(str::to_string),
+        };
+        let mut errors = vec![
+            error(1, "/name", "value is required, but empty", Some("\"\"")),
+            error(2, "/taxId", "the referenced tax doesn't exist", None),
+            error(3, "", "payload is too large", None),
+        ];
+        errors.extend((4..12).map(|row| error(row, "/name", "value is required, but empty", None)));
+
+        assert_eq!(
+            get_aggregated_lines(&errors),
+            [
+                "9 rows /name: value is required, but empty (rows 1, 4, 5, 6, 7 and 4 more)",
+                "row 2 /taxId: the referenced tax doesn't exist",
+                "row 3: payload is too large",
+            ]
+        );
+        assert_eq!(
+            get_aggregated_lines(&errors[..1]),
+            [r#"row 1 /name: value is required, but empty, got """#]
+        );
+    }
+
+    #[test]
+    fn webhook_payload_of_failed_run() {
+        let args = SyncArgs::from(PreviewArgs {
+            profile: PathBuf::from("./profiles/product.yaml"),
+            map: vec![],
+            file: PathBuf::from("./products.csv"),
+            format: FileFormat::Csv,
+            rows: 5,
+            no_validate: false,
+        });
+        let result = Err(SyncFailure::from(
+            anyhow!("connection refused").context("failed to read the schema"),
+        ));
+
+        assert_eq!(
+            serde_json::to_value(WebhookPayload::new(&args, &result)).unwrap(),
+            json!({
+                "status": "failed",
+                "mode": "import",
+                "profile": "./profiles/product.yaml",
+                "file": "./products.csv",
+                "error": "failed to read the schema: connection refused",
+                "report": null
+            })
+        );
+
+        // the rows rejected until the error are still reported
+        let result = Err(SyncFailure {
+            error: anyhow!("max try count reached"),
+            report: Some(Box::new(SyncReport {
+                file: PathBuf::from("./products.csv"),
+                failed_rows: vec![RowError {
+                    row_index: 9,
+                    pointer: "/stock".to_string(),
+                    message: "This value should be of type int.".to_string(),
+                    value: None,
+                }],
+                written_count: 500,
+                ..Default::default()
+            })),
+        });
+        let payload = serde_json::to_value(WebhookPayload::new(&args, &result)).unwrap();
+        assert_eq!(payload["status"], "failed");
+        assert_eq!(payload["error"], "max try count reached");
+        assert_eq!(payload["report"]["written_count"], 500);
+        assert_eq!(
+            payload["report"]["failed_rows"],
+            json!([{ "row_index": 9, "pointer": "/stock", "message": "This value should be of type int." }])
+        );
+    }
+
+    #[test]
+    fn synced_ids_are_limited() {
+        let mut synced_ids = SyncedIds::default();
+        synced_ids.add(SyncResult {
+            written: vec![json!("a"); MAX_REPORTED_IDS - 1],
+            deleted: vec![json!("b")],
+        });
+        synced_ids.add(SyncResult {
+            written: vec![json!("c"); 3],
+            deleted: vec![],
+        });
+
+        assert_eq!(synced_ids.written_count, MAX_REPORTED_IDS + 2);
+        assert_eq!(synced_ids.written.len(), MAX_REPORTED_IDS);
+        assert_eq!(synced_ids.written.last(), Some(&json!("c")));
+        assert_eq!(synced_ids.deleted_count, 1);
+        assert_eq!(synced_ids.deleted, [json!("b")]);
+    }
+
+    #[test]
+    fn send_webhook_with_retry() {
+        let mut server = mockito::Server::new();
+        let failing = server
+            .mock("POST", "/hook")
+            .with_status(503)
+            .expect(1)
+            .create();
+        let succeeding = server
+            .mock("POST", "/hook")
+            .match_header("content-type", "application/json")
+            .match_body(mockito::Matcher::PartialJson(json!({ "status": "failed" })))
+            .create();
+
+        let args = SyncArgs::from(PreviewArgs {
+            profile: PathBuf::from("./profile.yaml"),
+            map: vec![],
+            file: PathBuf::from("./data.csv"),
+            format: FileFormat::Csv,
+            rows: 5,
+            no_validate: false,
+        });
+        let result = Err(SyncFailure::from(anyhow!("failed")));
+        let payload = WebhookPayload::new(&args, &result);
+        let url = format!("{}/hook", server.url());
+        let retry = RetryOptions {
+            delay_ms: 0,
+            ..Default::default()
+        };
+
+        send_webhook(&url, &payload, None, NonZeroU8::new(2).unwrap(), &retry).unwrap();
+        failing.assert();
+        succeeding.assert();
+
+        let broken = server
+            .mock("POST", "/broken")
+            .with_status(500)
+            .expect(2)
+            .create();
+        let url = format!("{}/broken", server.url());
+        let error =
+            send_webhook(&url, &payload, None, NonZeroU8::new(2).unwrap(), &retry).unwrap_err();
+        broken.assert();
+        assert_eq!(
+            format!("{error:#}"),
+            "failed after 2 tries: webhook responded with status 500 Internal Server Error"
+        );
+    }
+}
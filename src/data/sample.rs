@@ -0,0 +1,210 @@
+//! Sample files of a profile, which can be handed out as the specification of the import files
+
+use crate::cli::FileFormat;
+use crate::config_file::{ColumnType, Mapping, Profile};
+use anyhow::Context;
+use std::path::Path;
+
+/// Separator of id lists, if the mapping doesn't define one
+const DEFAULT_SEPARATOR: &str = "|";
+
+/// Write a file with the header line of the profile and `rows` rows of example values
+pub fn write_sample(
+    profile: &Profile,
+    path: &Path,
+    format: FileFormat,
+    rows: usize,
+) -> anyhow::Result<()> {
+    let delimiter = format
+        .delimiter()
+        .context("samples can only be written as csv or tsv")?;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_path(path)
+        .with_context(|| format!("failed to create sample file {path:?}"))?;
+
+    let columns = get_sample_columns(profile);
+    writer.write_record(columns.iter().map(|(column, _)| column))?;
+    for row in 0..rows {
+        writer.write_record(
+            columns
+                .iter()
+                .map(|(column, m)| sample_value(column, m, row)),
+        )?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Columns of the sample with the mapping of their example values, which are the columns read
+/// on import (e.g. every column of a `concat`). A column read by multiple mappings
+/// (e.g. split into several fields) is only written once
+fn get_sample_columns(profile: &Profile) -> Vec<(&str, &Mapping)> {
+    let mut columns: Vec<(&str, &Mapping)> = vec![];
+    for mapping in &profile.mappings {
+        for column in mapping.get_import_columns() {
+            if !columns.iter().any(|(existing, _)| *existing == column) {
+                columns.push((column, mapping));
+            }
+        }
+    }
+
+    columns
+}
+
+/// Example value of the column of the mapping for the (zero based) row, inferred from the default,
+/// the lookup, the column type and the entity path. The columns of a `concat` get text values
+fn sample_value(column: &str, mapping: &Mapping, row: usize) -> String {
+    let number = row + 1;
+    let column_type = match mapping {
+        Mapping::ByPath(by_path) if by_path.concat.is_some() => {
+            return format!("{column} {number}")
+        }
+        Mapping::ByPath(by_path) => {
+            if let Some(default) = &by_path.default {
+                return default.clone();
+            }
+            if let Some(lookup) = &by_path.lookup {
+                return format!("{} {number}", lookup.field);
+            }
+
+            let field = by_path
+                .entity_path
+                .rsplit('.')
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches('?');
+            if field.ends_with("Ids") {
+                let separator = by_path.separator.as_deref().unwrap_or(DEFAULT_SEPARATOR);
+                return [sample_id(number), sample_id(number + 1)].join(separator);
+            }
+            if field == "id" || field.ends_with("Id") {
+                return sample_id(number);
+            }
+
+            &by_path.column_type
+        }
+        Mapping::ByScript(by_script) => &by_script.column_type,
+    };
+
+    match column_type {
+        Some(ColumnType::Number) => number.to_string(),
+        Some(ColumnType::Boolean) => row.is_multiple_of(2).to_string(),
+        Some(ColumnType::String) | None => format!("{column} {number}"),
+    }
+}
+
+/// Id in the format of shopware (32 hex characters), which differs per number
+fn sample_id(number: usize) -> String {
+    format!("{number:032x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_values() {
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "id"
+    entity_path: "id"
+  - file_column: "name"
+    entity_path: "name"
+  - file_column: "stock"
+    entity_path: "stock"
+    column_type: "number"
+  - file_column: "active"
+    entity_path: "active"
+    column_type: "boolean"
+  - file_column: "tax"
+    entity_path: "taxId"
+    default: "19"
+  - file_column: "manufacturer"
+    entity_path: "manufacturerId"
+    lookup:
+      entity: "product_manufacturer"
+      field: "name"
+  - file_column: "categories"
+    entity_path: "categoryIds"
+    separator: ","
+  - file_column: "price"
+    key: "price"
+"#,
+        )
+        .unwrap();
+
+        let rows: Vec<Vec<String>> = (0..2)
+            .map(|row| {
+                profile
+                    .mappings
+                    .iter()
+                    .map(|m| sample_value(m.get_file_column(), m, row))
+                    .collect()
+            })
+            .collect();
+
+        assert_eq!(
+            rows[0],
+            vec![
+                "00000000000000000000000000000001",
+                "name 1",
+                "1",
+                "true",
+                "19",
+                "name 1",
+                "00000000000000000000000000000001,00000000000000000000000000000002",
+                "price 1",
+            ]
+        );
+        assert_eq!(rows[1][2..4], ["2", "false"]);
+    }
+
+    #[test]
+    fn shared_columns_are_written_once() {
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+mappings:
+  - file_column: "name"
+    entity_path: "name"
+  - file_column: "name"
+    entity_path: "metaTitle"
+  - file_column: "stock"
+    entity_path: "stock"
+    column_type: "number"
+  - file_column: "street"
+    entity_path: "street"
+    concat:
+      columns: ["street name", "house number"]
+      separator: " "
+  - file_column: "house number"
+    entity_path: "customFields.houseNumber"
+"#,
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join("sw-sync-cli-shared-sample-columns.csv");
+
+        write_sample(&profile, &path, FileFormat::Csv, 1).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            content,
+            "name;stock;street name;house number\nname 1;1;street name 1;house number 1\n"
+        );
+
+        // the sample has all columns read on import
+        let headers = csv::StringRecord::from(
+            content
+                .lines()
+                .next()
+                .unwrap()
+                .split(';')
+                .collect::<Vec<_>>(),
+        );
+        assert!(crate::data::validate_file_columns(&headers, &profile.mappings).is_ok());
+    }
+}
@@ -0,0 +1,331 @@
+//! Cleanup of deserialized entities before they are sent to shopware
+
+use crate::api::Entity;
+use crate::config_file::PayloadLimit;
+
+/// Reduce the entity to its primary key fields (by the `primary_key` flag of the entity schema),
+/// which are all that's needed to delete it. The entity is left untouched, if the schema doesn't know any
+pub fn retain_primary_keys(entity_name: &str, entity: &mut Entity, api_schema: &Entity) {
+    let Some(properties) = api_schema
+        .get(entity_name)
+        .and_then(|e| e.get("properties"))
+        .and_then(|p| p.as_object())
+    else {
+        return;
+    };
+    let is_primary_key = |key: &str| {
+        properties
+            .get(key)
+            .and_then(|p| p.get("flags"))
+            .and_then(|f| f.get("primary_key"))
+            .and_then(|f| f.as_bool())
+            == Some(true)
+    };
+
+    if properties.keys().any(|key| is_primary_key(key)) {
+        entity.retain(|key, _| is_primary_key(key));
+    }
+}
+
+/// Remove all fields from the entity (and nested association objects) that can't be written
+/// over the API according to the entity schema (`write_protected`, `runtime` and `computed` fields).
+///
+/// Returns the paths of the removed fields, e.g. `autoIncrement` or `manufacturer.translated`
+pub fn remove_write_protected_fields(
+    entity_name: &str,
+    entity: &mut Entity,
+    api_schema: &Entity,
+) -> Vec<String> {
+    let mut removed = vec![];
+    remove_write_protected_fields_recursive(entity_name, entity, api_schema, "", &mut removed);
+    removed
+}
+
+fn remove_write_protected_fields_recursive(
+    entity_name: &str,
+    entity: &mut Entity,
+    api_schema: &Entity,
+    path_prefix: &str,
+    removed: &mut Vec<String>,
+) {
+    let Some(properties) = api_schema
+        .get(entity_name)
+        .and_then(|e| e.get("properties"))
+        .and_then(|p| p.as_object())
+    else {
+        return;
+    };
+
+    entity.retain(|key, _| {
+        let Some(flags) = properties.get(key).and_then(|p| p.get("flags")) else {
+            return true;
+        };
+
+        let is_write_protected = flags.get("write_protected").is_some()
+            || flags.get("runtime").and_then(|f| f.as_bool()) == Some(true)
+            || flags.get("computed").and_then(|f| f.as_bool()) == Some(true);
+
+        if is_write_protected {
+            removed.push(format!("{path_prefix}{key}"));
+        }
+
+        !is_write_protected
+    });
+
+    for (key, value) in entity.iter_mut() {
+        let Some(property) = properties.get(key) else {
+            continue;
+        };
+        if property.get("type").and_then(|t| t.as_str()) != Some("association") {
+            continue;
+        }
+        let Some(association_entity) = property.get("entity").and_then(|e| e.as_str()) else {
+            continue;
+        };
+        let is_to_many = matches!(
+            property.get("relation").and_then(|r| r.as_str()),
+            Some("one_to_many" | "many_to_many")
+        );
+
+        let nested_prefix = format!("{path_prefix}{key}.");
+        match value {
+            serde_json::Value::Object(child) if !is_to_many => {
+                remove_write_protected_fields_recursive(
+                    association_entity,
+                    child,
+                    api_schema,
+                    &nested_prefix,
+                    removed,
+                );
+            }
+            // to-many associations can also be written as an object, e.g. translations keyed by language
+            serde_json::Value::Object(children) => {
+                for child in children.values_mut() {
+                    if let serde_json::Value::Object(child) = child {
+                        remove_write_protected_fields_recursive(
+                            association_entity,
+                            child,
+                            api_schema,
+                            &nested_prefix,
+                            removed,
+                        );
+                    }
+                }
+            }
+            serde_json::Value::Array(children) => {
+                for child in children {
+                    if let serde_json::Value::Object(child) = child {
+                        remove_write_protected_fields_recursive(
+                            association_entity,
+                            child,
+                            api_schema,
+                            &nested_prefix,
+                            removed,
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Remove the `strip_fields` of the limit (in their order) from an entity, whose JSON payload
+/// is bigger than the `max_bytes` of the limit, until it fits.
+///
+/// Returns the removed fields, or an error if the payload is still too large
+pub fn limit_payload_size(
+    entity: &mut Entity,
+    limit: &PayloadLimit,
+) -> anyhow::Result<Vec<String>> {
+    let mut stripped = vec![];
+    let mut size = get_payload_size(entity);
+    for field in &limit.strip_fields {
+        if size <= limit.max_bytes.get() {
+            break;
+        }
+        if remove_field(entity, field) {
+            stripped.push(field.clone());
+            size = get_payload_size(entity);
+        }
+    }
+
+    if size > limit.max_bytes.get() {
+        anyhow::bail!(
+            "payload too large: {size} bytes, but the limit is {} bytes",
+            limit.max_bytes
+        );
+    }
+
+    Ok(stripped)
+}
+
+fn get_payload_size(entity: &Entity) -> usize {
+    serde_json::to_vec(entity).map_or(0, |payload| payload.len())
+}
+
+/// Remove a field by its path (like `translations.de-DE.description`), returns if it existed
+fn remove_field(entity: &mut Entity, path: &str) -> bool {
+    match path.split_once('.') {
+        Some((key, rest)) => match entity.get_mut(key) {
+            Some(serde_json::Value::Object(child)) => remove_field(child, rest),
+            _ => false,
+        },
+        None => entity.remove(path).is_some(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn retain_primary_keys_for_delete() {
+        let api_schema = json!({
+            "product_category": {
+                "properties": {
+                    "productId": { "type": "uuid", "flags": { "primary_key": true } },
+                    "categoryId": { "type": "uuid", "flags": { "primary_key": true } },
+                    "product": { "type": "association", "entity": "product" }
+                }
+            },
+            "plugin_entity": { "properties": { "name": { "type": "string" } } }
+        });
+        let api_schema = api_schema.as_object().unwrap();
+
+        let mut entity =
+            json!({ "productId": "a", "categoryId": "b", "product": { "name": "Shirt" } })
+                .as_object()
+                .unwrap()
+                .clone();
+        retain_primary_keys("product_category", &mut entity, api_schema);
+        assert_eq!(
+            serde_json::Value::Object(entity),
+            json!({ "productId": "a", "categoryId": "b" })
+        );
+
+        let mut entity = json!({ "name": "a" }).as_object().unwrap().clone();
+        retain_primary_keys("plugin_entity", &mut entity, api_schema);
+        assert_eq!(serde_json::Value::Object(entity), json!({ "name": "a" }));
+    }
+
+    #[test]
+    fn remove_write_protected_fields_nested() {
+        let api_schema = json!({
+            "product": {
+                "entity": "product",
+                "properties": {
+                    "id": { "type": "uuid", "flags": { "primary_key": true } },
+                    "autoIncrement": { "type": "int", "flags": { "write_protected": [[]] } },
+                    "available": { "type": "boolean", "flags": { "write_protected": [["system"]] } },
+                    "stock": { "type": "int", "flags": { "required": true } },
+                    "manufacturer": {
+                        "type": "association",
+                        "relation": "many_to_one",
+                        "entity": "product_manufacturer"
+                    },
+                    "media": {
+                        "type": "association",
+                        "relation": "one_to_many",
+                        "entity": "product_media"
+                    }
+                }
+            },
+            "product_manufacturer": {
+                "entity": "product_manufacturer",
+                "properties": {
+                    "name": { "type": "string" },
+                    "translated": { "type": "json_object", "flags": { "computed": true, "runtime": true } }
+                }
+            },
+            "product_media": {
+                "entity": "product_media",
+                "properties": {
+                    "position": { "type": "int" },
+                    "thumbnails": { "type": "json_object", "flags": { "runtime": true } }
+                }
+            }
+        });
+
+        let mut entity: Entity = serde_json::from_value(json!({
+            "id": "abc",
+            "autoIncrement": 42,
+            "available": true,
+            "stock": 10,
+            "unknown": "kept",
+            "manufacturer": {
+                "name": "shopware",
+                "translated": { "name": "shopware" }
+            },
+            "media": [
+                { "position": 1, "thumbnails": [] }
+            ]
+        }))
+        .unwrap();
+
+        let mut removed =
+            remove_write_protected_fields("product", &mut entity, api_schema.as_object().unwrap());
+        removed.sort_unstable();
+
+        assert_eq!(
+            removed,
+            vec![
+                "autoIncrement",
+                "available",
+                "manufacturer.translated",
+                "media.thumbnails"
+            ]
+        );
+        assert_eq!(
+            serde_json::Value::Object(entity),
+            json!({
+                "id": "abc",
+                "stock": 10,
+                "unknown": "kept",
+                "manufacturer": {
+                    "name": "shopware"
+                },
+                "media": [
+                    { "position": 1 }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn limit_payload_size_by_stripping_fields() {
+        let limit = PayloadLimit {
+            max_bytes: NonZeroUsize::new(80).unwrap(),
+            strip_fields: vec![
+                "missing".to_string(),
+                "translations.de-DE.description".to_string(),
+                "description".to_string(),
+            ],
+        };
+
+        let mut small: Entity =
+            serde_json::from_value(json!({ "id": "a", "description": "short" })).unwrap();
+        assert!(limit_payload_size(&mut small, &limit).unwrap().is_empty());
+        assert_eq!(small["description"], json!("short"));
+
+        let mut entity: Entity = serde_json::from_value(json!({
+            "id": "a",
+            "description": "x".repeat(20),
+            "translations": { "de-DE": { "description": "y".repeat(40) } },
+        }))
+        .unwrap();
+        assert_eq!(
+            limit_payload_size(&mut entity, &limit).unwrap(),
+            vec!["translations.de-DE.description"]
+        );
+        assert_eq!(entity["description"], json!("x".repeat(20)));
+        assert_eq!(entity["translations"], json!({ "de-DE": {} }));
+
+        let mut too_large: Entity =
+            serde_json::from_value(json!({ "id": "a", "name": "z".repeat(100) })).unwrap();
+        let error = limit_payload_size(&mut too_large, &limit).unwrap_err();
+        assert!(error.to_string().starts_with("payload too large"));
+    }
+}
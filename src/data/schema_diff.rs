@@ -0,0 +1,156 @@
+//! Differences between the entity schemas of two shops (`schema-diff`)
+
+use crate::api::Entity;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    pub entities_only_in_source: Vec<String>,
+    pub entities_only_in_target: Vec<String>,
+    /// fields of the entities existing in both shops, by entity name
+    pub fields: BTreeMap<String, FieldDiff>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub only_in_source: Vec<String>,
+    pub only_in_target: Vec<String>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.entities_only_in_source.is_empty()
+            && self.entities_only_in_target.is_empty()
+            && self.fields.is_empty()
+    }
+}
+
+/// Compare the entities and their fields (properties) of two entity schemas,
+/// everything is sorted by name
+pub fn diff_schemas(source: &Entity, target: &Entity) -> SchemaDiff {
+    let mut diff = SchemaDiff {
+        entities_only_in_source: get_missing_keys(source, target),
+        entities_only_in_target: get_missing_keys(target, source),
+        ..Default::default()
+    };
+
+    let no_properties = Entity::new();
+    for (entity, source_schema) in source {
+        let Some(target_schema) = target.get(entity) else {
+            continue;
+        };
+        let source_properties = get_properties(source_schema).unwrap_or(&no_properties);
+        let target_properties = get_properties(target_schema).unwrap_or(&no_properties);
+
+        let field_diff = FieldDiff {
+            only_in_source: get_missing_keys(source_properties, target_properties),
+            only_in_target: get_missing_keys(target_properties, source_properties),
+        };
+        if field_diff != FieldDiff::default() {
+            diff.fields.insert(entity.clone(), field_diff);
+        }
+    }
+
+    diff
+}
+
+fn get_properties(entity_schema: &serde_json::Value) -> Option<&Entity> {
+    entity_schema.get("properties").and_then(|p| p.as_object())
+}
+
+/// Keys of `a`, which don't exist in `b`
+fn get_missing_keys(a: &Entity, b: &Entity) -> Vec<String> {
+    let mut keys: Vec<String> = a.keys().filter(|k| !b.contains_key(*k)).cloned().collect();
+    keys.sort_unstable();
+
+    keys
+}
+
+impl Display for SchemaDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !self.entities_only_in_source.is_empty() {
+            writeln!(
+                f,
+                "Entities only in the source shop: {}",
+                self.entities_only_in_source.join(", ")
+            )?;
+        }
+        if !self.entities_only_in_target.is_empty() {
+            writeln!(
+                f,
+                "Entities only in the target shop: {}",
+                self.entities_only_in_target.join(", ")
+            )?;
+        }
+
+        for (entity, field_diff) in &self.fields {
+            writeln!(f, "Entity '{entity}':")?;
+            if !field_diff.only_in_source.is_empty() {
+                writeln!(
+                    f,
+                    "  fields only in the source shop: {}",
+                    field_diff.only_in_source.join(", ")
+                )?;
+            }
+            if !field_diff.only_in_target.is_empty() {
+                writeln!(
+                    f,
+                    "  fields only in the target shop: {}",
+                    field_diff.only_in_target.join(", ")
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn schema_differences() {
+        let source = json!({
+            "product": { "properties": { "id": {}, "name": {}, "customSearchKeywords": {} } },
+            "category": { "properties": { "id": {}, "name": {} } },
+            "swag_paypal_transaction": { "properties": { "id": {} } },
+        });
+        let target = json!({
+            "product": { "properties": { "id": {}, "name": {}, "ratingAverage": {} } },
+            "category": { "properties": { "name": {}, "id": {} } },
+            "custom_entity_blog": { "properties": { "id": {} } },
+        });
+        let source = source.as_object().unwrap();
+        let target = target.as_object().unwrap();
+
+        let diff = diff_schemas(source, target);
+        assert_eq!(
+            diff,
+            SchemaDiff {
+                entities_only_in_source: vec!["swag_paypal_transaction".to_string()],
+                entities_only_in_target: vec!["custom_entity_blog".to_string()],
+                fields: BTreeMap::from([(
+                    "product".to_string(),
+                    FieldDiff {
+                        only_in_source: vec!["customSearchKeywords".to_string()],
+                        only_in_target: vec!["ratingAverage".to_string()],
+                    }
+                )]),
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "Entities only in the source shop: swag_paypal_transaction
+Entities only in the target shop: custom_entity_blog
+Entity 'product':
+  fields only in the source shop: customSearchKeywords
+  fields only in the target shop: ratingAverage
+"
+        );
+
+        assert!(diff_schemas(source, source).is_empty());
+    }
+}
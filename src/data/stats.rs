@@ -0,0 +1,232 @@
+//! Per-column statistics of the imported file (`--column-stats`),
+//! which help to spot systematic problems of a feed, like a shifted column
+
+use crate::config_file::{ColumnType, Mapping};
+use crate::data::transform::get_json_value_from_string;
+use csv::StringRecord;
+use serde::Serialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Statistics of all mapped columns, collected per file chunk and merged afterward
+#[derive(Debug, Clone, Default)]
+pub struct ColumnStatistics {
+    columns: Vec<ColumnStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnStats {
+    pub column: String,
+    /// cells which are empty or `null`
+    pub nulls: usize,
+    /// estimated amount of distinct values (a few percent off for many values)
+    pub distinct_estimate: u64,
+    /// length of the shortest value in characters (without nulls)
+    pub min_length: Option<usize>,
+    /// length of the longest value in characters
+    pub max_length: Option<usize>,
+    /// values which can't be converted into the `column_type` of the mapping
+    pub cast_failures: usize,
+    #[serde(skip)]
+    column_index: Option<usize>,
+    #[serde(skip)]
+    column_type: Option<ColumnType>,
+    #[serde(skip)]
+    distinct_sketch: DistinctSketch,
+}
+
+impl ColumnStatistics {
+    pub fn new(headers: &StringRecord, mappings: &[Mapping]) -> Self {
+        let columns = mappings
+            .iter()
+            .map(|mapping| {
+                let column = mapping.get_file_column();
+                let column_type = match mapping {
+                    Mapping::ByPath(m) => m.column_type.clone(),
+                    Mapping::ByScript(m) => m.column_type.clone(),
+                };
+
+                ColumnStats {
+                    column: column.to_owned(),
+                    nulls: 0,
+                    distinct_estimate: 0,
+                    min_length: None,
+                    max_length: None,
+                    cast_failures: 0,
+                    column_index: headers.iter().position(|header| header == column),
+                    column_type,
+                    distinct_sketch: DistinctSketch::default(),
+                }
+            })
+            .collect();
+
+        Self { columns }
+    }
+
+    pub fn add_row(&mut self, row: &StringRecord) {
+        for stats in &mut self.columns {
+            if let Some(value) = stats.column_index.and_then(|i| row.get(i)) {
+                stats.add_value(value);
+            }
+        }
+    }
+
+    /// Merge the statistics of another file chunk (with the same mappings)
+    pub fn merge(&mut self, other: Self) {
+        if self.columns.is_empty() {
+            *self = other;
+            return;
+        }
+
+        for (stats, other) in self.columns.iter_mut().zip(other.columns) {
+            stats.nulls += other.nulls;
+            stats.min_length = stats.min_length.into_iter().chain(other.min_length).min();
+            stats.max_length = stats.max_length.into_iter().chain(other.max_length).max();
+            stats.cast_failures += other.cast_failures;
+            stats.distinct_sketch.merge(&other.distinct_sketch);
+        }
+    }
+
+    pub fn into_columns(self) -> Vec<ColumnStats> {
+        self.columns
+            .into_iter()
+            .map(|mut stats| {
+                stats.distinct_estimate = stats.distinct_sketch.estimate();
+                stats
+            })
+            .collect()
+    }
+}
+
+impl ColumnStats {
+    fn add_value(&mut self, value: &str) {
+        if value.trim().is_empty() || value.eq_ignore_ascii_case("null") {
+            self.nulls += 1;
+            return;
+        }
+
+        let length = value.chars().count();
+        self.min_length = Some(self.min_length.map_or(length, |min| min.min(length)));
+        self.max_length = Some(self.max_length.map_or(length, |max| max.max(length)));
+        self.distinct_sketch.add(value);
+
+        // same conversion as on deserialize
+        if get_json_value_from_string(value, &self.column_type).is_err() {
+            self.cast_failures += 1;
+        }
+    }
+}
+
+/// HyperLogLog sketch to estimate the amount of distinct values in constant memory
+#[derive(Debug, Clone)]
+struct DistinctSketch {
+    registers: [u8; Self::REGISTERS],
+}
+
+impl Default for DistinctSketch {
+    fn default() -> Self {
+        Self {
+            registers: [0; Self::REGISTERS],
+        }
+    }
+}
+
+impl DistinctSketch {
+    const INDEX_BITS: u32 = 8;
+    const REGISTERS: usize = 1 << Self::INDEX_BITS;
+
+    fn add(&mut self, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let index = (hash >> (u64::BITS - Self::INDEX_BITS)) as usize;
+        let rank =
+            ((hash << Self::INDEX_BITS).leading_zeros() + 1).min(u64::BITS - Self::INDEX_BITS + 1);
+        self.registers[index] = self.registers[index].max(rank as u8);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        for (register, other) in self.registers.iter_mut().zip(other.registers) {
+            *register = (*register).max(other);
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = Self::REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // small range correction (linear counting)
+        let empty_registers = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && empty_registers > 0 {
+            return (m * (m / empty_registers as f64).ln()).round() as u64;
+        }
+
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_statistics_of_chunks() {
+        let mappings: Vec<Mapping> = serde_yaml::from_str(
+            r"
+- file_column: 'product number'
+  entity_path: 'productNumber'
+- file_column: 'stock'
+  entity_path: 'stock'
+  column_type: 'number'
+",
+        )
+        .unwrap();
+        let headers = StringRecord::from(vec!["stock", "product number"]);
+
+        let mut first_chunk = ColumnStatistics::new(&headers, &mappings);
+        first_chunk.add_row(&StringRecord::from(vec!["10", "SW10001"]));
+        first_chunk.add_row(&StringRecord::from(vec!["", "SW10002"]));
+        let mut second_chunk = ColumnStatistics::new(&headers, &mappings);
+        second_chunk.add_row(&StringRecord::from(vec!["ten", "SW1"]));
+        second_chunk.add_row(&StringRecord::from(vec!["null", "SW10001"]));
+
+        let mut statistics = ColumnStatistics::default();
+        statistics.merge(first_chunk);
+        statistics.merge(second_chunk);
+        let columns = statistics.into_columns();
+
+        assert_eq!(columns[0].column, "product number");
+        assert_eq!(columns[0].nulls, 0);
+        assert_eq!(columns[0].distinct_estimate, 3);
+        assert_eq!(columns[0].min_length, Some(3));
+        assert_eq!(columns[0].max_length, Some(7));
+        assert_eq!(columns[0].cast_failures, 0);
+
+        assert_eq!(columns[1].column, "stock");
+        assert_eq!(columns[1].nulls, 2);
+        assert_eq!(columns[1].min_length, Some(2));
+        assert_eq!(columns[1].max_length, Some(3));
+        assert_eq!(columns[1].cast_failures, 1);
+    }
+
+    #[test]
+    fn distinct_estimate_of_many_values() {
+        let mut sketch = DistinctSketch::default();
+        for i in 0..100_000 {
+            sketch.add(&format!("SW{i}"));
+            sketch.add(&format!("SW{i}"));
+        }
+
+        let estimate = sketch.estimate();
+        assert!(
+            (80_000..120_000).contains(&estimate),
+            "estimate {estimate} is too far off"
+        );
+    }
+}
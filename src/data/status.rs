@@ -0,0 +1,169 @@
+//! Status file of a running sync (`--status-file`), which is updated periodically,
+//! so monitoring agents (and humans) can check the progress without the output of the process
+
+use crate::cli::SyncMode;
+use crate::SyncContext;
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Interval of the updates of the status file
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum State {
+    Running,
+    Finished,
+    Failed,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Status<'a> {
+    state: State,
+    mode: SyncMode,
+    entity: &'a str,
+    pid: u32,
+    started_at: String,
+    updated_at: String,
+    elapsed_secs: u64,
+    /// processed rows (imported or exported)
+    rows_done: usize,
+    /// rows of the imported file or entities to export, if known
+    total_rows: Option<usize>,
+    /// latest processed chunk: its first row on import, the page on export
+    last_chunk: Option<usize>,
+    /// rows rejected by the server (or its validation)
+    failed_rows: usize,
+    /// rows which weren't imported, e.g. because of the schema constraints
+    skipped_rows: usize,
+    /// estimated seconds until the end, based on the throughput so far
+    eta_secs: Option<u64>,
+}
+
+/// Run the sync while the status file is updated in the background,
+/// with the final state (`finished` or `failed`) written at the end
+pub fn with_status_file<T>(
+    path: &Path,
+    mode: SyncMode,
+    context: &SyncContext,
+    run: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let started_at = Utc::now();
+    let start = Instant::now();
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+    let result = std::thread::scope(|s| {
+        s.spawn(move || loop {
+            let status = collect_status(State::Running, mode, context, started_at, start);
+            if let Err(e) = write_status(path, &status) {
+                println!("Warning: {e:#}");
+            }
+            match stop_rx.recv_timeout(STATUS_INTERVAL) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        });
+
+        let result = run();
+        drop(stop_tx);
+        result
+    });
+
+    let state = match result {
+        Ok(_) => State::Finished,
+        Err(_) => State::Failed,
+    };
+    // the result of the sync is kept, even if the final status can't be written
+    if let Err(e) = write_status(
+        path,
+        &collect_status(state, mode, context, started_at, start),
+    ) {
+        println!("Warning: {e:#}");
+    }
+
+    result
+}
+
+fn collect_status<'a>(
+    state: State,
+    mode: SyncMode,
+    context: &'a SyncContext,
+    started_at: DateTime<Utc>,
+    start: Instant,
+) -> Status<'a> {
+    let (rows_done, last_chunk) = {
+        let chunk_metrics = context.chunk_metrics.lock().unwrap();
+        (
+            chunk_metrics.iter().map(|metrics| metrics.rows).sum(),
+            chunk_metrics.last().map(|metrics| metrics.chunk),
+        )
+    };
+    let total_rows = context.total_rows.get().copied();
+    let elapsed = start.elapsed();
+
+    Status {
+        state,
+        mode,
+        entity: &context.profile.entity,
+        pid: std::process::id(),
+        started_at: started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        updated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        elapsed_secs: elapsed.as_secs(),
+        rows_done,
+        total_rows,
+        last_chunk,
+        failed_rows: context.failed_rows.lock().unwrap().len()
+            + context.remote_validation_errors.lock().unwrap().len(),
+        skipped_rows: context.skipped_rows.lock().unwrap().len(),
+        eta_secs: match state {
+            State::Running => get_eta(elapsed, rows_done, total_rows).map(|eta| eta.as_secs()),
+            State::Finished | State::Failed => None,
+        },
+    }
+}
+
+/// Remaining time, if the throughput stays the same
+fn get_eta(elapsed: Duration, rows_done: usize, total_rows: Option<usize>) -> Option<Duration> {
+    let total_rows = total_rows?;
+    if rows_done == 0 {
+        return None;
+    }
+
+    let remaining_rows = total_rows.saturating_sub(rows_done);
+    Some(elapsed.mul_f64(remaining_rows as f64 / rows_done as f64))
+}
+
+/// Write the status into a temporary file first, so readers never see a partially written file
+fn write_status(path: &Path, status: &Status) -> anyhow::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    std::fs::write(&temp_path, serde_json::to_vec_pretty(status)?)
+        .with_context(|| format!("failed to write status file {path:?}"))?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("failed to write status file {path:?}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_remaining_time() {
+        let elapsed = Duration::from_secs(60);
+        assert_eq!(get_eta(elapsed, 0, Some(1000)), None);
+        assert_eq!(get_eta(elapsed, 250, None), None);
+        assert_eq!(
+            get_eta(elapsed, 250, Some(1000)),
+            Some(Duration::from_secs(180))
+        );
+        // more rows than expected, e.g. entities created during the export
+        assert_eq!(get_eta(elapsed, 1200, Some(1000)), Some(Duration::ZERO));
+    }
+}
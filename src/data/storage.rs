@@ -0,0 +1,228 @@
+//! Where the rows of an import are read from and the rows of an export are written to.
+//!
+//! The `--file` argument selects the backend, the pipeline only reads and writes streams of the file name:
+//! - `-`: the import is read from stdin
+//! - `http://` or `https://` URLs: the import is downloaded while it's read
+//! - everything else: local files, which are the only supported output of exports so far
+
+use crate::api::get_user_agent;
+use anyhow::Context;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Input of an import
+pub trait DataSource: Debug + Send + Sync {
+    /// Reader from the start of the file
+    fn open(&self, file: &Path) -> anyhow::Result<Box<dyn Read + Send>>;
+}
+
+/// Output of an export
+pub trait DataSink: Debug + Send + Sync {
+    /// Writer of the file, replacing its previous content
+    fn create(&self, file: &Path) -> anyhow::Result<Box<dyn Write + Send + Sync>>;
+
+    /// Remove the file again, e.g. after a failed export
+    fn remove(&self, file: &Path) -> anyhow::Result<()>;
+}
+
+/// Backend of the imported file
+pub fn get_source(file: &Path) -> Box<dyn DataSource> {
+    if file == Path::new("-") {
+        Box::new(Stdin)
+    } else if is_url(file) {
+        Box::new(Http)
+    } else {
+        Box::new(LocalFiles)
+    }
+}
+
+/// Backend of the exported file, only local files are supported so far.
+/// Stdout isn't supported, because it's used for the progress output
+pub fn get_sink(_file: &Path) -> Box<dyn DataSink> {
+    Box::new(LocalFiles)
+}
+
+/// Whether the file is stored on the local file system,
+/// only local files can have files next to them (like checkpoints or manifests)
+pub fn is_local_file(file: &Path) -> bool {
+    file != Path::new("-") && !is_url(file)
+}
+
+fn is_url(file: &Path) -> bool {
+    let file = file.to_string_lossy();
+    file.starts_with("http://") || file.starts_with("https://")
+}
+
+#[derive(Debug)]
+pub struct LocalFiles;
+
+impl DataSource for LocalFiles {
+    fn open(&self, file: &Path) -> anyhow::Result<Box<dyn Read + Send>> {
+        let file = File::open(file).with_context(|| format!("failed to open {file:?}"))?;
+        Ok(Box::new(file))
+    }
+}
+
+impl DataSink for LocalFiles {
+    fn create(&self, file: &Path) -> anyhow::Result<Box<dyn Write + Send + Sync>> {
+        let file = File::create(file).with_context(|| format!("failed to create {file:?}"))?;
+        Ok(Box::new(file))
+    }
+
+    fn remove(&self, file: &Path) -> anyhow::Result<()> {
+        std::fs::remove_file(file).with_context(|| format!("failed to remove {file:?}"))
+    }
+}
+
+/// Standard input, e.g. for files piped from other tools
+#[derive(Debug)]
+pub struct Stdin;
+
+impl DataSource for Stdin {
+    fn open(&self, _file: &Path) -> anyhow::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(std::io::stdin()))
+    }
+}
+
+/// Files downloaded with a GET request, e.g. feeds of suppliers
+#[derive(Debug)]
+pub struct Http;
+
+impl DataSource for Http {
+    fn open(&self, file: &Path) -> anyhow::Result<Box<dyn Read + Send>> {
+        let url = file.to_string_lossy();
+        // the body is streamed while the import is running, so it can take longer than any fixed timeout
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(get_user_agent(None))
+            .timeout(None)
+            .build()?;
+        let response = client
+            .get(url.as_ref())
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .with_context(|| format!("failed to download {url}"))?;
+
+        Ok(Box::new(response))
+    }
+}
+
+/// Files kept in memory, for tests of the pipeline (like the end-to-end tests with the fake shop of the `testing` module)
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, Default)]
+pub struct InMemory {
+    files: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<std::path::PathBuf, Vec<u8>>>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl InMemory {
+    pub fn insert(&self, file: &Path, content: impl Into<Vec<u8>>) {
+        let mut files = self.files.lock().unwrap();
+        files.insert(file.to_path_buf(), content.into());
+    }
+
+    pub fn get(&self, file: &Path) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(file).cloned()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl DataSource for InMemory {
+    fn open(&self, file: &Path) -> anyhow::Result<Box<dyn Read + Send>> {
+        let content = self
+            .get(file)
+            .with_context(|| format!("{file:?} doesn't exist"))?;
+        Ok(Box::new(std::io::Cursor::new(content)))
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl DataSink for InMemory {
+    fn create(&self, file: &Path) -> anyhow::Result<Box<dyn Write + Send + Sync>> {
+        self.insert(file, vec![]);
+        Ok(Box::new(InMemoryWriter {
+            files: self.clone(),
+            file: file.to_path_buf(),
+        }))
+    }
+
+    fn remove(&self, file: &Path) -> anyhow::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        files
+            .remove(file)
+            .map(drop)
+            .with_context(|| format!("{file:?} doesn't exist"))
+    }
+}
+
+/// Appends the written bytes to a file of `InMemory`
+#[cfg(any(test, feature = "testing"))]
+struct InMemoryWriter {
+    files: InMemory,
+    file: std::path::PathBuf,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Write for InMemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut files = self.files.files.lock().unwrap();
+        files
+            .entry(self.file.clone())
+            .or_default()
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_by_file_argument() {
+        assert_eq!(format!("{:?}", get_source(Path::new("-"))), "Stdin");
+        assert_eq!(
+            format!(
+                "{:?}",
+                get_source(Path::new("https://example.com/feed.csv"))
+            ),
+            "Http"
+        );
+        assert_eq!(
+            format!("{:?}", get_source(Path::new("./products.csv"))),
+            "LocalFiles"
+        );
+
+        assert!(is_local_file(Path::new("./-/products.csv")));
+        assert!(!is_local_file(Path::new("http://example.com/products.csv")));
+    }
+
+    #[test]
+    fn download_from_url() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/feed.csv")
+            .with_body("id;name\n1;Cap\n")
+            .create();
+
+        let file = format!("{}/feed.csv", server.url());
+        let mut content = String::new();
+        get_source(Path::new(&file))
+            .open(Path::new(&file))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        mock.assert();
+        assert_eq!(content, "id;name\n1;Cap\n");
+
+        let missing = format!("{}/missing.csv", server.url());
+        assert!(get_source(Path::new(&missing))
+            .open(Path::new(&missing))
+            .is_err());
+    }
+}
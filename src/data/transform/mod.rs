@@ -1,47 +1,114 @@
 //! Everything related to data transformations
 
+pub mod plugins;
 pub mod script;
+pub mod wasm;
 
 use crate::api::Entity;
-use crate::config_file::{ColumnType, Mapping, Profile};
+use crate::config_file::{
+    ColumnType, ConcatColumns, EmptyAs, EntityPathMapping, Mapping, Profile, SplitColumn,
+    StringQuoting, ValueFormat,
+};
+use crate::data::transform::plugins::RowTransformer;
 use crate::data::ScriptingEnvironment;
 use anyhow::Context;
+use base64::Engine;
 use csv::StringRecord;
+use itertools::Itertools;
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 /// Deserialize a single row of the input (CSV) file into a json object
 pub fn deserialize_row(
     headers: &StringRecord,
     row: &StringRecord,
+    row_index: usize,
     profile: &Profile,
     scripting_environment: &ScriptingEnvironment,
+    transformers: &[Box<dyn RowTransformer>],
 ) -> anyhow::Result<Entity> {
     // Either run deserialize script or create initial empty entity object
-    let mut entity = scripting_environment.run_deserialize(headers, row, profile)?;
+    let mut entity = scripting_environment.run_deserialize(headers, row, row_index, profile)?;
 
     for mapping in &profile.mappings {
         match mapping {
             Mapping::ByPath(path_mapping) => {
-                let column_index = headers
-                    .iter()
-                    .position(|header| header == path_mapping.file_column)
-                    .with_context(|| {
-                        format!(
-                            "Can't find column '{}' in CSV headers",
-                            path_mapping.file_column
-                        )
-                    })?;
+                // wildcard paths are rejected before the import starts
+                let cell = get_mapping_cell(path_mapping, headers, row)?;
 
-                let raw_value = row
-                    .get(column_index)
-                    .context("failed to get column of row")?;
+                let raw_value = match (cell.as_str(), &path_mapping.default) {
+                    ("", Some(default)) => default.as_str(),
+                    (raw_value, _) => raw_value,
+                };
 
-                let json_value = get_json_value_from_string(raw_value, &path_mapping.column_type)
-                    .with_context(|| {
-                    format!("error in column \"{}\"", &headers[column_index])
-                })?;
+                let (raw_value, column_type) =
+                    strip_text_marker(raw_value, &path_mapping.column_type, profile);
+                let is_empty = raw_value.trim().is_empty();
+                let json_value = match (path_mapping.empty_as, is_empty) {
+                    (Some(EmptyAs::Null | EmptyAs::Skip), true) => Ok(serde_json::Value::Null),
+                    (Some(EmptyAs::EmptyString), true) => {
+                        Ok(serde_json::Value::String(String::new()))
+                    }
+                    // the representation of null values on export (`null_value`)
+                    _ if raw_value == profile.get_null_value() => Ok(serde_json::Value::Null),
+                    _ if !path_mapping.mappings.is_empty() => {
+                        get_nested_elements(raw_value, path_mapping)
+                    }
+                    _ => get_json_value_from_string(raw_value, column_type),
+                }
+                .and_then(|json_value| {
+                    check_mapping_constraints(path_mapping, raw_value, &json_value)?;
+                    if path_mapping.base64_file {
+                        // a truncated base64 value would be corrupt, the size is checked instead
+                        read_base64_file(path_mapping, json_value)
+                    } else {
+                        Ok(truncate_value(path_mapping, json_value))
+                    }
+                })
+                .with_context(|| format!("error in column \"{}\"", path_mapping.file_column))?;
+
+                if let Some(currency) = &path_mapping.currency {
+                    if !json_value.is_null() {
+                        let currency_id = scripting_environment
+                            .currencies
+                            .get_mapping_currency_id(currency)?;
+                        insert_price_field(
+                            &mut entity,
+                            &path_mapping.entity_path,
+                            &currency_id,
+                            json_value,
+                        )?;
+                    }
+                    continue;
+                }
 
-                entity.insert_by_path(&path_mapping.entity_path, json_value);
+                let entity_path = match &path_mapping.language {
+                    Some(language) => Cow::Owned(get_translation_entity_path(
+                        &path_mapping.entity_path,
+                        language,
+                    )),
+                    None => Cow::Borrowed(path_mapping.entity_path.as_str()),
+                };
+                let (entity_path, json_value) = match &path_mapping.id_list_association {
+                    Some(association) if !json_value.is_null() => (
+                        Cow::Borrowed(association.as_str()),
+                        get_id_list_value(raw_value, path_mapping),
+                    ),
+                    _ => (entity_path, json_value),
+                };
+                let write_null = match (path_mapping.empty_as, is_empty) {
+                    (Some(EmptyAs::Null), true) => true,
+                    (Some(EmptyAs::Skip), true) => false,
+                    _ => path_mapping.write_null,
+                };
+                if write_null {
+                    // explicitly clear the field, if the value is null
+                    entity.set_by_path(&entity_path, json_value);
+                } else {
+                    entity.insert_by_path(&entity_path, json_value);
+                }
             }
             Mapping::ByScript(_script_mapping) => {
                 // nothing to do here, the script already executed beforehand
@@ -49,34 +116,188 @@ pub fn deserialize_row(
         }
     }
 
+    for transformer in transformers {
+        transformer
+            .deserialize(&mut entity)
+            .with_context(|| format!("transformer '{}' failed", transformer.name()))?;
+    }
+
     Ok(entity)
 }
 
-/// Serialize a single entity (as json object) into a single row (string columns)
+/// Cell of the mapping in the row, which is combined of multiple columns (`concat`)
+/// or a part of the column (`split`) as well
+pub fn get_mapping_cell(
+    path_mapping: &EntityPathMapping,
+    headers: &StringRecord,
+    row: &StringRecord,
+) -> anyhow::Result<String> {
+    let cell = match &path_mapping.concat {
+        Some(concat) => Cow::Owned(concat_columns(concat, headers, row)?),
+        None => Cow::Borrowed(get_cell(
+            path_mapping.get_import_column(headers),
+            headers,
+            row,
+        )?),
+    };
+
+    Ok(match &path_mapping.split {
+        Some(split) => split_column(split, &cell).to_owned(),
+        None => cell.into_owned(),
+    })
+}
+
+fn get_cell<'a>(
+    column: &str,
+    headers: &StringRecord,
+    row: &'a StringRecord,
+) -> anyhow::Result<&'a str> {
+    let column_index = headers
+        .iter()
+        .position(|header| header == column)
+        .with_context(|| format!("Can't find column '{column}' in CSV headers"))?;
+
+    row.get(column_index).context("failed to get column of row")
+}
+
+/// Join the non-empty cells of the columns (`concat` of the mapping)
+fn concat_columns(
+    concat: &ConcatColumns,
+    headers: &StringRecord,
+    row: &StringRecord,
+) -> anyhow::Result<String> {
+    let mut values = vec![];
+    for column in &concat.columns {
+        let value = get_cell(column, headers, row)?.trim();
+        if !value.is_empty() {
+            values.push(value);
+        }
+    }
+
+    Ok(values.join(&concat.separator))
+}
+
+/// Part of the cell (`split` of the mapping), empty if the cell has fewer parts
+fn split_column<'a>(split: &SplitColumn, cell: &'a str) -> &'a str {
+    let part = match split.parts {
+        Some(parts) => cell
+            .splitn(parts.get(), split.separator.as_str())
+            .nth(split.index),
+        None => cell.split(split.separator.as_str()).nth(split.index),
+    };
+
+    part.unwrap_or_default().trim()
+}
+
+/// Serialize a single entity (as json object) into a single row (string columns).
+/// Returns `None` if the serialize script skipped the entity
 pub fn serialize_entity(
     entity: &Entity,
+    row_index: usize,
     profile: &Profile,
     scripting_environment: &ScriptingEnvironment,
-) -> anyhow::Result<Vec<String>> {
-    let script_row = scripting_environment.run_serialize(entity)?;
+    transformers: &[Box<dyn RowTransformer>],
+) -> anyhow::Result<Option<Vec<String>>> {
+    let entity = if transformers.is_empty() {
+        Cow::Borrowed(entity)
+    } else {
+        let mut entity = entity.clone();
+        for transformer in transformers {
+            transformer
+                .serialize(&mut entity)
+                .with_context(|| format!("transformer '{}' failed", transformer.name()))?;
+        }
+        Cow::Owned(entity)
+    };
+
+    let Some(script_row) = scripting_environment.run_serialize(&entity, row_index)? else {
+        return Ok(None);
+    };
     let mut row = Vec::with_capacity(profile.mappings.len());
 
     for mapping in &profile.mappings {
         match mapping {
+            Mapping::ByPath(path_mapping) if is_wildcard_path(&path_mapping.entity_path) => {
+                let values = entity
+                    .get_all_by_path(&path_mapping.entity_path)
+                    .with_context(|| {
+                        format!(
+                            "could not get the values of the wildcard path '{}' specified in mapping",
+                            path_mapping.entity_path
+                        )
+                    })?;
+
+                let value_str = values
+                    .into_iter()
+                    .filter(|value| !value.is_null())
+                    .map(|value| match value {
+                        serde_json::Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    })
+                    .join(get_separator(path_mapping));
+
+                row.push(add_text_marker(value_str, true, profile));
+            }
             Mapping::ByPath(path_mapping) => {
-                let value = entity.get_by_path(&path_mapping.entity_path)
+                let value = match (&path_mapping.currency, &path_mapping.language) {
+                    (Some(currency), _) => {
+                        let currency_id = scripting_environment
+                            .currencies
+                            .get_mapping_currency_id(currency)?;
+                        Some(get_price_field(
+                            &entity,
+                            &path_mapping.entity_path,
+                            &currency_id,
+                        )?)
+                    }
+                    (None, Some(language)) => {
+                        entity.get_translated_by_path(&path_mapping.entity_path, language)
+                    }
+                    (None, None) => entity.get_by_path(&path_mapping.entity_path),
+                };
+                let value = value
                     .with_context(|| format!(
                         "could not get field path '{}' specified in mapping (you might try the optional chaining operator '?.' to fallback to null), entity attributes:\n{}",
                         path_mapping.entity_path,
                         serde_json::to_string_pretty(&entity).unwrap()) // expensive for big entities
                     )?;
 
-                let value_str = match value {
-                    serde_json::Value::String(s) => s.clone(),
-                    other => serde_json::to_string(other)?,
+                let formatted = match &path_mapping.format {
+                    Some(format) => format_value(format, value).with_context(|| {
+                        format!(
+                            "failed to format the value of '{}'",
+                            path_mapping.entity_path
+                        )
+                    })?,
+                    None => None,
                 };
+                let value_str = match (formatted, value) {
+                    (Some(formatted), _) => formatted,
+                    (None, serde_json::Value::String(s)) => s.clone(),
+                    (None, serde_json::Value::Null) => profile.get_null_value().to_owned(),
+                    (None, serde_json::Value::Array(elements))
+                        if !path_mapping.mappings.is_empty() =>
+                    {
+                        join_nested_elements(elements, path_mapping).with_context(|| {
+                            format!(
+                                "failed to get the nested mappings of '{}'",
+                                path_mapping.entity_path
+                            )
+                        })?
+                    }
+                    (None, serde_json::Value::Array(ids))
+                        if path_mapping.id_list_association.is_some() =>
+                    {
+                        ids.iter()
+                            .filter_map(serde_json::Value::as_str)
+                            .join(get_separator(path_mapping))
+                    }
+                    (None, other) => serde_json::to_string(other)?,
+                };
+                let is_text =
+                    value.is_string() || path_mapping.column_type == Some(ColumnType::String);
 
-                row.push(value_str);
+                row.push(add_text_marker(value_str, is_text, profile));
             }
             Mapping::ByScript(script_mapping) => {
                 let value = script_row
@@ -91,19 +312,121 @@ pub fn serialize_entity(
                 let value_str = if value.is_string() {
                     // workaround: we don't need "json string" quotes here, so we use the inner string value directly
                     value.to_string()
+                } else if value.is_unit() {
+                    profile.get_null_value().to_owned()
                 } else {
                     serde_json::to_string(value)?
                 };
+                let is_text =
+                    value.is_string() || script_mapping.column_type == Some(ColumnType::String);
 
-                row.push(value_str);
+                row.push(add_text_marker(value_str, is_text, profile));
             }
         }
     }
 
-    Ok(row)
+    Ok(Some(row))
+}
+
+/// Price field (like `price.gross`) and the field of the price of a mapping with a `currency`
+fn split_price_path(entity_path: &str) -> anyhow::Result<(&str, &str)> {
+    entity_path
+        .split_once('.')
+        .map(|(price_field, field)| (price_field.trim_end_matches('?'), field))
+        .filter(|(_, field)| !field.contains('.'))
+        .with_context(|| {
+            format!("entity_path '{entity_path}' with a currency needs to be a field of a price, like 'price.gross'")
+        })
+}
+
+/// Write a field into the price of the currency, which is added to the list of prices if it doesn't exist yet.
+/// Plain prices don't need a deserialize script building the list with this
+fn insert_price_field(
+    entity: &mut Entity,
+    entity_path: &str,
+    currency_id: &str,
+    value: serde_json::Value,
+) -> anyhow::Result<()> {
+    let (price_field, field) = split_price_path(entity_path)?;
+    let prices = entity.entry(price_field).or_insert(serde_json::Value::Null);
+    if prices.is_null() {
+        *prices = serde_json::Value::Array(vec![]);
+    }
+    let prices = prices
+        .as_array_mut()
+        .with_context(|| format!("'{price_field}' isn't a list of prices"))?;
+
+    let price = match prices
+        .iter()
+        .position(|price| price["currencyId"].as_str() == Some(currency_id))
+    {
+        Some(index) => &mut prices[index],
+        None => {
+            prices.push(serde_json::json!({ "currencyId": currency_id, "linked": true }));
+            prices.last_mut().expect("pushed above")
+        }
+    };
+    price
+        .as_object_mut()
+        .with_context(|| format!("'{price_field}' contains a price which isn't an object"))?
+        .insert(field.to_owned(), value);
+
+    Ok(())
+}
+
+/// Field of the price of the currency, null if the entity has no price in that currency
+fn get_price_field<'a>(
+    entity: &'a Entity,
+    entity_path: &str,
+    currency_id: &str,
+) -> anyhow::Result<&'a serde_json::Value> {
+    let (price_field, field) = split_price_path(entity_path)?;
+    let value = entity
+        .get(price_field)
+        .and_then(serde_json::Value::as_array)
+        .and_then(|prices| {
+            prices
+                .iter()
+                .find(|price| price["currencyId"].as_str() == Some(currency_id))
+        })
+        .and_then(|price| price.get(field));
+
+    Ok(value.unwrap_or(&serde_json::Value::Null))
 }
 
-fn get_json_value_from_string(
+/// Value formatted with the `format` of the mapping, `None` if no option applies to it
+/// (like `decimals` to a string or a string which isn't a date)
+fn format_value(format: &ValueFormat, value: &serde_json::Value) -> anyhow::Result<Option<String>> {
+    let formatted = match value {
+        serde_json::Value::Number(number) => format
+            .decimals
+            .and_then(|decimals| number.as_f64().map(|number| format!("{number:.decimals$}"))),
+        serde_json::Value::Bool(true) => format.true_label.clone(),
+        serde_json::Value::Bool(false) => format.false_label.clone(),
+        serde_json::Value::String(s) => {
+            let Some(date_format) = &format.date_format else {
+                return Ok(None);
+            };
+            let mut formatted = String::new();
+            // invalid format strings fail while writing, instead of panicking
+            let result = if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(s) {
+                write!(formatted, "{}", date_time.format(date_format))
+            } else if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+                write!(formatted, "{}", date.format(date_format))
+            } else {
+                return Ok(None);
+            };
+            result.map_err(|_| anyhow::anyhow!("invalid date_format '{date_format}'"))?;
+            Some(formatted)
+        }
+        _ => None,
+    };
+
+    Ok(formatted)
+}
+
+/// Convert the cell into the `column_type` of its mapping (or guess the type without one)
+pub fn get_json_value_from_string(
     raw_input: &str,
     column_type: &Option<ColumnType>,
 ) -> anyhow::Result<serde_json::Value> {
@@ -133,16 +456,303 @@ fn get_json_value_from_string(
     }
 }
 
-trait EntityPath {
+/// Prefix of numeric-looking text values with `string_quoting: apostrophe`
+const TEXT_MARKER: char = '\'';
+
+/// Mark numeric-looking text values (like `0012345`) with an apostrophe, so spreadsheet applications
+/// don't convert them into numbers, if `string_quoting: apostrophe` is configured
+fn add_text_marker(value: String, is_text: bool, profile: &Profile) -> String {
+    let looks_numeric = value.parse::<f64>().is_ok() && value.chars().any(|c| c.is_ascii_digit());
+    if profile.string_quoting == StringQuoting::Apostrophe
+        && is_text
+        && (looks_numeric || value.starts_with(TEXT_MARKER))
+    {
+        format!("{TEXT_MARKER}{value}")
+    } else {
+        value
+    }
+}
+
+/// Remove the apostrophe of `string_quoting: apostrophe` again, the value is then always a string.
+/// Columns with a `number` or `boolean` type are never marked, so their values are kept as they are
+pub fn strip_text_marker<'a>(
+    raw_value: &'a str,
+    column_type: &'a Option<ColumnType>,
+    profile: &Profile,
+) -> (&'a str, &'a Option<ColumnType>) {
+    const STRING_TYPE: &Option<ColumnType> = &Some(ColumnType::String);
+
+    match (raw_value.strip_prefix(TEXT_MARKER), column_type) {
+        (Some(text), None | Some(ColumnType::String))
+            if profile.string_quoting == StringQuoting::Apostrophe =>
+        {
+            (text, STRING_TYPE)
+        }
+        _ => (raw_value, column_type),
+    }
+}
+
+/// Check the `required`, `allow_null` and `allowed_values` options of a mapping for a deserialized cell
+fn check_mapping_constraints(
+    mapping: &EntityPathMapping,
+    raw_value: &str,
+    json_value: &serde_json::Value,
+) -> anyhow::Result<()> {
+    if mapping.required && raw_value.trim().is_empty() {
+        anyhow::bail!("value is required, but the cell is empty");
+    }
+
+    if !mapping.allow_null && json_value.is_null() {
+        anyhow::bail!("value can't be null (allow_null is false), got '{raw_value}'");
+    }
+
+    let value = raw_value.trim();
+    if !mapping.allowed_values.is_empty()
+        && !value.is_empty()
+        && !json_value.is_null()
+        && !mapping.allowed_values.iter().any(|allowed| {
+            if mapping.ignore_case {
+                allowed.to_lowercase() == value.to_lowercase()
+            } else {
+                allowed == value
+            }
+        })
+    {
+        anyhow::bail!(
+            "value '{value}' isn't one of the allowed values: {}",
+            mapping.allowed_values.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Cut strings after the `max_length` characters of the mapping
+fn truncate_value(mapping: &EntityPathMapping, json_value: serde_json::Value) -> serde_json::Value {
+    match (json_value, mapping.max_length) {
+        (serde_json::Value::String(value), Some(max_length))
+            if value.chars().count() > max_length =>
+        {
+            serde_json::Value::String(value.chars().take(max_length).collect())
+        }
+        (json_value, _) => json_value,
+    }
+}
+
+/// Replace the file path of a `base64_file` mapping with the base64 encoded content of the file,
+/// relative paths are resolved from the directory of the imported file.
+/// Files which would exceed the `max_length` when encoded are rejected before they are read
+fn read_base64_file(
+    mapping: &EntityPathMapping,
+    json_value: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    if !mapping.base64_file || json_value.is_null() {
+        return Ok(json_value);
+    }
+
+    let path = match &json_value {
+        serde_json::Value::String(path) => PathBuf::from(path.trim()),
+        other => PathBuf::from(other.to_string()),
+    };
+    let path = match &mapping.base64_dir {
+        Some(dir) => dir.join(path),
+        None => path,
+    };
+    if let Some(max_length) = mapping.max_length {
+        let size = std::fs::metadata(&path)
+            .with_context(|| format!("failed to read base64_file {path:?}"))?
+            .len();
+        let encoded_length = size.div_ceil(3).saturating_mul(4);
+        if encoded_length > max_length as u64 {
+            anyhow::bail!(
+                "base64_file {path:?} is too large: {encoded_length} characters encoded, but max_length is {max_length}"
+            );
+        }
+    }
+    let content =
+        std::fs::read(&path).with_context(|| format!("failed to read base64_file {path:?}"))?;
+
+    Ok(serde_json::Value::String(
+        base64::engine::general_purpose::STANDARD.encode(content),
+    ))
+}
+
+/// Rewrite the path of a translatable field into the path inside the `translations` association,
+/// keyed by the locale code (which the API accepts in place of the language id on write).
+/// Example: `manufacturer?.name` with `de-DE` becomes `manufacturer?.translations.de-DE.name`
+pub(crate) fn get_translation_entity_path(entity_path: &str, language: &str) -> String {
+    match entity_path.rsplit_once('.') {
+        Some((parent, field)) => format!("{parent}.translations.{language}.{field}"),
+        None => format!("translations.{language}.{entity_path}"),
+    }
+}
+
+/// Separator of the values of a wildcard path or an id list, if the mapping doesn't define one
+const DEFAULT_SEPARATOR: &str = "|";
+
+fn get_separator(mapping: &EntityPathMapping) -> &str {
+    mapping.separator.as_deref().unwrap_or(DEFAULT_SEPARATOR)
+}
+
+/// Payload of the many-to-many association of an id list field, e.g. `[{ "id": "..." }]`
+/// for the cell `id1|id2` of a `categoryIds` column
+fn get_id_list_value(raw_value: &str, mapping: &EntityPathMapping) -> serde_json::Value {
+    raw_value
+        .split(get_separator(mapping))
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| serde_json::json!({ "id": id }))
+        .collect()
+}
+
+/// Separator of the fields of an element of nested mappings, if the mapping doesn't define one
+const DEFAULT_FIELD_SEPARATOR: &str = ":";
+
+fn get_field_separator(mapping: &EntityPathMapping) -> &str {
+    mapping
+        .field_separator
+        .as_deref()
+        .unwrap_or(DEFAULT_FIELD_SEPARATOR)
+}
+
+/// Escapes the separators (and itself) inside the fields of nested mappings, e.g. `https\://example.com`
+const NESTED_ESCAPE: char = '\\';
+
+/// Parts of the value between the separators, which aren't escaped. The escapes are kept,
+/// so the fields of an element can be split again
+fn split_escaped<'a>(value: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut chars = value.char_indices();
+    while let Some((index, c)) = chars.next() {
+        if c == NESTED_ESCAPE {
+            chars.next();
+        } else if index >= start && value[index..].starts_with(separator) {
+            parts.push(&value[start..index]);
+            start = index + separator.len();
+        }
+    }
+    parts.push(&value[start..]);
+
+    parts
+}
+
+fn unescape_nested_field(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            NESTED_ESCAPE => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+/// The reverse of `unescape_nested_field`, the escape is put in front of every character
+/// which starts one of the separators
+fn escape_nested_field(field: &str, separators: [&str; 2]) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        if c == NESTED_ESCAPE || separators.iter().any(|s| s.starts_with(c)) {
+            escaped.push(NESTED_ESCAPE);
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Elements of a to-many association with nested mappings, e.g. `[{ "ruleId": "a", "quantityStart": 1 }]`
+/// for the cell `a:1`. Empty fields are left out of the element,
+/// separators inside the fields are escaped with a backslash (`https\://example.com`)
+fn get_nested_elements(
+    raw_value: &str,
+    mapping: &EntityPathMapping,
+) -> anyhow::Result<serde_json::Value> {
+    if raw_value.trim().is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+
+    let mut elements = vec![];
+    for raw_element in split_escaped(raw_value, get_separator(mapping)) {
+        let fields = split_escaped(raw_element, get_field_separator(mapping));
+        if fields.len() > mapping.mappings.len() {
+            anyhow::bail!(
+                "the element '{raw_element}' has {} fields, but only {} nested mappings",
+                fields.len(),
+                mapping.mappings.len()
+            );
+        }
+
+        let mut element = Entity::new();
+        for (nested, field) in mapping.mappings.iter().zip(fields) {
+            let field = unescape_nested_field(field);
+            let value = get_json_value_from_string(field.trim(), &nested.column_type)
+                .with_context(|| format!("error in the nested field '{}'", nested.entity_path))?;
+            element.insert_by_path(&nested.entity_path, value);
+        }
+        elements.push(serde_json::Value::Object(element));
+    }
+
+    Ok(serde_json::Value::Array(elements))
+}
+
+/// Cell of a to-many association with nested mappings, the reverse of `get_nested_elements`
+fn join_nested_elements(
+    elements: &[serde_json::Value],
+    mapping: &EntityPathMapping,
+) -> anyhow::Result<String> {
+    let separators = [get_separator(mapping), get_field_separator(mapping)];
+    let mut raw_elements = Vec::with_capacity(elements.len());
+    for element in elements {
+        let element = element
+            .as_object()
+            .context("the elements of nested mappings need to be objects")?;
+        let mut fields =
+            mapping
+                .mappings
+                .iter()
+                .map(|nested| match element.get_by_path(&nested.entity_path) {
+                    Some(serde_json::Value::String(s)) => escape_nested_field(s, separators),
+                    Some(serde_json::Value::Null) | None => String::new(),
+                    Some(other) => escape_nested_field(&other.to_string(), separators),
+                });
+        raw_elements.push(fields.join(get_field_separator(mapping)));
+    }
+
+    Ok(raw_elements.join(get_separator(mapping)))
+}
+
+/// Paths like `categories[*].name` collect the values of all array elements
+pub(crate) fn is_wildcard_path(entity_path: &str) -> bool {
+    entity_path.contains("[*]")
+}
+
+pub(crate) trait EntityPath {
     /// Search for a value inside a json object tree by a given path.
     /// Example path `object.child.attribute`
     /// Path with null return, if not existing: `object?.child?.attribute`
     fn get_by_path(&self, path: &str) -> Option<&serde_json::Value>;
 
+    /// Search for a translated field value inside the `translations` association
+    /// of the object the path points to, picking the translation by its locale code.
+    /// Returns null if there is no translation for that language
+    fn get_translated_by_path(&self, path: &str, language: &str) -> Option<&serde_json::Value>;
+
+    /// Collect the values of a path with wildcards over arrays, like `categories[*].name`.
+    /// A null array results in no values
+    fn get_all_by_path(&self, path: &str) -> Option<Vec<&serde_json::Value>>;
+
     /// Insert a value into a given path
     /// ## Invariant:
-    /// Does nothing if the value is Null (to not create objects with only null values)
+    /// Does nothing if the value is Null (to not create objects with only null values),
+    /// use `set_by_path` for mappings that opt into writing null values
     fn insert_by_path(&mut self, path: &str, value: serde_json::Value);
+
+    /// Insert a value into a given path, including Null values (e.g. to clear a field)
+    fn set_by_path(&mut self, path: &str, value: serde_json::Value);
 }
 
 impl EntityPath for Entity {
@@ -193,31 +803,76 @@ impl EntityPath for Entity {
         Some(value)
     }
 
+    fn get_translated_by_path(&self, path: &str, language: &str) -> Option<&serde_json::Value> {
+        let (translations, field) = match path.rsplit_once('.') {
+            Some((parent, field)) => match self.get_by_path(parent)? {
+                serde_json::Value::Object(map) => (map.get("translations"), field),
+                serde_json::Value::Null => return Some(&serde_json::Value::Null),
+                _ => return None,
+            },
+            None => (self.get("translations"), path),
+        };
+
+        let serde_json::Value::Array(translations) = translations? else {
+            return None;
+        };
+
+        let translation = translations.iter().find(|t| {
+            t.pointer("/language/locale/code")
+                .and_then(serde_json::Value::as_str)
+                == Some(language)
+        });
+
+        match translation {
+            Some(translation) => Some(
+                translation
+                    .get(field.trim_end_matches('?'))
+                    .unwrap_or(&serde_json::Value::Null),
+            ),
+            None => Some(&serde_json::Value::Null),
+        }
+    }
+
+    fn get_all_by_path(&self, path: &str) -> Option<Vec<&serde_json::Value>> {
+        let Some((array_path, element_path)) = path.split_once("[*]") else {
+            return self.get_by_path(path).map(|value| vec![value]);
+        };
+
+        let elements = match self.get_by_path(array_path)? {
+            serde_json::Value::Array(elements) => elements,
+            serde_json::Value::Null => return Some(vec![]),
+            _ => return None,
+        };
+
+        if element_path.is_empty() {
+            return Some(elements.iter().collect());
+        }
+
+        let element_path = element_path.strip_prefix('.')?;
+        let mut values = vec![];
+        for element in elements {
+            let serde_json::Value::Object(element) = element else {
+                return None;
+            };
+            values.extend(element.get_all_by_path(element_path)?);
+        }
+
+        Some(values)
+    }
+
     fn insert_by_path(&mut self, path: &str, value: serde_json::Value) {
-        assert!(!path.is_empty(), "empty entity_path encountered");
         if value.is_null() {
             return; // do nothing
         }
 
-        let mut tokens = path.split('.').map(|t| t.trim_end_matches('?')).peekable();
+        self.set_by_path(path, value);
+    }
 
-        let first_token = tokens.next().expect("has a value because non empty");
-        let pointer = self.entry(first_token).or_insert_with(|| {
-            if tokens.peek().is_none() {
-                value.clone()
-            } else {
-                let child = Entity::with_capacity(1);
-                serde_json::Value::Object(child)
-            }
-        });
-        if tokens.peek().is_none() {
-            *pointer = value;
-            return;
-        }
+    fn set_by_path(&mut self, path: &str, value: serde_json::Value) {
+        assert!(!path.is_empty(), "empty entity_path encountered");
 
-        let mut pointer = pointer
-            .as_object_mut()
-            .expect("insert_by_path lead to non object");
+        let mut tokens = path.split('.').map(|t| t.trim_end_matches('?')).peekable();
+        let mut pointer = self;
         while let Some(token) = tokens.next() {
             if tokens.peek().is_none() {
                 // simply insert the value
@@ -225,12 +880,20 @@ impl EntityPath for Entity {
                 return;
             }
 
-            pointer = pointer
-                .entry(token)
-                .or_insert_with(|| {
-                    let child = Entity::with_capacity(1);
-                    serde_json::Value::Object(child)
-                })
+            let child = pointer.entry(token).or_insert_with(|| {
+                let child = Entity::with_capacity(1);
+                serde_json::Value::Object(child)
+            });
+            if child.is_null() {
+                if value.is_null() {
+                    // the parent is already cleared and with it all its fields
+                    return;
+                }
+
+                *child = serde_json::Value::Object(Entity::with_capacity(1));
+            }
+
+            pointer = child
                 .as_object_mut()
                 .expect("insert_by_path lead to non object");
         }
@@ -239,9 +902,22 @@ impl EntityPath for Entity {
 
 #[cfg(test)]
 mod tests {
-    use crate::config_file::ColumnType;
-    use crate::data::transform::{get_json_value_from_string, EntityPath};
+    use crate::api::{CurrencyList, Entity, IsoLanguageList};
+    use crate::config_file::{
+        ColumnType, ConcatColumns, EmptyAs, EntityPathMapping, Mapping, NestedMapping, Profile,
+        ScriptLimits, SplitColumn, StringQuoting, ValueFormat,
+    };
+    use crate::data::prepare_scripting_environment;
+    use crate::data::transform::{
+        add_text_marker, check_mapping_constraints, deserialize_row, format_value,
+        get_json_value_from_string, get_translation_entity_path, insert_price_field,
+        read_base64_file, serialize_entity, split_column, strip_text_marker, truncate_value,
+        EntityPath,
+    };
+    use csv::StringRecord;
     use serde_json::{json, Number, Value};
+    use std::collections::BTreeMap;
+    use std::num::NonZeroUsize;
 
     #[test]
     fn test_get_by_path() {
@@ -282,6 +958,754 @@ mod tests {
         assert_eq!(entity.get_by_path("child.hello?.bar"), Some(&Value::Null));
     }
 
+    #[test]
+    fn test_get_all_by_path() {
+        let entity = json!({
+            "name": "shirt",
+            "categories": [
+                { "name": "Clothing", "tags": [{ "name": "summer" }] },
+                { "name": null, "tags": [{ "name": "sale" }, { "name": "new" }] },
+            ],
+            "options": null,
+        });
+        let entity = match entity {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            entity.get_all_by_path("categories[*].name"),
+            Some(vec![&json!("Clothing"), &Value::Null])
+        );
+        assert_eq!(
+            entity.get_all_by_path("categories[*].tags[*].name"),
+            Some(vec![&json!("summer"), &json!("sale"), &json!("new")])
+        );
+        assert_eq!(entity.get_all_by_path("options[*].name"), Some(vec![]));
+        assert_eq!(entity.get_all_by_path("name[*]"), None);
+        assert_eq!(entity.get_all_by_path("categories[*].unknown"), None);
+        assert_eq!(
+            entity.get_all_by_path("categories[*].unknown?"),
+            Some(vec![&Value::Null, &Value::Null])
+        );
+    }
+
+    #[test]
+    fn test_get_translated_by_path() {
+        let entity = json!({
+            "name": "default name",
+            "translations": [
+                {
+                    "name": "english name",
+                    "language": { "locale": { "code": "en-GB" } }
+                },
+                {
+                    "name": "deutscher Name",
+                    "language": { "locale": { "code": "de-DE" } }
+                }
+            ],
+            "manufacturer": {
+                "translations": [
+                    {
+                        "name": "Hersteller",
+                        "language": { "locale": { "code": "de-DE" } }
+                    }
+                ]
+            },
+            "tax": null
+        });
+
+        let entity = match entity {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            entity.get_translated_by_path("name", "de-DE"),
+            Some(&json!("deutscher Name"))
+        );
+        assert_eq!(
+            entity.get_translated_by_path("name", "en-GB"),
+            Some(&json!("english name"))
+        );
+        assert_eq!(
+            entity.get_translated_by_path("name", "nl-NL"),
+            Some(&Value::Null)
+        );
+        assert_eq!(
+            entity.get_translated_by_path("manufacturer?.name", "de-DE"),
+            Some(&json!("Hersteller"))
+        );
+        assert_eq!(
+            entity.get_translated_by_path("tax?.name", "de-DE"),
+            Some(&Value::Null)
+        );
+        assert_eq!(entity.get_translated_by_path("unit.name", "de-DE"), None);
+    }
+
+    #[test]
+    fn test_get_translation_entity_path() {
+        assert_eq!(
+            get_translation_entity_path("name", "de-DE"),
+            "translations.de-DE.name"
+        );
+        assert_eq!(
+            get_translation_entity_path("manufacturer?.name", "de-DE"),
+            "manufacturer?.translations.de-DE.name"
+        );
+    }
+
+    #[test]
+    fn test_check_mapping_constraints() {
+        let mapping = EntityPathMapping {
+            required: true,
+            allow_null: false,
+            ..Default::default()
+        };
+        assert!(check_mapping_constraints(&mapping, "42", &json!(42)).is_ok());
+        assert!(check_mapping_constraints(&mapping, " ", &Value::Null).is_err());
+        assert!(check_mapping_constraints(&mapping, "null", &Value::Null).is_err());
+
+        let mapping = EntityPathMapping {
+            required: true,
+            allow_null: true,
+            ..Default::default()
+        };
+        assert!(check_mapping_constraints(&mapping, "null", &Value::Null).is_ok());
+        assert!(check_mapping_constraints(&mapping, "", &Value::Null).is_err());
+
+        let mapping = EntityPathMapping {
+            required: false,
+            allow_null: true,
+            ..Default::default()
+        };
+        assert!(check_mapping_constraints(&mapping, "", &Value::Null).is_ok());
+
+        let mapping = EntityPathMapping {
+            allowed_values: vec!["open".to_string(), "in_progress".to_string()],
+            ..Default::default()
+        };
+        assert!(check_mapping_constraints(&mapping, "open", &json!("open")).is_ok());
+        assert!(check_mapping_constraints(&mapping, "", &Value::Null).is_ok());
+        // null cells are checked by allow_null, not by the allowed values
+        assert!(check_mapping_constraints(&mapping, "null", &Value::Null).is_ok());
+        let not_null_mapping = EntityPathMapping {
+            allow_null: false,
+            ..mapping.clone()
+        };
+        assert!(
+            check_mapping_constraints(&not_null_mapping, "null", &Value::Null)
+                .is_err_and(|e| e.to_string().contains("allow_null"))
+        );
+        assert!(check_mapping_constraints(&mapping, "Open", &json!("Open"))
+            .is_err_and(|e| e.to_string()
+                == "value 'Open' isn't one of the allowed values: open, in_progress"));
+        assert!(check_mapping_constraints(&mapping, "opne", &json!("opne")).is_err());
+
+        let mapping = EntityPathMapping {
+            ignore_case: true,
+            ..mapping
+        };
+        assert!(check_mapping_constraints(&mapping, "Open", &json!("Open")).is_ok());
+        assert!(check_mapping_constraints(&mapping, "IN_PROGRESS", &json!("IN_PROGRESS")).is_ok());
+        assert!(check_mapping_constraints(&mapping, "opne", &json!("opne")).is_err());
+    }
+
+    #[test]
+    fn test_truncate_value() {
+        let mapping = EntityPathMapping {
+            max_length: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(truncate_value(&mapping, json!("Käsekuchen")), json!("Käs"));
+        assert_eq!(truncate_value(&mapping, json!("abc")), json!("abc"));
+        assert_eq!(truncate_value(&mapping, json!(12345)), json!(12345));
+
+        let unlimited = EntityPathMapping::default();
+        assert_eq!(
+            truncate_value(&unlimited, json!("Käsekuchen")),
+            json!("Käsekuchen")
+        );
+    }
+
+    #[test]
+    fn test_read_base64_file() {
+        let path = std::env::temp_dir().join("sw-sync-cli-read-base64-file.txt");
+        std::fs::write(&path, "Hello").unwrap();
+        let mapping = EntityPathMapping {
+            base64_file: true,
+            ..Default::default()
+        };
+
+        let path_value = json!(path.to_string_lossy());
+        assert_eq!(
+            read_base64_file(&mapping, path_value.clone()).unwrap(),
+            json!("SGVsbG8=")
+        );
+        assert_eq!(
+            read_base64_file(&mapping, Value::Null).unwrap(),
+            Value::Null
+        );
+        assert_eq!(
+            read_base64_file(&EntityPathMapping::default(), path_value.clone()).unwrap(),
+            path_value
+        );
+
+        // relative paths are resolved from the directory of the imported file
+        let relative_mapping = EntityPathMapping {
+            base64_dir: Some(std::env::temp_dir()),
+            ..mapping.clone()
+        };
+        assert_eq!(
+            read_base64_file(&relative_mapping, json!("sw-sync-cli-read-base64-file.txt")).unwrap(),
+            json!("SGVsbG8=")
+        );
+
+        // "Hello" is encoded into 8 characters, it isn't truncated
+        let limited_mapping = EntityPathMapping {
+            max_length: Some(8),
+            ..mapping.clone()
+        };
+        assert_eq!(
+            read_base64_file(&limited_mapping, path_value.clone()).unwrap(),
+            json!("SGVsbG8=")
+        );
+        let limited_mapping = EntityPathMapping {
+            max_length: Some(7),
+            ..mapping.clone()
+        };
+        assert!(read_base64_file(&limited_mapping, path_value.clone())
+            .is_err_and(|e| e.to_string().contains("8 characters encoded")));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_base64_file(&mapping, path_value)
+            .is_err_and(|e| e.to_string().contains("failed to read base64_file")));
+    }
+
+    #[test]
+    fn test_deserialize_row_null_handling() {
+        let scripting_environment = prepare_scripting_environment(
+            "",
+            "",
+            IsoLanguageList::default(),
+            CurrencyList::default(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let mapping = |column: &str, empty_as| {
+            Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: column.to_string(),
+                entity_path: column.to_string(),
+                empty_as,
+                ..Default::default()
+            }))
+        };
+        let profile = Profile {
+            mappings: vec![
+                mapping("default", None),
+                mapping("null", Some(EmptyAs::Null)),
+                mapping("empty_string", Some(EmptyAs::EmptyString)),
+                mapping("skip", Some(EmptyAs::Skip)),
+                Mapping::ByPath(Box::new(EntityPathMapping {
+                    file_column: "write_null".to_string(),
+                    entity_path: "write_null".to_string(),
+                    write_null: true,
+                    ..Default::default()
+                })),
+            ],
+            ..Default::default()
+        };
+        let headers = StringRecord::from(vec![
+            "default",
+            "null",
+            "empty_string",
+            "skip",
+            "write_null",
+        ]);
+        let row = StringRecord::from(vec!["", "", "", "", "null"]);
+
+        let entity =
+            deserialize_row(&headers, &row, 0, &profile, &scripting_environment, &[]).unwrap();
+        assert_eq!(
+            Value::Object(entity),
+            json!({
+                "null": null,
+                "empty_string": "",
+                "write_null": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_concat_and_split_columns() {
+        let scripting_environment = prepare_scripting_environment(
+            "",
+            "",
+            IsoLanguageList::default(),
+            CurrencyList::default(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let split = |entity_path: &str, index, parts| {
+            Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "full name".to_string(),
+                entity_path: entity_path.to_string(),
+                split: Some(SplitColumn {
+                    separator: " ".to_string(),
+                    index,
+                    parts,
+                }),
+                ..Default::default()
+            }))
+        };
+        let profile = Profile {
+            mappings: vec![
+                Mapping::ByPath(Box::new(EntityPathMapping {
+                    file_column: "name".to_string(),
+                    entity_path: "name".to_string(),
+                    concat: Some(ConcatColumns {
+                        columns: vec!["first name".to_string(), "last name".to_string()],
+                        separator: " ".to_string(),
+                    }),
+                    ..Default::default()
+                })),
+                split("firstName", 0, None),
+                split("lastName", 1, NonZeroUsize::new(2)),
+                split("title", 3, None),
+            ],
+            ..Default::default()
+        };
+        let headers = StringRecord::from(vec!["first name", "last name", "full name"]);
+
+        // missing parts are empty cells, which are skipped by default
+        let row = StringRecord::from(vec!["Jane", "Doe", "Mary Ann Smith"]);
+        let entity =
+            deserialize_row(&headers, &row, 0, &profile, &scripting_environment, &[]).unwrap();
+        assert_eq!(
+            Value::Object(entity),
+            json!({
+                "name": "Jane Doe",
+                "firstName": "Mary",
+                "lastName": "Ann Smith",
+            })
+        );
+
+        // empty cells are left out of the concatenation
+        let row = StringRecord::from(vec!["", "Doe", "Smith"]);
+        let entity =
+            deserialize_row(&headers, &row, 0, &profile, &scripting_environment, &[]).unwrap();
+        assert_eq!(entity["name"], json!("Doe"));
+
+        let split = SplitColumn {
+            separator: "x".to_string(),
+            index: 2,
+            parts: None,
+        };
+        assert_eq!(split_column(&split, "30 x 20 x 10"), "10");
+        assert_eq!(split_column(&split, "30x20"), "");
+    }
+
+    #[test]
+    fn test_id_lists() {
+        let scripting_environment = prepare_scripting_environment(
+            "",
+            "",
+            IsoLanguageList::default(),
+            CurrencyList::default(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let profile = Profile {
+            mappings: vec![Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "categories".to_string(),
+                entity_path: "categoryIds".to_string(),
+                id_list_association: Some("categories".to_string()),
+                ..Default::default()
+            }))],
+            ..Default::default()
+        };
+        let headers = StringRecord::from(vec!["categories"]);
+
+        let entity = deserialize_row(
+            &headers,
+            &StringRecord::from(vec!["a| b|"]),
+            0,
+            &profile,
+            &scripting_environment,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            Value::Object(entity),
+            json!({ "categories": [{ "id": "a" }, { "id": "b" }] })
+        );
+
+        let entity = deserialize_row(
+            &headers,
+            &StringRecord::from(vec![""]),
+            0,
+            &profile,
+            &scripting_environment,
+            &[],
+        )
+        .unwrap();
+        assert!(entity.is_empty());
+
+        let entity = json!({ "categoryIds": ["a", "b"] });
+        let row = serialize_entity(
+            entity.as_object().unwrap(),
+            0,
+            &profile,
+            &scripting_environment,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(row, Some(vec!["a|b".to_string()]));
+    }
+
+    #[test]
+    fn test_price_of_currency() {
+        let scripting_environment = prepare_scripting_environment(
+            "",
+            "",
+            IsoLanguageList::default(),
+            CurrencyList {
+                data: std::collections::HashMap::from([("USD".to_string(), "usd".to_string())]),
+                ..Default::default()
+            },
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let mapping = |file_column: &str, entity_path: &str, currency: &str| {
+            Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: file_column.to_string(),
+                entity_path: entity_path.to_string(),
+                currency: Some(currency.to_string()),
+                ..Default::default()
+            }))
+        };
+        let profile = Profile {
+            mappings: vec![
+                mapping("net", "price.net", "default"),
+                mapping("gross", "price.gross", "default"),
+                mapping("gross usd", "price.gross", "USD"),
+            ],
+            ..Default::default()
+        };
+        let headers = StringRecord::from(vec!["net", "gross", "gross usd"]);
+
+        let entity = deserialize_row(
+            &headers,
+            &StringRecord::from(vec!["10", "11.9", "13"]),
+            0,
+            &profile,
+            &scripting_environment,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            Value::Object(entity.clone()),
+            json!({
+                "price": [
+                    {
+                        "currencyId": "b7d2554b0ce847cd82f3ac9bd1c0dfca",
+                        "linked": true,
+                        "net": 10,
+                        "gross": 11.9,
+                    },
+                    { "currencyId": "usd", "linked": true, "gross": 13 },
+                ],
+            })
+        );
+        assert_eq!(
+            serialize_entity(&entity, 0, &profile, &scripting_environment, &[])
+                .unwrap()
+                .unwrap(),
+            ["10", "11.9", "13"]
+        );
+
+        // empty cells don't add a price
+        let entity = deserialize_row(
+            &headers,
+            &StringRecord::from(vec!["", "", ""]),
+            0,
+            &profile,
+            &scripting_environment,
+            &[],
+        )
+        .unwrap();
+        assert!(entity.is_empty());
+        assert_eq!(
+            serialize_entity(&entity, 0, &profile, &scripting_environment, &[])
+                .unwrap()
+                .unwrap(),
+            ["null", "null", "null"]
+        );
+
+        assert!(
+            insert_price_field(&mut Entity::new(), "price.listPrice.gross", "usd", json!(1))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_null_value() {
+        let scripting_environment = prepare_scripting_environment(
+            "",
+            "",
+            IsoLanguageList::default(),
+            CurrencyList::default(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let mapping = |entity_path: &str| {
+            Mapping::ByPath(Box::new(EntityPathMapping {
+                entity_path: entity_path.to_string(),
+                ..Default::default()
+            }))
+        };
+        let mut profile = Profile {
+            mappings: vec![
+                mapping("ean"),
+                mapping("manufacturer?.name"),
+                mapping("stock"),
+            ],
+            ..Default::default()
+        };
+        let entity = json!({ "ean": null, "manufacturer": null, "stock": 0 });
+        let serialize = |profile: &Profile| {
+            serialize_entity(
+                entity.as_object().unwrap(),
+                0,
+                profile,
+                &scripting_environment,
+                &[],
+            )
+            .unwrap()
+            .unwrap()
+        };
+
+        assert_eq!(serialize(&profile), ["null", "null", "0"]);
+        profile.null_value = Some("NULL".to_string());
+        assert_eq!(serialize(&profile), ["NULL", "NULL", "0"]);
+        profile.null_value = Some(String::new());
+        assert_eq!(serialize(&profile), ["", "", "0"]);
+
+        // exported files are imported with the same null values
+        let headers = StringRecord::from(vec!["ean", "manufacturer?.name", "stock"]);
+        for null_value in ["null", "NULL", "-", ""] {
+            profile.null_value = Some(null_value.to_string());
+            for mapping in &mut profile.mappings {
+                if let Mapping::ByPath(mapping) = mapping {
+                    mapping.file_column = mapping.entity_path.clone();
+                    // text columns too, like the EAN
+                    mapping.column_type =
+                        (mapping.entity_path == "ean").then_some(ColumnType::String);
+                    mapping.write_null = true;
+                }
+            }
+            let row = StringRecord::from(serialize(&profile));
+            let imported =
+                deserialize_row(&headers, &row, 0, &profile, &scripting_environment, &[]).unwrap();
+            assert_eq!(
+                serde_json::Value::Object(imported),
+                json!({ "ean": null, "manufacturer": { "name": null }, "stock": 0 }),
+                "{null_value}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_nested_mappings() {
+        let scripting_environment = prepare_scripting_environment(
+            "",
+            "",
+            IsoLanguageList::default(),
+            CurrencyList::default(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let nested = |entity_path: &str, column_type| NestedMapping {
+            entity_path: entity_path.to_string(),
+            column_type,
+        };
+        let profile = Profile {
+            mappings: vec![Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "prices".to_string(),
+                entity_path: "prices".to_string(),
+                mappings: vec![
+                    nested("ruleId", Some(ColumnType::String)),
+                    nested("quantityStart", None),
+                    nested("quantityEnd", None),
+                ],
+                ..Default::default()
+            }))],
+            ..Default::default()
+        };
+        let headers = StringRecord::from(vec!["prices"]);
+        let row = StringRecord::from(vec!["a:1:9|b:10"]);
+
+        let entity =
+            deserialize_row(&headers, &row, 0, &profile, &scripting_environment, &[]).unwrap();
+        assert_eq!(
+            Value::Object(entity.clone()),
+            json!({
+                "prices": [
+                    { "ruleId": "a", "quantityStart": 1, "quantityEnd": 9 },
+                    { "ruleId": "b", "quantityStart": 10 },
+                ],
+            })
+        );
+        assert_eq!(
+            serialize_entity(&entity, 0, &profile, &scripting_environment, &[])
+                .unwrap()
+                .unwrap(),
+            ["a:1:9|b:10:"]
+        );
+
+        let row = StringRecord::from(vec!["a:1:10:5"]);
+        assert!(deserialize_row(&headers, &row, 0, &profile, &scripting_environment, &[]).is_err());
+        let row = StringRecord::from(vec![""]);
+        let entity =
+            deserialize_row(&headers, &row, 0, &profile, &scripting_environment, &[]).unwrap();
+        assert!(entity.is_empty());
+
+        // separators inside the values are escaped
+        let profile = Profile {
+            mappings: vec![Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "media".to_string(),
+                entity_path: "media".to_string(),
+                mappings: vec![
+                    nested("position", None),
+                    nested("media.url", Some(ColumnType::String)),
+                    nested("media.alt", Some(ColumnType::String)),
+                ],
+                ..Default::default()
+            }))],
+            ..Default::default()
+        };
+        let entity = json!({
+            "media": [
+                { "position": 1, "media": { "url": "https://shop.test/front.jpg", "alt": "front|back" } },
+                { "position": 2, "media": { "url": "https://shop.test/side.jpg", "alt": "C:\\side" } },
+            ],
+        });
+        let cell =
+            r"1:https\://shop.test/front.jpg:front\|back|2:https\://shop.test/side.jpg:C\:\\side";
+        assert_eq!(
+            serialize_entity(
+                entity.as_object().unwrap(),
+                0,
+                &profile,
+                &scripting_environment,
+                &[]
+            )
+            .unwrap()
+            .unwrap(),
+            [cell]
+        );
+        let headers = StringRecord::from(vec!["media"]);
+        let row = StringRecord::from(vec![cell]);
+        assert_eq!(
+            Value::Object(
+                deserialize_row(&headers, &row, 0, &profile, &scripting_environment, &[]).unwrap()
+            ),
+            entity
+        );
+    }
+
+    #[test]
+    fn test_format_value() {
+        let format = ValueFormat {
+            decimals: Some(2),
+            date_format: Some("%d.%m.%Y".to_string()),
+            true_label: Some("yes".to_string()),
+            false_label: None,
+        };
+
+        let formatted = |value: Value| format_value(&format, &value).unwrap();
+        assert_eq!(formatted(json!(19.9)), Some("19.90".to_string()));
+        assert_eq!(formatted(json!(3)), Some("3.00".to_string()));
+        assert_eq!(formatted(json!(true)), Some("yes".to_string()));
+        assert_eq!(formatted(json!(false)), None);
+        assert_eq!(
+            formatted(json!("2024-05-01T22:30:00.000+00:00")),
+            Some("01.05.2024".to_string())
+        );
+        assert_eq!(
+            formatted(json!("1990-12-24")),
+            Some("24.12.1990".to_string())
+        );
+        assert_eq!(formatted(json!("SW10001")), None);
+        assert_eq!(formatted(Value::Null), None);
+
+        let invalid = ValueFormat {
+            date_format: Some("%Q".to_string()),
+            ..Default::default()
+        };
+        assert!(format_value(&invalid, &json!("1990-12-24")).is_err());
+    }
+
+    #[test]
+    fn test_text_marker() {
+        let profile = Profile {
+            string_quoting: StringQuoting::Apostrophe,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            add_text_marker("0012345".into(), true, &profile),
+            "'0012345"
+        );
+        assert_eq!(add_text_marker("1.5e3".into(), true, &profile), "'1.5e3");
+        assert_eq!(
+            add_text_marker("'quoted".into(), true, &profile),
+            "''quoted"
+        );
+        assert_eq!(add_text_marker("SW10001".into(), true, &profile), "SW10001");
+        assert_eq!(add_text_marker("NaN".into(), true, &profile), "NaN");
+        assert_eq!(add_text_marker("42".into(), false, &profile), "42");
+        assert_eq!(
+            add_text_marker("0012345".into(), true, &Profile::default()),
+            "0012345"
+        );
+
+        assert_eq!(
+            strip_text_marker("'0012345", &None, &profile),
+            ("0012345", &Some(ColumnType::String))
+        );
+        assert_eq!(
+            strip_text_marker("''quoted", &None, &profile),
+            ("'quoted", &Some(ColumnType::String))
+        );
+        assert_eq!(
+            strip_text_marker("42", &Some(ColumnType::Number), &profile),
+            ("42", &Some(ColumnType::Number))
+        );
+        assert_eq!(
+            strip_text_marker("'0012345", &Some(ColumnType::String), &profile),
+            ("0012345", &Some(ColumnType::String))
+        );
+        assert_eq!(
+            strip_text_marker("'42", &Some(ColumnType::Number), &profile),
+            ("'42", &Some(ColumnType::Number))
+        );
+        assert_eq!(
+            strip_text_marker("'true", &Some(ColumnType::Boolean), &profile),
+            ("'true", &Some(ColumnType::Boolean))
+        );
+        assert_eq!(
+            strip_text_marker("'0012345", &None, &Profile::default()),
+            ("'0012345", &None)
+        );
+    }
+
     #[test]
     fn test_insert_by_path() {
         let entity = json!({
@@ -427,6 +1851,46 @@ mod tests {
                 },
             })
         );
+
+        entity.set_by_path("child.hello", serde_json::Value::Null);
+        assert_eq!(
+            Value::Object(entity.clone()),
+            json!({
+                "fiz": 42,
+                "child": {
+                    "bar": "buz",
+                    "hello": null,
+                },
+                "another": {
+                    "nested": "replaced"
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_nested_null() {
+        let mut entity = Entity::new();
+        entity.set_by_path("manufacturer", Value::Null);
+        // clearing a field of a cleared parent keeps the parent cleared
+        entity.set_by_path("manufacturer.name", Value::Null);
+        entity.insert_by_path("manufacturer.link", Value::Null);
+        assert_eq!(
+            Value::Object(entity.clone()),
+            json!({ "manufacturer": null })
+        );
+        assert_eq!(entity.get_by_path("manufacturer.name"), Some(&Value::Null));
+
+        // a value for a field of a null parent replaces the parent
+        entity.insert_by_path("manufacturer.name", json!("shopware"));
+        entity.set_by_path("unit.translations.DEFAULT.name", Value::Null);
+        assert_eq!(
+            Value::Object(entity),
+            json!({
+                "manufacturer": { "name": "shopware" },
+                "unit": { "translations": { "DEFAULT": { "name": null } } },
+            })
+        );
     }
 
     #[test]
@@ -0,0 +1,321 @@
+//! Import helpers for customers and newsletter recipients, whose raw payloads
+//! otherwise leave them in states the storefront would never create
+//!
+//! - `customer_group_approval`: customers imported into a customer group are approved for it,
+//!   by clearing the `requestedGroupId` (otherwise a registration request stays open in the administration).
+//!   Rows which write the `requestedGroupId` themselves are left untouched
+//! - `newsletter_direct_opt_in`: rows which explicitly subscribe (status `subscribed` or `direct`) are imported
+//!   as subscribed without double opt-in (status `direct`). All other statuses are left alone, so unsubscribed
+//!   (`optOut`) and confirmed (`optIn`) recipients keep their state. A missing `hash` of a subscribing row
+//!   (required on create) is generated randomly
+//! - `customer_address_dedup`: addresses without an id get an id derived from the customer and the normalized
+//!   recipient (name and company), street, zip code and city, so every import updates the same address instead
+//!   of creating a new one. The same address as billing and shipping address (or twice in `addresses`) is merged
+//!   into one. The ids depend on the key of the customer (see `get_customer_key`), so importing the same
+//!   customers by another key (e.g. the id instead of the email) creates their addresses again
+
+use crate::api::Entity;
+use crate::data::transform::plugins::RowTransformer;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct CustomerGroupApproval;
+
+impl RowTransformer for CustomerGroupApproval {
+    fn name(&self) -> &'static str {
+        "customer_group_approval"
+    }
+
+    fn deserialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let has_group = entity.get("groupId").is_some_and(|id| !id.is_null())
+            || entity
+                .get("group")
+                .and_then(|group| group.get("id"))
+                .is_some_and(|id| !id.is_null());
+
+        if has_group && !entity.contains_key("requestedGroupId") {
+            entity.insert("requestedGroupId".to_string(), Value::Null);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct CustomerAddressDedup;
+
+impl CustomerAddressDedup {
+    const ADDRESS_FIELDS: [&'static str; 2] = ["defaultBillingAddress", "defaultShippingAddress"];
+}
+
+impl RowTransformer for CustomerAddressDedup {
+    fn name(&self) -> &'static str {
+        "customer_address_dedup"
+    }
+
+    fn deserialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let Some(customer_key) = get_customer_key(entity) else {
+            // without any key, the addresses can't be assigned to the customer
+            return Ok(());
+        };
+
+        for field in Self::ADDRESS_FIELDS {
+            if let Some(Value::Object(address)) = entity.get_mut(field) {
+                add_address_id(&customer_key, address);
+            }
+        }
+
+        if let Some(Value::Array(addresses)) = entity.get_mut("addresses") {
+            let mut ids = HashSet::new();
+            for address in addresses.iter_mut() {
+                if let Value::Object(address) = address {
+                    add_address_id(&customer_key, address);
+                }
+            }
+            addresses.retain(|address| match address.get("id").and_then(Value::as_str) {
+                Some(id) => ids.insert(id.to_owned()),
+                None => true,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies the customer across imports: its id, customer number or email, whichever comes first.
+/// It's part of the address ids, so they change when the rows get another of these fields
+fn get_customer_key(entity: &Entity) -> Option<String> {
+    ["id", "customerNumber", "email"].iter().find_map(|field| {
+        entity
+            .get(*field)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| format!("{field}:{}", value.to_lowercase()))
+    })
+}
+
+/// Add an id derived from the customer and the normalized recipient and location of the address, if it has none
+fn add_address_id(customer_key: &str, address: &mut Entity) {
+    if address.get("id").is_some_and(|id| !id.is_null()) {
+        return;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(customer_key.as_bytes());
+    // different recipients at the same location (like colleagues in an office) are different addresses
+    for field in ["firstName", "lastName", "company"] {
+        let value = address
+            .get(field)
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        hasher.update([0x1f]);
+        hasher.update(normalize_address_value(value).as_bytes());
+    }
+    for field in ["street", "zipcode", "city"] {
+        let Some(Value::String(value)) = address.get(field) else {
+            // incomplete addresses are left to the server validation
+            return;
+        };
+        hasher.update([0x1f]);
+        hasher.update(normalize_address_value(value).as_bytes());
+    }
+
+    let id = format!("{:x}", hasher.finalize());
+    address.insert("id".to_string(), Value::String(id[..32].to_string()));
+}
+
+/// Lowercase without whitespace and punctuation, with the common abbreviation of streets,
+/// so `Main Street 1` and `main street  1` or `Hauptstraße 1` and `Hauptstr. 1` are the same
+fn normalize_address_value(value: &str) -> String {
+    value
+        .to_lowercase()
+        .replace("straße", "str")
+        .replace("strasse", "str")
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct NewsletterDirectOptIn;
+
+impl RowTransformer for NewsletterDirectOptIn {
+    fn name(&self) -> &'static str {
+        "newsletter_direct_opt_in"
+    }
+
+    fn deserialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let is_subscribing = entity
+            .get("status")
+            .and_then(Value::as_str)
+            .is_some_and(|status| {
+                status.eq_ignore_ascii_case("subscribed") || status.eq_ignore_ascii_case("direct")
+            });
+        if !is_subscribing {
+            return Ok(());
+        }
+        entity.insert("status".to_string(), Value::String("direct".to_string()));
+
+        let has_hash = entity
+            .get("hash")
+            .and_then(Value::as_str)
+            .is_some_and(|hash| !hash.is_empty());
+        if !has_hash {
+            // the hash authorizes the (un)subscribe links, it must not be guessable
+            entity.insert(
+                "hash".to_string(),
+                Value::String(Uuid::new_v4().simple().to_string()),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn approve_customer_group() {
+        let mut customer: Entity =
+            serde_json::from_value(json!({ "group": { "id": "a".repeat(32) } })).unwrap();
+        CustomerGroupApproval.deserialize(&mut customer).unwrap();
+        assert_eq!(customer["requestedGroupId"], Value::Null);
+
+        let mut requested: Entity = serde_json::from_value(json!({
+            "groupId": "a".repeat(32),
+            "requestedGroupId": "b".repeat(32),
+        }))
+        .unwrap();
+        CustomerGroupApproval.deserialize(&mut requested).unwrap();
+        assert_eq!(requested["requestedGroupId"], json!("b".repeat(32)));
+
+        let mut without_group: Entity =
+            serde_json::from_value(json!({ "email": "a@b.c" })).unwrap();
+        CustomerGroupApproval
+            .deserialize(&mut without_group)
+            .unwrap();
+        assert!(!without_group.contains_key("requestedGroupId"));
+    }
+
+    #[test]
+    fn deduplicate_customer_addresses() {
+        let address =
+            |street: &str| json!({ "street": street, "zipcode": "12345", "city": "Berlin" });
+        let mut customer: Entity = serde_json::from_value(json!({
+            "email": "Jane@Example.com",
+            "defaultBillingAddress": address("Hauptstraße 1"),
+            "defaultShippingAddress": address("hauptstr. 1"),
+            "addresses": [address("Hauptstrasse 1"), address("Nebenweg 2"), { "id": "a", "street": "x" }],
+        }))
+        .unwrap();
+        CustomerAddressDedup.deserialize(&mut customer).unwrap();
+
+        let billing_id = customer["defaultBillingAddress"]["id"].clone();
+        assert_eq!(billing_id.as_str().unwrap().len(), 32);
+        assert_eq!(customer["defaultShippingAddress"]["id"], billing_id);
+        let addresses = customer["addresses"].as_array().unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[0]["id"], billing_id);
+        assert_ne!(addresses[1]["id"], billing_id);
+        assert_eq!(addresses[2]["id"], json!("a"));
+
+        // the same customer gets the same id on the next import
+        let mut next_import: Entity = serde_json::from_value(json!({
+            "email": "jane@example.com",
+            "defaultBillingAddress": address("Hauptstraße 1"),
+        }))
+        .unwrap();
+        CustomerAddressDedup.deserialize(&mut next_import).unwrap();
+        assert_eq!(next_import["defaultBillingAddress"]["id"], billing_id);
+
+        // other customers get their own addresses
+        let mut other: Entity = serde_json::from_value(json!({
+            "email": "john@example.com",
+            "defaultBillingAddress": address("Hauptstraße 1"),
+        }))
+        .unwrap();
+        CustomerAddressDedup.deserialize(&mut other).unwrap();
+        assert_ne!(other["defaultBillingAddress"]["id"], billing_id);
+
+        // other recipients at the same location are kept apart
+        let recipient = |first_name: &str, company: Option<&str>| {
+            json!({
+                "firstName": first_name,
+                "lastName": "Doe",
+                "company": company,
+                "street": "Hauptstraße 1",
+                "zipcode": "12345",
+                "city": "Berlin",
+            })
+        };
+        let mut colleagues: Entity = serde_json::from_value(json!({
+            "email": "jane@example.com",
+            "defaultBillingAddress": recipient("Jane", Some("Acme GmbH")),
+            "defaultShippingAddress": recipient("John", Some("Acme GmbH")),
+            "addresses": [recipient("jane", Some("ACME GmbH")), recipient("Jane", None)],
+        }))
+        .unwrap();
+        CustomerAddressDedup.deserialize(&mut colleagues).unwrap();
+        let billing_id = colleagues["defaultBillingAddress"]["id"].clone();
+        assert_ne!(colleagues["defaultShippingAddress"]["id"], billing_id);
+        let addresses = colleagues["addresses"].as_array().unwrap();
+        assert_eq!(addresses[0]["id"], billing_id);
+        assert_ne!(addresses[1]["id"], billing_id);
+    }
+
+    #[test]
+    fn newsletter_recipients_without_double_opt_in() {
+        let mut recipient: Entity =
+            serde_json::from_value(json!({ "email": "Jane@Example.com", "status": "subscribed" }))
+                .unwrap();
+        NewsletterDirectOptIn.deserialize(&mut recipient).unwrap();
+        assert_eq!(recipient["status"], json!("direct"));
+        let hash = recipient["hash"].as_str().unwrap();
+        assert_eq!(hash.len(), 32);
+
+        // the hash is random, not derived from the email
+        let mut same_email: Entity =
+            serde_json::from_value(json!({ "email": "jane@example.com", "status": "direct" }))
+                .unwrap();
+        NewsletterDirectOptIn.deserialize(&mut same_email).unwrap();
+        assert_eq!(same_email["status"], json!("direct"));
+        assert_ne!(same_email["hash"], json!(hash));
+
+        let mut with_hash: Entity = serde_json::from_value(json!({
+            "email": "jane@example.com",
+            "status": "subscribed",
+            "hash": "abc",
+        }))
+        .unwrap();
+        NewsletterDirectOptIn.deserialize(&mut with_hash).unwrap();
+        assert_eq!(with_hash["hash"], json!("abc"));
+    }
+
+    #[test]
+    fn newsletter_recipients_keep_their_status() {
+        for status in ["optOut", "optIn", "notSet"] {
+            let mut recipient: Entity = serde_json::from_value(json!({
+                "email": "jane@example.com",
+                "status": status,
+            }))
+            .unwrap();
+            NewsletterDirectOptIn.deserialize(&mut recipient).unwrap();
+            assert_eq!(recipient["status"], json!(status));
+            assert!(!recipient.contains_key("hash"));
+        }
+
+        let mut without_status: Entity =
+            serde_json::from_value(json!({ "email": "jane@example.com" })).unwrap();
+        NewsletterDirectOptIn
+            .deserialize(&mut without_status)
+            .unwrap();
+        assert!(!without_status.contains_key("status"));
+    }
+}
@@ -0,0 +1,75 @@
+//! Validates the check digit of the `ean` field on import (GTIN-8, GTIN-12, GTIN-13 and GTIN-14)
+
+use crate::api::Entity;
+use crate::data::transform::plugins::RowTransformer;
+
+#[derive(Debug)]
+pub struct EanValidation;
+
+impl RowTransformer for EanValidation {
+    fn name(&self) -> &'static str {
+        "ean_validation"
+    }
+
+    fn deserialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let ean = match entity.get("ean") {
+            Some(serde_json::Value::String(ean)) => ean.clone(),
+            // numbers can happen without column_type "string" (losing leading zeros)
+            Some(serde_json::Value::Number(ean)) => ean.to_string(),
+            _ => return Ok(()),
+        };
+
+        if !is_valid_gtin(&ean) {
+            anyhow::bail!("invalid EAN '{ean}', the check digit or length doesn't match");
+        }
+
+        Ok(())
+    }
+}
+
+fn is_valid_gtin(gtin: &str) -> bool {
+    if !matches!(gtin.len(), 8 | 12 | 13 | 14) || !gtin.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+
+    let digits: Vec<u32> = gtin.bytes().map(|b| u32::from(b - b'0')).collect();
+    let (check_digit, payload) = digits.split_last().expect("length is checked above");
+
+    // weights alternate 3 and 1, starting with 3 from the right (next to the check digit)
+    let sum: u32 = payload
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+
+    (10 - sum % 10) % 10 == *check_digit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn validate_gtin() {
+        assert!(is_valid_gtin("4006381333931"));
+        assert!(is_valid_gtin("73513537"));
+        assert!(is_valid_gtin("036000291452"));
+        assert!(!is_valid_gtin("4006381333932"));
+        assert!(!is_valid_gtin("400638133393"));
+        assert!(!is_valid_gtin("400638133393a"));
+    }
+
+    #[test]
+    fn validate_entity_ean() {
+        let mut valid: Entity = serde_json::from_value(json!({ "ean": "4006381333931" })).unwrap();
+        let mut invalid: Entity =
+            serde_json::from_value(json!({ "ean": "4006381333932" })).unwrap();
+        let mut missing = Entity::new();
+
+        assert!(EanValidation.deserialize(&mut valid).is_ok());
+        assert!(EanValidation.deserialize(&mut invalid).is_err());
+        assert!(EanValidation.deserialize(&mut missing).is_ok());
+    }
+}
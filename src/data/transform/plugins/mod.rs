@@ -0,0 +1,134 @@
+//! Custom transformations written in Rust, for hot paths where scripts are too slow
+//!
+//! Transformers are compiled into the binary (most of them behind a feature flag)
+//! and enabled by name in the profile:
+//! ```yaml
+//! transformers:
+//!   - "ean_validation"
+//! ```
+//!
+//! To add a new transformer, implement [`RowTransformer`] in a new module
+//! and add it to [`available_transformers`].
+
+mod customer_fields;
+#[cfg(feature = "ean-validation")]
+mod ean_validation;
+mod product_fields;
+mod promotion_codes;
+
+use crate::api::{Entity, SwClient};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use thiserror::Error;
+
+pub trait RowTransformer: Debug + Send + Sync {
+    /// Name used in the profile to enable the transformer
+    fn name(&self) -> &'static str;
+
+    /// Called once before the sync, e.g. to load data from the shop
+    fn prepare(&mut self, _sw_client: &dyn SwClient) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called on import for every entity, after the mappings and the deserialize script
+    fn deserialize(&self, _entity: &mut Entity) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called on export for every entity, before the serialize script and the mappings
+    fn serialize(&self, _entity: &mut Entity) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Error of a transformer about the values of a single row,
+/// the row is skipped (and reported) instead of failing the whole sync
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct InvalidRow {
+    /// JSON pointer to the invalid field, e.g. `/individualCodeCount`
+    pub pointer: String,
+    pub message: String,
+}
+
+/// All transformers which are compiled into this binary
+fn available_transformers() -> Vec<Box<dyn RowTransformer>> {
+    vec![
+        #[cfg(feature = "ean-validation")]
+        Box::new(ean_validation::EanValidation),
+        Box::new(product_fields::ProductVisibilities::default()),
+        Box::new(product_fields::ProductCover),
+        Box::new(promotion_codes::PromotionCodes),
+        Box::new(customer_fields::CustomerGroupApproval),
+        Box::new(customer_fields::NewsletterDirectOptIn),
+        Box::new(customer_fields::CustomerAddressDedup),
+    ]
+}
+
+/// Look up the transformers enabled in the profile by their name
+pub fn get_transformers(names: &[String]) -> anyhow::Result<Vec<Box<dyn RowTransformer>>> {
+    let mut available = available_transformers();
+    let available_names: Vec<&'static str> = available.iter().map(|t| t.name()).collect();
+    if let Some(name) = find_duplicate(&available_names) {
+        anyhow::bail!("transformer '{name}' is registered more than once in this build");
+    }
+    if let Some(name) = find_duplicate(names) {
+        anyhow::bail!("transformer '{name}' is enabled more than once in the profile");
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            let position = available
+                .iter()
+                .position(|t| t.name() == name)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "transformer '{name}' is not available in this build (it might need to be enabled with a feature flag), available transformers: {available_names:?}"
+                    )
+                })?;
+
+            Ok(available.swap_remove(position))
+        })
+        .collect()
+}
+
+fn find_duplicate<T: AsRef<str>>(names: &[T]) -> Option<&str> {
+    let mut seen = HashSet::new();
+    names
+        .iter()
+        .map(AsRef::as_ref)
+        .find(|name| !seen.insert(*name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_unknown_transformer() {
+        let result = get_transformers(&["unknown".to_string()]);
+
+        assert!(result.is_err_and(|e| e
+            .to_string()
+            .contains("transformer 'unknown' is not available in this build")));
+    }
+
+    #[test]
+    fn duplicate_transformers() {
+        let names: Vec<&str> = available_transformers().iter().map(|t| t.name()).collect();
+        assert_eq!(find_duplicate(&names), None);
+
+        let result = get_transformers(&["product_cover".to_string(), "product_cover".to_string()]);
+        assert!(result.is_err_and(|e| e
+            .to_string()
+            .contains("transformer 'product_cover' is enabled more than once in the profile")));
+    }
+
+    #[test]
+    fn get_no_transformers() {
+        let transformers = get_transformers(&[]).unwrap();
+
+        assert!(transformers.is_empty());
+    }
+}
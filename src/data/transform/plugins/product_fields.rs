@@ -0,0 +1,338 @@
+//! Readable columns for the nested product structures `visibilities` and `media` / `cover`,
+//! which otherwise need the full association payloads (including ids) in the file
+//!
+//! - `product_visibilities`: the `visibilities` field is written as `Storefront: all|Headless: search`
+//!   (sales channel names with the visibility `all`, `search` or `link`, defaults to `all`)
+//! - `product_cover`: the `media` field is written as media ids (`id1|id2`), ordered by their position,
+//!   and the `cover` field as the (1-based) position of the cover in that list (defaults to the first one)
+//!
+//! On import the product `id` is needed, because the ids of the nested records are derived from it,
+//! so existing records are updated instead of created again.
+//! Rows of new products without an `id` get a random one for the cover, which needs the id of its product media.
+
+use crate::api::{Entity, SwClient};
+use crate::data::transform::plugins::RowTransformer;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const SEPARATOR: char = '|';
+
+#[derive(Debug, Default)]
+pub struct ProductVisibilities {
+    /// sales channel ids by their name
+    sales_channels: HashMap<String, String>,
+}
+
+impl RowTransformer for ProductVisibilities {
+    fn name(&self) -> &'static str {
+        "product_visibilities"
+    }
+
+    fn prepare(&mut self, sw_client: &dyn SwClient) -> anyhow::Result<()> {
+        self.sales_channels = sw_client.get_sales_channels()?;
+        Ok(())
+    }
+
+    fn deserialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let Some(Value::String(visibilities)) = entity.get("visibilities") else {
+            return Ok(());
+        };
+        let product_id = get_product_id(entity);
+
+        let mut payload = vec![];
+        for visibility in visibilities.split(SEPARATOR).map(str::trim) {
+            if visibility.is_empty() {
+                continue;
+            }
+
+            let (name, level) = match visibility.rsplit_once(':') {
+                Some((name, level)) => (name.trim(), level.trim()),
+                None => (visibility, "all"),
+            };
+            let sales_channel_id = self.sales_channels.get(name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "sales channel '{name}' of the visibilities not found, available: {:?}",
+                    self.sales_channels.keys().collect::<Vec<_>>()
+                )
+            })?;
+            let level = match level {
+                "all" => 30,
+                "search" => 20,
+                "link" => 10,
+                other => anyhow::bail!(
+                    "unknown visibility '{other}' for sales channel '{name}', use 'all', 'search' or 'link'"
+                ),
+            };
+
+            let mut visibility = json!({
+                "salesChannelId": sales_channel_id,
+                "visibility": level,
+            });
+            if let Some(product_id) = product_id {
+                visibility["id"] = Value::String(get_child_id(product_id, sales_channel_id));
+            }
+            payload.push(visibility);
+        }
+
+        entity.insert("visibilities".to_string(), Value::Array(payload));
+        Ok(())
+    }
+
+    fn serialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let Some(Value::Array(visibilities)) = entity.get("visibilities") else {
+            return Ok(());
+        };
+
+        let mut readable = vec![];
+        for visibility in visibilities {
+            let Some(sales_channel_id) = visibility.get("salesChannelId").and_then(Value::as_str)
+            else {
+                continue;
+            };
+            let name = self
+                .sales_channels
+                .iter()
+                .find(|(_name, id)| *id == sales_channel_id)
+                .map_or(sales_channel_id, |(name, _id)| name.as_str());
+            let level = match visibility.get("visibility").and_then(Value::as_u64) {
+                Some(30) => "all",
+                Some(20) => "search",
+                _ => "link",
+            };
+
+            readable.push(format!("{name}: {level}"));
+        }
+        readable.sort_unstable();
+
+        entity.insert(
+            "visibilities".to_string(),
+            Value::String(readable.join(&SEPARATOR.to_string())),
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ProductCover;
+
+impl RowTransformer for ProductCover {
+    fn name(&self) -> &'static str {
+        "product_cover"
+    }
+
+    fn deserialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let Some(Value::String(media_ids)) = entity.get("media") else {
+            return Ok(());
+        };
+        let media_ids = media_ids.clone();
+        let product_id = match get_product_id(entity) {
+            Some(product_id) => product_id.to_string(),
+            None => {
+                // the cover references the product media by its id, which is derived from the product id
+                let product_id = Uuid::new_v4().simple().to_string();
+                entity.insert("id".to_string(), Value::String(product_id.clone()));
+                product_id
+            }
+        };
+        let media_ids: Vec<&str> = media_ids
+            .split(SEPARATOR)
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .collect();
+
+        let cover_position = match entity.get("cover") {
+            Some(Value::Number(position)) => position
+                .as_u64()
+                .and_then(|p| usize::try_from(p).ok())
+                .filter(|p| (1..=media_ids.len()).contains(p))
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "cover position {position} is out of range of the {} media",
+                        media_ids.len()
+                    )
+                })?,
+            Some(Value::Null) | None => 1,
+            Some(other) => {
+                anyhow::bail!("cover needs to be the position of the media, got {other}")
+            }
+        };
+
+        let media: Vec<Value> = media_ids
+            .iter()
+            .enumerate()
+            .map(|(position, media_id)| {
+                json!({
+                    "id": get_child_id(&product_id, media_id),
+                    "mediaId": media_id,
+                    "position": position,
+                })
+            })
+            .collect();
+
+        let cover_id = media
+            .get(cover_position - 1)
+            .map(|cover| cover["id"].clone());
+        entity.remove("cover");
+        if let Some(cover_id) = cover_id {
+            entity.insert("coverId".to_string(), cover_id);
+        }
+        entity.insert("media".to_string(), Value::Array(media));
+
+        Ok(())
+    }
+
+    fn serialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let Some(Value::Array(media)) = entity.get("media") else {
+            return Ok(());
+        };
+
+        let mut media: Vec<&Value> = media.iter().collect();
+        media.sort_by_key(|m| {
+            m.get("position")
+                .and_then(Value::as_u64)
+                .unwrap_or(u64::MAX)
+        });
+
+        let cover_id = entity.get("coverId").and_then(Value::as_str);
+        let cover_position = media
+            .iter()
+            .position(|m| m.get("id").and_then(Value::as_str) == cover_id && cover_id.is_some())
+            .map(|position| json!(position + 1));
+        let media_ids = media
+            .iter()
+            .filter_map(|m| m.get("mediaId").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join(&SEPARATOR.to_string());
+
+        entity.insert("media".to_string(), Value::String(media_ids));
+        entity.insert("cover".to_string(), cover_position.unwrap_or(Value::Null));
+
+        Ok(())
+    }
+}
+
+fn get_product_id(entity: &Entity) -> Option<&str> {
+    entity.get("id").and_then(Value::as_str)
+}
+
+/// Stable id of a nested record, derived from the product id and the referenced id
+fn get_child_id(product_id: &str, referenced_id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(product_id.as_bytes());
+    hasher.update(referenced_id.as_bytes());
+    let hash = format!("{:x}", hasher.finalize());
+
+    hash[..32].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRODUCT_ID: &str = "0190b7c5e0a67a4c8d7e3c4f2b1a0f9e";
+
+    fn visibilities_transformer() -> ProductVisibilities {
+        ProductVisibilities {
+            sales_channels: HashMap::from([
+                ("Storefront".to_string(), "a".repeat(32)),
+                ("Headless".to_string(), "b".repeat(32)),
+            ]),
+        }
+    }
+
+    #[test]
+    fn deserialize_visibilities() {
+        let transformer = visibilities_transformer();
+        let mut entity: Entity = serde_json::from_value(json!({
+            "id": PRODUCT_ID,
+            "visibilities": "Storefront: all|Headless:search",
+        }))
+        .unwrap();
+
+        transformer.deserialize(&mut entity).unwrap();
+        assert_eq!(
+            entity["visibilities"],
+            json!([
+                {
+                    "id": get_child_id(PRODUCT_ID, &"a".repeat(32)),
+                    "salesChannelId": "a".repeat(32),
+                    "visibility": 30
+                },
+                {
+                    "id": get_child_id(PRODUCT_ID, &"b".repeat(32)),
+                    "salesChannelId": "b".repeat(32),
+                    "visibility": 20
+                }
+            ])
+        );
+
+        let mut unknown: Entity =
+            serde_json::from_value(json!({ "visibilities": "Marketplace" })).unwrap();
+        assert!(transformer.deserialize(&mut unknown).is_err());
+    }
+
+    #[test]
+    fn serialize_visibilities() {
+        let transformer = visibilities_transformer();
+        let mut entity: Entity = serde_json::from_value(json!({
+            "visibilities": [
+                { "salesChannelId": "b".repeat(32), "visibility": 10 },
+                { "salesChannelId": "a".repeat(32), "visibility": 30 },
+            ],
+        }))
+        .unwrap();
+
+        transformer.serialize(&mut entity).unwrap();
+        assert_eq!(
+            entity["visibilities"],
+            json!("Headless: link|Storefront: all")
+        );
+    }
+
+    #[test]
+    fn deserialize_and_serialize_cover() {
+        let mut entity: Entity = serde_json::from_value(json!({
+            "id": PRODUCT_ID,
+            "media": "m1|m2",
+            "cover": 2,
+        }))
+        .unwrap();
+
+        ProductCover.deserialize(&mut entity).unwrap();
+        let cover_id = get_child_id(PRODUCT_ID, "m2");
+        assert_eq!(
+            Value::Object(entity.clone()),
+            json!({
+                "id": PRODUCT_ID,
+                "coverId": cover_id,
+                "media": [
+                    { "id": get_child_id(PRODUCT_ID, "m1"), "mediaId": "m1", "position": 0 },
+                    { "id": cover_id, "mediaId": "m2", "position": 1 },
+                ],
+            })
+        );
+
+        ProductCover.serialize(&mut entity).unwrap();
+        assert_eq!(entity["media"], json!("m1|m2"));
+        assert_eq!(entity["cover"], json!(2));
+
+        let mut out_of_range: Entity =
+            serde_json::from_value(json!({ "media": "m1", "cover": 3 })).unwrap();
+        assert!(ProductCover.deserialize(&mut out_of_range).is_err());
+    }
+
+    #[test]
+    fn deserialize_cover_of_new_product() {
+        let mut entity: Entity =
+            serde_json::from_value(json!({ "media": "m1|m2", "cover": 2 })).unwrap();
+
+        ProductCover.deserialize(&mut entity).unwrap();
+        let product_id = entity["id"].as_str().unwrap();
+        assert_eq!(product_id.len(), 32);
+        let cover_id = get_child_id(product_id, "m2");
+        assert_eq!(entity["coverId"], json!(cover_id));
+        assert_eq!(entity["media"][1]["id"], json!(cover_id));
+    }
+}
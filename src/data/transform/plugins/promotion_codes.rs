@@ -0,0 +1,279 @@
+//! Generates the individual codes of promotions on import (`promotion_codes`),
+//! instead of listing every code in a separate file:
+//! ```yaml
+//! transformers:
+//!   - "promotion_codes"
+//! mappings:
+//!   - file_column: "individual code pattern"
+//!     entity_path: "individualCodePattern"
+//!   - file_column: "individual code count"
+//!     entity_path: "individualCodeCount"
+//!     validate: false
+//! ```
+//!
+//! The pattern uses the placeholders of the administration, `%s` for a letter and `%d` for a digit
+//! (e.g. `SUMMER-%s%s%d%d%d`). The codes are random, but their ids are derived from the promotion `id`
+//! and the position of the code. So importing the same file again doesn't create additional codes,
+//! but replaces the existing ones with new codes. Leave the count empty once the codes are handed out.
+//!
+//! At most [`MAX_CODES`] codes are generated per promotion, rows with a higher count are skipped.
+
+use crate::api::Entity;
+use crate::data::transform::plugins::{InvalidRow, RowTransformer};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+const COUNT_FIELD: &str = "individualCodeCount";
+
+/// Maximum number of individual codes generated for one promotion (row)
+const MAX_CODES: u64 = 10_000;
+
+#[derive(Debug)]
+pub struct PromotionCodes;
+
+impl RowTransformer for PromotionCodes {
+    fn name(&self) -> &'static str {
+        "promotion_codes"
+    }
+
+    fn deserialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        let count = match entity.remove(COUNT_FIELD) {
+            Some(Value::Number(count)) => count
+                .as_u64()
+                .ok_or_else(|| anyhow::anyhow!("{COUNT_FIELD} needs to be a positive number"))?,
+            Some(Value::Null) | None => return Ok(()),
+            Some(other) => anyhow::bail!("{COUNT_FIELD} needs to be a number, got {other}"),
+        };
+        if count == 0 {
+            return Ok(());
+        }
+        if count > MAX_CODES {
+            return Err(InvalidRow {
+                pointer: format!("/{COUNT_FIELD}"),
+                message: format!(
+                    "at most {MAX_CODES} individual codes can be generated, got {count}"
+                ),
+            }
+            .into());
+        }
+
+        let Some(Value::String(promotion_id)) = entity.get("id") else {
+            anyhow::bail!("the promotion id is needed to generate individual codes");
+        };
+        let Some(Value::String(pattern)) = entity.get("individualCodePattern") else {
+            anyhow::bail!("individualCodePattern is needed to generate individual codes");
+        };
+
+        let codes = generate_codes(pattern, count)?;
+        let individual_codes: Vec<Value> = codes
+            .into_iter()
+            .enumerate()
+            .map(|(position, code)| {
+                json!({
+                    "id": get_code_id(promotion_id, position),
+                    "code": code,
+                })
+            })
+            .collect();
+
+        entity.insert("useCodes".to_string(), Value::Bool(true));
+        entity.insert("useIndividualCodes".to_string(), Value::Bool(true));
+        entity.insert(
+            "individualCodes".to_string(),
+            Value::Array(individual_codes),
+        );
+        Ok(())
+    }
+
+    fn serialize(&self, entity: &mut Entity) -> anyhow::Result<()> {
+        // only known if the individualCodes association is loaded
+        let count = match entity.get("individualCodes") {
+            Some(Value::Array(codes)) => json!(codes.len()),
+            _ => Value::Null,
+        };
+        entity.insert(COUNT_FIELD.to_string(), count);
+
+        Ok(())
+    }
+}
+
+/// Generate `count` distinct random codes of the pattern
+fn generate_codes(pattern: &str, count: u64) -> anyhow::Result<Vec<String>> {
+    let letters = pattern.matches("%s").count() as u32;
+    let digits = pattern.matches("%d").count() as u32;
+    let possible_codes = 26u64
+        .checked_pow(letters)
+        .and_then(|l| 10u64.checked_pow(digits).and_then(|d| l.checked_mul(d)))
+        .unwrap_or(u64::MAX);
+    // at most half of the possible codes, otherwise finding the last free codes takes forever
+    if letters + digits == 0 || possible_codes / 2 < count {
+        anyhow::bail!(
+            "the pattern '{pattern}' allows only {possible_codes} different codes for {count} codes, add more placeholders (%s or %d)"
+        );
+    }
+
+    let mut random = RandomBytes::default();
+    let mut codes = Vec::with_capacity(count as usize);
+    let mut generated = HashSet::with_capacity(count as usize);
+    while (codes.len() as u64) < count {
+        let code = generate_code(pattern, &mut random);
+        if generated.insert(code.clone()) {
+            codes.push(code);
+        }
+    }
+
+    Ok(codes)
+}
+
+fn generate_code(pattern: &str, random: &mut RandomBytes) -> String {
+    let mut code = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find('%') {
+        code.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        if let Some(after) = placeholder.strip_prefix("%s") {
+            code.push(char::from(b'A' + random.below(26)));
+            rest = after;
+        } else if let Some(after) = placeholder.strip_prefix("%d") {
+            code.push(char::from(b'0' + random.below(10)));
+            rest = after;
+        } else {
+            code.push('%');
+            rest = &placeholder[1..];
+        }
+    }
+    code.push_str(rest);
+
+    code
+}
+
+/// Random bytes of the operating system, taken from v4 UUIDs
+#[derive(Default)]
+struct RandomBytes {
+    bytes: Vec<u8>,
+}
+
+impl RandomBytes {
+    fn next(&mut self) -> u8 {
+        if self.bytes.is_empty() {
+            let uuid = Uuid::new_v4().into_bytes();
+            // the bytes 6 and 8 contain the version and variant bits, which aren't random
+            self.bytes = uuid
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| *i != 6 && *i != 8)
+                .map(|(_, byte)| byte)
+                .collect();
+        }
+        self.bytes.pop().unwrap()
+    }
+
+    /// Uniformly distributed number in `0..bound`
+    fn below(&mut self, bound: u8) -> u8 {
+        // bytes above the last multiple of bound are rejected, they would prefer the low numbers
+        let limit = u8::MAX - u8::MAX % bound;
+        loop {
+            let byte = self.next();
+            if byte < limit {
+                return byte % bound;
+            }
+        }
+    }
+}
+
+/// Stable id of the code at this position, so importing the same row again replaces the codes
+fn get_code_id(promotion_id: &str, position: usize) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(promotion_id.as_bytes());
+    hasher.update(position.to_le_bytes());
+    let hash = format!("{:x}", hasher.finalize());
+
+    hash[..32].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROMOTION_ID: &str = "0190b7c5e0a67a4c8d7e3c4f2b1a0f9e";
+
+    #[test]
+    fn generate_individual_codes() {
+        let mut entity: Entity = serde_json::from_value(json!({
+            "id": PROMOTION_ID,
+            "individualCodePattern": "SUMMER-%s%s%d%d%d",
+            "individualCodeCount": 50,
+        }))
+        .unwrap();
+
+        PromotionCodes.deserialize(&mut entity).unwrap();
+        assert!(!entity.contains_key(COUNT_FIELD));
+        assert_eq!(entity["useIndividualCodes"], json!(true));
+
+        let codes = entity["individualCodes"].as_array().unwrap();
+        assert_eq!(codes.len(), 50);
+        let distinct: HashSet<&str> = codes.iter().map(|c| c["code"].as_str().unwrap()).collect();
+        assert_eq!(distinct.len(), 50);
+        for code in distinct {
+            assert_eq!(code.len(), "SUMMER-AB123".len());
+            assert!(code.starts_with("SUMMER-"));
+            assert!(code[7..9].chars().all(|c| c.is_ascii_uppercase()));
+            assert!(code[9..].chars().all(|c| c.is_ascii_digit()));
+        }
+
+        // the same row replaces the codes with new ones of the same ids
+        let mut again: Entity = serde_json::from_value(json!({
+            "id": PROMOTION_ID,
+            "individualCodePattern": "SUMMER-%s%s%d%d%d",
+            "individualCodeCount": 50,
+        }))
+        .unwrap();
+        PromotionCodes.deserialize(&mut again).unwrap();
+        let ids = |entity: &Entity| -> Vec<Value> {
+            entity["individualCodes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| c["id"].clone())
+                .collect()
+        };
+        assert_eq!(ids(&again), ids(&entity));
+        assert_ne!(again["individualCodes"], entity["individualCodes"]);
+    }
+
+    #[test]
+    fn count_over_the_maximum() {
+        let mut entity: Entity = serde_json::from_value(json!({
+            "id": PROMOTION_ID,
+            "individualCodePattern": "SUMMER-%s%s%s%s%d%d%d%d",
+            "individualCodeCount": u64::MAX,
+        }))
+        .unwrap();
+
+        let error = PromotionCodes.deserialize(&mut entity).unwrap_err();
+        let invalid = error.downcast_ref::<InvalidRow>().unwrap();
+        assert_eq!(invalid.pointer, "/individualCodeCount");
+        assert!(!entity.contains_key("individualCodes"));
+    }
+
+    #[test]
+    fn random_numbers_below_bound() {
+        let mut random = RandomBytes::default();
+        let mut seen = HashSet::new();
+        for _ in 0..1000 {
+            let number = random.below(26);
+            assert!(number < 26);
+            seen.insert(number);
+        }
+        assert_eq!(seen.len(), 26);
+    }
+
+    #[test]
+    fn pattern_with_too_few_placeholders() {
+        assert!(generate_codes("CODE-%d", 5).is_ok());
+        assert!(generate_codes("CODE-%d", 10).is_err());
+        assert!(generate_codes("CODE", 1).is_err());
+    }
+}
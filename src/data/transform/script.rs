@@ -1,18 +1,109 @@
 //! Everything scripting related
 
 use crate::api::{CurrencyList, Entity, IsoLanguageList};
-use crate::config_file::{Mapping, Profile};
-use crate::data::transform::get_json_value_from_string;
+use crate::config_file::{Mapping, Profile, ScriptLimits};
+use crate::data::report::RowWarning;
+use crate::data::transform::wasm::WasmModule;
+use crate::data::transform::{get_json_value_from_string, strip_text_marker};
 use anyhow::Context;
 use csv::StringRecord;
 use rhai::packages::{BasicArrayPackage, CorePackage, MoreStringPackage, Package};
-use rhai::{Engine, OptimizationLevel, Position, Scope, AST};
+use rhai::{
+    Dynamic, Engine, EvalAltResult, FuncRegistration, OptimizationLevel, Position, Scope, AST,
+};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+struct ScriptRun {
+    start: Instant,
+    row_index: usize,
+}
+
+thread_local! {
+    /// Script run on the current thread, used to enforce `ScriptLimits::timeout_ms`
+    /// and to assign `warn` messages to their row
+    static CURRENT_SCRIPT_RUN: Cell<Option<ScriptRun>> = const { Cell::new(None) };
+    /// Time spent in scripts on the current thread, since the last `take_script_time`
+    static SCRIPT_TIME: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Take the time spent in scripts on the current thread (and reset it),
+/// a chunk is always processed by a single thread
+pub fn take_script_time() -> Duration {
+    SCRIPT_TIME.with(|time| time.replace(Duration::ZERO))
+}
+
+/// Adds the time until it is dropped to the script time of the current thread
+struct ScriptTimer(Instant);
+
+impl ScriptTimer {
+    fn start() -> Self {
+        Self(Instant::now())
+    }
+}
+
+impl Drop for ScriptTimer {
+    fn drop(&mut self) {
+        let elapsed = self.0.elapsed();
+        SCRIPT_TIME.with(|time| time.set(time.get() + elapsed));
+    }
+}
+
+/// Thrown by `skip()` inside a script to drop the current entity
+#[derive(Debug, Clone, Copy)]
+struct SkipRow;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ScriptOutcome {
+    Completed,
+    /// the script called `skip()`
+    Skipped,
+}
+
+/// Run a script AST and keep track of the current run for the timeout and warnings
+fn run_script(
+    engine: &Engine,
+    scope: &mut Scope,
+    ast: &AST,
+    row_index: usize,
+) -> anyhow::Result<ScriptOutcome> {
+    CURRENT_SCRIPT_RUN.set(Some(ScriptRun {
+        start: Instant::now(),
+        row_index,
+    }));
+    let result = engine.run_ast_with_scope(scope, ast);
+    CURRENT_SCRIPT_RUN.set(None);
+
+    match result {
+        Ok(()) => Ok(ScriptOutcome::Completed),
+        Err(e) => match *e {
+            EvalAltResult::ErrorRuntime(value, _) if value.is::<SkipRow>() => {
+                Ok(ScriptOutcome::Skipped)
+            }
+            EvalAltResult::ErrorTerminated(reason, position) => {
+                Err(anyhow::anyhow!("{reason} ({position})"))
+            }
+            e => Err(anyhow::Error::new(e)),
+        },
+    }
+}
 
 #[derive(Debug)]
 pub struct ScriptingEnvironment {
     pub engine: Engine,
     pub serialize: Option<AST>,
     pub deserialize: Option<AST>,
+    /// replaces the Rhai scripts if the profile uses `script_engine: wasm`
+    pub wasm: Option<WasmModule>,
+    /// name of the imported / exported file, available as `file` inside the scripts
+    pub file_name: String,
+    /// currencies of the shop, also used for the `currency` of mappings
+    pub currencies: CurrencyList,
+    /// collected messages of `warn(message)` calls inside the scripts
+    warnings: Arc<Mutex<Vec<RowWarning>>>,
 }
 
 impl ScriptingEnvironment {
@@ -21,14 +112,16 @@ impl ScriptingEnvironment {
         &self,
         headers: &StringRecord,
         row: &StringRecord,
+        row_index: usize,
         profile: &Profile,
     ) -> anyhow::Result<Entity> {
-        let Some(deserialize_script) = &self.deserialize else {
+        if self.deserialize.is_none() && self.wasm.is_none() {
             return Ok(Entity::with_capacity(profile.mappings.len()));
-        };
+        }
+        let _timer = ScriptTimer::start();
 
         // build row object
-        let mut script_row = rhai::Map::new();
+        let mut script_row = serde_json::Map::new();
         let script_mappings = profile.mappings.iter().filter_map(|m| match m {
             Mapping::ByScript(s) => Some(s),
             Mapping::ByPath(_) => None,
@@ -45,22 +138,46 @@ impl ScriptingEnvironment {
                 .get(column_index)
                 .context("failed to get column of row")?;
 
-            let json_value = get_json_value_from_string(raw_value, &mapping.column_type)?;
+            let (raw_value, column_type) =
+                strip_text_marker(raw_value, &mapping.column_type, profile);
+            let json_value = get_json_value_from_string(raw_value, column_type)?;
 
-            let script_value = rhai::serde::to_dynamic(json_value)
-                .context("failed to convert CSV value into script value")?;
-
-            script_row.insert(mapping.key.as_str().into(), script_value);
+            script_row.insert(mapping.key.clone(), json_value);
         }
 
+        let Some(deserialize_script) = &self.deserialize else {
+            let wasm = self
+                .wasm
+                .as_ref()
+                .expect("wasm module exists without script");
+            let input = serde_json::json!({
+                "row": script_row,
+                "columns": headers.iter().collect::<Vec<_>>(),
+                "row_index": row_index,
+                "file": self.file_name,
+                "mode": "import",
+            });
+            return match wasm.call("deserialize", &input)? {
+                serde_json::Value::Object(entity) => Ok(entity),
+                other => anyhow::bail!("wasm deserialize needs to return an object, got: {other}"),
+            };
+        };
+
+        let script_row: rhai::Map = rhai::serde::to_dynamic(script_row)
+            .context("failed to convert CSV value into script value")?
+            .cast();
+
         // run the script
-        let mut scope = Scope::new();
+        let mut scope = self.new_scope(row_index, "import");
         scope.push_constant("row", script_row);
         let entity_dynamic = rhai::Map::new();
         scope.push("entity", entity_dynamic);
 
-        self.engine
-            .run_ast_with_scope(&mut scope, deserialize_script)?;
+        if run_script(&self.engine, &mut scope, deserialize_script, row_index)?
+            == ScriptOutcome::Skipped
+        {
+            anyhow::bail!("skip() can only be used in the serialize_script");
+        }
 
         // get the entity out of the script
         let row_result: rhai::Map = scope
@@ -75,13 +192,34 @@ impl ScriptingEnvironment {
         Ok(entity_after_script)
     }
 
-    /// Just returns a default value if there is no script
-    pub fn run_serialize(&self, entity: &Entity) -> anyhow::Result<rhai::Map> {
+    /// Just returns a default value if there is no script.
+    /// Returns `None` if the entity should be skipped (`skip()` or `null` returned by wasm)
+    pub fn run_serialize(
+        &self,
+        entity: &Entity,
+        row_index: usize,
+    ) -> anyhow::Result<Option<rhai::Map>> {
+        let _timer = ScriptTimer::start();
+        if let Some(wasm) = &self.wasm {
+            let input = serde_json::json!({
+                "entity": entity,
+                "row_index": row_index,
+                "file": self.file_name,
+                "mode": "export",
+            });
+            let row = wasm.call("serialize", &input)?;
+            return match row {
+                serde_json::Value::Null => Ok(None),
+                serde_json::Value::Object(_) => Ok(Some(rhai::serde::to_dynamic(row)?.cast())),
+                other => anyhow::bail!("wasm serialize needs to return an object, got: {other}"),
+            };
+        }
+
         let Some(serialize_script) = &self.serialize else {
-            return Ok(rhai::Map::new());
+            return Ok(Some(rhai::Map::new()));
         };
 
-        let mut scope = Scope::new();
+        let mut scope = self.new_scope(row_index, "export");
 
         // this is potentially expensive for big entities!
         // we might only want to pass some data into the script...
@@ -91,13 +229,32 @@ impl ScriptingEnvironment {
         let row_dynamic = rhai::Map::new();
         scope.push("row", row_dynamic);
 
-        self.engine
-            .run_ast_with_scope(&mut scope, serialize_script)?;
+        if run_script(&self.engine, &mut scope, serialize_script, row_index)?
+            == ScriptOutcome::Skipped
+        {
+            return Ok(None);
+        }
 
         let row_result: rhai::Map = scope
             .get_value("row")
             .expect("row should exist in script scope");
-        Ok(row_result)
+        Ok(Some(row_result))
+    }
+
+    /// Take all warnings emitted by the scripts so far, ordered by row
+    pub fn take_warnings(&self) -> Vec<RowWarning> {
+        let mut warnings = std::mem::take(&mut *self.warnings.lock().unwrap());
+        warnings.sort_by_key(|w| w.row_index);
+        warnings
+    }
+
+    /// Scope with the metadata of the current row
+    fn new_scope(&self, row_index: usize, mode: &str) -> Scope<'static> {
+        let mut scope = Scope::new();
+        scope.push_constant("row_index", row_index as rhai::INT);
+        scope.push_constant("file", self.file_name.clone());
+        scope.push_constant("mode", mode.to_string());
+        scope
     }
 }
 
@@ -106,8 +263,16 @@ pub fn prepare_scripting_environment(
     raw_deserialize_script: &str,
     language_list: IsoLanguageList,
     currency_list: CurrencyList,
+    constants: &BTreeMap<String, serde_json::Value>,
+    limits: ScriptLimits,
 ) -> anyhow::Result<ScriptingEnvironment> {
-    let engine = get_base_engine(language_list, currency_list);
+    let mut engine = get_base_engine(language_list, currency_list.clone());
+    register_constants(&mut engine, constants)?;
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    register_warn(&mut engine, Arc::clone(&warnings));
+    register_skip(&mut engine);
+    apply_script_limits(&mut engine, limits);
+
     let serialize_ast = if raw_serialize_script.is_empty() {
         None
     } else {
@@ -129,9 +294,91 @@ pub fn prepare_scripting_environment(
         engine,
         serialize: serialize_ast,
         deserialize: deserialize_ast,
+        wasm: None,
+        file_name: String::new(),
+        currencies: currency_list,
+        warnings,
     })
 }
 
+/// `skip()` drops the current entity from the export and stops the script
+fn register_skip(engine: &mut Engine) {
+    FuncRegistration::new("skip")
+        .with_volatility(true)
+        .register_into_engine(engine, || -> Result<(), Box<EvalAltResult>> {
+            Err(EvalAltResult::ErrorRuntime(Dynamic::from(SkipRow), Position::NONE).into())
+        });
+}
+
+/// Collect `warn(message)` calls of the scripts together with the row they occurred in,
+/// instead of printing them between the output of the parallel workers
+fn register_warn(engine: &mut Engine, warnings: Arc<Mutex<Vec<RowWarning>>>) {
+    // volatile, otherwise the optimizer already calls it with constant messages during compilation
+    FuncRegistration::new("warn")
+        .with_volatility(true)
+        .register_into_engine(engine, move |message: &str| {
+            let row_index = CURRENT_SCRIPT_RUN
+                .get()
+                .map(|run| run.row_index)
+                .unwrap_or_default();
+            warnings.lock().unwrap().push(RowWarning {
+                row_index,
+                message: message.to_string(),
+            });
+        });
+}
+
+/// Make the profile `constants` available with `get_constant("name")`
+fn register_constants(
+    engine: &mut Engine,
+    constants: &BTreeMap<String, serde_json::Value>,
+) -> anyhow::Result<()> {
+    let constants = constants
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), rhai::serde::to_dynamic(value)?)))
+        .collect::<anyhow::Result<BTreeMap<String, Dynamic>>>()
+        .context("failed to convert profile constants into script values")?;
+
+    engine.register_fn(
+        "get_constant",
+        move |name: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            constants.get(name).cloned().ok_or_else(|| {
+                format!(
+                    "get_constant called with '{name}' but there is no such constant in the profile. Available constants: {:?}",
+                    constants.keys().collect::<Vec<_>>()
+                )
+                .into()
+            })
+        },
+    );
+
+    Ok(())
+}
+
+/// Sandbox the engine, so a buggy script (e.g. an infinite loop) fails the row instead of hanging
+fn apply_script_limits(engine: &mut Engine, limits: ScriptLimits) {
+    engine.set_max_operations(limits.max_operations);
+    engine.set_max_call_levels(limits.max_call_levels);
+
+    if limits.timeout_ms > 0 {
+        let timeout = Duration::from_millis(limits.timeout_ms);
+        engine.on_progress(move |operations| {
+            // checking the clock on every operation would be too expensive
+            if operations % 1024 != 0 {
+                return None;
+            }
+
+            match CURRENT_SCRIPT_RUN.get() {
+                Some(run) if run.start.elapsed() > timeout => Some(Dynamic::from(format!(
+                    "script exceeded the timeout of {}ms (script_limits.timeout_ms)",
+                    timeout.as_millis()
+                ))),
+                _ => None,
+            }
+        });
+    }
+}
+
 fn get_base_engine(language_list: IsoLanguageList, currency_list: CurrencyList) -> Engine {
     let mut engine = Engine::new_raw();
     engine.set_optimization_level(OptimizationLevel::Full);
@@ -154,14 +401,31 @@ fn get_base_engine(language_list: IsoLanguageList, currency_list: CurrencyList)
 
     // Add custom utility functions to engine
     engine.register_fn("get_default", inside_script::get_default);
+    engine.register_fn("strip_html", inside_script::strip_html);
+    engine.register_fn("html_to_plaintext", inside_script::html_to_plaintext);
+    engine.register_fn("truncate_words", inside_script::truncate_words);
+    engine.register_fn("slugify", inside_script::slugify);
+    engine.register_fn("gross_to_net", inside_script::gross_to_net);
+    engine.register_fn("net_to_gross", inside_script::net_to_gross);
+    engine.register_fn("round_money", inside_script::round_money);
 
-    engine.register_fn("get_language_by_iso", move |iso: &str| {
-        language_list.get_language_id_by_iso_code(iso)
-    });
+    engine.register_fn(
+        "get_language_by_iso",
+        move |iso: &str| -> Result<String, Box<EvalAltResult>> {
+            language_list
+                .get_language_id_by_iso_code(iso)
+                .map_err(|e| e.to_string().into())
+        },
+    );
 
-    engine.register_fn("get_currency_by_iso", move |iso: &str| {
-        currency_list.get_currency_id_by_iso_code(iso)
-    });
+    engine.register_fn(
+        "get_currency_by_iso",
+        move |iso: &str| -> Result<String, Box<EvalAltResult>> {
+            currency_list
+                .get_currency_id_by_iso_code(iso)
+                .map_err(|e| e.to_string().into())
+        },
+    );
 
     // Some reference implementations below
     /*
@@ -185,7 +449,7 @@ fn get_base_engine(language_list: IsoLanguageList, currency_list: CurrencyList)
 /// Important, don't use the type `String` as function parameters, see
 /// <https://rhai.rs/book/rust/strings.html>
 mod inside_script {
-    use rhai::ImmutableString;
+    use rhai::{Dynamic, EvalAltResult, ImmutableString, Position, FLOAT, INT};
 
     /// Imitate
     /// [Defaults.php from Shopware](https://github.com/shopware/shopware/blob/03cfe8cca937e6e45c9c3e15821d1449dfd01d82/src/Core/Defaults.php)
@@ -217,13 +481,231 @@ mod inside_script {
             )
         }
     }
+
+    /// Remove all HTML tags, but keep the text (and HTML entities) as it is
+    pub fn strip_html(html: &str) -> String {
+        let mut text = String::with_capacity(html.len());
+        let mut inside_tag = false;
+        for c in html.chars() {
+            match c {
+                '<' => inside_tag = true,
+                '>' if inside_tag => inside_tag = false,
+                c if !inside_tag => text.push(c),
+                _ => {}
+            }
+        }
+
+        decode_html_entities(&text)
+    }
+
+    /// Convert HTML into readable plain text: block elements and `<br>` become line breaks,
+    /// `<script>` / `<style>` content is dropped, entities are decoded and whitespace is collapsed
+    pub fn html_to_plaintext(html: &str) -> String {
+        const BLOCK_TAGS: &[&str] = &[
+            "br", "p", "div", "li", "ul", "ol", "tr", "table", "h1", "h2", "h3", "h4", "h5", "h6",
+        ];
+
+        let mut text = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(tag_start) = rest.find('<') {
+            text.push_str(&rest[..tag_start]);
+            let Some(tag_end) = rest[tag_start..].find('>') else {
+                // unclosed tag, drop the rest
+                rest = "";
+                break;
+            };
+            let tag = &rest[tag_start + 1..tag_start + tag_end];
+            rest = &rest[tag_start + tag_end + 1..];
+
+            let tag_name = tag
+                .trim_start_matches('/')
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+
+            if !tag.starts_with('/') && (tag_name == "script" || tag_name == "style") {
+                let closing_tag = format!("</{tag_name}");
+                rest = match rest.to_ascii_lowercase().find(&closing_tag) {
+                    Some(closing_start) => {
+                        let after_closing = &rest[closing_start..];
+                        after_closing
+                            .find('>')
+                            .map_or("", |end| &after_closing[end + 1..])
+                    }
+                    None => "",
+                };
+            } else if BLOCK_TAGS.contains(&tag_name.as_str()) {
+                text.push('\n');
+            }
+        }
+        text.push_str(rest);
+
+        decode_html_entities(&text)
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn decode_html_entities(text: &str) -> String {
+        let mut decoded = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('&') {
+            decoded.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let entity = rest[1..]
+                .find(';')
+                .filter(|&end| end <= 10)
+                .map(|end| &rest[1..=end]);
+            let replacement = match entity {
+                Some("amp") => Some('&'),
+                Some("lt") => Some('<'),
+                Some("gt") => Some('>'),
+                Some("quot") => Some('"'),
+                Some("apos") => Some('\''),
+                Some("nbsp") => Some(' '),
+                Some(e) if e.starts_with("#x") || e.starts_with("#X") => {
+                    u32::from_str_radix(&e[2..], 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                }
+                Some(e) if e.starts_with('#') => e[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+
+            match (replacement, entity) {
+                (Some(c), Some(entity)) => {
+                    decoded.push(c);
+                    rest = &rest[entity.len() + 2..];
+                }
+                _ => {
+                    decoded.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        decoded.push_str(rest);
+
+        decoded
+    }
+
+    /// Keep only the first `max_words` words of the text (whitespace is collapsed)
+    pub fn truncate_words(text: &str, max_words: INT) -> String {
+        let max_words = usize::try_from(max_words).unwrap_or_default();
+        text.split_whitespace()
+            .take(max_words)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Create a lowercase, URL friendly version of the text, e.g. `Größe & Farbe` -> `groesse-farbe`.
+    /// HTML entities are decoded and latin letters with diacritics are transliterated,
+    /// letters of other scripts (like `ελλάδα`) are kept
+    pub fn slugify(text: &str) -> String {
+        let text = decode_html_entities(text);
+        let mut slug = String::with_capacity(text.len());
+        for c in text.chars().flat_map(char::to_lowercase) {
+            match c {
+                // combining diacritical marks of decomposed characters, like `e\u{301}`
+                '\u{300}'..='\u{36f}' => {}
+                c if c.is_ascii_alphanumeric() => slug.push(c),
+                c if c.is_alphanumeric() => match transliterate(c) {
+                    Some(latin) => slug.push_str(latin),
+                    None => slug.push(c),
+                },
+                _ if !slug.is_empty() && !slug.ends_with('-') => slug.push('-'),
+                _ => {}
+            }
+        }
+
+        slug.trim_end_matches('-').to_string()
+    }
+
+    /// ASCII replacement of a lowercase latin letter with diacritics (German umlauts like `ae`)
+    fn transliterate(c: char) -> Option<&'static str> {
+        let latin = match c {
+            'ä' | 'æ' => "ae",
+            'ö' | 'ø' | 'œ' => "oe",
+            'ü' => "ue",
+            'ß' => "ss",
+            'à' | 'á' | 'â' | 'ã' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'ç' | 'ć' | 'č' => "c",
+            'ď' | 'đ' | 'ð' => "d",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => "i",
+            'ł' => "l",
+            'ñ' | 'ń' | 'ň' => "n",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ō' | 'ő' => "o",
+            'ŕ' | 'ř' => "r",
+            'ś' | 'š' | 'ş' => "s",
+            'ť' | 'ţ' => "t",
+            'þ' => "th",
+            'ù' | 'ú' | 'û' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+            'ý' | 'ÿ' => "y",
+            'ź' | 'ż' | 'ž' => "z",
+            _ => return None,
+        };
+
+        Some(latin)
+    }
+
+    /// Accept integers as well as floats, e.g. a tax rate of `19`
+    fn to_float(value: &Dynamic) -> Result<FLOAT, Box<EvalAltResult>> {
+        value
+            .as_float()
+            .or_else(|_| value.as_int().map(|i| i as FLOAT))
+            .map_err(|type_name| format!("expected a number, got '{type_name}'").into())
+    }
+
+    /// Net price for a gross price and a tax rate in percent (e.g. `19`)
+    pub fn gross_to_net(gross: Dynamic, tax_rate: Dynamic) -> Result<FLOAT, Box<EvalAltResult>> {
+        Ok(to_float(&gross)? * 100.0 / (100.0 + to_float(&tax_rate)?))
+    }
+
+    /// Gross price for a net price and a tax rate in percent (e.g. `19`)
+    pub fn net_to_gross(net: Dynamic, tax_rate: Dynamic) -> Result<FLOAT, Box<EvalAltResult>> {
+        Ok(to_float(&net)? * (100.0 + to_float(&tax_rate)?) / 100.0)
+    }
+
+    /// Round half away from zero to the given decimal places, based on the shortest
+    /// decimal representation of the value (so `1.005` is rounded to `1.01` and not `1.00`)
+    pub fn round_money(value: Dynamic, decimals: INT) -> Result<FLOAT, Box<EvalAltResult>> {
+        let value = to_float(&value)?;
+        if !value.is_finite() {
+            return Ok(value);
+        }
+
+        // beyond it the result doesn't change anymore (floats range from ~1e-324 to ~1e308)
+        const MAX_DECIMALS: INT = 400;
+        let decimals = decimals.clamp(-MAX_DECIMALS, MAX_DECIMALS);
+        let parse = |representation: String| {
+            representation.parse::<FLOAT>().map_err(|e| {
+                EvalAltResult::ErrorArithmetic(
+                    format!("failed to round {value} to {decimals} decimals: {e}"),
+                    Position::NONE,
+                )
+                .into()
+            })
+        };
+
+        // shifting the decimal point in the string representation avoids the error of `value * 10^decimals`
+        let shifted = parse(format!("{value}e{decimals}"))?;
+        if !shifted.is_finite() {
+            // more decimals than the precision of the float, so there is nothing to round
+            return Ok(value);
+        }
+
+        parse(format!("{}e{}", shifted.round(), -decimals))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config_file::EntityScriptMapping;
-    use rhai::Dynamic;
+    use crate::config_file::{EntityScriptMapping, IsoCodeOptions, UnknownIsoCode};
     use serde_json::json;
     use std::collections::HashMap;
 
@@ -238,11 +720,10 @@ mod tests {
             "a13966f91ef24dcabccf1668e3618955".to_string(),
         );
 
-        let locale_list = IsoLanguageList {
+        IsoLanguageList {
             data: language_list_inner,
-        };
-
-        locale_list
+            iso_codes: IsoCodeOptions::default(),
+        }
     }
 
     fn create_currency_list() -> CurrencyList {
@@ -256,11 +737,10 @@ mod tests {
             "cae49554610b4df2be0fbd61be51f66d".to_string(),
         );
 
-        let currency_list = CurrencyList {
+        CurrencyList {
             data: currency_list_inner,
-        };
-
-        currency_list
+            iso_codes: IsoCodeOptions::default(),
+        }
     }
 
     #[test]
@@ -276,6 +756,8 @@ mod tests {
         "#,
             create_language_iso_list(),
             create_currency_list(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
         )
         .unwrap();
 
@@ -285,7 +767,7 @@ mod tests {
         }))
         .unwrap();
 
-        let row = script_env.run_serialize(&entity).unwrap();
+        let row = script_env.run_serialize(&entity, 0).unwrap().unwrap();
         let row_json: serde_json::Value =
             serde_json::from_value(rhai::serde::from_dynamic(&Dynamic::from(row)).unwrap())
                 .unwrap();
@@ -315,9 +797,12 @@ mod tests {
             entity["defaultCurrencyId"] = get_default("CURRENCY");
             entity["languageId"] = get_language_by_iso("de-DE");
             entity["currencyId"] = get_currency_by_iso("USD");
+            entity["salesChannelId"] = get_constant("salesChannelId");
         "#,
             iso_list.clone(),
             currency_list.clone(),
+            &BTreeMap::from([("salesChannelId".to_string(), json!("abc"))]),
+            ScriptLimits::default(),
         )
         .unwrap();
 
@@ -328,11 +813,15 @@ mod tests {
                     file_column: "bar".to_string(),
                     key: "bar_key".to_string(),
                     column_type: None,
+                    header: None,
+                    width: None,
                 }),
                 Mapping::ByScript(EntityScriptMapping {
                     file_column: "number + 1".to_string(),
                     key: "number_plus_one".to_string(),
                     column_type: None,
+                    header: None,
+                    width: None,
                 }),
             ],
             ..Default::default()
@@ -341,7 +830,7 @@ mod tests {
         let row = StringRecord::from(vec!["buzz", "43"]);
 
         let entity = script_env
-            .run_deserialize(&headers, &row, &profile)
+            .run_deserialize(&headers, &row, 0, &profile)
             .unwrap();
 
         assert_eq!(
@@ -350,10 +839,243 @@ mod tests {
                 "fiz": "buzz",
                 "number": 42,
                 "defaultCurrencyId": inside_script::get_default("CURRENCY"),
-                "languageId": iso_list.get_language_id_by_iso_code("de-DE"),
-                "currencyId": currency_list.get_currency_id_by_iso_code("USD"),
+                "languageId": iso_list.get_language_id_by_iso_code("de-DE").unwrap(),
+                "currencyId": currency_list.get_currency_id_by_iso_code("USD").unwrap(),
+                "salesChannelId": "abc",
             }))
             .unwrap()
         );
     }
+
+    #[test]
+    fn unknown_iso_code_fails_the_row() {
+        let mut languages = create_language_iso_list();
+        languages.iso_codes.unknown = UnknownIsoCode::Error;
+        let script_env = prepare_scripting_environment(
+            "",
+            r#"
+            entity.languageId = get_language_by_iso(row.locale);
+        "#,
+            languages,
+            create_currency_list(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        let profile = Profile {
+            mappings: vec![Mapping::ByScript(EntityScriptMapping {
+                file_column: "locale".to_string(),
+                key: "locale".to_string(),
+                column_type: None,
+                header: None,
+                width: None,
+            })],
+            ..Default::default()
+        };
+        let headers = StringRecord::from(vec!["locale"]);
+
+        let entity = script_env
+            .run_deserialize(&headers, &StringRecord::from(vec!["de-DE"]), 0, &profile)
+            .unwrap();
+        assert_eq!(
+            entity["languageId"],
+            json!("cf8eb267dd2a4c54be07bf4b50d65ab5")
+        );
+
+        let error = script_env
+            .run_deserialize(&headers, &StringRecord::from(vec!["xx-XX"]), 1, &profile)
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("language with iso code 'xx-XX' not found"));
+    }
+
+    #[test]
+    fn script_limits_stop_infinite_loop() {
+        let infinite_loop = r#"
+            loop { row["counter"] = 1; }
+        "#;
+        let entity = Entity::new();
+
+        let script_env = prepare_scripting_environment(
+            infinite_loop,
+            "",
+            create_language_iso_list(),
+            create_currency_list(),
+            &BTreeMap::new(),
+            ScriptLimits {
+                max_operations: 10_000,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let error = script_env.run_serialize(&entity, 0).unwrap_err();
+        assert!(error.to_string().contains("Too many operations"), "{error}");
+
+        let script_env = prepare_scripting_environment(
+            infinite_loop,
+            "",
+            create_language_iso_list(),
+            create_currency_list(),
+            &BTreeMap::new(),
+            ScriptLimits {
+                timeout_ms: 50,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let error = script_env.run_serialize(&entity, 0).unwrap_err();
+        assert!(error.to_string().contains("timeout of 50ms"), "{error}");
+    }
+
+    #[test]
+    fn text_helpers() {
+        let html = "<h1>Title</h1><p>Some <b>bold</b>&nbsp;text &amp; more</p><script>alert('x');</script><ul><li>one</li><li>two&#33;</li></ul>";
+
+        assert_eq!(
+            inside_script::strip_html(html),
+            "TitleSome bold text & morealert('x');onetwo!"
+        );
+        // a lone `&` or `<` isn't an entity / tag
+        assert_eq!(
+            inside_script::strip_html("Salt & Pepper &unknown; &#xZZ; 3 < 4"),
+            "Salt & Pepper &unknown; &#xZZ; 3 "
+        );
+        assert_eq!(
+            inside_script::strip_html("&lt;b&gt; is a &quot;tag&quot;"),
+            "<b> is a \"tag\""
+        );
+        assert_eq!(
+            inside_script::html_to_plaintext(html),
+            "Title\nSome bold text & more\none\ntwo!"
+        );
+        assert_eq!(
+            inside_script::truncate_words("  the quick\nbrown fox ", 3),
+            "the quick brown"
+        );
+        assert_eq!(inside_script::truncate_words("the quick", -1), "");
+        assert_eq!(
+            inside_script::slugify(" Größe & Farbe: Rot!"),
+            "groesse-farbe-rot"
+        );
+        assert_eq!(
+            inside_script::slugify("Crème Brûlée &amp; Açaí"),
+            "creme-brulee-acai"
+        );
+        // decomposed characters (e + combining acute accent)
+        assert_eq!(inside_script::slugify("Cafe\u{301} Ærø"), "cafe-aeroe");
+        assert_eq!(inside_script::slugify("Ελλάδα 2024"), "ελλάδα-2024");
+        assert_eq!(inside_script::slugify("--- !!! ---"), "");
+    }
+
+    #[test]
+    fn price_helpers() {
+        let round = |value: f64, decimals| {
+            inside_script::round_money(Dynamic::from_float(value), decimals).unwrap()
+        };
+        assert_eq!(round(1.005, 2), 1.01);
+        assert_eq!(round(-1.005, 2), -1.01);
+        assert_eq!(round(2.675, 2), 2.68);
+        assert_eq!(round(1234.5, 0), 1235.0);
+        assert_eq!(round(1234.5, -1), 1230.0);
+        // out of range values don't panic
+        assert_eq!(round(1e308, 10), 1e308);
+        assert_eq!(round(f64::MAX, 2), f64::MAX);
+        assert_eq!(round(1.5, i64::MAX), 1.5);
+        assert_eq!(round(1234.5, i64::MIN), 0.0);
+        assert!(round(f64::NAN, 2).is_nan());
+
+        let net =
+            inside_script::gross_to_net(Dynamic::from_float(119.0), Dynamic::from_int(19)).unwrap();
+        assert_eq!(net, 100.0);
+        let gross =
+            inside_script::net_to_gross(Dynamic::from_float(100.0), Dynamic::from_float(7.0))
+                .unwrap();
+        assert_eq!(gross, 107.0);
+        assert!(inside_script::gross_to_net(Dynamic::from("119"), Dynamic::from_int(19)).is_err());
+    }
+
+    #[test]
+    fn row_metadata_in_scope() {
+        let mut script_env = prepare_scripting_environment(
+            r#"
+            row["meta"] = `${file}:${row_index}:${mode}`;
+        "#,
+            "",
+            create_language_iso_list(),
+            create_currency_list(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+        script_env.file_name = "products.csv".to_string();
+
+        let row = script_env
+            .run_serialize(&Entity::new(), 42)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            row.get("meta").unwrap().clone().into_string().unwrap(),
+            "products.csv:42:export"
+        );
+    }
+
+    #[test]
+    fn collect_warnings() {
+        let script_env = prepare_scripting_environment(
+            r#"
+            if entity.stock < 0 {
+                warn("negative stock");
+            }
+        "#,
+            "",
+            create_language_iso_list(),
+            create_currency_list(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+
+        let negative_stock: Entity = serde_json::from_value(json!({ "stock": -1 })).unwrap();
+        let positive_stock: Entity = serde_json::from_value(json!({ "stock": 1 })).unwrap();
+        script_env.run_serialize(&negative_stock, 3).unwrap();
+        script_env.run_serialize(&positive_stock, 2).unwrap();
+        script_env.run_serialize(&negative_stock, 1).unwrap();
+
+        assert_eq!(
+            script_env.take_warnings(),
+            vec![
+                RowWarning {
+                    row_index: 1,
+                    message: "negative stock".to_string()
+                },
+                RowWarning {
+                    row_index: 3,
+                    message: "negative stock".to_string()
+                },
+            ]
+        );
+        assert!(script_env.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn skip_entity() {
+        let script_env = prepare_scripting_environment(
+            r#"
+            if entity.stock == 0 {
+                skip();
+            }
+            row["stock"] = entity.stock;
+        "#,
+            "",
+            create_language_iso_list(),
+            create_currency_list(),
+            &BTreeMap::new(),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+
+        let no_stock: Entity = serde_json::from_value(json!({ "stock": 0 })).unwrap();
+        let stock: Entity = serde_json::from_value(json!({ "stock": 5 })).unwrap();
+        assert!(script_env.run_serialize(&no_stock, 0).unwrap().is_none());
+        assert!(script_env.run_serialize(&stock, 1).unwrap().is_some());
+    }
 }
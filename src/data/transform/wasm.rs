@@ -0,0 +1,259 @@
+//! WebAssembly scripting backend (feature `wasm`), as an alternative to Rhai scripts
+//!
+//! The module needs to export its `memory` and the following functions (without any imports):
+//! - `alloc(len: i32) -> i32` returns a pointer to `len` bytes of memory for the input
+//! - `serialize(ptr: i32, len: i32) -> i64` receives the entity as JSON and returns the row as JSON
+//! - `deserialize(ptr: i32, len: i32) -> i64` receives the row as JSON and returns the entity as JSON
+//!
+//! The input is an object with the entity (`entity`) or the row (`row`) and the metadata of the row
+//! (`row_index`, `file`, `mode` and on import the column names of the file in `columns`).
+//! The returned `i64` packs the pointer (upper 32 bits) and the length (lower 32 bits)
+//! of the UTF-8 encoded JSON output inside the module memory.
+//!
+//! Instances are reused for the following rows of a worker, so `alloc` needs to reuse its memory.
+//! The `script_limits` of the profile apply to every call: `max_operations` as fuel
+//! and `timeout_ms` as epoch deadline, a call exceeding them fails the row.
+
+use crate::config_file::ScriptLimits;
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+#[cfg(feature = "wasm")]
+use std::sync::Mutex;
+
+#[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+pub struct WasmModule {
+    #[cfg(feature = "wasm")]
+    engine: wasmtime::Engine,
+    #[cfg(feature = "wasm")]
+    module: wasmtime::Module,
+    limits: ScriptLimits,
+    /// idle instances, every worker takes one for a call and puts it back afterward
+    #[cfg(feature = "wasm")]
+    instances: Mutex<Vec<WasmInstance>>,
+}
+
+impl Debug for WasmModule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmModule")
+            .field("limits", &self.limits)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "wasm")]
+struct WasmInstance {
+    store: wasmtime::Store<()>,
+    instance: wasmtime::Instance,
+}
+
+/// Interval of the epoch ticks, which are counted for the timeout of a call
+#[cfg(feature = "wasm")]
+const EPOCH_TICK: std::time::Duration = std::time::Duration::from_millis(10);
+
+#[cfg(feature = "wasm")]
+impl WasmModule {
+    pub fn load(path: &Path, limits: ScriptLimits) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        let engine = Self::new_engine(limits)?;
+        let module = wasmtime::Module::from_file(&engine, path)
+            .with_context(|| format!("failed to load wasm module {path:?}"))?;
+
+        Ok(Self::new(engine, module, limits))
+    }
+
+    fn new_engine(limits: ScriptLimits) -> anyhow::Result<wasmtime::Engine> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(limits.max_operations > 0);
+        config.epoch_interruption(limits.timeout_ms > 0);
+        let engine = wasmtime::Engine::new(&config)?;
+
+        if limits.timeout_ms > 0 {
+            // ends together with the engine
+            let weak_engine = engine.weak();
+            std::thread::spawn(move || {
+                while let Some(engine) = weak_engine.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    std::thread::sleep(EPOCH_TICK);
+                }
+            });
+        }
+
+        Ok(engine)
+    }
+
+    fn new(engine: wasmtime::Engine, module: wasmtime::Module, limits: ScriptLimits) -> Self {
+        Self {
+            engine,
+            module,
+            limits,
+            instances: Mutex::new(vec![]),
+        }
+    }
+
+    /// Call an exported function of the module with a JSON value and return its JSON output
+    pub fn call(
+        &self,
+        function: &str,
+        input: &serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let mut instance = match self.instances.lock().unwrap().pop() {
+            Some(instance) => instance,
+            None => self.instantiate()?,
+        };
+
+        let result = self.call_instance(&mut instance, function, input);
+        // a failed call (e.g. a trap) can leave the instance in an inconsistent state
+        if result.is_ok() {
+            self.instances.lock().unwrap().push(instance);
+        }
+
+        result
+    }
+
+    fn instantiate(&self) -> anyhow::Result<WasmInstance> {
+        let mut store = wasmtime::Store::new(&self.engine, ());
+        let instance = wasmtime::Instance::new(&mut store, &self.module, &[])?;
+
+        Ok(WasmInstance { store, instance })
+    }
+
+    fn call_instance(
+        &self,
+        WasmInstance { store, instance }: &mut WasmInstance,
+        function: &str,
+        input: &serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        use anyhow::Context;
+
+        if self.limits.max_operations > 0 {
+            store.set_fuel(self.limits.max_operations)?;
+        }
+        if self.limits.timeout_ms > 0 {
+            store.set_epoch_deadline(
+                self.limits
+                    .timeout_ms
+                    .div_ceil(EPOCH_TICK.as_millis() as u64),
+            );
+        }
+
+        let memory = instance
+            .get_memory(&mut *store, "memory")
+            .context("wasm module doesn't export its 'memory'")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut *store, "alloc")?;
+        let func = instance.get_typed_func::<(i32, i32), i64>(&mut *store, function)?;
+
+        let input = serde_json::to_vec(input)?;
+        let input_len = i32::try_from(input.len()).context("wasm input is too big")?;
+        let input_ptr = alloc.call(&mut *store, input_len)?;
+        memory.write(&mut *store, input_ptr as usize, &input)?;
+
+        let packed = func
+            .call(&mut *store, (input_ptr, input_len))
+            .with_context(|| {
+                format!(
+                    "wasm function '{function}' failed (it might have exceeded the script_limits of {}ms / {} operations)",
+                    self.limits.timeout_ms, self.limits.max_operations
+                )
+            })?;
+        let output_ptr = (packed as u64 >> 32) as usize;
+        let output_len = (packed as u64 & 0xffff_ffff) as usize;
+
+        let mut output = vec![0; output_len];
+        memory.read(&*store, output_ptr, &mut output)?;
+
+        serde_json::from_slice(&output)
+            .with_context(|| format!("wasm function '{function}' returned invalid JSON"))
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+impl WasmModule {
+    pub fn load(_path: &Path, _limits: ScriptLimits) -> anyhow::Result<Self> {
+        anyhow::bail!("script_engine 'wasm' is not available in this build, it needs to be compiled with the feature 'wasm'")
+    }
+
+    pub fn call(
+        &self,
+        _function: &str,
+        _input: &serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        unreachable!("wasm module can't be loaded without the feature 'wasm'")
+    }
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Echoes the input back as output
+    const ECHO_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                i32.const 1024)
+            (func $echo (param $ptr i32) (param $len i32) (result i64)
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len))))
+            (export "serialize" (func $echo))
+            (export "deserialize" (func $echo)))
+    "#;
+
+    /// Never returns
+    const LOOP_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                i32.const 1024)
+            (func (export "serialize") (param i32) (param i32) (result i64)
+                (loop $forever (br $forever))
+                i64.const 0))
+    "#;
+
+    fn load(wat: &str, limits: ScriptLimits) -> WasmModule {
+        let engine = WasmModule::new_engine(limits).unwrap();
+        let module = wasmtime::Module::new(&engine, wat).unwrap();
+        WasmModule::new(engine, module, limits)
+    }
+
+    #[test]
+    fn call_wasm_module() {
+        let wasm = load(ECHO_MODULE, ScriptLimits::default());
+
+        let input = json!({ "entity": { "name": "shopware", "stock": 42 }, "row_index": 3 });
+        assert_eq!(wasm.call("serialize", &input).unwrap(), input);
+        // the instance is reused for the next call
+        assert_eq!(wasm.instances.lock().unwrap().len(), 1);
+        assert_eq!(wasm.call("deserialize", &input).unwrap(), input);
+        assert_eq!(wasm.instances.lock().unwrap().len(), 1);
+        assert!(wasm.call("unknown", &input).is_err());
+    }
+
+    #[test]
+    fn endless_wasm_module_is_stopped() {
+        let input = json!({});
+
+        let wasm = load(
+            LOOP_MODULE,
+            ScriptLimits {
+                max_operations: 10_000,
+                timeout_ms: 0,
+                ..Default::default()
+            },
+        );
+        assert!(wasm.call("serialize", &input).is_err());
+
+        let wasm = load(
+            LOOP_MODULE,
+            ScriptLimits {
+                timeout_ms: 50,
+                ..Default::default()
+            },
+        );
+        assert!(wasm.call("serialize", &input).is_err());
+        assert!(wasm.instances.lock().unwrap().is_empty());
+    }
+}
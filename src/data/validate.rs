@@ -1,5 +1,8 @@
 use crate::api::Entity;
-use crate::config_file::{EntityPathMapping, Mapping};
+use crate::config_file::{EntityPathMapping, Mapping, Profile};
+use crate::data::export::META_COLUMNS;
+use csv::StringRecord;
+use std::collections::HashSet;
 
 /// Validate paths for entity
 pub fn validate_paths_for_entity(
@@ -14,15 +17,30 @@ pub fn validate_paths_for_entity(
 
     for entry in mappings {
         let path_mapping = match entry {
-            Mapping::ByPath(path_mapping) => path_mapping,
-            Mapping::ByScript(_) => continue,
+            Mapping::ByPath(path_mapping) if path_mapping.validate => path_mapping,
+            Mapping::ByPath(_) | Mapping::ByScript(_) => continue,
         };
 
+        if let Some(lookup) = &path_mapping.lookup {
+            let has_field = api_schema
+                .get(&lookup.entity)
+                .and_then(|x| x.get("properties"))
+                .and_then(|x| x.get(&lookup.field))
+                .is_some();
+            if !has_field {
+                anyhow::bail!(
+                    "Lookup entity {} does not have a field {}",
+                    lookup.entity,
+                    lookup.field
+                );
+            }
+        }
+
         let path = path_mapping.entity_path.split('.').collect::<Vec<_>>();
         let root_path = path[0];
 
-        // if path ends with ? remove it
-        let root_path = root_path.trim_end_matches('?');
+        // if path ends with ? or is a wildcard over an array ([*]) remove it
+        let root_path = root_path.trim_end_matches('?').trim_end_matches("[*]");
 
         let Some(root_property) = api_schema
             .get(entity)
@@ -33,8 +51,61 @@ pub fn validate_paths_for_entity(
             anyhow::bail!("Entity {} does not have a field {}", entity, root_path);
         };
 
+        // prices of a currency are read / written in the list of prices, like `price.gross`
+        if path_mapping.currency.is_some() {
+            if path.len() != 2 || root_property["type"].as_str() != Some("json_object") {
+                anyhow::bail!(
+                    "Field {} in {} is not a field of a price (like 'price.gross'), but a currency is set in the mapping",
+                    path_mapping.entity_path,
+                    entity
+                );
+            }
+            continue;
+        }
+
         // if path has only one part it should be a simple field
         if path.len() == 1 {
+            if path_mapping.language.is_some()
+                && !root_property
+                    .get("flags")
+                    .and_then(|f| f.get("translatable"))
+                    .and_then(|t| t.as_bool())
+                    .unwrap_or(false)
+            {
+                anyhow::bail!(
+                    "Field {} in {} is not translatable, but a language is set in the mapping",
+                    root_path,
+                    entity
+                );
+            }
+
+            // the nested mappings are validated against the entity of the association elements
+            if !path_mapping.mappings.is_empty() {
+                let association_entity = root_property
+                    .get("entity")
+                    .and_then(|e| e.as_str())
+                    .filter(|_| root_property["type"].as_str() == Some("association"));
+                let Some(association_entity) = association_entity else {
+                    anyhow::bail!(
+                        "Field {} in {} is not an association, but it has nested mappings",
+                        root_path,
+                        entity
+                    );
+                };
+                let nested_mappings = path_mapping
+                    .mappings
+                    .iter()
+                    .map(|nested| {
+                        Mapping::ByPath(Box::new(EntityPathMapping {
+                            file_column: path_mapping.file_column.clone(),
+                            entity_path: nested.entity_path.clone(),
+                            ..Default::default()
+                        }))
+                    })
+                    .collect();
+                validate_paths_for_entity(association_entity, &nested_mappings, api_schema)?;
+            }
+
             continue;
         }
 
@@ -47,11 +118,10 @@ pub fn validate_paths_for_entity(
         let path = path[1..].join(".");
 
         // create a new mapping with the new path
-        let mapping = Mapping::ByPath(EntityPathMapping {
-            file_column: path_mapping.file_column.clone(),
+        let mapping = Mapping::ByPath(Box::new(EntityPathMapping {
             entity_path: path,
-            column_type: path_mapping.column_type.clone(),
-        });
+            ..path_mapping.as_ref().clone()
+        }));
 
         // validate the new mapping
         validate_paths_for_entity(entity_name, &vec![mapping], api_schema)?;
@@ -60,19 +130,314 @@ pub fn validate_paths_for_entity(
     Ok(())
 }
 
+/// Association paths (like `manufacturer.media`) which don't exist in the entity schema, with the reason.
+/// The API silently ignores them and the exported fields stay empty.
+/// Associations of mappings with `validate: false` (e.g. of plugin fields missing in the schema) aren't checked
+pub fn get_invalid_associations(
+    entity: &str,
+    associations: &HashSet<String>,
+    mappings: &[Mapping],
+    api_schema: &Entity,
+) -> Vec<String> {
+    let unvalidated_paths: Vec<String> = mappings
+        .iter()
+        .filter_map(|mapping| match mapping {
+            Mapping::ByPath(by_path) if !by_path.validate => {
+                Some(by_path.entity_path.replace('?', ""))
+            }
+            _ => None,
+        })
+        .collect();
+    let is_unvalidated = |association: &str| {
+        unvalidated_paths.iter().any(|path| {
+            path.strip_prefix(association)
+                .is_some_and(|rest| rest.starts_with('.'))
+        })
+    };
+
+    let mut invalid = associations
+        .iter()
+        .filter(|association| !is_unvalidated(association))
+        .filter_map(|association| {
+            get_association_error(entity, association, api_schema)
+                .map(|error| format!("{association} ({error})"))
+        })
+        .collect::<Vec<_>>();
+    invalid.sort_unstable();
+
+    invalid
+}
+
+/// Reason why the association path doesn't exist, `None` if it is valid
+fn get_association_error(entity: &str, association: &str, api_schema: &Entity) -> Option<String> {
+    let mut current_entity = entity;
+    for field in association.split('.') {
+        let Some(property) = api_schema
+            .get(current_entity)
+            .and_then(|e| e.get("properties"))
+            .and_then(|p| p.get(field))
+        else {
+            return Some(format!("{current_entity} has no field {field}"));
+        };
+        let association_entity = property
+            .get("entity")
+            .and_then(|e| e.as_str())
+            .filter(|_| property.get("type").and_then(|t| t.as_str()) == Some("association"));
+        let Some(association_entity) = association_entity else {
+            return Some(format!("{field} of {current_entity} is not an association"));
+        };
+        current_entity = association_entity;
+    }
+
+    None
+}
+
+/// Validate that all mapped columns exist in the headers of the imported file
+pub fn validate_file_columns(headers: &StringRecord, mappings: &[Mapping]) -> anyhow::Result<()> {
+    let missing_columns = mappings
+        .iter()
+        .filter(|m| !matches!(m, Mapping::ByPath(m) if m.has_lookup_ids(headers)))
+        .flat_map(Mapping::get_import_columns)
+        .filter(|column| !headers.iter().any(|header| header == *column))
+        .collect::<Vec<_>>();
+
+    if !missing_columns.is_empty() {
+        anyhow::bail!(
+            "Columns {:?} of the profile mappings not found in the file headers {:?}",
+            missing_columns,
+            headers
+        );
+    }
+
+    Ok(())
+}
+
+/// Validate that the columns read by the profile itself (like the `action_column`) exist
+/// in the headers of the imported file, before the first row fails
+pub fn validate_row_columns(headers: &StringRecord, profile: &Profile) -> anyhow::Result<()> {
+    let missing_columns = profile
+        .get_row_columns()
+        .into_iter()
+        .filter(|column| !headers.iter().any(|header| header == *column))
+        .collect::<Vec<_>>();
+
+    if !missing_columns.is_empty() {
+        anyhow::bail!(
+            "Columns {:?} of the profile not found in the file headers {:?}",
+            missing_columns,
+            headers
+        );
+    }
+
+    Ok(())
+}
+
+/// Columns of the imported file, which aren't used by any mapping (`--warn-unused`).
+/// The meta-columns of exports are ignored, because they are never imported
+pub fn get_unmapped_columns(headers: &StringRecord, mappings: &[Mapping]) -> Vec<String> {
+    headers
+        .iter()
+        .filter(|header| !META_COLUMNS.contains(header))
+        .filter(|header| {
+            !mappings.iter().any(|m| match m {
+                Mapping::ByPath(m) if m.has_lookup_ids(headers) => {
+                    m.get_import_column(headers) == *header
+                }
+                m => m.get_import_columns().contains(header),
+            })
+        })
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Validate that `base64_file` mappings write into fields which accept inline data,
+/// which are text fields and JSON fields (like `customFields.attachment`).
+/// Files like digital products or documents have to be uploaded as media instead
+pub fn validate_base64_files(
+    entity: &str,
+    mappings: &[Mapping],
+    api_schema: &Entity,
+) -> anyhow::Result<()> {
+    const INLINE_DATA_TYPES: [&str; 3] = ["string", "text", "json_object"];
+
+    for path_mapping in mappings.iter().filter_map(|m| match m {
+        Mapping::ByPath(path_mapping) if path_mapping.base64_file => Some(path_mapping),
+        Mapping::ByPath(_) | Mapping::ByScript(_) => None,
+    }) {
+        let mut current_entity = entity;
+        let mut fields = path_mapping.entity_path.split('.').peekable();
+        let field_type = loop {
+            let Some(field) = fields.next() else {
+                break "association";
+            };
+            let field = field.trim_end_matches('?');
+            let property = api_schema
+                .get(current_entity)
+                .and_then(|e| e.get("properties"))
+                .and_then(|p| p.get(field));
+            let field_type = property
+                .and_then(|p| p.get("type"))
+                .and_then(|t| t.as_str())
+                .unwrap_or("unknown");
+            match property
+                .and_then(|p| p.get("entity"))
+                .and_then(|e| e.as_str())
+            {
+                Some(association_entity) if field_type == "association" => {
+                    current_entity = association_entity;
+                }
+                // the rest of the path is inside the JSON field
+                _ if field_type == "json_object" => break field_type,
+                _ if fields.peek().is_some() => break "unknown",
+                _ => break field_type,
+            }
+        };
+
+        if !INLINE_DATA_TYPES.contains(&field_type) {
+            anyhow::bail!(
+                "base64_file of column '{}' can only be used for text or JSON fields (like custom fields), but '{}' is of type '{field_type}'. Files like digital products or documents have to be uploaded as media",
+                path_mapping.file_column,
+                path_mapping.entity_path
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate that the scripts only reference keys of the `row` object, which are defined
+/// by the script mappings (`key`), so typos don't fail in the middle of a sync
+pub fn validate_script_keys(profile: &Profile) -> anyhow::Result<()> {
+    let keys = profile
+        .mappings
+        .iter()
+        .filter_map(|m| match m {
+            Mapping::ByScript(script_mapping) => Some(script_mapping.key.as_str()),
+            Mapping::ByPath(_) => None,
+        })
+        .collect::<HashSet<_>>();
+
+    for (script_name, script) in [
+        ("serialize_script", &profile.serialize_script),
+        ("deserialize_script", &profile.deserialize_script),
+    ] {
+        let mut undefined_keys = get_script_row_keys(script)
+            .into_iter()
+            .filter(|key| !keys.contains(key))
+            .collect::<Vec<_>>();
+        undefined_keys.sort_unstable();
+        undefined_keys.dedup();
+
+        if !undefined_keys.is_empty() {
+            let mut keys = keys.iter().collect::<Vec<_>>();
+            keys.sort_unstable();
+            anyhow::bail!(
+                "{script_name} references the keys {undefined_keys:?} of the row, but there are no mappings with these keys. Available keys: {keys:?}"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Keys of the `row` object accessed in a script, like `row.key` or `row["key"]`.
+/// Just a simple text search, which ignores line comments and method calls like `row.keys()`
+fn get_script_row_keys(script: &str) -> Vec<&str> {
+    let is_identifier_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut keys = vec![];
+
+    for line in script.lines() {
+        let line = line.split_once("//").map_or(line, |(code, _comment)| code);
+        for (start, _) in line.match_indices("row") {
+            if line[..start].ends_with(is_identifier_char) {
+                continue;
+            }
+
+            let rest = &line[start + "row".len()..];
+            if let Some(rest) = rest.strip_prefix('.') {
+                let key_end = rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len());
+                if !rest[key_end..].trim_start().starts_with('(') {
+                    keys.push(&rest[..key_end]);
+                }
+            } else if let Some(rest) = rest.strip_prefix("[\"") {
+                if let Some(key_end) = rest.find('"') {
+                    keys.push(&rest[..key_end]);
+                }
+            }
+        }
+    }
+
+    keys
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::config_file::{EntityPathMapping, Mapping};
+    use crate::config_file::{
+        EntityPathMapping, EntityScriptMapping, Lookup, Mapping, NestedMapping, Profile,
+    };
+    use crate::data::validate::{
+        get_invalid_associations, get_script_row_keys, get_unmapped_columns, validate_base64_files,
+        validate_file_columns, validate_paths_for_entity, validate_row_columns,
+        validate_script_keys,
+    };
+    use csv::StringRecord;
     use serde_json::json;
 
+    #[test]
+    fn base64_files_only_for_inline_data() {
+        let api_schema = json!({
+            "product": {
+                "properties": {
+                    "description": { "type": "text" },
+                    "customFields": { "type": "json_object" },
+                    "stock": { "type": "int" },
+                    "downloads": { "type": "association", "entity": "product_download" },
+                }
+            },
+            "product_download": {
+                "properties": {
+                    "mediaId": { "type": "uuid" },
+                    "customFields": { "type": "json_object" },
+                }
+            }
+        });
+        let api_schema = api_schema.as_object().unwrap();
+        let mappings = |entity_path: &str| {
+            vec![Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "file".to_string(),
+                entity_path: entity_path.to_string(),
+                base64_file: true,
+                ..Default::default()
+            }))]
+        };
+
+        for entity_path in [
+            "description",
+            "customFields.attachment",
+            "downloads?.customFields.attachment",
+        ] {
+            assert!(
+                validate_base64_files("product", &mappings(entity_path), api_schema).is_ok(),
+                "{entity_path}"
+            );
+        }
+        for entity_path in ["stock", "downloads", "downloads.mediaId", "missing"] {
+            assert!(
+                validate_base64_files("product", &mappings(entity_path), api_schema)
+                    .is_err_and(|e| e.to_string().contains("uploaded as media")),
+                "{entity_path}"
+            );
+        }
+    }
+
     #[test]
     fn validate_non_existent_entity() {
         let entity = "nonexistent";
-        let mapping = vec![Mapping::ByPath(EntityPathMapping {
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
             file_column: "manufacturer id".to_string(),
             entity_path: "manufacturerId".to_string(),
-            column_type: None,
-        })];
+            ..Default::default()
+        }))];
         let api_schema = json!({
             "product": {
             }
@@ -89,14 +454,60 @@ mod tests {
             .contains("Entity nonexistent not found in API schema")));
     }
 
+    #[test]
+    fn validate_lookup_field() {
+        let mapping = |field: &str| {
+            vec![Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "manufacturer name".to_string(),
+                entity_path: "manufacturerId".to_string(),
+                lookup: Some(Lookup {
+                    entity: "product_manufacturer".to_string(),
+                    field: field.to_string(),
+                    create_missing: false,
+                    create_with: Default::default(),
+                    id_column: None,
+                }),
+                ..Default::default()
+            }))]
+        };
+        let api_schema = json!({
+            "product": {
+                "properties": {
+                    "manufacturerId": { "type": "uuid" }
+                }
+            },
+            "product_manufacturer": {
+                "properties": {
+                    "name": { "type": "string" }
+                }
+            }
+        });
+        let api_schema = api_schema.as_object().unwrap();
+
+        assert!(crate::data::validate::validate_paths_for_entity(
+            "product",
+            &mapping("name"),
+            api_schema
+        )
+        .is_ok());
+        assert!(crate::data::validate::validate_paths_for_entity(
+            "product",
+            &mapping("title"),
+            api_schema
+        )
+        .is_err_and(|e| e
+            .to_string()
+            .contains("Lookup entity product_manufacturer does not have a field title")));
+    }
+
     #[test]
     fn validate_non_existent_simple_path() {
         let entity = "product";
-        let mapping = vec![Mapping::ByPath(EntityPathMapping {
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
             file_column: "manufacturer id".to_string(),
             entity_path: "manufacturerId".to_string(),
-            column_type: None,
-        })];
+            ..Default::default()
+        }))];
         let api_schema = json!({
             "product": {
             }
@@ -116,11 +527,11 @@ mod tests {
     #[test]
     fn validate_existing_simple_path() {
         let entity = "product";
-        let mapping = vec![Mapping::ByPath(EntityPathMapping {
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
             file_column: "manufacturer id".to_string(),
             entity_path: "manufacturerId".to_string(),
-            column_type: None,
-        })];
+            ..Default::default()
+        }))];
         let api_schema = json!({
             "product": {
                 "entity": "product",
@@ -144,11 +555,11 @@ mod tests {
     #[test]
     fn validate_non_existent_association() {
         let entity = "product";
-        let mapping = vec![Mapping::ByPath(EntityPathMapping {
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
             file_column: "manufacturer name".to_string(),
             entity_path: "manufacturer.name".to_string(),
-            column_type: None,
-        })];
+            ..Default::default()
+        }))];
         let api_schema = json!({
             "product": {
                 "entity": "product",
@@ -174,11 +585,11 @@ mod tests {
     #[test]
     fn validate_existing_association() {
         let entity = "product";
-        let mapping = vec![Mapping::ByPath(EntityPathMapping {
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
             file_column: "manufacturer name".to_string(),
             entity_path: "manufacturer.name".to_string(),
-            column_type: None,
-        })];
+            ..Default::default()
+        }))];
         let api_schema = json!({
             "product": {
                 "entity": "product",
@@ -211,11 +622,11 @@ mod tests {
     #[test]
     fn validate_valid_optional_value() {
         let entity = "product";
-        let mapping = vec![Mapping::ByPath(EntityPathMapping {
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
             file_column: "manufacturer name".to_string(),
             entity_path: "manufacturer?.name".to_string(),
-            column_type: None,
-        })];
+            ..Default::default()
+        }))];
         let api_schema = json!({
             "product": {
                 "entity": "product",
@@ -248,11 +659,11 @@ mod tests {
     #[test]
     fn validate_invalid_optional_value() {
         let entity = "product";
-        let mapping = vec![Mapping::ByPath(EntityPathMapping {
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
             file_column: "manufacturer name".to_string(),
             entity_path: "manufacturer?.name".to_string(),
-            column_type: None,
-        })];
+            ..Default::default()
+        }))];
         let api_schema = json!({
             "product": {
                 "entity": "product",
@@ -287,11 +698,11 @@ mod tests {
     #[test]
     fn validate_valid_nested_association() {
         let entity = "product";
-        let mapping = vec![Mapping::ByPath(EntityPathMapping {
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
             file_column: "tax country".to_string(),
             entity_path: "tax.country.name".to_string(),
-            column_type: None,
-        })];
+            ..Default::default()
+        }))];
         let api_schema = json!({
             "product": {
                 "entity": "product",
@@ -329,4 +740,338 @@ mod tests {
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn validate_translatable_field_with_language() {
+        let entity = "product";
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
+            file_column: "name (german)".to_string(),
+            entity_path: "manufacturer?.name".to_string(),
+            language: Some("de-DE".to_string()),
+            ..Default::default()
+        }))];
+        let api_schema = json!({
+            "product": {
+                "entity": "product",
+                "properties": {
+                    "manufacturer": {
+                        "type": "association",
+                        "entity": "product_manufacturer"
+                    }
+                }
+            },
+            "product_manufacturer": {
+                "entity": "product_manufacturer",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "flags": {
+                            "translatable": true
+                        }
+                    }
+                }
+            }
+        });
+
+        let result = crate::data::validate::validate_paths_for_entity(
+            entity,
+            &mapping,
+            api_schema.as_object().unwrap(),
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn validate_non_translatable_field_with_language() {
+        let entity = "product";
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
+            file_column: "product number (german)".to_string(),
+            entity_path: "productNumber".to_string(),
+            language: Some("de-DE".to_string()),
+            ..Default::default()
+        }))];
+        let api_schema = json!({
+            "product": {
+                "entity": "product",
+                "properties": {
+                    "productNumber": {
+                        "type": "string"
+                    }
+                }
+            }
+        });
+
+        let result = crate::data::validate::validate_paths_for_entity(
+            entity,
+            &mapping,
+            api_schema.as_object().unwrap(),
+        );
+
+        assert!(result.is_err_and(|x| x
+            .to_string()
+            .contains("Field productNumber in product is not translatable")));
+    }
+
+    #[test]
+    fn validate_nested_mappings() {
+        let mapping = |nested_path: &str| {
+            vec![Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "prices".to_string(),
+                entity_path: "prices".to_string(),
+                mappings: vec![NestedMapping {
+                    entity_path: nested_path.to_string(),
+                    column_type: None,
+                }],
+                ..Default::default()
+            }))]
+        };
+        let api_schema = json!({
+            "product": {
+                "entity": "product",
+                "properties": {
+                    "prices": {
+                        "type": "association",
+                        "entity": "product_price"
+                    }
+                }
+            },
+            "product_price": {
+                "entity": "product_price",
+                "properties": {
+                    "ruleId": {
+                        "type": "uuid"
+                    }
+                }
+            }
+        });
+        let api_schema = api_schema.as_object().unwrap();
+
+        assert!(validate_paths_for_entity("product", &mapping("ruleId"), api_schema).is_ok());
+        assert!(validate_paths_for_entity("product", &mapping("unknown"), api_schema).is_err());
+    }
+
+    #[test]
+    fn validate_skipped_for_mapping() {
+        let entity = "product";
+        let mapping = vec![Mapping::ByPath(Box::new(EntityPathMapping {
+            file_column: "plugin field".to_string(),
+            entity_path: "pluginField".to_string(),
+            validate: false,
+            ..Default::default()
+        }))];
+        let api_schema = json!({
+            "product": {
+                "entity": "product",
+                "properties": {
+                    "productNumber": {
+                        "type": "string"
+                    }
+                }
+            }
+        });
+
+        let result = crate::data::validate::validate_paths_for_entity(
+            entity,
+            &mapping,
+            api_schema.as_object().unwrap(),
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn validate_columns_in_headers() {
+        let mappings = vec![
+            Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "id".to_string(),
+                entity_path: "id".to_string(),
+                ..Default::default()
+            })),
+            Mapping::ByScript(EntityScriptMapping {
+                file_column: "gross price".to_string(),
+                key: "gross_price".to_string(),
+                column_type: None,
+                header: None,
+                width: None,
+            }),
+        ];
+
+        let headers = StringRecord::from(vec!["id", "gross price"]);
+        assert!(validate_file_columns(&headers, &mappings).is_ok());
+
+        let headers = StringRecord::from(vec!["id", "net price"]);
+        assert!(validate_file_columns(&headers, &mappings)
+            .is_err_and(|e| e.to_string().contains("[\"gross price\"]")));
+
+        let headers = StringRecord::from(vec!["_id", "id", "gross price", "net price", "stock"]);
+        assert_eq!(
+            get_unmapped_columns(&headers, &mappings),
+            vec!["net price", "stock"]
+        );
+    }
+
+    #[test]
+    fn validate_row_columns_in_headers() {
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+entity: product
+action_column: "action"
+id_strategy:
+  type: take-from-column
+  column: "external id"
+mappings: []
+"#,
+        )
+        .unwrap();
+        assert_eq!(profile.get_row_columns(), vec!["action", "external id"]);
+
+        let headers = StringRecord::from(vec!["action", "external id", "name"]);
+        assert!(validate_row_columns(&headers, &profile).is_ok());
+
+        let headers = StringRecord::from(vec!["action", "id", "name"]);
+        assert!(validate_row_columns(&headers, &profile)
+            .is_err_and(|e| e.to_string().contains("[\"external id\"]")));
+    }
+
+    #[test]
+    fn lookup_id_column_of_older_files() {
+        let mappings = vec![Mapping::ByPath(Box::new(EntityPathMapping {
+            file_column: "rule".to_string(),
+            entity_path: "ruleId".to_string(),
+            lookup: Some(Lookup {
+                entity: "rule".to_string(),
+                field: "name".to_string(),
+                create_missing: false,
+                create_with: Default::default(),
+                id_column: Some("rule id".to_string()),
+            }),
+            ..Default::default()
+        }))];
+        let Mapping::ByPath(mapping) = &mappings[0] else {
+            unreachable!();
+        };
+
+        let headers = StringRecord::from(vec!["id", "rule"]);
+        assert!(validate_file_columns(&headers, &mappings).is_ok());
+        assert!(!mapping.has_lookup_ids(&headers));
+        assert_eq!(mapping.get_import_column(&headers), "rule");
+
+        let headers = StringRecord::from(vec!["id", "rule id"]);
+        assert!(validate_file_columns(&headers, &mappings).is_ok());
+        assert!(mapping.has_lookup_ids(&headers));
+        assert_eq!(mapping.get_import_column(&headers), "rule id");
+        assert_eq!(get_unmapped_columns(&headers, &mappings), vec!["id"]);
+
+        // the column of the lookup values wins
+        let headers = StringRecord::from(vec!["rule id", "rule"]);
+        assert!(!mapping.has_lookup_ids(&headers));
+        assert_eq!(get_unmapped_columns(&headers, &mappings), vec!["rule id"]);
+
+        let headers = StringRecord::from(vec!["id"]);
+        assert!(validate_file_columns(&headers, &mappings)
+            .is_err_and(|e| e.to_string().contains("[\"rule\"]")));
+    }
+
+    #[test]
+    fn script_row_keys() {
+        let script = r#"
+            // row.commented_out
+            entity.price = row.gross_price + row["net price"];
+            let index = row_index; let keys = row.keys();
+            if arrow.x { row.tax_rate = 19; } // row.comment
+        "#;
+
+        assert_eq!(
+            get_script_row_keys(script),
+            vec!["gross_price", "net price", "tax_rate"]
+        );
+    }
+
+    #[test]
+    fn validate_undefined_script_keys() {
+        let mut profile = Profile {
+            mappings: vec![Mapping::ByScript(EntityScriptMapping {
+                file_column: "gross price".to_string(),
+                key: "gross_price".to_string(),
+                column_type: None,
+                header: None,
+                width: None,
+            })],
+            deserialize_script: "entity.price = row.gross_price;".to_string(),
+            ..Default::default()
+        };
+        assert!(validate_script_keys(&profile).is_ok());
+
+        profile.serialize_script = "row.gros_price = entity.price;".to_string();
+        assert!(validate_script_keys(&profile).is_err_and(|e| e
+            .to_string()
+            .contains("serialize_script references the keys [\"gros_price\"]")));
+    }
+
+    #[test]
+    fn validate_association_paths() {
+        let api_schema = json!({
+            "product": {
+                "entity": "product",
+                "properties": {
+                    "name": { "type": "string" },
+                    "manufacturer": {
+                        "type": "association",
+                        "relation": "many_to_one",
+                        "entity": "product_manufacturer"
+                    }
+                }
+            },
+            "product_manufacturer": {
+                "entity": "product_manufacturer",
+                "properties": {
+                    "media": {
+                        "type": "association",
+                        "relation": "many_to_one",
+                        "entity": "media"
+                    }
+                }
+            },
+            "media": {
+                "entity": "media",
+                "properties": {}
+            }
+        });
+        let api_schema = api_schema.as_object().unwrap();
+
+        let valid = ["manufacturer".to_string(), "manufacturer.media".to_string()].into();
+        assert!(get_invalid_associations("product", &valid, &[], api_schema).is_empty());
+
+        let invalid = [
+            "manufacturer".to_string(),
+            "manufacturers".to_string(),
+            "name".to_string(),
+            "manufacturer.logo".to_string(),
+        ]
+        .into();
+        assert_eq!(
+            get_invalid_associations("product", &invalid, &[], api_schema),
+            vec![
+                "manufacturer.logo (product_manufacturer has no field logo)",
+                "manufacturers (product has no field manufacturers)",
+                "name (name of product is not an association)"
+            ]
+        );
+
+        // the association of a plugin field, which is missing in the schema
+        let mappings = vec![Mapping::ByPath(Box::new(EntityPathMapping {
+            file_column: "manufacturer logo".to_string(),
+            entity_path: "manufacturer?.logo.url".to_string(),
+            validate: false,
+            ..Default::default()
+        }))];
+        assert_eq!(
+            get_invalid_associations("product", &invalid, &mappings, api_schema),
+            vec![
+                "manufacturers (product has no field manufacturers)",
+                "name (name of product is not an association)"
+            ]
+        );
+    }
 }
@@ -0,0 +1,365 @@
+//! Library of the sw-sync-cli, to embed the import and export of profiles into other tools
+//!
+//! A sync runs on a [`SyncContext`], which is created from the arguments of the sync command
+//! and a client of the shop (see [`api::SwClient`]). End-to-end tests of profiles can use the fake shop
+//! of the `testing` module (feature `testing`) instead of a live shop.
+
+use crate::api::{get_user_agent, Entity, SwClient, SwClientOptions, SyncAction};
+use crate::cli::{ActiveHours, FileFormat, SyncArgs, SyncMode};
+use crate::config_file::{IdStrategy, Mapping, OrderPrices, Profile, ScriptEngine};
+use crate::data::{
+    add_live_version_filter, add_product_stream_filter, add_sales_channel_filter, get_associations,
+    get_includes, get_invalid_associations, get_schema_constraints, get_sink, get_source,
+    get_transformers, is_local_file, is_wildcard_path, prepare_documents_folder,
+    prepare_scripting_environment, read_checkpoint, resolve_id_lists, validate_base64_files,
+    validate_paths_for_entity, validate_script_keys, ChunkMetrics, ColumnStatistics, DataSink,
+    DataSource, LookupCache, RetryRow, RowError, RowTransformer, SchemaConstraint,
+    ScriptingEnvironment, SyncedChunks, SyncedIds, WasmModule,
+};
+use crate::paths::get_long_path;
+use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::num::NonZeroU8;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+pub mod api;
+pub mod cli;
+pub mod config_file;
+pub mod data;
+pub mod paths;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+/// Name of the indexer, which is triggered per chunk with `--index-per-chunk`
+pub const PRODUCT_INDEXER: &str = "product.indexer";
+
+#[derive(Debug)]
+pub struct SyncContext {
+    pub sw_client: Arc<dyn SwClient>,
+    pub profile: Profile,
+    /// specifies the input or output file
+    pub file: PathBuf,
+    /// backend of the imported file, like local files or stdin
+    pub source: Box<dyn DataSource>,
+    /// backend of the exported file
+    pub sink: Box<dyn DataSink>,
+    pub format: FileFormat,
+    /// end the exported lines with CRLF instead of LF
+    pub crlf: bool,
+    /// compress the exported file with gzip
+    pub gzip: bool,
+    /// write a manifest with the row count and checksum next to the exported file
+    pub manifest: bool,
+    /// path of the profile, written into the manifest
+    pub profile_path: PathBuf,
+    pub limit: Option<u64>,
+    pub scripting_environment: ScriptingEnvironment,
+    pub transformers: Vec<Box<dyn RowTransformer>>,
+    pub associations: HashSet<String>,
+    /// fields of the export response per entity, empty to export all fields
+    pub includes: BTreeMap<String, BTreeSet<String>>,
+    /// entity schema of the shop, used to sanitize import payloads
+    pub api_schema: Entity,
+    /// length and pattern constraints of the OpenAPI schema, checked on import
+    pub schema_constraints: Vec<SchemaConstraint>,
+    pub in_flight_limit: usize,
+    /// sync the chunks of the import in file order
+    pub ordered: bool,
+    /// index the written products after every sync chunk
+    pub index_per_chunk: bool,
+    pub try_count: NonZeroU8,
+    /// approximate memory ceiling in bytes for the import
+    pub max_memory: Option<usize>,
+    /// export without counting the entities first
+    pub skip_count: bool,
+    /// continue the export if a page fails
+    pub skip_failed_pages: bool,
+    /// pages which failed and were skipped during the export
+    pub failed_pages: Mutex<Vec<u64>>,
+    /// suppress entities which were already exported on a previous page
+    pub dedupe: bool,
+    /// previous export, only the changes to it are exported
+    pub baseline: Option<PathBuf>,
+    /// amount of suppressed duplicates during the export
+    pub suppressed_duplicates: AtomicUsize,
+    /// collect the unused columns of the file and the empty columns of the export
+    pub warn_unused: bool,
+    /// columns of the imported file, which aren't used by any mapping
+    pub unmapped_columns: Mutex<Vec<String>>,
+    /// mapped columns of the export, which didn't have any value
+    pub empty_columns: Mutex<Vec<String>>,
+    /// statistics of the mapped columns of the import, if enabled
+    pub column_stats: Option<Mutex<ColumnStatistics>>,
+    /// ids of the values resolved by lookups
+    pub lookup_cache: LookupCache,
+    /// whether the imported entities are written or deleted
+    pub sync_action: SyncAction,
+    /// only validate the chunks on the server, without writing them (`--validate-remote`)
+    pub validate_remote: bool,
+    /// errors of the server validation per row
+    pub remote_validation_errors: Mutex<Vec<RowError>>,
+    /// rows which weren't imported, e.g. because their payload is too large
+    pub skipped_rows: Mutex<Vec<RowError>>,
+    /// rows of the imported file or entities to export, for the ETA of the status file
+    pub total_rows: OnceLock<usize>,
+    /// progress is written to this file (`--status-file`)
+    pub status_file: Option<PathBuf>,
+    /// timings of the processed chunks (import) or pages (export)
+    pub chunk_metrics: Mutex<Vec<ChunkMetrics>>,
+    /// rows which were rejected by the server on import
+    pub failed_rows: Mutex<Vec<RowError>>,
+    /// written orders which couldn't be recalculated (`order_prices: "recalculate"`)
+    pub failed_recalculations: AtomicUsize,
+    /// payloads of the rows which failed because of transient errors, for the retry file
+    pub retry_rows: Mutex<Vec<RetryRow>>,
+    /// no more chunks are started after this point in time (`--max-duration`)
+    pub deadline: Option<Instant>,
+    /// the chunks are only synced within this daily window (`--active-hours`)
+    pub active_hours: Option<ActiveHours>,
+    /// content hashes of the completely synced chunks (`--skip-synced-chunks`)
+    pub synced_chunks: Option<SyncedChunks>,
+    /// first row of the imported file, after a resumed import
+    pub start_row: usize,
+    /// row where the import stopped because of the deadline
+    pub resume_row: Mutex<Option<usize>>,
+    /// ids of the written and deleted entities, only collected for the report or the webhook
+    pub sync_results: Option<Mutex<SyncedIds>>,
+}
+
+/// Options of the client for a sync command, with the connection settings of the arguments
+pub fn get_client_options(args: &SyncArgs, client_options: &SwClientOptions) -> SwClientOptions {
+    SwClientOptions {
+        connect_timeout: Duration::from_secs(args.connect_timeout),
+        timeout: (args.timeout > 0).then(|| Duration::from_secs(args.timeout)),
+        pool_max_idle_per_host: args.pool_max_idle,
+        tcp_keepalive: args.keep_alive.map(Duration::from_secs),
+        http2_prior_knowledge: args.http2,
+        max_response_size: (args.max_response_size > 0)
+            .then(|| args.max_response_size.saturating_mul(1024 * 1024)),
+        user_agent: client_options.user_agent.clone().or_else(|| {
+            let profile_name = args.profile.file_stem().map(|name| name.to_string_lossy());
+            Some(get_user_agent(profile_name.as_deref()))
+        }),
+        ..client_options.clone()
+    }
+}
+
+/// Context of a sync command with the arguments against the shop of the client
+pub fn create_context(
+    args: &SyncArgs,
+    sw_client: Arc<dyn SwClient>,
+) -> anyhow::Result<SyncContext> {
+    let start = Instant::now();
+    let mut profile = Profile::read_profile(&args.profile)?;
+    profile.apply_mapping_overrides(&args.map)?;
+    if args.index_per_chunk && profile.entity != "product" {
+        anyhow::bail!(
+            "--index-per-chunk is only supported for the product entity, not '{}'",
+            profile.entity
+        );
+    }
+    if args.mode != SyncMode::Export {
+        // otherwise only the first row would fail
+        if let Some(mapping) = profile.mappings.iter().find_map(|mapping| match mapping {
+            Mapping::ByPath(by_path) if is_wildcard_path(&by_path.entity_path) => Some(by_path),
+            _ => None,
+        }) {
+            anyhow::bail!(
+                "wildcard entity_path '{}' of column '{}' can only be used on export",
+                mapping.entity_path,
+                mapping.file_column
+            );
+        }
+    }
+    if args.skip_synced_chunks && profile.id_strategy == Some(IdStrategy::RandomUuid) {
+        // the payloads get new ids on every run, so their chunks never match the synced ones
+        anyhow::bail!("--skip-synced-chunks can't be used with the random-uuid id_strategy");
+    }
+    if profile.order_prices == OrderPrices::Recalculate && profile.entity != "order" {
+        anyhow::bail!(
+            "order_prices \"recalculate\" is only supported for the order entity, not '{}'",
+            profile.entity
+        );
+    }
+    if let Some(backoff) = args.backoff {
+        profile.retry.backoff = backoff;
+    }
+    let try_count = args.get_try_count(profile.retry.retries);
+    // the actions of change logs have to be applied in file order
+    let ordered = args.ordered || profile.action_column.is_some();
+
+    let api_schema = sw_client.entity_schema()?;
+    resolve_id_lists(&mut profile, &api_schema);
+    if args.mode == SyncMode::Export {
+        add_live_version_filter(&mut profile, &api_schema);
+        prepare_documents_folder(&profile)?;
+        if let Some(sales_channel) = args.sales_channel.clone().or(profile.sales_channel.clone()) {
+            add_sales_channel_filter(&mut profile, &sales_channel, sw_client.as_ref())?;
+        }
+        if let Some(product_stream) = args
+            .product_stream
+            .clone()
+            .or(profile.product_stream.clone())
+        {
+            add_product_stream_filter(&mut profile, &product_stream, sw_client.as_ref())?;
+        }
+    }
+    let start_row = if args.resume {
+        let start_row = read_checkpoint(&args.file, &profile.entity)?;
+        println!("resuming the import at row {start_row}");
+        start_row
+    } else {
+        0
+    };
+    let synced_chunks = if args.skip_synced_chunks {
+        Some(SyncedChunks::read(&args.file, &profile.entity)?)
+    } else {
+        None
+    };
+
+    if args.mode == SyncMode::Import {
+        validate_base64_files(&profile.entity, &profile.mappings, &api_schema)?;
+        // stdin and URLs have no directory, their paths are resolved from the working directory
+        if let Some(dir) = args.file.parent().filter(|_| is_local_file(&args.file)) {
+            profile.set_base64_dir(dir);
+        }
+    }
+
+    let associations = get_associations(&profile, &api_schema);
+    let schema_constraints = match args.mode {
+        SyncMode::Import if !args.no_schema_constraints => match sw_client.openapi_schema() {
+            Ok(openapi_schema) => get_schema_constraints(&profile, &api_schema, &openapi_schema),
+            Err(e) => {
+                println!("Warning: the OpenAPI schema couldn't be read, the values aren't checked against its constraints: {e}");
+                vec![]
+            }
+        },
+        SyncMode::Import | SyncMode::Export | SyncMode::Delete => vec![],
+    };
+    let includes = match args.mode {
+        SyncMode::Export => get_includes(&profile, &api_schema),
+        SyncMode::Import | SyncMode::Delete => BTreeMap::new(),
+    };
+    let entity = &profile.entity;
+
+    if args.no_validate {
+        println!("Warning: skipping the validation of the profile mappings");
+    } else {
+        validate_paths_for_entity(entity, &profile.mappings, &api_schema)?;
+        validate_script_keys(&profile)?;
+        // associations are only requested on export
+        if args.mode == SyncMode::Export {
+            let invalid_associations =
+                get_invalid_associations(entity, &associations, &profile.mappings, &api_schema);
+            if !invalid_associations.is_empty() {
+                println!("Warning: the associations {invalid_associations:?} of entity {entity} don't exist in the API schema, they are ignored");
+            }
+        }
+    }
+
+    let mut transformers = get_transformers(&profile.transformers)?;
+    for transformer in &mut transformers {
+        transformer
+            .prepare(sw_client.as_ref())
+            .with_context(|| format!("failed to prepare transformer '{}'", transformer.name()))?;
+    }
+
+    let mut language_list = sw_client.get_languages()?;
+    language_list.iso_codes = profile.iso_codes.clone();
+    let mut currency_list = sw_client.get_currencies()?;
+    currency_list.iso_codes = profile.iso_codes.clone();
+
+    let mut scripting_environment = prepare_scripting_environment(
+        &profile.serialize_script,
+        &profile.deserialize_script,
+        language_list,
+        currency_list,
+        &profile.constants,
+        profile.script_limits,
+    )?;
+
+    if profile.script_engine == ScriptEngine::Wasm {
+        if !profile.serialize_script.is_empty() || !profile.deserialize_script.is_empty() {
+            anyhow::bail!("serialize_script and deserialize_script can't be used with script_engine 'wasm', use wasm_module instead");
+        }
+        let wasm_module = profile
+            .wasm_module
+            .as_ref()
+            .context("wasm_module is required for script_engine 'wasm'")?;
+        scripting_environment.wasm = Some(WasmModule::load(wasm_module, profile.script_limits)?);
+    }
+    scripting_environment.file_name = args
+        .file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Ok(SyncContext {
+        sw_client,
+        profile,
+        // stdin and URLs aren't paths of the file system
+        file: if is_local_file(&args.file) {
+            get_long_path(&args.file)
+        } else {
+            args.file.clone()
+        },
+        source: get_source(&args.file),
+        sink: get_sink(&args.file),
+        format: args.format,
+        crlf: args.crlf,
+        gzip: args.gzip,
+        manifest: args.manifest,
+        profile_path: args.profile.clone(),
+        limit: args.limit,
+        scripting_environment,
+        transformers,
+        associations,
+        includes,
+        api_schema,
+        schema_constraints,
+        in_flight_limit: args.in_flight_limit,
+        ordered,
+        index_per_chunk: args.index_per_chunk,
+        try_count,
+        max_memory: args.max_memory.map(|mb| mb.saturating_mul(1024 * 1024)),
+        skip_count: args.skip_count,
+        skip_failed_pages: args.skip_failed_pages,
+        failed_pages: Mutex::new(vec![]),
+        dedupe: args.dedupe,
+        baseline: args.baseline.as_deref().map(get_long_path),
+        suppressed_duplicates: AtomicUsize::new(0),
+        warn_unused: args.warn_unused,
+        unmapped_columns: Mutex::new(vec![]),
+        empty_columns: Mutex::new(vec![]),
+        column_stats: args
+            .column_stats
+            .then(|| Mutex::new(ColumnStatistics::default())),
+        lookup_cache: LookupCache::default(),
+        sync_action: match args.mode {
+            SyncMode::Delete => SyncAction::Delete,
+            SyncMode::Import | SyncMode::Export => SyncAction::Upsert,
+        },
+        validate_remote: args.validate_remote,
+        remote_validation_errors: Mutex::new(vec![]),
+        skipped_rows: Mutex::new(vec![]),
+        total_rows: OnceLock::new(),
+        status_file: args.status_file.clone(),
+        chunk_metrics: Mutex::new(vec![]),
+        failed_rows: Mutex::new(vec![]),
+        failed_recalculations: AtomicUsize::new(0),
+        retry_rows: Mutex::new(vec![]),
+        // a deadline too far in the future to be represented is never reached anyway
+        deadline: args
+            .max_duration
+            .and_then(|duration| start.checked_add(duration)),
+        active_hours: args.active_hours,
+        synced_chunks,
+        start_row,
+        resume_row: Mutex::new(None),
+        sync_results: (args.report.is_some() || args.webhook.is_some())
+            .then(|| Mutex::new(SyncedIds::default())),
+    })
+}
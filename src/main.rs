@@ -1,86 +1,229 @@
-use crate::api::SwClient;
-use crate::cli::{Cli, Commands, SyncMode};
-use crate::config_file::{Credentials, Mapping, Profile, DEFAULT_PROFILES};
-use crate::data::validate_paths_for_entity;
-use crate::data::{export, import, prepare_scripting_environment, ScriptingEnvironment};
+use anyhow::Context;
 use clap::Parser;
-use std::collections::HashSet;
 use std::fs;
-use std::num::NonZeroU8;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Instant;
-
-mod api;
-mod cli;
-mod config_file;
-mod data;
-
-#[derive(Debug)]
-pub struct SyncContext {
-    pub sw_client: SwClient,
-    pub profile: Profile,
-    /// specifies the input or output file
-    pub file: PathBuf,
-    pub limit: Option<u64>,
-    pub scripting_environment: ScriptingEnvironment,
-    pub associations: HashSet<String>,
-    pub in_flight_limit: usize,
-    pub try_count: NonZeroU8,
-}
+use std::time::{Duration, Instant};
+use sw_sync_cli::api::{SwClient, SwClientOptions, SwHttpClient};
+use sw_sync_cli::cli::{Cli, Commands, FileFormat, SyncArgs, SyncMode};
+use sw_sync_cli::config_file::{
+    Credentials, Profile, RetryOptions, DEFAULT_PROFILES, PROFILES_DIR,
+};
+use sw_sync_cli::data::{
+    add_live_version_filter, check_profile, cleanup_media, compare_snapshots, diff_schemas, export,
+    export_by_language, fetch_snapshot, get_associations, get_compared_mappings, import,
+    import_retry_file, is_local_file, preview, send_webhook, update_checkpoint, with_status_file,
+    write_retry_file, write_sample, RunLock, SyncFailure, SyncReport, WebhookPayload,
+};
+use sw_sync_cli::{create_context, get_client_options, SyncContext, PRODUCT_INDEXER};
+use uuid::Uuid;
+
+/// Exit code of an import, which stopped after `--max-duration` and can be resumed (`EX_TEMPFAIL`)
+const RESUMABLE_EXIT_CODE: i32 = 75;
 
 fn main() -> anyhow::Result<()> {
     let start_instant = Instant::now();
     let cli = Cli::parse();
+    let mut resumable = false;
+    let correlation_id = cli
+        .correlation_id
+        .clone()
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+    if cli.command.sends_requests() {
+        println!("correlation id of this run: {correlation_id}");
+    }
+    let client_options = SwClientOptions {
+        read_only: cli.read_only,
+        user_agent: cli.user_agent.clone(),
+        correlation_id: Some(correlation_id),
+        ..Default::default()
+    };
 
     match cli.command {
         Commands::Index { skip } => {
-            index(skip)?;
+            index(skip, client_options)?;
             println!("Successfully triggered indexing.");
         }
         Commands::CopyProfiles { force, list, path } => {
             copy_profiles(force, list, path);
         }
-        Commands::Auth { domain, id, secret } => {
-            auth(domain, id, secret)?;
+        Commands::Auth {
+            env,
+            domain,
+            id,
+            secret,
+            app_confirmation,
+        } => {
+            let credentials = match app_confirmation {
+                Some(path) => {
+                    let mut credentials = Credentials::read_app_confirmation(&path)?;
+                    if let Some(domain) = domain {
+                        credentials.base_url = domain;
+                    }
+                    if credentials.base_url.is_empty() {
+                        anyhow::bail!("the app confirmation has no shopUrl, set the URL of the shop with --domain");
+                    }
+                    credentials
+                }
+                None => Credentials {
+                    base_url: domain.unwrap_or_default(),
+                    access_key_id: id.unwrap_or_default(),
+                    access_key_secret: secret.unwrap_or_default(),
+                },
+            };
+            auth(env, credentials, client_options)?;
             println!("Successfully authenticated. You can continue with other commands now.");
         }
-        Commands::Sync {
-            mode,
+        Commands::Sample {
             profile,
+            map,
             file,
-            limit,
-            disable_index,
-            // verbose,
-            in_flight_limit,
-            try_count,
+            format,
+            rows,
         } => {
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(in_flight_limit)
-                .build_global()
-                .unwrap();
-            println!("using at most {in_flight_limit} number of threads in a pool");
-            let context = create_context(profile, file, limit, in_flight_limit, try_count)?;
-
-            match mode {
-                SyncMode::Import => {
-                    import(Arc::new(context))?;
-
-                    println!("Imported successfully");
-                    if disable_index {
-                        println!("Indexing was skipped, you might want to run the indexers in your shop later. Go to Settings -> System -> Caches & indexes");
-                        println!("Or simply run: sw-sync-cli index");
-                    } else {
-                        println!("Triggering indexing...");
-                        index(vec![])?;
-                        println!("Successfully triggered indexing.");
+            let mut profile = Profile::read_profile(profile)?;
+            profile.apply_mapping_overrides(&map)?;
+            write_sample(&profile, &file, format, rows)?;
+            println!("Wrote a sample with {rows} rows to {file:?}");
+        }
+        Commands::Check {
+            profile,
+            map,
+            mode,
+            file,
+            format,
+            rows,
+        } => {
+            let mut profile = Profile::read_profile(profile)?;
+            profile.apply_mapping_overrides(&map)?;
+            let credentials = Credentials::read_credentials()?;
+            let sw_client = SwHttpClient::new(credentials, client_options)?;
+
+            let report = check_profile(
+                &mut profile,
+                &sw_client,
+                mode,
+                file.as_deref(),
+                format,
+                rows,
+            )?;
+            println!("{report}");
+            if !report.is_ready() {
+                anyhow::bail!("the profile isn't ready, see the failed checks above");
+            }
+        }
+        Commands::SchemaDiff { source, target } => {
+            let source_client = SwHttpClient::new(
+                Credentials::read_env_credentials(&source)?,
+                client_options.clone(),
+            )?;
+            let target_client =
+                SwHttpClient::new(Credentials::read_env_credentials(&target)?, client_options)?;
+            let source_schema = source_client
+                .entity_schema()
+                .context("failed to fetch the entity schema of the source shop")?;
+            let target_schema = target_client
+                .entity_schema()
+                .context("failed to fetch the entity schema of the target shop")?;
+
+            let diff = diff_schemas(&source_schema, &target_schema);
+            if diff.is_empty() {
+                println!("The entity schemas of both shops match.");
+            } else {
+                println!("{diff}");
+            }
+        }
+        Commands::Compare {
+            entity,
+            source,
+            target,
+            profile,
+            key,
+            ignore,
+        } => {
+            let profile_path =
+                profile.unwrap_or_else(|| PathBuf::from(format!("default_{entity}")));
+            let mut profile = Profile::read_profile(&profile_path)?;
+            if profile.entity != entity {
+                anyhow::bail!(
+                    "the profile {profile_path:?} is for the entity '{}', not '{entity}'",
+                    profile.entity
+                );
+            }
+            let source_client = SwHttpClient::new(
+                Credentials::read_env_credentials(&source)?,
+                client_options.clone(),
+            )?;
+            let target_client =
+                SwHttpClient::new(Credentials::read_env_credentials(&target)?, client_options)?;
+            let api_schema = source_client
+                .entity_schema()
+                .context("failed to fetch the entity schema of the source shop")?;
+            add_live_version_filter(&mut profile, &api_schema);
+            let associations = get_associations(&profile, &api_schema);
+
+            let mappings = get_compared_mappings(&profile, &ignore);
+            let source_snapshot =
+                fetch_snapshot(&profile, &associations, &key, &mappings, &source_client)
+                    .context("failed to read the entities of the source shop")?;
+            let target_snapshot =
+                fetch_snapshot(&profile, &associations, &key, &mappings, &target_client)
+                    .context("failed to read the entities of the target shop")?;
+
+            let comparison = compare_snapshots(&mappings, &source_snapshot, &target_snapshot);
+            println!("{comparison}");
+            if !comparison.is_empty() {
+                anyhow::bail!(
+                    "the {entity} entities of both shops differ, see the differences above"
+                );
+            }
+            println!("The {entity} entities of both shops match.");
+        }
+        Commands::CleanupMedia { folder, force } => {
+            let credentials = Credentials::read_credentials()?;
+            let sw_client = SwHttpClient::new(credentials, client_options)?;
+            let count = cleanup_media(&sw_client, folder.as_deref(), force)?;
+            if !force {
+                println!(
+                    "Found {count} unused media, nothing was deleted (delete them with --force)"
+                );
+            } else {
+                println!("Successfully deleted {count} unused media.");
+            }
+        }
+        Commands::Sync(args) => {
+            let result = sync(&args, &client_options);
+            if let Some(webhook) = &args.webhook {
+                let payload = WebhookPayload::new(&args, &result);
+                // the profile might not be readable, so only the retry arguments are used
+                let mut retry = RetryOptions::default();
+                if let Some(backoff) = args.backoff {
+                    retry.backoff = backoff;
+                }
+                let try_count = args.get_try_count(None);
+                let timeout = (args.timeout > 0).then(|| Duration::from_secs(args.timeout));
+
+                match send_webhook(webhook, &payload, timeout, try_count, &retry) {
+                    Ok(()) => println!("Sent the summary to the webhook"),
+                    // the error of the run is more important
+                    Err(e) if result.is_err() => {
+                        println!("Warning: failed to send the summary to the webhook: {e:#}");
                     }
+                    Err(e) => return Err(e.context("failed to send the summary to the webhook")),
                 }
-                SyncMode::Export => {
-                    export(Arc::new(context))?;
+            }
+            resumable = result
+                .map_err(|failure| failure.error)?
+                .resume_row
+                .is_some();
+        }
+        Commands::Preview(args) => {
+            let rows = args.rows;
+            let context = Arc::new(create_shop_context(&SyncArgs::from(args), &client_options)?);
 
-                    println!("Exported successfully");
-                }
+            for (index, entity) in preview(&context, rows)?.iter().enumerate() {
+                println!("row {index}:\n{}", serde_json::to_string_pretty(entity)?);
             }
         }
     }
@@ -89,14 +232,203 @@ fn main() -> anyhow::Result<()> {
         "This whole command executed in {:.3}s",
         start_instant.elapsed().as_secs_f32()
     );
+    if resumable {
+        std::process::exit(RESUMABLE_EXIT_CODE);
+    }
 
     Ok(())
 }
 
-fn index(skip: Vec<String>) -> anyhow::Result<()> {
+fn sync(args: &SyncArgs, client_options: &SwClientOptions) -> Result<SyncReport, SyncFailure> {
+    let context = prepare_sync(args, client_options)?;
+
+    let context = if args.split_by_language {
+        // the context is only given back after the export of all languages
+        let context = Arc::new(export_by_language(context)?);
+        println!("Exported successfully");
+        context
+    } else {
+        let context = Arc::new(context);
+        if let Err(error) = run_sync(args, &context) {
+            return Err(SyncFailure {
+                error,
+                report: Some(Box::new(SyncReport::collect(&context))),
+            });
+        }
+        context
+    };
+
+    let sync_report = SyncReport::collect(&context);
+    sync_report.print_summary();
+    if let Some(report_path) = &args.report {
+        sync_report.write(report_path)?;
+        println!("Wrote report to {report_path:?}");
+    }
+
+    Ok(sync_report)
+}
+
+/// Check the arguments and create the context of the sync
+fn prepare_sync(args: &SyncArgs, client_options: &SwClientOptions) -> anyhow::Result<SyncContext> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.in_flight_limit)
+        .build_global()
+        .unwrap();
+    println!(
+        "using at most {} number of threads in a pool",
+        args.in_flight_limit
+    );
+    if !is_local_file(&args.file)
+        && (args.mode == SyncMode::Export
+            || args.skip_synced_chunks
+            || args.max_duration.is_some()
+            || args.resume)
+    {
+        anyhow::bail!("stdin and URLs can only be imported, without --skip-synced-chunks, --max-duration and --resume");
+    }
+    if client_options.read_only && args.mode != SyncMode::Export {
+        // otherwise every chunk and then every single row would fail on its sync request
+        anyhow::bail!("--read-only can only be used on export, imports and deletes need to write");
+    }
+    if args.split_by_language && args.mode != SyncMode::Export {
+        anyhow::bail!("--split-by-language can only be used on export");
+    }
+    if args.active_hours.is_some() && args.mode == SyncMode::Export {
+        anyhow::bail!("--active-hours can only be used on import or delete");
+    }
+    if args.crlf && (args.mode != SyncMode::Export || args.format == FileFormat::Parquet) {
+        anyhow::bail!("--crlf can only be used on export of text files");
+    }
+    if args.gzip && (args.mode != SyncMode::Export || args.format == FileFormat::Parquet) {
+        anyhow::bail!("--gzip can only be used on export of text files");
+    }
+    if args.manifest && args.mode != SyncMode::Export {
+        anyhow::bail!("--manifest can only be used on export");
+    }
+    if args.baseline.is_some()
+        && (args.mode != SyncMode::Export
+            || args.format.delimiter().is_none()
+            || args.split_by_language)
+    {
+        anyhow::bail!("--baseline can only be used on export of delimited files (without --split-by-language)");
+    }
+    if args.skip_synced_chunks
+        && (args.mode == SyncMode::Export || args.validate_remote || args.retry_file.is_some())
+    {
+        anyhow::bail!("--skip-synced-chunks can only be used on import or delete of a file");
+    }
+    if args.status_file.is_some() && args.split_by_language {
+        anyhow::bail!("--status-file can't be used with --split-by-language");
+    }
+    if args.validate_remote && args.mode == SyncMode::Export {
+        anyhow::bail!("--validate-remote can only be used on import or delete");
+    }
+    if args.retry_file.is_some() && args.mode == SyncMode::Export {
+        anyhow::bail!("--retry-file can only be used on import or delete");
+    }
+    if (args.max_duration.is_some() || args.resume)
+        && (args.mode == SyncMode::Export || args.retry_file.is_some())
+    {
+        anyhow::bail!("--max-duration and --resume can only be used on import or delete of a file");
+    }
+    if args.format == FileFormat::Parquet && args.dedupe {
+        anyhow::bail!("--dedupe can't be used with the parquet format, because its pages are encoded in parallel");
+    }
+    if args.ordered && args.mode == SyncMode::Export {
+        anyhow::bail!("--ordered can only be used on import or delete");
+    }
+    if args.index_per_chunk && (args.mode != SyncMode::Import || args.validate_remote) {
+        anyhow::bail!("--index-per-chunk can only be used on import");
+    }
+    // a remote validation is a dry run, it doesn't delete anything
+    if args.mode == SyncMode::Delete && !args.yes && !args.validate_remote {
+        anyhow::bail!("-m delete deletes every entity of the file, confirm it with --yes (or check it first with --validate-remote)");
+    }
+    create_shop_context(args, client_options)
+}
+
+fn run_sync(args: &SyncArgs, context: &Arc<SyncContext>) -> anyhow::Result<()> {
+    match args.mode {
+        SyncMode::Import | SyncMode::Delete => {
+            // remote validations don't write anything
+            let _lock = if args.no_lock || args.validate_remote {
+                None
+            } else {
+                Some(RunLock::acquire(
+                    context.sw_client.base_url(),
+                    &context.profile.entity,
+                    args.lock_wait,
+                )?)
+            };
+            let run = || match &args.retry_file {
+                Some(retry_file) => import_retry_file(Arc::clone(context), retry_file),
+                None => import(Arc::clone(context)),
+            };
+            match &args.status_file {
+                Some(status_file) => with_status_file(status_file, args.mode, context, run)?,
+                None => run()?,
+            }
+            if let Some(synced_chunks) = &context.synced_chunks {
+                if synced_chunks.skipped() > 0 {
+                    println!(
+                        "Skipped {} chunks, which were already synced by a previous run",
+                        synced_chunks.skipped()
+                    );
+                }
+            }
+            if let Some(retry_file) = write_retry_file(context)? {
+                println!("Warning: some rows failed because of temporary errors, retry them later with: --retry-file {retry_file:?}");
+            }
+
+            if let Some(checkpoint) = update_checkpoint(context)? {
+                println!("Stopped after the maximum duration, wrote the checkpoint {checkpoint:?}");
+                println!("Continue the import later with: --resume");
+            } else if args.validate_remote {
+                println!("Validated successfully, nothing was written");
+            } else {
+                if args.mode == SyncMode::Delete {
+                    println!("Deleted successfully");
+                } else {
+                    println!("Imported successfully");
+                }
+                if args.disable_index {
+                    println!("Indexing was skipped, you might want to run the indexers in your shop later. Go to Settings -> System -> Caches & indexes");
+                    println!("Or simply run: sw-sync-cli index");
+                } else {
+                    println!("Triggering indexing...");
+                    // the products were already indexed during the import
+                    let skip = if args.index_per_chunk {
+                        vec![PRODUCT_INDEXER.to_string()]
+                    } else {
+                        vec![]
+                    };
+                    context.sw_client.index(skip)?;
+                    println!("Successfully triggered indexing.");
+                }
+            }
+
+            let failed_recalculations = context.failed_recalculations.load(Ordering::Relaxed);
+            if failed_recalculations > 0 {
+                anyhow::bail!("the prices of {failed_recalculations} written orders couldn't be recalculated, see the failed rows");
+            }
+        }
+        SyncMode::Export => {
+            let run = || export(Arc::clone(context));
+            match &args.status_file {
+                Some(status_file) => with_status_file(status_file, args.mode, context, run)?,
+                None => run()?,
+            }
+            println!("Exported successfully");
+        }
+    }
+
+    Ok(())
+}
+
+fn index(skip: Vec<String>, client_options: SwClientOptions) -> anyhow::Result<()> {
     let credentials = Credentials::read_credentials()?;
 
-    let sw_client = SwClient::new(credentials)?;
+    let sw_client = SwHttpClient::new(credentials, client_options)?;
     sw_client.index(skip)?;
 
     Ok(())
@@ -121,7 +453,7 @@ pub fn copy_profiles(force: bool, list: bool, path: Option<PathBuf>) {
 
         path
     } else {
-        PathBuf::from("./profiles")
+        PathBuf::from(PROFILES_DIR)
     };
 
     if let Err(e) = fs::create_dir_all(&dir_path) {
@@ -144,66 +476,36 @@ pub fn copy_profiles(force: bool, list: bool, path: Option<PathBuf>) {
     }
 }
 
-fn auth(domain: String, id: String, secret: String) -> anyhow::Result<()> {
-    let credentials = Credentials {
-        base_url: domain.trim_end_matches('/').to_string(),
-        access_key_id: id,
-        access_key_secret: secret,
-    };
+fn auth(
+    env: Option<String>,
+    mut credentials: Credentials,
+    client_options: SwClientOptions,
+) -> anyhow::Result<()> {
+    if let Some(env) = &env {
+        Credentials::validate_env_name(env)?;
+    }
+    credentials.base_url = credentials.base_url.trim_end_matches('/').to_string();
 
     // check if credentials work
-    let _ = SwClient::new(credentials.clone())?;
+    let _ = SwHttpClient::new(credentials.clone(), client_options)?;
 
     // write them to file
     let serialized = toml::to_string(&credentials)?;
-    std::fs::write("./.credentials.toml", serialized)?;
+    match env {
+        Some(env) => std::fs::write(format!("./.credentials.{env}.toml"), serialized)?,
+        None => std::fs::write("./.credentials.toml", serialized)?,
+    }
 
     Ok(())
 }
 
-fn create_context(
-    profile_path: PathBuf,
-    file: PathBuf,
-    limit: Option<u64>,
-    in_flight_limit: usize,
-    try_count: NonZeroU8,
+/// Context of a sync command against the shop of the credentials
+fn create_shop_context(
+    args: &SyncArgs,
+    client_options: &SwClientOptions,
 ) -> anyhow::Result<SyncContext> {
-    let profile = Profile::read_profile(profile_path)?;
-    let mut associations = profile.associations.clone();
-    for mapping in &profile.mappings {
-        if let Mapping::ByPath(by_path) = mapping {
-            if let Some((association, _field)) = by_path.entity_path.rsplit_once('.') {
-                associations.insert(association.trim_end_matches('?').to_owned());
-            }
-        }
-    }
-
     let credentials = Credentials::read_credentials()?;
-    let sw_client = SwClient::new(credentials)?;
-
-    let api_schema = sw_client.entity_schema();
-    let entity = &profile.entity;
-
-    validate_paths_for_entity(entity, &profile.mappings, &api_schema?)?;
-
-    let language_list = sw_client.get_languages()?;
-    let currency_list = sw_client.get_currencies()?;
-
-    let scripting_environment = prepare_scripting_environment(
-        &profile.serialize_script,
-        &profile.deserialize_script,
-        language_list,
-        currency_list,
-    )?;
-
-    Ok(SyncContext {
-        sw_client,
-        profile,
-        file,
-        limit,
-        scripting_environment,
-        associations,
-        in_flight_limit,
-        try_count,
-    })
+    let sw_client = SwHttpClient::new(credentials, get_client_options(args, client_options))?;
+
+    create_context(args, Arc::new(sw_client))
 }
@@ -0,0 +1,93 @@
+//! Platform specific locations and paths: the config directory of the user
+//! (e.g. `%APPDATA%\sw-sync-cli` on Windows) and long paths on Windows
+
+use std::path::{Path, PathBuf};
+
+/// Name of the directory of the CLI inside the config directory of the user
+const CONFIG_DIR_NAME: &str = "sw-sync-cli";
+
+/// Paths of Windows are limited to this length, unless they have the `\\?\` prefix
+#[cfg_attr(not(windows), allow(dead_code))]
+const MAX_PATH: usize = 260;
+
+/// Directory of the user-wide profiles and credentials, which are used if they don't exist
+/// in the working directory: `%APPDATA%\sw-sync-cli` on Windows,
+/// `$XDG_CONFIG_HOME/sw-sync-cli` (or `~/.config/sw-sync-cli`) on other systems
+pub fn get_config_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let base_dir = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let base_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    base_dir.map(|dir| dir.join(CONFIG_DIR_NAME))
+}
+
+/// Path of a file, which is read from the working directory or else from the config directory
+/// (which can exceed the length limit of Windows below `%APPDATA%`).
+/// Returns the path in the working directory, if it exists in neither
+pub fn find_config_file(file_name: &str) -> PathBuf {
+    let local_path = PathBuf::from(".").join(file_name);
+    if local_path.exists() {
+        return local_path;
+    }
+
+    match get_config_dir().map(|dir| dir.join(file_name)) {
+        Some(path) if path.exists() => get_long_path(&path),
+        _ => local_path,
+    }
+}
+
+/// Path which can exceed the length limit of Windows: long paths are made absolute
+/// and get the `\\?\` prefix there. Other paths (and all paths on other systems) stay unchanged
+pub fn get_long_path(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    if let Some(long_path) = std::path::absolute(path)
+        .ok()
+        .and_then(|absolute| add_long_path_prefix(&absolute.to_string_lossy()))
+    {
+        return PathBuf::from(long_path);
+    }
+
+    path.to_path_buf()
+}
+
+/// Prefix of an absolute Windows path, if it's too long for the regular APIs
+#[cfg_attr(not(windows), allow(dead_code))]
+fn add_long_path_prefix(absolute_path: &str) -> Option<String> {
+    if absolute_path.len() < MAX_PATH || absolute_path.starts_with(r"\\?\") {
+        return None;
+    }
+
+    match absolute_path.strip_prefix(r"\\") {
+        // network shares like \\server\share\file.csv
+        Some(unc_path) => Some(format!(r"\\?\UNC\{unc_path}")),
+        None => Some(format!(r"\\?\{absolute_path}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_path_prefix() {
+        let folder = "a".repeat(MAX_PATH);
+
+        assert_eq!(add_long_path_prefix(r"C:\import\products.csv"), None);
+        assert_eq!(
+            add_long_path_prefix(&format!(r"C:\{folder}\products.csv")),
+            Some(format!(r"\\?\C:\{folder}\products.csv"))
+        );
+        assert_eq!(
+            add_long_path_prefix(&format!(r"\\server\share\{folder}\products.csv")),
+            Some(format!(r"\\?\UNC\server\share\{folder}\products.csv"))
+        );
+        assert_eq!(
+            add_long_path_prefix(&format!(r"\\?\C:\{folder}\products.csv")),
+            None
+        );
+    }
+}
@@ -0,0 +1,809 @@
+//! Fake shop for end-to-end tests of profiles (import / export) without a live shop
+//!
+//! [`FakeShop`] implements [`SwClient`] like a shop with the entity schema of `fixtures/` would:
+//! searches return the entities of the shop (paginated, filters are ignored)
+//! and the payloads of sync requests are recorded instead of written.
+//! Sync responses contain the primary keys like the shop would, with the `versionId` of versioned entities.
+//!
+//! The module is part of the library with the feature `testing`:
+//!
+//! ```ignore
+//! let shop = FakeShop::new();
+//! let files = InMemory::default();
+//! files.insert(Path::new("products.csv"), "id;name\n0190e5c6a2d07b4e9d2b3d0c3f1a1b01;Cap\n");
+//! let mut context = shop.create_context(&["-m", "import", "-p", "my_profile.yaml", "-f", "products.csv"])?;
+//! context.source = Box::new(files);
+//! import(Arc::new(context))?;
+//! assert_eq!(shop.synced_operations()[0]["entity"], "product");
+//! ```
+
+use crate::api::filter::{Criteria, CriteriaFilter};
+use crate::api::{
+    Entity, SwApiError, SwClient, SwError, SwErrorBody, SwListResponse, SyncAction, SyncResult,
+};
+use crate::cli::{Cli, Commands};
+use crate::{create_context, SyncContext};
+use clap::Parser;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::sync::{Arc, Mutex};
+
+const ENTITY_SCHEMA_FIXTURE: &str = include_str!("../fixtures/entity-schema-2024-08-01.json");
+const OPENAPI_SCHEMA_FIXTURE: &str = include_str!("../fixtures/openapi3-excerpt.json");
+const ENTITIES_FIXTURE: &str = include_str!("../fixtures/fake-shop-entities.json");
+const LIVE_VERSION_ID: &str = "0fa91ce3e96a4bc2be4bd9ce752c3425";
+
+/// Shop in memory, the clients of all languages share the recorded requests
+#[derive(Debug, Clone)]
+pub struct FakeShop {
+    /// entities per entity name, like `{"product": [...]}`
+    entities: Arc<Value>,
+    entity_schema: Arc<Entity>,
+    /// entities with the `versionId` in their primary key (like products and orders)
+    versioned_entities: Arc<HashSet<String>>,
+    requests: Arc<FakeShopRequests>,
+}
+
+#[derive(Debug, Default)]
+struct FakeShopRequests {
+    /// `write_data` operations of the received sync requests
+    synced: Mutex<Vec<Value>>,
+    recalculated_orders: Mutex<Vec<String>>,
+    /// orders, whose recalculation is answered with an error
+    failing_recalculations: Mutex<HashSet<String>>,
+}
+
+impl FakeShop {
+    /// Shop with the entities of `fixtures/fake-shop-entities.json` (languages, currencies and a few products)
+    pub fn new() -> Self {
+        Self::with_entities(Self::fixture_entities())
+    }
+
+    /// Shop with the given entities per entity name, like `{"product": [...]}`
+    pub fn with_entities(entities: Value) -> Self {
+        let entity_schema: Entity = serde_json::from_str(ENTITY_SCHEMA_FIXTURE).unwrap();
+        let versioned_entities = entity_schema
+            .iter()
+            .filter(|(_, definition)| {
+                definition["properties"]["versionId"]["flags"]["primary_key"] == json!(true)
+            })
+            .map(|(entity, _)| entity.clone())
+            .collect();
+
+        Self {
+            entities: Arc::new(entities),
+            entity_schema: Arc::new(entity_schema),
+            versioned_entities: Arc::new(versioned_entities),
+            requests: Arc::default(),
+        }
+    }
+
+    /// Entities of `fixtures/fake-shop-entities.json`, to extend them for [`FakeShop::with_entities`]
+    pub fn fixture_entities() -> Value {
+        serde_json::from_str(ENTITIES_FIXTURE).unwrap()
+    }
+
+    /// Context of a sync command against this shop, the arguments are the ones after `sw-sync-cli sync`
+    pub fn create_context(&self, args: &[&str]) -> anyhow::Result<SyncContext> {
+        let cli = Cli::try_parse_from(["sw-sync-cli", "sync"].iter().chain(args).copied())?;
+        let Commands::Sync(args) = cli.command else {
+            unreachable!("parsed as sync command");
+        };
+
+        create_context(&args, Arc::new(self.clone()))
+    }
+
+    /// `write_data` operations (`{"entity": ..., "action": ..., "payload": [...]}`) of the sync requests so far
+    pub fn synced_operations(&self) -> Vec<Value> {
+        self.requests.synced.lock().unwrap().clone()
+    }
+
+    /// Answer the recalculation of the order with an error, like for an order with a deleted product
+    pub fn fail_recalculation(&self, order_id: &str) {
+        self.requests
+            .failing_recalculations
+            .lock()
+            .unwrap()
+            .insert(order_id.to_string());
+    }
+
+    /// Ids of the successfully recalculated orders so far
+    pub fn recalculated_orders(&self) -> Vec<String> {
+        self.requests.recalculated_orders.lock().unwrap().clone()
+    }
+
+    fn get_entities(&self, entity: &str) -> &[Value] {
+        self.entities[entity]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+impl Default for FakeShop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SwClient for FakeShop {
+    fn base_url(&self) -> &str {
+        "http://fake-shop.test"
+    }
+
+    /// Translations aren't faked, every language has the same entities
+    fn with_language(&self, _language_id: String) -> Arc<dyn SwClient> {
+        Arc::new(self.clone())
+    }
+
+    /// The fake shop is authenticated as an admin integration
+    fn get_integration_privileges(&self) -> Result<Option<BTreeSet<String>>, SwApiError> {
+        Ok(None)
+    }
+
+    /// Record the operation and answer with the primary keys of its payload
+    fn sync(
+        &self,
+        entity: &str,
+        action: SyncAction,
+        payload: &[Entity],
+    ) -> Result<SyncResult, SwApiError> {
+        let ids: Vec<Value> = payload
+            .iter()
+            .filter_map(|entry| entry.get("id").cloned())
+            .map(|id| {
+                if self.versioned_entities.contains(entity) {
+                    json!({ "id": id, "versionId": LIVE_VERSION_ID })
+                } else {
+                    id
+                }
+            })
+            .collect();
+        self.requests.synced.lock().unwrap().push(json!({
+            "entity": entity,
+            "action": action,
+            "payload": payload,
+        }));
+
+        Ok(match action {
+            SyncAction::Upsert => SyncResult {
+                written: ids,
+                deleted: vec![],
+            },
+            SyncAction::Delete => SyncResult {
+                written: vec![],
+                deleted: ids,
+            },
+        })
+    }
+
+    /// Every payload is valid, nothing is recorded
+    fn validate_sync(
+        &self,
+        _entity: &str,
+        _action: SyncAction,
+        _payload: &[Entity],
+    ) -> Result<Vec<SwError>, SwApiError> {
+        Ok(vec![])
+    }
+
+    fn entity_schema(&self) -> Result<Entity, SwApiError> {
+        Ok(Entity::clone(&self.entity_schema))
+    }
+
+    fn openapi_schema(&self) -> Result<Value, SwApiError> {
+        Ok(serde_json::from_str(OPENAPI_SCHEMA_FIXTURE)?)
+    }
+
+    /// Documents are empty files
+    fn download_document(
+        &self,
+        _document_id: &str,
+        _deep_link_code: &str,
+    ) -> Result<Vec<u8>, SwApiError> {
+        Ok(vec![])
+    }
+
+    fn get_total(&self, entity: &str, _filter: &[CriteriaFilter]) -> Result<u64, SwApiError> {
+        Ok(self.get_entities(entity).len() as u64)
+    }
+
+    /// Page of the entities, the filters, sortings and associations of the criteria are ignored
+    fn list(
+        &self,
+        entity: &str,
+        criteria: &Criteria,
+    ) -> Result<SwListResponse<Entity>, SwApiError> {
+        let limit = criteria.limit.unwrap_or(usize::MAX);
+        let data = self
+            .get_entities(entity)
+            .iter()
+            .skip(limit.saturating_mul(criteria.page.saturating_sub(1) as usize))
+            .take(limit)
+            .filter_map(|entity| entity.as_object().cloned())
+            .collect();
+
+        Ok(SwListResponse { data })
+    }
+
+    fn index(&self, _skip: Vec<String>) -> Result<(), SwApiError> {
+        Ok(())
+    }
+
+    fn index_products(&self, _ids: &[&str]) -> Result<(), SwApiError> {
+        Ok(())
+    }
+
+    fn recalculate_order(&self, order_id: &str) -> Result<(), SwApiError> {
+        if self
+            .requests
+            .failing_recalculations
+            .lock()
+            .unwrap()
+            .contains(order_id)
+        {
+            return Err(SwApiError::Server(
+                StatusCode::BAD_REQUEST,
+                SwErrorBody {
+                    errors: vec![SwError::GenericError {
+                        code: "CHECKOUT__ORDER_RECALCULATION_FAILED".to_string(),
+                        detail: Some("Order can't be recalculated.".to_string()),
+                        status: "400".to_string(),
+                        title: "Bad Request".to_string(),
+                        meta: None,
+                    }],
+                },
+            ));
+        }
+        self.requests
+            .recalculated_orders
+            .lock()
+            .unwrap()
+            .push(order_id.to_string());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::SyncMode;
+    use crate::config_file::{EntityPathMapping, Mapping, ValueFormat};
+    use crate::data::{
+        export, export_by_language, import, with_status_file, write_retry_file, InMemory,
+    };
+    use std::num::NonZeroUsize;
+    use std::path::Path;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn import_default_product_profile() {
+        let files = InMemory::default();
+        files.insert(
+            Path::new("products.csv"),
+            "id;tax id;manufacturer id;default manufacturer name;media id;cover id;product number;active;stock;\
+             tax rate;tax name;unit;ean;categories;visibilities;properties;default name;default description;\
+             prices;default price net;default price gross\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a1b03;0190e5c6a2d07b4e9d2b3d0c3f1a1c01;;;;;SW-3;true;5;\
+             ;;;;;;;Cap;;;10;11.9\n",
+        );
+        let shop = FakeShop::new();
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "import",
+                "-p",
+                "default_product",
+                "-f",
+                "products.csv",
+            ])
+            .unwrap();
+        context.source = Box::new(files);
+        import(Arc::new(context)).unwrap();
+
+        let operations = shop.synced_operations();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0]["entity"], "product");
+        assert_eq!(operations[0]["action"], "upsert");
+        let product = &operations[0]["payload"][0];
+        assert_eq!(product["id"], "0190e5c6a2d07b4e9d2b3d0c3f1a1b03");
+        assert_eq!(product["productNumber"], "SW-3");
+        assert_eq!(product["name"], "Cap");
+        assert_eq!(product["stock"], 5);
+        assert_eq!(
+            product["price"],
+            json!([{
+                "net": 10,
+                "gross": 11.9,
+                "linked": true,
+                "currencyId": "b7d2554b0ce847cd82f3ac9bd1c0dfca"
+            }])
+        );
+    }
+
+    #[test]
+    fn import_advanced_prices_with_rule_ids() {
+        // file of the profile version before the rule lookup
+        let files = InMemory::default();
+        files.insert(
+            Path::new("prices.csv"),
+            "id;product id;rule id;quantity start;quantity end;default price net;default price gross;\
+             default list price net;default list price gross;default cheapest price net;\
+             default cheapest price gross;default percentage net;default percentage gross\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a1d01;0190e5c6a2d07b4e9d2b3d0c3f1a1b03;\
+             0190e5c6a2d07b4e9d2b3d0c3f1a1e01;1;;10;11.9;;;;;;\n",
+        );
+        let shop = FakeShop::new();
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "import",
+                "-p",
+                "default_advanced_price",
+                "-f",
+                "prices.csv",
+            ])
+            .unwrap();
+        context.source = Box::new(files);
+        import(Arc::new(context)).unwrap();
+
+        let operations = shop.synced_operations();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(
+            operations[0]["payload"][0]["ruleId"],
+            "0190e5c6a2d07b4e9d2b3d0c3f1a1e01"
+        );
+    }
+
+    #[test]
+    fn delete_tag_assignments() {
+        let files = InMemory::default();
+        files.insert(
+            Path::new("tags.csv"),
+            "product id;tag\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a1b03;Sale\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a1b04;sale\n",
+        );
+        let mut entities = FakeShop::fixture_entities();
+        entities["tag"] = json!([{ "id": "0190e5c6a2d07b4e9d2b3d0c3f1a1f01", "name": "Sale" }]);
+        let shop = FakeShop::with_entities(entities);
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "delete",
+                "-p",
+                "default_product_tag",
+                "-f",
+                "tags.csv",
+                "--yes",
+            ])
+            .unwrap();
+        context.source = Box::new(files);
+        import(Arc::new(context)).unwrap();
+
+        // both rows in one request, only with the primary keys of the assignment
+        let operations = shop.synced_operations();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0]["entity"], "product_tag");
+        assert_eq!(operations[0]["action"], "delete");
+        let assignment = |product_id: &str| {
+            json!({
+                "productId": product_id,
+                "productVersionId": "0fa91ce3e96a4bc2be4bd9ce752c3425",
+                "tagId": "0190e5c6a2d07b4e9d2b3d0c3f1a1f01",
+            })
+        };
+        assert_eq!(
+            operations[0]["payload"],
+            json!([
+                assignment("0190e5c6a2d07b4e9d2b3d0c3f1a1b03"),
+                assignment("0190e5c6a2d07b4e9d2b3d0c3f1a1b04"),
+            ])
+        );
+    }
+
+    #[test]
+    fn ordered_import_syncs_the_chunks_in_file_order() {
+        let ids: Vec<String> = (0..20)
+            .map(|i| format!("0190e5c6a2d07b4e9d2b3d0c3f1a2{i:03}"))
+            .collect();
+        let mut csv = "id;stock;price net;price gross\n".to_string();
+        for id in &ids {
+            csv.push_str(&format!("{id};5;;\n"));
+        }
+        let files = InMemory::default();
+        files.insert(Path::new("stock.csv"), csv);
+        let shop = FakeShop::new();
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "import",
+                "-p",
+                "default_product_stock",
+                "-f",
+                "stock.csv",
+                "--ordered",
+                "-i",
+                "2",
+            ])
+            .unwrap();
+        context.source = Box::new(files);
+        context.profile.sync_chunk_size = NonZeroUsize::new(1);
+        import(Arc::new(context)).unwrap();
+
+        let synced_ids: Vec<Value> = shop
+            .synced_operations()
+            .iter()
+            .map(|operation| operation["payload"][0]["id"].clone())
+            .collect();
+        assert_eq!(synced_ids, ids);
+    }
+
+    #[test]
+    fn change_log_is_applied_in_file_order() {
+        let profile = std::env::temp_dir().join("sw-sync-cli-change-log.yaml");
+        std::fs::write(
+            &profile,
+            "entity: product\n\
+             action_column: action\n\
+             mappings:\n  \
+             - file_column: id\n    \
+               entity_path: id\n  \
+             - file_column: stock\n    \
+               entity_path: stock\n",
+        )
+        .unwrap();
+        let files = InMemory::default();
+        files.insert(
+            Path::new("changes.csv"),
+            "action;id;stock\n\
+             delete;0190e5c6a2d07b4e9d2b3d0c3f1a3001;\n\
+             upsert;0190e5c6a2d07b4e9d2b3d0c3f1a3001;5\n\
+             delete;0190e5c6a2d07b4e9d2b3d0c3f1a3001;\n\
+             upsert;0190e5c6a2d07b4e9d2b3d0c3f1a3002;7\n\
+             upsert;0190e5c6a2d07b4e9d2b3d0c3f1a3003;9\n\
+             delete;0190e5c6a2d07b4e9d2b3d0c3f1a3002;\n",
+        );
+        let shop = FakeShop::new();
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "import",
+                "-p",
+                profile.to_str().unwrap(),
+                "-f",
+                "changes.csv",
+                "-i",
+                "3",
+            ])
+            .unwrap();
+        std::fs::remove_file(&profile).unwrap();
+        assert!(context.ordered);
+        context.source = Box::new(files);
+        context.profile.sync_chunk_size = NonZeroUsize::new(2);
+        import(Arc::new(context)).unwrap();
+
+        // one operation per run of the same action in a chunk, the chunks in file order
+        let operations: Vec<(Value, usize)> = shop
+            .synced_operations()
+            .iter()
+            .map(|operation| {
+                let payload = operation["payload"].as_array().unwrap();
+                (operation["action"].clone(), payload.len())
+            })
+            .collect();
+        assert_eq!(
+            operations,
+            [
+                (json!("delete"), 1),
+                (json!("upsert"), 1),
+                (json!("delete"), 1),
+                (json!("upsert"), 1),
+                (json!("upsert"), 1),
+                (json!("delete"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn written_orders_are_recalculated() {
+        let profile = std::env::temp_dir().join("sw-sync-cli-recalculated-orders.yaml");
+        std::fs::write(
+            &profile,
+            "entity: order\n\
+             order_prices: recalculate\n\
+             mappings:\n  \
+             - file_column: id\n    \
+               entity_path: id\n  \
+             - file_column: order number\n    \
+               entity_path: orderNumber\n",
+        )
+        .unwrap();
+        let files = InMemory::default();
+        files.insert(
+            Path::new("orders.csv"),
+            "id;order number\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a4001;10001\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a4002;10002\n",
+        );
+        let shop = FakeShop::new();
+        shop.fail_recalculation("0190e5c6a2d07b4e9d2b3d0c3f1a4002");
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "import",
+                "-p",
+                profile.to_str().unwrap(),
+                "-f",
+                "orders.csv",
+            ])
+            .unwrap();
+        std::fs::remove_file(&profile).unwrap();
+        context.source = Box::new(files);
+        let context = Arc::new(context);
+        import(Arc::clone(&context)).unwrap();
+
+        assert_eq!(
+            shop.recalculated_orders(),
+            ["0190e5c6a2d07b4e9d2b3d0c3f1a4001"]
+        );
+        assert_eq!(context.failed_recalculations.load(Ordering::Relaxed), 1);
+        let failed_rows = context.failed_rows.lock().unwrap();
+        assert_eq!(failed_rows.len(), 1);
+        assert_eq!(failed_rows[0].row_index, 1);
+        assert!(failed_rows[0].message.contains(
+            "failed to recalculate the prices of order 0190e5c6a2d07b4e9d2b3d0c3f1a4002"
+        ));
+    }
+
+    #[test]
+    fn status_file_counts_rows_while_reading() {
+        let files = InMemory::default();
+        files.insert(
+            Path::new("stock.csv"),
+            "id;stock;price net;price gross\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a4001;5;;\n\
+             ;;;\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a4002;7;;\n",
+        );
+        let shop = FakeShop::new();
+        // the directory doesn't exist, so the status can't be written
+        let status_file = std::env::temp_dir()
+            .join("sw-sync-cli-missing-dir")
+            .join("status.json");
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "import",
+                "-p",
+                "default_product_stock",
+                "-f",
+                "stock.csv",
+            ])
+            .unwrap();
+        context.source = Box::new(files);
+        context.profile.skip_blank_rows = true;
+        let context = Arc::new(context);
+
+        // failing status writes are only warnings, the result of the sync is kept
+        with_status_file(&status_file, SyncMode::Import, &context, || {
+            import(Arc::clone(&context))
+        })
+        .unwrap();
+        assert_eq!(context.total_rows.get(), Some(&2));
+    }
+
+    #[test]
+    fn blank_rows_are_skipped_without_counting_against_the_limit() {
+        let files = InMemory::default();
+        files.insert(
+            Path::new("stock.csv"),
+            "id;stock;price net;price gross\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a5001;5;;\n\
+             ;;;\n\
+             \" \";;;\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a5002;7;;\n\
+             0190e5c6a2d07b4e9d2b3d0c3f1a5003;9;;\n",
+        );
+        let shop = FakeShop::new();
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "import",
+                "-p",
+                "default_product_stock",
+                "-f",
+                "stock.csv",
+                "--limit",
+                "2",
+            ])
+            .unwrap();
+        context.source = Box::new(files);
+        context.profile.skip_blank_rows = true;
+        let context = Arc::new(context);
+        import(Arc::clone(&context)).unwrap();
+
+        let synced_ids: Vec<Value> = shop
+            .synced_operations()
+            .iter()
+            .flat_map(|operation| operation["payload"].as_array().unwrap().clone())
+            .map(|entity| entity["id"].clone())
+            .collect();
+        assert_eq!(
+            synced_ids,
+            [
+                "0190e5c6a2d07b4e9d2b3d0c3f1a5001",
+                "0190e5c6a2d07b4e9d2b3d0c3f1a5002"
+            ]
+        );
+        assert!(context.skipped_rows.lock().unwrap().is_empty());
+        assert!(context.failed_rows.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn wildcard_paths_are_rejected_before_the_import() {
+        let shop = FakeShop::new();
+
+        let result = shop.create_context(&[
+            "-m",
+            "import",
+            "-p",
+            "default_product",
+            "-f",
+            "products.csv",
+            "--map",
+            "category names=categories[*].name",
+        ]);
+        let Err(error) = result else {
+            panic!("the import with a wildcard path should fail");
+        };
+        assert_eq!(
+            error.to_string(),
+            "wildcard entity_path 'categories[*].name' of column 'category names' can only be used on export"
+        );
+    }
+
+    #[test]
+    fn outdated_retry_file_is_removed() {
+        let file = std::env::temp_dir().join("sw-sync-cli-outdated-retry-file.csv");
+        let retry_file =
+            std::env::temp_dir().join("sw-sync-cli-outdated-retry-file.csv.retry.json");
+        let shop = FakeShop::new();
+        let context = shop
+            .create_context(&[
+                "-m",
+                "import",
+                "-p",
+                "default_product_stock",
+                "-f",
+                file.to_str().unwrap(),
+            ])
+            .unwrap();
+
+        // the run stopped before all rows were synced
+        std::fs::write(&retry_file, "{}").unwrap();
+        *context.resume_row.lock().unwrap() = Some(500);
+        assert_eq!(write_retry_file(&context).unwrap(), None);
+        assert!(retry_file.exists());
+
+        *context.resume_row.lock().unwrap() = None;
+        assert_eq!(write_retry_file(&context).unwrap(), None);
+        assert!(!retry_file.exists());
+    }
+
+    #[test]
+    fn export_default_product_profile() {
+        let files = InMemory::default();
+        let shop = FakeShop::new();
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "export",
+                "-p",
+                "default_product",
+                "-f",
+                "products.csv",
+            ])
+            .unwrap();
+        context.sink = Box::new(files.clone());
+        export(Arc::new(context)).unwrap();
+        let exported = String::from_utf8(files.get(Path::new("products.csv")).unwrap()).unwrap();
+
+        let mut lines = exported.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("id;tax id;manufacturer id;"));
+        let rows: Vec<&str> = lines.collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0],
+            "0190e5c6a2d07b4e9d2b3d0c3f1a1b01;0190e5c6a2d07b4e9d2b3d0c3f1a1c01;null;null;[];null;\
+             SW-1;true;10;19;Standard rate;null;null;[];[];[];T-Shirt;null;null;10.0;11.9"
+        );
+        assert!(rows[1].contains(";SW-2;false;0;"));
+        assert!(shop.synced_operations().is_empty());
+    }
+
+    #[test]
+    fn export_by_language_sums_up_counters() {
+        let files = InMemory::default();
+        let mut entities = FakeShop::fixture_entities();
+        let products = entities["product"].as_array_mut().unwrap();
+        for product in products.iter_mut() {
+            product["description"] = json!("");
+        }
+        products.push(products[0].clone());
+        let shop = FakeShop::with_entities(entities);
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "export",
+                "-p",
+                "default_product",
+                "-f",
+                "products.csv",
+                "--split-by-language",
+                "--dedupe",
+                "--warn-unused",
+            ])
+            .unwrap();
+        context.sink = Box::new(files.clone());
+        let context = export_by_language(context).unwrap();
+
+        assert!(files.get(Path::new("products.en-GB.csv")).is_some());
+        assert!(files.get(Path::new("products.de-DE.csv")).is_some());
+        // the duplicate of both files
+        assert_eq!(context.suppressed_duplicates.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            *context.empty_columns.lock().unwrap(),
+            ["default description"]
+        );
+    }
+
+    #[test]
+    fn failed_export_removes_file() {
+        let files = InMemory::default();
+        let shop = FakeShop::with_entities(json!({
+            "product": [{ "id": "0190e5c6a2d07b4e9d2b3d0c3f1a1b01", "releaseDate": "2024-08-01" }],
+        }));
+
+        let mut context = shop
+            .create_context(&[
+                "-m",
+                "export",
+                "-p",
+                "default_product",
+                "-f",
+                "products.csv",
+            ])
+            .unwrap();
+        context.sink = Box::new(files.clone());
+        // an invalid date format fails the page
+        context
+            .profile
+            .mappings
+            .push(Mapping::ByPath(Box::new(EntityPathMapping {
+                file_column: "release date".to_string(),
+                entity_path: "releaseDate".to_string(),
+                format: Some(ValueFormat {
+                    date_format: Some("%Q".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })));
+
+        assert!(export(Arc::new(context)).is_err());
+        assert!(files.get(Path::new("products.csv")).is_none());
+    }
+}